@@ -14,6 +14,9 @@
 #   /build.sh --both          # Build both editions
 #   /build.sh --tools         # Build mkgamedisk tool only
 #
+# Put a folder of ROMs on the GameBoy CD with ROMS_DIR=<dir> (or mount it
+# at /input/roms).
+#
 
 set -e
 
@@ -189,6 +192,7 @@ create_noemu_iso() {
     local IMG_NAME="$1"
     local ISO_NAME="$2"
     local VOLUME_ID="$3"
+    local ROMS_DIR="$4"
 
     echo "      Creating $ISO_NAME (no-emulation El Torito)..."
 
@@ -215,6 +219,13 @@ create_noemu_iso() {
     # Copy floppy image for fallback/installation
     cp "build/$IMG_NAME" build/iso/
 
+    # ROM collection, browsed from the CD by the kernel (Joliet keeps long names)
+    if [ -n "$ROMS_DIR" ]; then
+        echo "      Adding ROMs from $ROMS_DIR"
+        mkdir -p build/iso/ROMS
+        cp -r "$ROMS_DIR"/. build/iso/ROMS/
+    fi
+
     # Determine boot image size in CD sectors (2048 bytes each)
     # boot.img needs to cover: boot(1) + stage2(8) + kernel(128) = ~137 sectors minimum
     # We'll use the actual file size
@@ -316,7 +327,17 @@ if [ "$BUILD_GAMEBOY" = "yes" ]; then
         echo "      No ROM file specified (use ROM_FILE env var or mount to /input/game.gb)"
     fi
 
-    create_noemu_iso "gameboy-system.img" "gameboy-system.iso" "GAMEBOY_OS"
+    # ROM collection for a standalone game disc
+    ROMS_DIR="${ROMS_DIR:-}"
+    if [ -z "$ROMS_DIR" ] && [ -d "/input/roms" ]; then
+        ROMS_DIR="/input/roms"
+    fi
+    if [ -n "$ROMS_DIR" ] && [ ! -d "$ROMS_DIR" ]; then
+        echo "      ROM folder $ROMS_DIR not found, building the CD without ROMs"
+        ROMS_DIR=""
+    fi
+
+    create_noemu_iso "gameboy-system.img" "gameboy-system.iso" "GAMEBOY_OS" "$ROMS_DIR"
 fi
 
 echo ""
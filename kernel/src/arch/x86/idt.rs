@@ -7,6 +7,7 @@ use core::arch::global_asm;
 use core::mem::size_of;
 use super::gdt::selectors;
 use super::pic;
+use crate::storage::ata::Channel;
 
 /// IDT Entry (Interrupt Gate Descriptor)
 #[derive(Debug, Clone, Copy)]
@@ -148,6 +149,8 @@ extern "C" {
     fn irq_stub_0();
     fn irq_stub_1();
     fn irq_stub_12();
+    fn irq_stub_14();
+    fn irq_stub_15();
     fn irq_stub_default();
 }
 
@@ -307,6 +310,18 @@ global_asm!(
     "    push 44",             // IRQ 12 = INT 44 (mouse/touchpad)
     "    jmp isr_common",
 
+    ".global irq_stub_14",
+    "irq_stub_14:",
+    "    push 0",
+    "    push 46",             // IRQ 14 = INT 46 (primary IDE)
+    "    jmp isr_common",
+
+    ".global irq_stub_15",
+    "irq_stub_15:",
+    "    push 0",
+    "    push 47",             // IRQ 15 = INT 47 (secondary IDE)
+    "    jmp isr_common",
+
     ".global irq_stub_default",
     "irq_stub_default:",
     "    push 0",
@@ -383,10 +398,12 @@ unsafe fn set_irq_handlers() {
     // IRQ 12 - PS/2 Mouse/Touchpad (INT 44) - dedicated handler
     IDT.0[44] = IdtEntry::interrupt_gate(irq_stub_12 as u32, selectors::KERNEL_CODE, 0);
 
-    // IRQ 13-15 use default handler
-    for i in 13..16 {
-        IDT.0[32 + i] = IdtEntry::interrupt_gate(irq_stub_default as u32, selectors::KERNEL_CODE, 0);
-    }
+    // IRQ 13 uses default handler
+    IDT.0[45] = IdtEntry::interrupt_gate(irq_stub_default as u32, selectors::KERNEL_CODE, 0);
+
+    // IRQ 14/15 - IDE channels (INT 46/47), for bus-master DMA completion
+    IDT.0[46] = IdtEntry::interrupt_gate(irq_stub_14 as u32, selectors::KERNEL_CODE, 0);
+    IDT.0[47] = IdtEntry::interrupt_gate(irq_stub_15 as u32, selectors::KERNEL_CODE, 0);
 }
 
 /// Main interrupt handler (called from assembly)
@@ -406,6 +423,8 @@ extern "C" fn interrupt_handler(frame: &InterruptFrame) {
         32 => timer_handler(),
         33 => keyboard_handler(),
         44 => mouse_handler(),  // IRQ 12 = interrupt 44
+        46 => ide_handler(Channel::Primary),
+        47 => ide_handler(Channel::Secondary),
 
         // Other IRQs
         32..=47 => {
@@ -579,3 +598,14 @@ fn mouse_handler() {
     // IRQ12 is on the slave PIC, so we need to send EOI to both
     pic::send_eoi(44);
 }
+
+/// IDE channel IRQ handler (bus-master DMA completion)
+fn ide_handler(channel: Channel) {
+    // IRQ 15 is also where the slave PIC puts spurious interrupts
+    if channel == Channel::Secondary && pic::is_spurious(15) {
+        return;
+    }
+
+    crate::storage::busmaster::handle_irq(channel);
+    pic::send_eoi(channel.irq());
+}
@@ -0,0 +1,175 @@
+//! Bochs Graphics Adapter (BGA) Driver
+//!
+//! The "DISPI" interface of the Bochs/QEMU standard VGA (PCI 1234:1111).
+//! Mode registers sit behind an index/data port pair (0x1CE/0x1CF); the
+//! linear framebuffer lives at the address in PCI BAR0.
+//!
+//! Only true-color linear modes are used. Disabling DISPI hands the card
+//! back to the standard VGA registers, so Mode 13h keeps working as the
+//! fallback on machines (and emulators) without BGA.
+
+use crate::arch::x86::io::{inw, outw};
+use crate::storage::pci;
+
+// =============================================================================
+// Registers
+// =============================================================================
+
+/// Index port
+const VBE_DISPI_IOPORT_INDEX: u16 = 0x01CE;
+/// Data port
+const VBE_DISPI_IOPORT_DATA: u16 = 0x01CF;
+
+/// DISPI register indices
+mod index {
+    pub const ID: u16 = 0x0;
+    pub const XRES: u16 = 0x1;
+    pub const YRES: u16 = 0x2;
+    pub const BPP: u16 = 0x3;
+    pub const ENABLE: u16 = 0x4;
+    pub const VIRT_WIDTH: u16 = 0x6;
+    pub const VIRT_HEIGHT: u16 = 0x7;
+    pub const X_OFFSET: u16 = 0x8;
+    pub const Y_OFFSET: u16 = 0x9;
+    pub const VIDEO_MEMORY_64K: u16 = 0xA;
+}
+
+/// Oldest and newest interface versions we know about
+const VBE_DISPI_ID0: u16 = 0xB0C0;
+const VBE_DISPI_ID5: u16 = 0xB0C5;
+
+/// ENABLE register bits
+const VBE_DISPI_DISABLED: u16 = 0x00;
+const VBE_DISPI_ENABLED: u16 = 0x01;
+const VBE_DISPI_GETCAPS: u16 = 0x02;
+const VBE_DISPI_LFB_ENABLED: u16 = 0x40;
+
+/// Bochs/QEMU standard VGA PCI IDs
+pub const BGA_VENDOR: u16 = 0x1234;
+pub const BGA_DEVICE: u16 = 0x1111;
+
+// =============================================================================
+// Mode Information
+// =============================================================================
+
+/// An active linear framebuffer mode
+#[derive(Debug, Clone, Copy)]
+pub struct BgaMode {
+    /// Linear framebuffer (physical = virtual, no paging)
+    pub lfb: *mut u8,
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel
+    pub bpp: u32,
+    /// Bytes per scanline
+    pub pitch: u32,
+}
+
+fn read_reg(index: u16) -> u16 {
+    unsafe {
+        outw(VBE_DISPI_IOPORT_INDEX, index);
+        inw(VBE_DISPI_IOPORT_DATA)
+    }
+}
+
+fn write_reg(index: u16, value: u16) {
+    unsafe {
+        outw(VBE_DISPI_IOPORT_INDEX, index);
+        outw(VBE_DISPI_IOPORT_DATA, value);
+    }
+}
+
+/// Check for a DISPI interface
+pub fn is_present() -> bool {
+    (VBE_DISPI_ID0..=VBE_DISPI_ID5).contains(&read_reg(index::ID))
+}
+
+/// Largest resolution and depth the adapter accepts
+pub fn max_mode() -> (u32, u32, u32) {
+    let enable = read_reg(index::ENABLE);
+    write_reg(index::ENABLE, enable | VBE_DISPI_GETCAPS);
+    let caps = (
+        read_reg(index::XRES) as u32,
+        read_reg(index::YRES) as u32,
+        read_reg(index::BPP) as u32,
+    );
+    write_reg(index::ENABLE, enable);
+    caps
+}
+
+/// Video memory size in bytes (0 if the interface is too old to say)
+pub fn video_memory() -> u32 {
+    if read_reg(index::ID) < 0xB0C2 {
+        return 0;
+    }
+    read_reg(index::VIDEO_MEMORY_64K) as u32 * 64 * 1024
+}
+
+/// Linear framebuffer address from the adapter's PCI BAR0
+pub fn lfb_address() -> Option<u32> {
+    let dev = pci::get_devices().find(BGA_VENDOR, BGA_DEVICE)?;
+    let bar0 = pci::config_read32(dev.bus, dev.device, dev.function, 0x10);
+    // Must be a memory BAR
+    if bar0 & 0x1 != 0 {
+        return None;
+    }
+    match bar0 & !0xF {
+        0 => None,
+        addr => Some(addr),
+    }
+}
+
+/// Switch to a linear true-color mode
+///
+/// Fails if there is no adapter, the mode exceeds its capabilities, or
+/// the framebuffer can't be located.
+pub fn set_mode(width: u32, height: u32, bpp: u32) -> Result<BgaMode, &'static str> {
+    if !is_present() {
+        return Err("BGA not present");
+    }
+    if !matches!(bpp, 16 | 24 | 32) {
+        return Err("Unsupported depth");
+    }
+
+    let (max_w, max_h, max_bpp) = max_mode();
+    if width > max_w || height > max_h || bpp > max_bpp {
+        return Err("Mode exceeds adapter limits");
+    }
+
+    let pitch = width * (bpp / 8);
+    let vram = video_memory();
+    if vram != 0 && pitch * height > vram {
+        return Err("Not enough video memory");
+    }
+
+    let lfb = lfb_address().ok_or("BGA framebuffer not found")?;
+
+    write_reg(index::ENABLE, VBE_DISPI_DISABLED);
+    write_reg(index::XRES, width as u16);
+    write_reg(index::YRES, height as u16);
+    write_reg(index::BPP, bpp as u16);
+    write_reg(index::VIRT_WIDTH, width as u16);
+    write_reg(index::VIRT_HEIGHT, height as u16);
+    write_reg(index::X_OFFSET, 0);
+    write_reg(index::Y_OFFSET, 0);
+    write_reg(index::ENABLE, VBE_DISPI_ENABLED | VBE_DISPI_LFB_ENABLED);
+
+    // The adapter silently clamps modes it doesn't like
+    if read_reg(index::XRES) as u32 != width || read_reg(index::YRES) as u32 != height {
+        disable();
+        return Err("Mode rejected");
+    }
+
+    Ok(BgaMode {
+        lfb: lfb as *mut u8,
+        width,
+        height,
+        bpp,
+        pitch,
+    })
+}
+
+/// Return to standard VGA operation
+pub fn disable() {
+    write_reg(index::ENABLE, VBE_DISPI_DISABLED);
+}
@@ -609,6 +609,11 @@ pub fn get_key() -> Option<BufferedKey> {
     unsafe { KEYBOARD.get_key() }
 }
 
+/// Whether either shift key is held
+pub fn is_shift_pressed() -> bool {
+    unsafe { (&*core::ptr::addr_of!(KEYBOARD)).is_shift_pressed() }
+}
+
 /// Initialize keyboard driver
 pub fn init() {
     // Enable keyboard IRQ (handled by PIC init)
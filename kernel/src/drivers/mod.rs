@@ -7,6 +7,7 @@
 
 pub mod armada_e500_hw;
 pub mod vga;
+pub mod bga;
 pub mod keyboard;
 pub mod mouse;
 pub mod ati_rage;
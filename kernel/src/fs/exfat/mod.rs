@@ -1,7 +1,8 @@
 //! exFAT Filesystem Driver
 //!
 //! exFAT (Extended File Allocation Table) driver for Rustacean OS.
-//! Chosen for USB drive compatibility and large file support.
+//! Chosen for USB drive compatibility and large file support: SD cards
+//! and USB sticks above 32 GB come formatted as exFAT.
 //!
 //! # Features
 //!
@@ -9,10 +10,34 @@
 //! - Long filename support (up to 255 characters)
 //! - No journaling (simpler, but less crash-resilient)
 //! - Widely compatible with Windows, macOS, Linux
+//!
+//! # Implementation
+//!
+//! - The main boot region is checked (signature, geometry, boot checksum)
+//!   before anything else is read
+//! - Files are read through the FAT, or as one run of clusters when their
+//!   stream entry is flagged NoFatChain
+//! - Directories are read as entry sets (file, stream extension, name
+//!   entries); sets with a bad checksum are skipped
+//! - Names are compared through the volume's up-case table
+//! - Writing (saves) allocates from the allocation bitmap, keeps new files
+//!   contiguous where it can, and sets VolumeDirty while metadata is in
+//!   flux. Order: data, FAT, bitmap, then the directory entry set, so an
+//!   interrupted write can only leak clusters
+//!
+//! Sectors go through a `storage::partition::BlockDevice`, which reads
+//! and writes them with `storage::ata`, so only 512-byte sectors are
+//! supported.
+
+extern crate alloc;
 
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use crate::storage::partition::BlockDevice;
 use super::{
     Filesystem, Metadata, FileType, OpenFlags, SeekFrom,
-    FsResult, FsError, DirEntry, ReadDir, Permissions,
+    FsResult, FsError, DirEntry, ReadDir, Permissions, MAX_FILENAME, dos_time_to_unix,
 };
 
 /// exFAT boot sector
@@ -180,10 +205,175 @@ pub mod cluster {
     pub const FIRST_VALID: u32 = 2;
 }
 
+// =============================================================================
+// Constants
+// =============================================================================
+
+const SECTOR_SIZE: usize = 512;
+const SECTOR_SHIFT: u8 = 9;
+
+/// Directory entry size, and the flag in the type byte of entries in use
+const ENTRY_SIZE: usize = 32;
+const ENTRY_IN_USE: u8 = 0x80;
+
+/// Name characters per name entry, and the largest entry set (file,
+/// stream, 17 name entries)
+const NAME_CHARS_PER_ENTRY: usize = 15;
+const MAX_SET_ENTRIES: usize = 2 + MAX_FILENAME.div_ceil(NAME_CHARS_PER_ENTRY);
+
+/// Stream extension general flags
+const ALLOCATION_POSSIBLE: u8 = 0x01;
+const NO_FAT_CHAIN: u8 = 0x02;
+
+/// Volume flags
+const VOLUME_ACTIVE_FAT: u16 = 0x01;
+const VOLUME_DIRTY: u16 = 0x02;
+
+/// Boot sector bytes left out of the boot checksum (VolumeFlags and
+/// PercentInUse change without it being recomputed)
+const VOLUME_FLAGS_OFFSET: usize = 106;
+const PERCENT_IN_USE_OFFSET: usize = 112;
+
+/// Main boot region sectors covered by the checksum in sector 11
+const BOOT_CHECKSUM_SECTORS: u64 = 11;
+
+/// Largest cluster: 32 MB (shift of bytes per sector + sectors per cluster)
+const MAX_CLUSTER_SHIFT: u8 = 25;
+
+/// Characters the up-case table can map
+const UPCASE_CHARS: usize = 0x10000;
+
+/// 1980-01-01 00:00, the earliest timestamp (there is no clock to stamp
+/// files)
+const EPOCH_TIMESTAMP: u32 = (1 << 21) | (1 << 16);
+
+/// Sectors per disk request
+const MAX_SECTORS_PER_IO: u64 = 128;
+
+const _: () = assert!(size_of::<ExfatBootSector>() == SECTOR_SIZE);
+const _: () = assert!(size_of::<FileEntry>() == ENTRY_SIZE);
+const _: () = assert!(size_of::<StreamEntry>() == ENTRY_SIZE);
+const _: () = assert!(size_of::<FileNameEntry>() == ENTRY_SIZE);
+
+// =============================================================================
+// Clusters and Entry Sets
+// =============================================================================
+
+/// The clusters of a file or directory
+#[derive(Debug, Clone, Copy)]
+struct Extent {
+    /// First cluster (0 if nothing is allocated)
+    first: u32,
+    /// NoFatChain: the clusters follow each other and the FAT is unused
+    contiguous: bool,
+    /// Allocated bytes (DataLength)
+    size: u64,
+}
+
+/// A directory, and where its own entry set is (`None` for the root)
+#[derive(Debug, Clone, Copy)]
+struct DirRef {
+    extent: Extent,
+    location: Option<SetLocation>,
+}
+
+/// Where an entry set is: the directory holding it and its first entry
+#[derive(Debug, Clone, Copy)]
+struct SetLocation {
+    dir: Extent,
+    index: u32,
+}
+
+/// A file entry with its stream extension and name
+#[derive(Clone, Copy)]
+struct EntrySet {
+    /// Index of the file entry in its directory
+    index: u32,
+    file: FileEntry,
+    stream: StreamEntry,
+    name: [u16; MAX_FILENAME],
+    name_len: usize,
+}
+
+impl EntrySet {
+    fn is_dir(&self) -> bool {
+        self.file.file_attributes & attrs::DIRECTORY != 0
+    }
+
+    fn name(&self) -> &[u16] {
+        &self.name[..self.name_len]
+    }
+
+    fn extent(&self) -> Extent {
+        Extent {
+            first: self.stream.first_cluster,
+            contiguous: self.stream.general_flags & NO_FAT_CHAIN != 0,
+            size: self.stream.data_length,
+        }
+    }
+
+    /// Record new clusters and lengths in the stream extension
+    fn set_extent(&mut self, extent: &Extent, valid_length: u64) {
+        let mut flags = self.stream.general_flags | ALLOCATION_POSSIBLE;
+        if extent.contiguous && extent.first != 0 {
+            flags |= NO_FAT_CHAIN;
+        } else {
+            flags &= !NO_FAT_CHAIN;
+        }
+        self.stream.general_flags = flags;
+        self.stream.first_cluster = extent.first;
+        self.stream.data_length = extent.size;
+        self.stream.valid_data_length = valid_length;
+    }
+
+    /// Entries in the set
+    fn entry_count(&self) -> usize {
+        1 + self.file.secondary_count as usize
+    }
+
+    /// The set as directory entries, checksum filled in
+    fn to_entries(&self) -> [[u8; ENTRY_SIZE]; MAX_SET_ENTRIES] {
+        let mut entries = [[0u8; ENTRY_SIZE]; MAX_SET_ENTRIES];
+        let count = self.entry_count();
+        entries[1] = entry_to_bytes(&self.stream);
+        for (i, chunk) in self.name().chunks(NAME_CHARS_PER_ENTRY).enumerate() {
+            let mut name = FileNameEntry {
+                entry_type: EntryType::FileNameExtension as u8,
+                general_flags: 0,
+                file_name: [0; NAME_CHARS_PER_ENTRY],
+            };
+            let mut chars = [0u16; NAME_CHARS_PER_ENTRY];
+            chars[..chunk.len()].copy_from_slice(chunk);
+            name.file_name = chars;
+            entries[2 + i] = entry_to_bytes(&name);
+        }
+
+        let mut file = self.file;
+        file.set_checksum = 0;
+        entries[0] = entry_to_bytes(&file);
+        file.set_checksum = set_checksum(&entries[..count]);
+        entries[0] = entry_to_bytes(&file);
+        entries
+    }
+}
+
+/// Position of a directory walk: the `index`th cluster of the directory
+/// is `cluster`
+#[derive(Clone, Copy)]
+struct Cursor {
+    index: u64,
+    cluster: u32,
+}
+
+// =============================================================================
+// Open Files
+// =============================================================================
+
 /// Maximum open files
 const MAX_OPEN_FILES: usize = 32;
 
 /// Open file handle
+#[derive(Clone, Copy)]
 struct OpenFile {
     /// Is this slot in use?
     in_use: bool,
@@ -191,10 +381,20 @@ struct OpenFile {
     first_cluster: u32,
     /// Current cluster
     current_cluster: u32,
+    /// Index of `current_cluster` in the file
+    current_index: u64,
+    /// NoFatChain file
+    contiguous: bool,
     /// Current position in file
     position: u64,
     /// File size
     size: u64,
+    /// Bytes written (ValidDataLength); the rest reads as zeros
+    valid_size: u64,
+    /// Allocated bytes
+    allocated: u64,
+    /// Where the file's entry set is
+    location: SetLocation,
     /// Open flags
     flags: OpenFlags,
 }
@@ -205,24 +405,65 @@ impl OpenFile {
             in_use: false,
             first_cluster: 0,
             current_cluster: 0,
+            current_index: 0,
+            contiguous: false,
             position: 0,
             size: 0,
+            valid_size: 0,
+            allocated: 0,
+            location: SetLocation {
+                dir: Extent { first: 0, contiguous: false, size: 0 },
+                index: 0,
+            },
             flags: OpenFlags::read_only(),
         }
     }
+
+    fn extent(&self) -> Extent {
+        Extent { first: self.first_cluster, contiguous: self.contiguous, size: self.allocated }
+    }
+
+    fn set_extent(&mut self, extent: &Extent) {
+        self.first_cluster = extent.first;
+        self.contiguous = extent.contiguous;
+        self.allocated = extent.size;
+        self.current_cluster = extent.first;
+        self.current_index = 0;
+    }
 }
 
+// =============================================================================
+// exFAT Filesystem
+// =============================================================================
+
 /// exFAT filesystem driver
 pub struct ExfatFilesystem {
     /// Is mounted?
     mounted: bool,
+    /// The volume; all sector numbers below are within it
+    volume: BlockDevice,
     /// Boot sector info
     bytes_per_sector: u32,
     sectors_per_cluster: u32,
-    cluster_heap_offset: u32,
+    /// log2 of the cluster size in bytes
+    cluster_shift: u8,
+    cluster_heap_offset: u64,
     root_cluster: u32,
     cluster_count: u32,
-    fat_offset: u32,
+    /// The active FAT
+    fat_offset: u64,
+    /// Root directory (always a FAT chain; its size comes from the chain)
+    root: Extent,
+    /// Allocation bitmap: one bit per cluster, cluster 2 first
+    bitmap: Extent,
+    /// Up-case table, expanded: `upcase[c]` is `c` in upper case
+    upcase: Vec<u16>,
+    /// VolumeFlags as mounted; a volume that was already dirty stays so
+    volume_flags: u16,
+    /// Free clusters, counted on the first write
+    free_count: Option<u32>,
+    /// Where to start looking for a free cluster
+    next_free: u32,
     /// Open files
     open_files: [OpenFile; MAX_OPEN_FILES],
 }
@@ -231,42 +472,947 @@ impl ExfatFilesystem {
     /// Create a new exFAT filesystem instance
     pub const fn new() -> Self {
         const EMPTY: OpenFile = OpenFile::empty();
+        const NO_EXTENT: Extent = Extent { first: 0, contiguous: false, size: 0 };
         Self {
             mounted: false,
+            volume: BlockDevice::new(0, 0, 0),
             bytes_per_sector: 512,
             sectors_per_cluster: 1,
+            cluster_shift: SECTOR_SHIFT,
             cluster_heap_offset: 0,
             root_cluster: 0,
             cluster_count: 0,
             fat_offset: 0,
+            root: NO_EXTENT,
+            bitmap: NO_EXTENT,
+            upcase: Vec::new(),
+            volume_flags: 0,
+            free_count: None,
+            next_free: cluster::FIRST_VALID,
             open_files: [EMPTY; MAX_OPEN_FILES],
         }
     }
-    
+
+    /// Choose the volume to mount (a partition, or a whole disk)
+    pub fn set_volume(&mut self, volume: BlockDevice) {
+        self.volume = volume;
+    }
+
+    // -------------------------------------------------------------------------
+    // Disk access
+    // -------------------------------------------------------------------------
+
+    fn read_sectors(&self, lba: u64, count: u64, buf: &mut [u8]) -> FsResult<()> {
+        self.volume.read_sectors(lba, count as u8, buf).map_err(|_| FsError::IoError)
+    }
+
+    fn write_sectors(&self, lba: u64, count: u64, buf: &[u8]) -> FsResult<()> {
+        self.volume.write_sectors(lba, count as u8, buf).map_err(|_| FsError::IoError)
+    }
+
+    fn read_sector(&self, lba: u64, buf: &mut [u8; SECTOR_SIZE]) -> FsResult<()> {
+        self.read_sectors(lba, 1, buf)
+    }
+
+    fn write_sector(&self, lba: u64, buf: &[u8; SECTOR_SIZE]) -> FsResult<()> {
+        self.write_sectors(lba, 1, buf)
+    }
+
     /// Calculate cluster address
     fn cluster_to_sector(&self, cluster: u32) -> u64 {
         let cluster_offset = (cluster - cluster::FIRST_VALID) as u64;
-        (self.cluster_heap_offset as u64) + (cluster_offset * self.sectors_per_cluster as u64)
+        self.cluster_heap_offset + (cluster_offset * self.sectors_per_cluster as u64)
+    }
+
+    /// Bytes per cluster
+    fn cluster_size(&self) -> u64 {
+        1 << self.cluster_shift
     }
-    
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= cluster::FIRST_VALID && cluster - cluster::FIRST_VALID < self.cluster_count
+    }
+
     /// Read a cluster from disk
-    fn read_cluster(&self, _cluster: u32, _buf: &mut [u8]) -> FsResult<()> {
-        // TODO: Implement actual disk I/O
-        Err(FsError::IoError)
+    fn read_cluster(&self, cluster: u32, buf: &mut [u8]) -> FsResult<()> {
+        if !self.is_data_cluster(cluster) {
+            return Err(FsError::InvalidFs);
+        }
+        let lba = self.cluster_to_sector(cluster);
+        let sectors = self.sectors_per_cluster as u64;
+        let mut done = 0;
+        while done < sectors {
+            let count = (sectors - done).min(MAX_SECTORS_PER_IO);
+            let at = done as usize * SECTOR_SIZE;
+            self.read_sectors(lba + done, count, &mut buf[at..at + count as usize * SECTOR_SIZE])?;
+            done += count;
+        }
+        Ok(())
     }
-    
-    /// Write a cluster to disk
-    fn write_cluster(&mut self, _cluster: u32, _buf: &[u8]) -> FsResult<()> {
-        // TODO: Implement actual disk I/O
-        Err(FsError::IoError)
+
+    /// Write a cluster to disk (zeros if `buf` is empty)
+    fn write_cluster(&mut self, cluster: u32, buf: &[u8]) -> FsResult<()> {
+        if !self.is_data_cluster(cluster) {
+            return Err(FsError::InvalidFs);
+        }
+        let zeros = [0u8; SECTOR_SIZE];
+        let lba = self.cluster_to_sector(cluster);
+        let sectors = self.sectors_per_cluster as u64;
+        let mut done = 0;
+        while done < sectors {
+            if buf.is_empty() {
+                self.write_sector(lba + done, &zeros)?;
+                done += 1;
+                continue;
+            }
+            let count = (sectors - done).min(MAX_SECTORS_PER_IO);
+            let at = done as usize * SECTOR_SIZE;
+            self.write_sectors(lba + done, count, &buf[at..at + count as usize * SECTOR_SIZE])?;
+            done += count;
+        }
+        Ok(())
     }
-    
+
+    // -------------------------------------------------------------------------
+    // FAT
+    // -------------------------------------------------------------------------
+
     /// Get next cluster in chain from FAT
-    fn get_next_cluster(&self, _cluster: u32) -> FsResult<u32> {
-        // TODO: Read from FAT
-        Err(FsError::IoError)
+    fn get_next_cluster(&self, cluster: u32) -> FsResult<u32> {
+        let byte = cluster as u64 * 4;
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(self.fat_offset + byte / SECTOR_SIZE as u64, &mut sector)?;
+        let at = (byte % SECTOR_SIZE as u64) as usize;
+        Ok(u32::from_le_bytes([sector[at], sector[at + 1], sector[at + 2], sector[at + 3]]))
+    }
+
+    /// Set FAT entries (cluster, value), one read-modify-write per sector
+    fn set_fat_entries(&self, entries: &[(u32, u32)]) -> FsResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut loaded: Option<u64> = None;
+        for &(cluster, value) in entries {
+            let byte = cluster as u64 * 4;
+            let lba = self.fat_offset + byte / SECTOR_SIZE as u64;
+            if loaded != Some(lba) {
+                if let Some(previous) = loaded {
+                    self.write_sector(previous, &sector)?;
+                }
+                self.read_sector(lba, &mut sector)?;
+                loaded = Some(lba);
+            }
+            let at = (byte % SECTOR_SIZE as u64) as usize;
+            sector[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        if let Some(lba) = loaded {
+            self.write_sector(lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    /// Clusters of `extent`, in order
+    fn chain(&self, extent: &Extent) -> FsResult<Vec<u32>> {
+        let count = extent.size.div_ceil(self.cluster_size());
+        if extent.first == 0 || count == 0 {
+            return Ok(Vec::new());
+        }
+        if count > self.cluster_count as u64 {
+            return Err(FsError::InvalidFs);
+        }
+        if extent.contiguous {
+            let last = extent.first as u64 + count - 1;
+            if !self.is_data_cluster(extent.first) || last > u32::MAX as u64 || !self.is_data_cluster(last as u32) {
+                return Err(FsError::InvalidFs);
+            }
+            return Ok((extent.first..=last as u32).collect());
+        }
+        let mut chain = self.fat_chain(extent.first)?;
+        chain.truncate(count as usize);
+        Ok(chain)
+    }
+
+    /// Follow the FAT from `first` to the end of the chain
+    fn fat_chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut current = first;
+        loop {
+            if !self.is_data_cluster(current) || chain.len() >= self.cluster_count as usize {
+                return Err(FsError::InvalidFs);
+            }
+            chain.push(current);
+            current = self.get_next_cluster(current)?;
+            if current == cluster::END {
+                return Ok(chain);
+            }
+        }
+    }
+
+    /// Cluster `n` of `extent`, following the FAT from `cursor`
+    fn cluster_at(&self, extent: &Extent, n: u64, cursor: &mut Cursor) -> FsResult<u32> {
+        if extent.contiguous {
+            let cluster = extent.first as u64 + n;
+            if cluster > u32::MAX as u64 || !self.is_data_cluster(cluster as u32) {
+                return Err(FsError::InvalidFs);
+            }
+            return Ok(cluster as u32);
+        }
+        if n < cursor.index {
+            *cursor = Cursor { index: 0, cluster: extent.first };
+        }
+        while cursor.index < n {
+            let next = self.get_next_cluster(cursor.cluster)?;
+            if !self.is_data_cluster(next) {
+                return Err(FsError::InvalidFs);
+            }
+            cursor.cluster = next;
+            cursor.index += 1;
+        }
+        Ok(cursor.cluster)
+    }
+
+    // -------------------------------------------------------------------------
+    // Allocation bitmap
+    // -------------------------------------------------------------------------
+
+    /// Sector of the bitmap holding the bit of `cluster`, and the bit's byte
+    fn bitmap_position(&self, cluster: u32, cursor: &mut Cursor) -> FsResult<(u64, usize)> {
+        let byte = (cluster - cluster::FIRST_VALID) as u64 / 8;
+        let bitmap_cluster = self.cluster_at(&self.bitmap, byte >> self.cluster_shift, cursor)?;
+        let within = byte & (self.cluster_size() - 1);
+        Ok((
+            self.cluster_to_sector(bitmap_cluster) + within / SECTOR_SIZE as u64,
+            (within % SECTOR_SIZE as u64) as usize,
+        ))
+    }
+
+    /// Find `count` free clusters, starting at the next-free hint
+    fn find_free_clusters(&self, count: usize, near: Option<u32>) -> FsResult<Vec<u32>> {
+        let mut found = Vec::with_capacity(count);
+        if count == 0 {
+            return Ok(found);
+        }
+
+        let start = near.filter(|&c| self.is_data_cluster(c)).unwrap_or(self.next_free);
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut loaded: Option<u64> = None;
+        let mut cursor = Cursor { index: 0, cluster: self.bitmap.first };
+        for i in 0..self.cluster_count {
+            let offset = (start - cluster::FIRST_VALID) as u64 + i as u64;
+            let cluster = cluster::FIRST_VALID + (offset % self.cluster_count as u64) as u32;
+            let (lba, byte) = self.bitmap_position(cluster, &mut cursor)?;
+            if loaded != Some(lba) {
+                self.read_sector(lba, &mut sector)?;
+                loaded = Some(lba);
+            }
+            if sector[byte] & (1 << ((cluster - cluster::FIRST_VALID) % 8)) == 0 {
+                found.push(cluster);
+                if found.len() == count {
+                    return Ok(found);
+                }
+            }
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Mark clusters used or free in the bitmap, keeping the free count
+    fn set_bitmap(&mut self, clusters: &[u32], used: bool) -> FsResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut loaded: Option<u64> = None;
+        let mut cursor = Cursor { index: 0, cluster: self.bitmap.first };
+        let mut changed = 0u32;
+        for &cluster in clusters {
+            let (lba, byte) = self.bitmap_position(cluster, &mut cursor)?;
+            if loaded != Some(lba) {
+                if let Some(previous) = loaded {
+                    self.write_sector(previous, &sector)?;
+                }
+                self.read_sector(lba, &mut sector)?;
+                loaded = Some(lba);
+            }
+            let bit = 1 << ((cluster - cluster::FIRST_VALID) % 8);
+            if (sector[byte] & bit != 0) != used {
+                sector[byte] ^= bit;
+                changed += 1;
+            }
+        }
+        if let Some(lba) = loaded {
+            self.write_sector(lba, &sector)?;
+        }
+
+        if let Some(free) = self.free_count.as_mut() {
+            *free = if used { free.saturating_sub(changed) } else { *free + changed };
+        }
+        if used {
+            if let Some(&last) = clusters.last() {
+                self.next_free = if last + 1 - cluster::FIRST_VALID < self.cluster_count {
+                    last + 1
+                } else {
+                    cluster::FIRST_VALID
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Count the clear bits of the bitmap
+    fn count_free(&self) -> FsResult<u32> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut used = 0u32;
+        let mut remaining = self.cluster_count;
+        for cluster in self.chain(&self.bitmap)? {
+            let lba = self.cluster_to_sector(cluster);
+            for i in 0..self.sectors_per_cluster as u64 {
+                if remaining == 0 {
+                    return Ok(self.cluster_count - used);
+                }
+                self.read_sector(lba + i, &mut sector)?;
+                let bits = remaining.min(SECTOR_SIZE as u32 * 8);
+                for (byte, &value) in sector.iter().enumerate().take(bits.div_ceil(8) as usize) {
+                    let valid = (bits - byte as u32 * 8).min(8);
+                    used += (value & ((1u16 << valid) - 1) as u8).count_ones();
+                }
+                remaining -= bits;
+            }
+        }
+        Ok(self.cluster_count - used)
+    }
+
+    /// Give `extent` `clusters` clusters in all, adding to the end of its
+    /// chain (the new clusters are not cleared)
+    ///
+    /// A NoFatChain extent stays one if the new clusters follow on;
+    /// otherwise its clusters are written into the FAT first.
+    fn grow(&mut self, extent: &mut Extent, clusters: u64) -> FsResult<()> {
+        let old = self.chain(extent)?;
+        if clusters <= old.len() as u64 {
+            return Ok(());
+        }
+        let near = old.last().map(|&last| last + 1);
+        let added = self.find_free_clusters((clusters - old.len() as u64) as usize, near)?;
+
+        let follows_on = added.windows(2).all(|w| w[1] == w[0] + 1)
+            && old.last().map_or(true, |&last| added[0] == last + 1);
+        let contiguous = (old.is_empty() || extent.contiguous) && follows_on;
+
+        if !contiguous {
+            let chain: Vec<u32> = if extent.contiguous {
+                old.iter().chain(added.iter()).copied().collect()
+            } else {
+                old.last().copied().into_iter().chain(added.iter().copied()).collect()
+            };
+            let mut links: Vec<(u32, u32)> = chain.windows(2).map(|w| (w[0], w[1])).collect();
+            links.push((*chain.last().unwrap_or(&added[0]), cluster::END));
+            self.set_fat_entries(&links)?;
+        }
+        self.set_bitmap(&added, true)?;
+
+        if old.is_empty() {
+            extent.first = added[0];
+        }
+        extent.contiguous = contiguous;
+        extent.size = clusters << self.cluster_shift;
+        Ok(())
+    }
+
+    /// Free the clusters of `extent`
+    fn release(&mut self, extent: &Extent) -> FsResult<()> {
+        let chain = self.chain(extent)?;
+        self.set_bitmap(&chain, false)
+    }
+
+    // -------------------------------------------------------------------------
+    // Volume flags
+    // -------------------------------------------------------------------------
+
+    /// Run a metadata update with VolumeDirty set on disk
+    ///
+    /// A volume that was dirty at mount time is left dirty for a repair
+    /// tool. Clearing the flag also brings PercentInUse up to date.
+    fn modify<T>(&mut self, update: impl FnOnce(&mut Self) -> FsResult<T>) -> FsResult<T> {
+        if self.free_count.is_none() {
+            self.free_count = Some(self.count_free()?);
+        }
+        self.write_volume_flags(self.volume_flags | VOLUME_DIRTY)?;
+        let result = update(self);
+        if result.is_ok() {
+            self.write_volume_flags(self.volume_flags)?;
+        }
+        result
+    }
+
+    fn write_volume_flags(&self, flags: u16) -> FsResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(0, &mut sector)?;
+        sector[VOLUME_FLAGS_OFFSET..VOLUME_FLAGS_OFFSET + 2].copy_from_slice(&flags.to_le_bytes());
+        if let Some(free) = self.free_count {
+            let used = (self.cluster_count - free) as u64;
+            sector[PERCENT_IN_USE_OFFSET] = (used * 100 / self.cluster_count as u64) as u8;
+        }
+        self.write_sector(0, &sector)
+    }
+
+    // -------------------------------------------------------------------------
+    // Directories
+    // -------------------------------------------------------------------------
+
+    /// Sector and byte offset of entry `index` of `dir`, or `None` past its end
+    fn entry_position(&self, dir: &Extent, index: u32, cursor: &mut Cursor) -> FsResult<Option<(u64, usize)>> {
+        let byte = index as u64 * ENTRY_SIZE as u64;
+        if byte >= dir.size {
+            return Ok(None);
+        }
+        let cluster = self.cluster_at(dir, byte >> self.cluster_shift, cursor)?;
+        let within = byte & (self.cluster_size() - 1);
+        Ok(Some((
+            self.cluster_to_sector(cluster) + within / SECTOR_SIZE as u64,
+            (within % SECTOR_SIZE as u64) as usize,
+        )))
+    }
+
+    /// Entry sets of `dir`
+    fn sets(&self, dir: &Extent) -> SetIter<'_> {
+        SetIter {
+            reader: DirReader::new(self, *dir),
+            index: 0,
+            done: false,
+            error: None,
+        }
+    }
+
+    /// Write consecutive entries of `dir` from `index`
+    fn write_entries(&self, dir: &Extent, index: u32, entries: &[[u8; ENTRY_SIZE]]) -> FsResult<()> {
+        let mut cursor = Cursor { index: 0, cluster: dir.first };
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut loaded: Option<u64> = None;
+        for (i, entry) in entries.iter().enumerate() {
+            let (lba, offset) = self
+                .entry_position(dir, index + i as u32, &mut cursor)?
+                .ok_or(FsError::InvalidFs)?;
+            if loaded != Some(lba) {
+                if let Some(previous) = loaded {
+                    self.write_sector(previous, &sector)?;
+                }
+                self.read_sector(lba, &mut sector)?;
+                loaded = Some(lba);
+            }
+            sector[offset..offset + ENTRY_SIZE].copy_from_slice(entry);
+        }
+        if let Some(lba) = loaded {
+            self.write_sector(lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite an entry set in place
+    fn write_set(&self, dir: &Extent, set: &EntrySet) -> FsResult<()> {
+        let entries = set.to_entries();
+        self.write_entries(dir, set.index, &entries[..set.entry_count()])
+    }
+
+    /// Mark an entry set unused
+    fn delete_set(&self, dir: &Extent, set: &EntrySet) -> FsResult<()> {
+        let mut entries = set.to_entries();
+        for entry in entries.iter_mut().take(set.entry_count()) {
+            entry[0] &= !ENTRY_IN_USE;
+        }
+        self.write_entries(dir, set.index, &entries[..set.entry_count()])
+    }
+
+    /// Find the entry set named `name` in `dir`
+    fn find_in(&self, dir: &Extent, name: &[u16]) -> FsResult<Option<EntrySet>> {
+        let hash = self.name_hash(name);
+        let mut sets = self.sets(dir);
+        let found = sets.find(|set| set.stream.name_hash == hash && self.names_equal(set.name(), name));
+        match sets.error {
+            Some(e) => Err(e),
+            None => Ok(found),
+        }
+    }
+
+    /// Find `count` consecutive unused entries in `dir`
+    fn find_free_entries(&self, dir: &Extent, count: usize) -> FsResult<Option<u32>> {
+        let mut reader = DirReader::new(self, *dir);
+        let mut run = 0;
+        let mut index = 0;
+        while let Some(entry) = reader.entry(index)? {
+            if entry[0] & ENTRY_IN_USE == 0 {
+                run += 1;
+                if run == count {
+                    return Ok(Some(index + 1 - count as u32));
+                }
+            } else {
+                run = 0;
+            }
+            index += 1;
+        }
+        Ok(None)
+    }
+
+    /// Add an entry set to a directory, growing it by a cluster if needed
+    fn add_set(&mut self, dir: &mut DirRef, set: &mut EntrySet) -> FsResult<()> {
+        let count = set.entry_count();
+        let index = match self.find_free_entries(&dir.extent, count)? {
+            Some(index) => index,
+            None => {
+                // Unused entries at the end carry on into the new cluster
+                let mut extent = dir.extent;
+                let old_entries = (extent.size / ENTRY_SIZE as u64) as u32;
+                let mut reader = DirReader::new(self, extent);
+                let mut tail = 0;
+                while tail < old_entries
+                    && reader.entry(old_entries - 1 - tail)?.is_some_and(|e| e[0] & ENTRY_IN_USE == 0)
+                {
+                    tail += 1;
+                }
+
+                let clusters = extent.size.div_ceil(self.cluster_size()) + 1;
+                self.grow(&mut extent, clusters)?;
+                let chain = self.chain(&extent)?;
+                self.write_cluster(*chain.last().ok_or(FsError::InvalidFs)?, &[])?;
+                self.update_dir_size(dir, extent)?;
+                old_entries - tail
+            }
+        };
+        set.index = index;
+        self.write_set(&dir.extent, set)
+    }
+
+    /// Record a directory's new extent, in its own entry set unless it is
+    /// the root
+    fn update_dir_size(&mut self, dir: &mut DirRef, extent: Extent) -> FsResult<()> {
+        dir.extent = extent;
+        match dir.location {
+            None => self.root = extent,
+            Some(location) => {
+                let mut reader = DirReader::new(self, location.dir);
+                let mut set = reader.set(location.index)?.ok_or(FsError::InvalidFs)?;
+                set.set_extent(&extent, extent.size);
+                self.write_set(&location.dir, &set)?;
+            }
+        }
+        Ok(())
+    }
+
+    // -------------------------------------------------------------------------
+    // Names and paths
+    // -------------------------------------------------------------------------
+
+    fn upcase(&self, c: u16) -> u16 {
+        self.upcase.get(c as usize).copied().unwrap_or(c)
+    }
+
+    /// NameHash of the stream extension: over the up-cased name
+    fn name_hash(&self, name: &[u16]) -> u16 {
+        name.iter().flat_map(|&c| self.upcase(c).to_le_bytes()).fold(0u16, |hash, byte| {
+            hash.rotate_right(1).wrapping_add(byte as u16)
+        })
+    }
+
+    fn names_equal(&self, a: &[u16], b: &[u16]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(&x, &y)| self.upcase(x) == self.upcase(y))
+    }
+
+    /// Find the directory or file at `path`; the root is `None`
+    fn lookup(&self, path: &str) -> FsResult<(DirRef, Option<EntrySet>)> {
+        let mut dir = DirRef { extent: self.root, location: None };
+        let mut found: Option<EntrySet> = None;
+        let mut name = [0u16; MAX_FILENAME];
+        for part in path.split('/').filter(|part| !part.is_empty() && *part != ".") {
+            if let Some(set) = found {
+                if !set.is_dir() {
+                    return Err(FsError::NotDirectory);
+                }
+                dir = DirRef {
+                    extent: set.extent(),
+                    location: Some(SetLocation { dir: dir.extent, index: set.index }),
+                };
+            }
+            let len = encode_name(part, &mut name)?;
+            found = Some(self.find_in(&dir.extent, &name[..len])?.ok_or(FsError::NotFound)?);
+        }
+        Ok((dir, found))
     }
-    
+
+    /// True if a directory on the way to `path` (the root excluded, the
+    /// last part too) starts at `cluster`
+    fn passes_through(&self, path: &str, cluster: u32) -> FsResult<bool> {
+        let path = path.trim_end_matches('/');
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let mut dir = self.root;
+        let mut name = [0u16; MAX_FILENAME];
+        for part in parent.split('/').filter(|part| !part.is_empty() && *part != ".") {
+            let len = encode_name(part, &mut name)?;
+            let set = self.find_in(&dir, &name[..len])?.ok_or(FsError::NotFound)?;
+            if set.stream.first_cluster == cluster {
+                return Ok(true);
+            }
+            dir = set.extent();
+        }
+        Ok(false)
+    }
+
+    /// The directory that `path` would be in, and its last part
+    fn lookup_parent<'p>(&self, path: &'p str) -> FsResult<(DirRef, &'p str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(FsError::InvalidPath);
+        }
+        match self.lookup(parent)? {
+            (dir, None) => Ok((dir, name)),
+            (dir, Some(set)) if set.is_dir() => Ok((
+                DirRef {
+                    extent: set.extent(),
+                    location: Some(SetLocation { dir: dir.extent, index: set.index }),
+                },
+                name,
+            )),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    /// New entry set for `name`, not yet in a directory
+    fn new_set(&self, name: &str, attributes: u16, extent: &Extent, valid_length: u64) -> FsResult<EntrySet> {
+        let mut set = EntrySet {
+            index: 0,
+            file: FileEntry {
+                entry_type: EntryType::File as u8,
+                secondary_count: 0,
+                set_checksum: 0,
+                file_attributes: attributes,
+                reserved1: 0,
+                create_timestamp: EPOCH_TIMESTAMP,
+                modified_timestamp: EPOCH_TIMESTAMP,
+                accessed_timestamp: EPOCH_TIMESTAMP,
+                create_10ms: 0,
+                modified_10ms: 0,
+                create_utc_offset: 0,
+                modified_utc_offset: 0,
+                accessed_utc_offset: 0,
+                reserved2: [0; 7],
+            },
+            stream: StreamEntry {
+                entry_type: EntryType::StreamExtension as u8,
+                general_flags: ALLOCATION_POSSIBLE,
+                reserved1: 0,
+                name_length: 0,
+                name_hash: 0,
+                reserved2: 0,
+                valid_data_length: 0,
+                reserved3: 0,
+                first_cluster: 0,
+                data_length: 0,
+            },
+            name: [0; MAX_FILENAME],
+            name_len: 0,
+        };
+        if name.chars().any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c)) {
+            return Err(FsError::InvalidPath);
+        }
+        set.name_len = encode_name(name, &mut set.name)?;
+        set.file.secondary_count = (1 + set.name_len.div_ceil(NAME_CHARS_PER_ENTRY)) as u8;
+        set.stream.name_length = set.name_len as u8;
+        set.stream.name_hash = self.name_hash(set.name());
+        set.set_extent(extent, valid_length);
+        Ok(set)
+    }
+
+    // -------------------------------------------------------------------------
+    // Mounting
+    // -------------------------------------------------------------------------
+
+    /// Read and check the main boot sector
+    fn read_boot_sector(&self) -> FsResult<ExfatBootSector> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(0, &mut sector)?;
+        if !is_exfat_boot_sector(&sector) {
+            return Err(FsError::InvalidFs);
+        }
+        Ok(unsafe { core::ptr::read_unaligned(sector.as_ptr() as *const ExfatBootSector) })
+    }
+
+    /// Check the main boot region's checksum (sector 11 repeats it)
+    fn check_boot_checksum(&self) -> FsResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut checksum = 0u32;
+        for i in 0..BOOT_CHECKSUM_SECTORS {
+            self.read_sector(i, &mut sector)?;
+            for (at, &byte) in sector.iter().enumerate() {
+                if i == 0 && (at == VOLUME_FLAGS_OFFSET || at == VOLUME_FLAGS_OFFSET + 1 || at == PERCENT_IN_USE_OFFSET) {
+                    continue;
+                }
+                checksum = checksum.rotate_right(1).wrapping_add(byte as u32);
+            }
+        }
+        self.read_sector(BOOT_CHECKSUM_SECTORS, &mut sector)?;
+        if sector.chunks(4).all(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) == checksum) {
+            Ok(())
+        } else {
+            Err(FsError::InvalidFs)
+        }
+    }
+
+    /// Take the boot sector's geometry, checking it fits together
+    fn load_geometry(&mut self, boot: &ExfatBootSector) -> FsResult<()> {
+        let sector_shift = boot.bytes_per_sector_shift;
+        let cluster_shift = sector_shift as u32 + boot.sectors_per_cluster_shift as u32;
+        let fat_sectors = boot.fat_length as u64 * boot.number_of_fats as u64;
+        let cluster_count = boot.cluster_count;
+        let heap_end = boot.cluster_heap_offset as u64 + ((cluster_count as u64) << boot.sectors_per_cluster_shift);
+
+        if sector_shift != SECTOR_SHIFT
+            || cluster_shift > MAX_CLUSTER_SHIFT as u32
+            || !(1..=2).contains(&boot.number_of_fats)
+            || boot.fat_offset < 24
+            || (boot.cluster_heap_offset as u64) < boot.fat_offset as u64 + fat_sectors
+            || cluster_count == 0
+            || (boot.fat_length as u64) * (SECTOR_SIZE as u64 / 4) < cluster_count as u64 + 2
+            || heap_end > boot.volume_length
+            || boot.volume_length > self.volume.sectors()
+        {
+            return Err(FsError::InvalidFs);
+        }
+
+        // With two FATs (TexFAT), VolumeFlags says which one is in use
+        let active_fat = if boot.number_of_fats == 2 && boot.volume_flags & VOLUME_ACTIVE_FAT != 0 { 1 } else { 0 };
+        self.bytes_per_sector = SECTOR_SIZE as u32;
+        self.sectors_per_cluster = 1 << boot.sectors_per_cluster_shift;
+        self.cluster_shift = cluster_shift as u8;
+        self.cluster_heap_offset = boot.cluster_heap_offset as u64;
+        self.fat_offset = boot.fat_offset as u64 + active_fat * boot.fat_length as u64;
+        self.cluster_count = cluster_count;
+        self.root_cluster = boot.root_directory_cluster;
+        self.volume_flags = boot.volume_flags;
+        if !self.is_data_cluster(self.root_cluster) {
+            return Err(FsError::InvalidFs);
+        }
+        Ok(())
+    }
+
+    /// Find the allocation bitmap and up-case table in the root directory
+    fn load_system_entries(&mut self) -> FsResult<()> {
+        let mut bitmap = None;
+        let mut upcase = None;
+        let mut reader = DirReader::new(self, self.root);
+        let mut index = 0;
+        while let Some(entry) = reader.entry(index)? {
+            let first = u32::from_le_bytes([entry[20], entry[21], entry[22], entry[23]]);
+            let length = u64::from_le_bytes(entry[24..32].try_into().unwrap());
+            let extent = Extent { first, contiguous: false, size: length };
+            match entry[0] {
+                0x00 => break,
+                // The first bitmap goes with the first FAT (TexFAT has two)
+                t if t == EntryType::AllocationBitmap as u8 && bitmap.is_none() => bitmap = Some(extent),
+                t if t == EntryType::UpcaseTable as u8 => {
+                    upcase = Some((extent, u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]])));
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+
+        let bitmap = bitmap.ok_or(FsError::InvalidFs)?;
+        if bitmap.size < (self.cluster_count as u64).div_ceil(8) {
+            return Err(FsError::InvalidFs);
+        }
+        self.bitmap = bitmap;
+
+        let (table, checksum) = upcase.ok_or(FsError::InvalidFs)?;
+        self.load_upcase(&table, checksum)
+    }
+
+    /// Read and expand the up-case table
+    ///
+    /// The table maps characters from U+0000 in order; 0xFFFF followed
+    /// by a count skips that many characters, which map to themselves.
+    fn load_upcase(&mut self, table: &Extent, expected: u32) -> FsResult<()> {
+        if table.size == 0 || table.size > UPCASE_CHARS as u64 * 2 {
+            return Err(FsError::InvalidFs);
+        }
+        let mut data = vec![0u8; (table.size.div_ceil(self.cluster_size()) << self.cluster_shift) as usize];
+        for (i, cluster) in self.chain(table)?.into_iter().enumerate() {
+            let at = i << self.cluster_shift;
+            self.read_cluster(cluster, &mut data[at..at + self.cluster_size() as usize])?;
+        }
+        let data = &data[..table.size as usize];
+        let checksum = data.iter().fold(0u32, |sum, &b| sum.rotate_right(1).wrapping_add(b as u32));
+        if checksum != expected {
+            return Err(FsError::InvalidFs);
+        }
+
+        if self.upcase.len() != UPCASE_CHARS {
+            self.upcase = vec![0; UPCASE_CHARS];
+        }
+        for (c, slot) in self.upcase.iter_mut().enumerate() {
+            *slot = c as u16;
+        }
+        let mut c = 0usize;
+        let mut values = data.chunks_exact(2).map(|v| u16::from_le_bytes([v[0], v[1]]));
+        while let Some(value) = values.next() {
+            if c >= UPCASE_CHARS {
+                break;
+            }
+            if value == 0xFFFF {
+                c += values.next().unwrap_or(0) as usize;
+            } else {
+                self.upcase[c] = value;
+                c += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // -------------------------------------------------------------------------
+    // File data
+    // -------------------------------------------------------------------------
+
+    /// Cluster holding byte `pos` of an open file
+    fn file_cluster(&self, file: &mut OpenFile, pos: u64) -> FsResult<u32> {
+        let mut cursor = Cursor { index: file.current_index, cluster: file.current_cluster };
+        if file.current_cluster == 0 {
+            cursor = Cursor { index: 0, cluster: file.first_cluster };
+        }
+        let cluster = self.cluster_at(&file.extent(), pos >> self.cluster_shift, &mut cursor)?;
+        file.current_index = cursor.index;
+        file.current_cluster = cursor.cluster;
+        Ok(cluster)
+    }
+
+    /// Copy file bytes from `pos` into `buf` (all within the allocation)
+    fn read_at(&self, file: &mut OpenFile, mut pos: u64, buf: &mut [u8]) -> FsResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let cluster = self.file_cluster(file, pos)?;
+            let within = pos & (self.cluster_size() - 1);
+            let lba = self.cluster_to_sector(cluster) + within / SECTOR_SIZE as u64;
+            let offset = (within % SECTOR_SIZE as u64) as usize;
+            let left = buf.len() - done;
+
+            if offset == 0 && left >= SECTOR_SIZE {
+                // Whole sectors straight into the buffer, up to the cluster end
+                let to_cluster_end = (self.cluster_size() - within) / SECTOR_SIZE as u64;
+                let count = (left / SECTOR_SIZE).min(to_cluster_end as usize).min(MAX_SECTORS_PER_IO as usize);
+                let bytes = count * SECTOR_SIZE;
+                self.read_sectors(lba, count as u64, &mut buf[done..done + bytes])?;
+                done += bytes;
+                pos += bytes as u64;
+            } else {
+                self.read_sector(lba, &mut sector)?;
+                let n = left.min(SECTOR_SIZE - offset);
+                buf[done..done + n].copy_from_slice(&sector[offset..offset + n]);
+                done += n;
+                pos += n as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `data` to the file at `pos` (all within the allocation);
+    /// an empty `data` with a `len` writes zeros
+    fn write_at(&self, file: &mut OpenFile, mut pos: u64, data: &[u8], len: usize) -> FsResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < len {
+            let cluster = self.file_cluster(file, pos)?;
+            let within = pos & (self.cluster_size() - 1);
+            let lba = self.cluster_to_sector(cluster) + within / SECTOR_SIZE as u64;
+            let offset = (within % SECTOR_SIZE as u64) as usize;
+            let n = (len - done).min(SECTOR_SIZE - offset);
+
+            if n == SECTOR_SIZE && !data.is_empty() {
+                self.write_sectors(lba, 1, &data[done..done + n])?;
+            } else {
+                if n < SECTOR_SIZE {
+                    self.read_sector(lba, &mut sector)?;
+                }
+                match data.is_empty() {
+                    true => sector[offset..offset + n].fill(0),
+                    false => sector[offset..offset + n].copy_from_slice(&data[done..done + n]),
+                }
+                self.write_sector(lba, &sector)?;
+            }
+            done += n;
+            pos += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Bring an open file's entry set up to date
+    fn write_file_entry(&self, file: &OpenFile) -> FsResult<()> {
+        let mut reader = DirReader::new(self, file.location.dir);
+        let mut set = reader.set(file.location.index)?.ok_or(FsError::InvalidFs)?;
+        let extent = Extent { size: file.allocated, ..file.extent() };
+        set.set_extent(&extent, file.valid_size);
+        set.stream.data_length = file.size;
+        set.file.file_attributes |= attrs::ARCHIVE;
+        self.write_set(&file.location.dir, &set)
+    }
+
+    /// Truncate an open file to nothing
+    fn truncate(&mut self, file: &mut OpenFile) -> FsResult<()> {
+        let old = file.extent();
+        file.set_extent(&Extent { first: 0, contiguous: false, size: 0 });
+        file.size = 0;
+        file.valid_size = 0;
+        // Entry first: a crash then loses clusters instead of leaving the
+        // file pointing at freed ones
+        self.write_file_entry(file)?;
+        self.release(&old)
+    }
+
+    /// Write for an open file: grow, fill any gap after the written data,
+    /// write, then update the entry set
+    fn write_file(&mut self, file: &mut OpenFile, buf: &[u8]) -> FsResult<usize> {
+        if file.flags.append {
+            file.position = file.size;
+        }
+        let start = file.position;
+        let end = start + buf.len() as u64;
+
+        if end > file.allocated {
+            let mut extent = file.extent();
+            self.grow(&mut extent, end.div_ceil(self.cluster_size()))?;
+            file.set_extent(&extent);
+        }
+        if start > file.valid_size {
+            self.write_at(file, file.valid_size, &[], (start - file.valid_size) as usize)?;
+        }
+        self.write_at(file, start, buf, buf.len())?;
+
+        file.position = end;
+        file.size = file.size.max(end);
+        file.valid_size = file.valid_size.max(end);
+        self.write_file_entry(file)?;
+        Ok(buf.len())
+    }
+
+    /// Remove a file or an empty directory
+    fn remove_entry(&mut self, path: &str, directory: bool) -> FsResult<()> {
+        let (dir, set) = self.lookup(path)?;
+        let set = set.ok_or(FsError::PermissionDenied)?;
+        if set.is_dir() != directory {
+            return Err(if directory { FsError::NotDirectory } else { FsError::IsDirectory });
+        }
+        if directory && self.sets(&set.extent()).next().is_some() {
+            return Err(FsError::PermissionDenied);
+        }
+        let in_use = self.open_files.iter().any(|f| {
+            f.in_use && f.location.dir.first == dir.extent.first && f.location.index == set.index
+        });
+        if in_use {
+            return Err(FsError::PermissionDenied);
+        }
+
+        self.modify(|fs| {
+            fs.delete_set(&dir.extent, &set)?;
+            fs.release(&set.extent())
+        })
+    }
+
+    // -------------------------------------------------------------------------
+    // Handles
+    // -------------------------------------------------------------------------
+
     /// Allocate a file handle
     fn alloc_handle(&mut self) -> FsResult<u64> {
         for (i, file) in self.open_files.iter_mut().enumerate() {
@@ -277,7 +1423,7 @@ impl ExfatFilesystem {
         }
         Err(FsError::TooManyOpenFiles)
     }
-    
+
     /// Get open file by handle
     fn get_file(&mut self, handle: u64) -> FsResult<&mut OpenFile> {
         let idx = handle as usize;
@@ -296,76 +1442,134 @@ impl Filesystem for ExfatFilesystem {
     fn name(&self) -> &'static str {
         "exfat"
     }
-    
+
     fn mount(&mut self) -> FsResult<()> {
         if self.mounted {
             return Ok(());
         }
-        
-        // TODO: Read boot sector and validate
-        // For now, just mark as mounted with defaults
-        
+
+        let boot = self.read_boot_sector()?;
+        self.check_boot_checksum()?;
+        self.load_geometry(&boot)?;
+
+        let root = self.fat_chain(self.root_cluster)?;
+        self.root = Extent {
+            first: self.root_cluster,
+            contiguous: false,
+            size: (root.len() as u64) << self.cluster_shift,
+        };
+        self.load_system_entries()?;
+
+        self.free_count = None;
+        self.next_free = cluster::FIRST_VALID;
         self.mounted = true;
         Ok(())
     }
-    
+
     fn unmount(&mut self) -> FsResult<()> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
-        
+
         // Close all open files
         for file in &mut self.open_files {
             file.in_use = false;
         }
-        
+
         self.mounted = false;
         Ok(())
     }
-    
+
     fn open(&mut self, path: &str, flags: OpenFlags) -> FsResult<u64> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
-        
-        // TODO: Implement path lookup and file opening
-        // For now, return a dummy handle
-        
+
+        let (dir, set) = match self.lookup(path) {
+            Ok((_, None)) => return Err(FsError::IsDirectory),
+            Ok((dir, Some(set))) => {
+                if flags.create && flags.exclusive {
+                    return Err(FsError::AlreadyExists);
+                }
+                (dir, set)
+            }
+            Err(FsError::NotFound) if flags.create => {
+                let (mut dir, name) = self.lookup_parent(path)?;
+                let empty = Extent { first: 0, contiguous: false, size: 0 };
+                let mut set = self.new_set(name, attrs::ARCHIVE, &empty, 0)?;
+                self.modify(|fs| fs.add_set(&mut dir, &mut set))?;
+                (dir, set)
+            }
+            Err(e) => return Err(e),
+        };
+        if set.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        if flags.write && set.file.file_attributes & attrs::READ_ONLY != 0 {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let mut file = OpenFile {
+            in_use: true,
+            position: 0,
+            size: set.stream.data_length,
+            valid_size: set.stream.valid_data_length.min(set.stream.data_length),
+            location: SetLocation { dir: dir.extent, index: set.index },
+            flags,
+            ..OpenFile::empty()
+        };
+        file.set_extent(&set.extent());
+        if flags.truncate && flags.write && file.size != 0 {
+            self.modify(|fs| fs.truncate(&mut file))?;
+        }
+
         let handle = self.alloc_handle()?;
-        let file = self.get_file(handle)?;
-        file.flags = flags;
-        file.position = 0;
-        file.size = 0;
-        file.first_cluster = 0;
-        file.current_cluster = 0;
-        
+        self.open_files[handle as usize] = file;
         Ok(handle)
     }
-    
+
     fn close(&mut self, handle: u64) -> FsResult<()> {
         let file = self.get_file(handle)?;
         file.in_use = false;
         Ok(())
     }
-    
+
     fn read(&mut self, handle: u64, buf: &mut [u8]) -> FsResult<usize> {
-        let _file = self.get_file(handle)?;
-        // TODO: Implement actual reading
-        Ok(0)
+        let mut file = *self.get_file(handle)?;
+        if !file.flags.read {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let len = (file.size.saturating_sub(file.position)).min(buf.len() as u64) as usize;
+        let start = file.position;
+        // Bytes past ValidDataLength were never written: they read as zeros
+        let stored = (file.valid_size.saturating_sub(start)).min(len as u64) as usize;
+        let result = self.read_at(&mut file, start, &mut buf[..stored]);
+        buf[stored..len].fill(0);
+        if result.is_ok() {
+            file.position += len as u64;
+        }
+        self.open_files[handle as usize] = file;
+        result.map(|_| len)
     }
-    
+
     fn write(&mut self, handle: u64, buf: &[u8]) -> FsResult<usize> {
-        let file = self.get_file(handle)?;
+        let mut file = *self.get_file(handle)?;
         if !file.flags.write {
             return Err(FsError::PermissionDenied);
         }
-        // TODO: Implement actual writing
-        Ok(0)
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let result = self.modify(|fs| fs.write_file(&mut file, buf));
+        self.open_files[handle as usize] = file;
+        result
     }
-    
+
     fn seek(&mut self, handle: u64, offset: i64, whence: SeekFrom) -> FsResult<u64> {
         let file = self.get_file(handle)?;
-        
+
         let new_pos = match whence {
             SeekFrom::Start => offset as u64,
             SeekFrom::Current => {
@@ -383,63 +1587,145 @@ impl Filesystem for ExfatFilesystem {
                 }
             }
         };
-        
+
         file.position = new_pos;
         Ok(new_pos)
     }
-    
-    fn stat(&self, _path: &str) -> FsResult<Metadata> {
+
+    fn stat(&self, path: &str) -> FsResult<Metadata> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
-        
-        // TODO: Implement path lookup and stat
-        Err(FsError::NotFound)
+
+        let Some(set) = self.lookup(path)?.1 else {
+            return Ok(Metadata {
+                file_type: FileType::Directory,
+                size: self.root.size,
+                permissions: Permissions::default_dir(),
+                created: 0,
+                modified: 0,
+                accessed: 0,
+            });
+        };
+        let file = set.file;
+        let mut permissions = if set.is_dir() { Permissions::default_dir() } else { Permissions::default_file() };
+        if file.file_attributes & attrs::READ_ONLY != 0 {
+            permissions.owner.write = false;
+        }
+        Ok(Metadata {
+            file_type: if set.is_dir() { FileType::Directory } else { FileType::Regular },
+            size: set.stream.data_length,
+            permissions,
+            created: dos_time_to_unix(file.create_timestamp, file.create_10ms),
+            modified: dos_time_to_unix(file.modified_timestamp, file.modified_10ms),
+            accessed: dos_time_to_unix(file.accessed_timestamp, 0),
+        })
     }
-    
-    fn readdir(&mut self, _path: &str) -> FsResult<ReadDir> {
+
+    fn readdir(&mut self, path: &str, offset: usize) -> FsResult<ReadDir> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
-        
-        // TODO: Implement directory reading
-        Ok(ReadDir::empty())
+
+        let extent = match self.lookup(path)? {
+            (dir, None) => dir.extent,
+            (_, Some(set)) if set.is_dir() => set.extent(),
+            _ => return Err(FsError::NotDirectory),
+        };
+        let mut listing = ReadDir::empty();
+        let mut sets = self.sets(&extent);
+        for set in sets.by_ref().skip(offset) {
+            let mut entry = DirEntry {
+                name: [0; MAX_FILENAME],
+                name_len: 0,
+                file_type: if set.is_dir() { FileType::Directory } else { FileType::Regular },
+                inode: set.stream.first_cluster as u64,
+                hidden: set.file.file_attributes & (attrs::HIDDEN | attrs::SYSTEM) != 0,
+            };
+            entry.name_len = decode_name(set.name(), &mut entry.name);
+            if !listing.add(entry) {
+                break;
+            }
+        }
+        match sets.error {
+            Some(e) => Err(e),
+            None => Ok(listing),
+        }
     }
-    
-    fn mkdir(&mut self, _path: &str) -> FsResult<()> {
+
+    fn mkdir(&mut self, path: &str) -> FsResult<()> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
-        
-        // TODO: Implement directory creation
-        Err(FsError::IoError)
+        if self.lookup(path).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (mut dir, name) = self.lookup_parent(path)?;
+        self.modify(|fs| {
+            // One cleared cluster: no entries yet
+            let mut extent = Extent { first: 0, contiguous: true, size: 0 };
+            fs.grow(&mut extent, 1)?;
+            fs.write_cluster(extent.first, &[])?;
+            let mut set = fs.new_set(name, attrs::DIRECTORY, &extent, extent.size)?;
+            fs.add_set(&mut dir, &mut set)
+        })
     }
-    
-    fn remove(&mut self, _path: &str) -> FsResult<()> {
+
+    fn remove(&mut self, path: &str) -> FsResult<()> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
-        
-        // TODO: Implement file removal
-        Err(FsError::IoError)
+
+        self.remove_entry(path, false)
     }
-    
-    fn rmdir(&mut self, _path: &str) -> FsResult<()> {
+
+    fn rmdir(&mut self, path: &str) -> FsResult<()> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
-        
-        // TODO: Implement directory removal
-        Err(FsError::IoError)
+
+        self.remove_entry(path, true)
     }
-    
-    fn rename(&mut self, _from: &str, _to: &str) -> FsResult<()> {
+
+    fn rename(&mut self, from: &str, to: &str) -> FsResult<()> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
-        
-        // TODO: Implement rename
-        Err(FsError::IoError)
+
+        let (from_dir, set) = self.lookup(from)?;
+        let set = set.ok_or(FsError::PermissionDenied)?;
+        if self.lookup(to).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+        let (mut to_dir, name) = self.lookup_parent(to)?;
+        // A directory can't move into itself or anything below it
+        if set.is_dir() && self.passes_through(to, set.stream.first_cluster)? {
+            return Err(FsError::InvalidPath);
+        }
+
+        // Same clusters and timestamps under the new name; the new set is
+        // written before the old one goes
+        let mut renamed = self.new_set(name, set.file.file_attributes, &set.extent(), set.stream.valid_data_length)?;
+        renamed.file.create_timestamp = set.file.create_timestamp;
+        renamed.file.modified_timestamp = set.file.modified_timestamp;
+        renamed.file.accessed_timestamp = set.file.accessed_timestamp;
+        renamed.file.create_10ms = set.file.create_10ms;
+        renamed.file.modified_10ms = set.file.modified_10ms;
+        self.modify(|fs| {
+            fs.add_set(&mut to_dir, &mut renamed)?;
+            // Within one directory, use the extent add_set may have grown
+            let from_dir = if to_dir.extent.first == from_dir.extent.first { to_dir } else { from_dir };
+            fs.delete_set(&from_dir.extent, &set)
+        })?;
+
+        // Handles to the file follow it
+        for file in self.open_files.iter_mut().filter(|f| f.in_use) {
+            if file.location.dir.first == from_dir.extent.first && file.location.index == set.index {
+                file.location = SetLocation { dir: to_dir.extent, index: renamed.index };
+            }
+        }
+        Ok(())
     }
 }
 
@@ -448,3 +1734,191 @@ impl Default for ExfatFilesystem {
         Self::new()
     }
 }
+
+// =============================================================================
+// Directory Reading
+// =============================================================================
+
+/// Reads a directory's entries, a sector at a time
+struct DirReader<'a> {
+    fs: &'a ExfatFilesystem,
+    dir: Extent,
+    cursor: Cursor,
+    sector: [u8; SECTOR_SIZE],
+    loaded: Option<u64>,
+}
+
+impl<'a> DirReader<'a> {
+    fn new(fs: &'a ExfatFilesystem, dir: Extent) -> Self {
+        DirReader {
+            fs,
+            dir,
+            cursor: Cursor { index: 0, cluster: dir.first },
+            sector: [0; SECTOR_SIZE],
+            loaded: None,
+        }
+    }
+
+    /// Entry `index`, or `None` past the end of the directory
+    fn entry(&mut self, index: u32) -> FsResult<Option<[u8; ENTRY_SIZE]>> {
+        let Some((lba, offset)) = self.fs.entry_position(&self.dir, index, &mut self.cursor)? else {
+            return Ok(None);
+        };
+        if self.loaded != Some(lba) {
+            self.fs.read_sector(lba, &mut self.sector)?;
+            self.loaded = Some(lba);
+        }
+        Ok(Some(self.sector[offset..offset + ENTRY_SIZE].try_into().unwrap()))
+    }
+
+    /// The entry set starting at `index`, if that is a valid one in use
+    fn set(&mut self, index: u32) -> FsResult<Option<EntrySet>> {
+        let Some(raw) = self.entry(index)? else { return Ok(None) };
+        let file: FileEntry = entry_from_bytes(&raw);
+        let count = 1 + file.secondary_count as usize;
+        if file.entry_type != EntryType::File as u8 || !(3..=MAX_SET_ENTRIES).contains(&count) {
+            return Ok(None);
+        }
+
+        let mut entries = [[0u8; ENTRY_SIZE]; MAX_SET_ENTRIES];
+        entries[0] = raw;
+        for i in 1..count {
+            match self.entry(index + i as u32)? {
+                Some(entry) => entries[i] = entry,
+                None => return Ok(None),
+            }
+        }
+        if set_checksum(&entries[..count]) != file.set_checksum {
+            return Ok(None);
+        }
+
+        let stream: StreamEntry = entry_from_bytes(&entries[1]);
+        let name_len = stream.name_length as usize;
+        if stream.entry_type != EntryType::StreamExtension as u8
+            || name_len == 0
+            || count < 2 + name_len.div_ceil(NAME_CHARS_PER_ENTRY)
+        {
+            return Ok(None);
+        }
+
+        let mut set = EntrySet { index, file, stream, name: [0; MAX_FILENAME], name_len };
+        for (i, entry) in entries[2..2 + name_len.div_ceil(NAME_CHARS_PER_ENTRY)].iter().enumerate() {
+            let name: FileNameEntry = entry_from_bytes(entry);
+            if name.entry_type != EntryType::FileNameExtension as u8 {
+                return Ok(None);
+            }
+            let chars = name.file_name;
+            let at = i * NAME_CHARS_PER_ENTRY;
+            let n = (name_len - at).min(NAME_CHARS_PER_ENTRY);
+            set.name[at..at + n].copy_from_slice(&chars[..n]);
+        }
+        Ok(Some(set))
+    }
+}
+
+/// Iterator over the file and directory entry sets of a directory
+///
+/// Skips unused entries, the bitmap, up-case table and volume label
+/// entries, and sets that fail their checksum. Ends at the end of the
+/// directory or at a read error (`error`).
+struct SetIter<'a> {
+    reader: DirReader<'a>,
+    index: u32,
+    done: bool,
+    error: Option<FsError>,
+}
+
+impl Iterator for SetIter<'_> {
+    type Item = EntrySet;
+
+    fn next(&mut self) -> Option<EntrySet> {
+        while !self.done {
+            let entry = match self.reader.entry(self.index) {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                Err(e) => {
+                    self.error = Some(e);
+                    break;
+                }
+            };
+            if entry[0] == EntryType::EndOfDirectory as u8 {
+                break;
+            }
+            if entry[0] == EntryType::File as u8 {
+                match self.reader.set(self.index) {
+                    Ok(Some(set)) => {
+                        self.index += set.entry_count() as u32;
+                        return Some(set);
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        self.error = Some(e);
+                        break;
+                    }
+                }
+            }
+            self.index += 1;
+        }
+        self.done = true;
+        None
+    }
+}
+
+// =============================================================================
+// Helpers
+// =============================================================================
+
+fn is_exfat_boot_sector(sector: &[u8; SECTOR_SIZE]) -> bool {
+    &sector[3..11] == b"EXFAT   " && sector[11..64].iter().all(|&b| b == 0) && sector[510..] == [0x55, 0xAA]
+}
+
+/// One of the packed entry structs from its 32 bytes
+fn entry_from_bytes<T: Copy>(raw: &[u8; ENTRY_SIZE]) -> T {
+    assert!(size_of::<T>() == ENTRY_SIZE);
+    unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const T) }
+}
+
+/// The 32 bytes of one of the packed entry structs
+fn entry_to_bytes<T: Copy>(entry: &T) -> [u8; ENTRY_SIZE] {
+    assert!(size_of::<T>() == ENTRY_SIZE);
+    let mut raw = [0u8; ENTRY_SIZE];
+    unsafe { core::ptr::write_unaligned(raw.as_mut_ptr() as *mut T, *entry) };
+    raw
+}
+
+/// SetChecksum: over every byte of the set except the checksum itself
+fn set_checksum(entries: &[[u8; ENTRY_SIZE]]) -> u16 {
+    let mut checksum = 0u16;
+    for (i, entry) in entries.iter().enumerate() {
+        for (at, &byte) in entry.iter().enumerate() {
+            if i == 0 && (at == 2 || at == 3) {
+                continue;
+            }
+            checksum = checksum.rotate_right(1).wrapping_add(byte as u16);
+        }
+    }
+    checksum
+}
+
+/// A path part as a UTF-16 name; returns its length
+fn encode_name(name: &str, out: &mut [u16; MAX_FILENAME]) -> FsResult<usize> {
+    let mut len = 0;
+    for unit in name.encode_utf16() {
+        *out.get_mut(len).ok_or(FsError::InvalidPath)? = unit;
+        len += 1;
+    }
+    Ok(len)
+}
+
+/// A UTF-16 name as UTF-8, as much as fits; returns the length
+fn decode_name(name: &[u16], out: &mut [u8; MAX_FILENAME]) -> usize {
+    let mut len = 0;
+    for c in char::decode_utf16(name.iter().copied()) {
+        let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+        if len + c.len_utf8() > out.len() {
+            break;
+        }
+        len += c.encode_utf8(&mut out[len..]).len();
+    }
+    len
+}
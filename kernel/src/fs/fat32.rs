@@ -0,0 +1,344 @@
+//! FAT32 through the `Filesystem` trait (FAT12 and FAT16 volumes too)
+//!
+//! `storage::fat32::Fat32` does the disk work and keeps the volume state;
+//! this adds paths and an open file table on top.
+//!
+//! - Paths are `/`-separated from the volume root; each part matches a
+//!   long or 8.3 name, ignoring ASCII case
+//! - Files and directories created with a name that isn't upper-case 8.3
+//!   get long name entries
+//! - `readdir` leaves out the `.` and `..` entries
+//! - Files are limited to 4 GB - 1 (the directory entry's 32-bit size)
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use crate::storage::fat32::{Fat32, DirEntry as Fat32Entry};
+use crate::storage::partition::BlockDevice;
+use super::{
+    Filesystem, Metadata, FileType, OpenFlags, SeekFrom,
+    FsResult, FsError, DirEntry, ReadDir, Permissions, MAX_FILENAME, dos_time_to_unix,
+};
+
+/// Maximum open files
+const MAX_OPEN_FILES: usize = 16;
+
+/// Deepest directory nesting followed up through `..` entries (more
+/// means the entries loop)
+const MAX_DEPTH: usize = 128;
+
+/// FAT attribute: read-only
+const ATTR_READ_ONLY: u8 = 0x01;
+
+/// Open file handle
+struct OpenFile {
+    /// Directory entry, size and first cluster kept up to date by writes
+    entry: Fat32Entry,
+    /// Current position in file
+    position: u64,
+    /// Open flags
+    flags: OpenFlags,
+}
+
+/// A FAT volume
+pub struct Fat32Filesystem {
+    /// Volume state
+    fs: Box<Fat32>,
+    volume: BlockDevice,
+    open_files: [Option<OpenFile>; MAX_OPEN_FILES],
+}
+
+impl Fat32Filesystem {
+    /// Create a filesystem that keeps its state in `fs` for `volume`
+    /// (mounted by `mount`)
+    pub fn new(fs: Box<Fat32>, volume: BlockDevice) -> Self {
+        const CLOSED: Option<OpenFile> = None;
+        Self {
+            fs,
+            volume,
+            open_files: [CLOSED; MAX_OPEN_FILES],
+        }
+    }
+
+    /// Entry at `path`; `None` for the root directory
+    fn lookup(&self, path: &str) -> FsResult<Option<Fat32Entry>> {
+        let fs = &*self.fs;
+        if !fs.is_mounted() {
+            return Err(FsError::NotMounted);
+        }
+
+        let mut found: Option<Fat32Entry> = None;
+        for part in path.split('/').filter(|part| !part.is_empty() && *part != ".") {
+            let dir = match &found {
+                None => fs.root_cluster(),
+                Some(entry) if entry.is_dir() => entry.first_cluster,
+                Some(_) => return Err(FsError::NotDirectory),
+            };
+            found = Some(fs.find_entry(dir, part.as_bytes()).map_err(fs_error)?.ok_or(FsError::NotFound)?);
+        }
+        Ok(found)
+    }
+
+    /// First cluster of the directory that `path` would be in, and its
+    /// last part
+    fn lookup_parent<'p>(&self, path: &'p str) -> FsResult<(u32, &'p str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() || name == "." || name == ".." || name.len() > MAX_FILENAME {
+            return Err(FsError::InvalidPath);
+        }
+        match self.lookup(parent)? {
+            None => Ok((self.fs.root_cluster(), name)),
+            Some(entry) if entry.is_dir() => Ok((entry.first_cluster, name)),
+            Some(_) => Err(FsError::NotDirectory),
+        }
+    }
+
+    /// True if a handle is open on `entry`
+    fn is_open(&self, entry: &Fat32Entry) -> bool {
+        self.open_files.iter().flatten().any(|file| file.entry.is_same(entry))
+    }
+}
+
+/// Get open file by handle
+fn get_file(open_files: &mut [Option<OpenFile>], handle: u64) -> FsResult<&mut OpenFile> {
+    open_files
+        .get_mut(handle as usize)
+        .and_then(Option::as_mut)
+        .ok_or(FsError::IoError)
+}
+
+impl Filesystem for Fat32Filesystem {
+    fn name(&self) -> &'static str {
+        self.fs.fat_type().name()
+    }
+
+    fn mount(&mut self) -> FsResult<()> {
+        self.fs.mount(self.volume).map_err(|_| FsError::InvalidFs)
+    }
+
+    fn unmount(&mut self) -> FsResult<()> {
+        if !self.fs.is_mounted() {
+            return Err(FsError::NotMounted);
+        }
+
+        // Close all open files
+        for file in &mut self.open_files {
+            *file = None;
+        }
+
+        self.fs.unmount();
+        Ok(())
+    }
+
+    fn open(&mut self, path: &str, flags: OpenFlags) -> FsResult<u64> {
+        let mut entry = match self.lookup(path) {
+            Ok(None) => return Err(FsError::IsDirectory),
+            Ok(Some(entry)) => {
+                if flags.create && flags.exclusive {
+                    return Err(FsError::AlreadyExists);
+                }
+                entry
+            }
+            Err(FsError::NotFound) if flags.create => {
+                let (dir, name) = self.lookup_parent(path)?;
+                self.fs.create_entry(dir, name.as_bytes(), false).map_err(fs_error)?
+            }
+            Err(e) => return Err(e),
+        };
+        if entry.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        if flags.write && entry.attr & ATTR_READ_ONLY != 0 {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let slot = self.open_files.iter().position(Option::is_none).ok_or(FsError::TooManyOpenFiles)?;
+        if flags.truncate && flags.write && entry.size != 0 {
+            self.fs.set_size(&mut entry, 0).map_err(fs_error)?;
+        }
+        self.open_files[slot] = Some(OpenFile { entry, position: 0, flags });
+        Ok(slot as u64)
+    }
+
+    fn close(&mut self, handle: u64) -> FsResult<()> {
+        get_file(&mut self.open_files, handle)?;
+        self.open_files[handle as usize] = None;
+        Ok(())
+    }
+
+    fn read(&mut self, handle: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let file = get_file(&mut self.open_files, handle)?;
+        if !file.flags.read {
+            return Err(FsError::PermissionDenied);
+        }
+        if file.position >= file.entry.size as u64 {
+            return Ok(0);
+        }
+
+        let read = self.fs
+            .read_at(&file.entry, file.position as u32, buf)
+            .map_err(fs_error)?;
+        file.position += read as u64;
+        Ok(read)
+    }
+
+    fn write(&mut self, handle: u64, buf: &[u8]) -> FsResult<usize> {
+        let file = get_file(&mut self.open_files, handle)?;
+        if !file.flags.write {
+            return Err(FsError::PermissionDenied);
+        }
+        if file.flags.append {
+            file.position = file.entry.size as u64;
+        }
+        if file.position + buf.len() as u64 > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+
+        self.fs
+            .write_at(&mut file.entry, file.position as u32, buf)
+            .map_err(fs_error)?;
+        file.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn seek(&mut self, handle: u64, offset: i64, whence: SeekFrom) -> FsResult<u64> {
+        let file = get_file(&mut self.open_files, handle)?;
+
+        let base = match whence {
+            SeekFrom::Start => 0,
+            SeekFrom::Current => file.position,
+            SeekFrom::End => file.entry.size as u64,
+        };
+        file.position = if offset >= 0 {
+            base + offset as u64
+        } else {
+            base.saturating_sub(offset.unsigned_abs())
+        };
+        Ok(file.position)
+    }
+
+    fn stat(&self, path: &str) -> FsResult<Metadata> {
+        let Some(entry) = self.lookup(path)? else {
+            return Ok(Metadata {
+                file_type: FileType::Directory,
+                size: 0,
+                permissions: Permissions::default_dir(),
+                created: 0,
+                modified: 0,
+                accessed: 0,
+            });
+        };
+        let mut permissions = if entry.is_dir() { Permissions::default_dir() } else { Permissions::default_file() };
+        if entry.attr & ATTR_READ_ONLY != 0 {
+            permissions.owner.write = false;
+        }
+        Ok(Metadata {
+            file_type: if entry.is_dir() { FileType::Directory } else { FileType::Regular },
+            size: entry.size as u64,
+            permissions,
+            created: dos_time_to_unix(entry.created, entry.created_10ms),
+            modified: dos_time_to_unix(entry.modified, 0),
+            accessed: dos_time_to_unix(entry.accessed, 0),
+        })
+    }
+
+    fn readdir(&mut self, path: &str, offset: usize) -> FsResult<ReadDir> {
+        let fs = &*self.fs;
+        let cluster = match self.lookup(path)? {
+            None => fs.root_cluster(),
+            Some(entry) if entry.is_dir() => entry.first_cluster,
+            Some(_) => return Err(FsError::NotDirectory),
+        };
+
+        let mut listing = ReadDir::empty();
+        let mut entries = fs.read_dir(cluster);
+        for entry in entries.by_ref().filter(|e| !e.is_dot()).skip(offset) {
+            let mut listed = DirEntry {
+                name: [0; MAX_FILENAME],
+                name_len: entry.name().len().min(MAX_FILENAME),
+                file_type: if entry.is_dir() { FileType::Directory } else { FileType::Regular },
+                inode: entry.first_cluster as u64,
+                hidden: entry.is_hidden(),
+            };
+            listed.name[..listed.name_len].copy_from_slice(&entry.name()[..listed.name_len]);
+            if !listing.add(listed) {
+                break;
+            }
+        }
+        match entries.error() {
+            Some(e) => Err(fs_error(e)),
+            None => Ok(listing),
+        }
+    }
+
+    fn mkdir(&mut self, path: &str) -> FsResult<()> {
+        let (dir, name) = self.lookup_parent(path)?;
+        self.fs.create_entry(dir, name.as_bytes(), true).map_err(fs_error)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &str) -> FsResult<()> {
+        let entry = self.lookup(path)?.ok_or(FsError::PermissionDenied)?;
+        if entry.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        if self.is_open(&entry) {
+            return Err(FsError::PermissionDenied);
+        }
+        self.fs.delete_entry(&entry).map_err(fs_error)
+    }
+
+    fn rmdir(&mut self, path: &str) -> FsResult<()> {
+        let entry = self.lookup(path)?.ok_or(FsError::PermissionDenied)?;
+        if !entry.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        self.fs.delete_entry(&entry).map_err(fs_error)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> FsResult<()> {
+        let entry = self.lookup(from)?.ok_or(FsError::PermissionDenied)?;
+        let (dir, name) = self.lookup_parent(to)?;
+        // A directory can't move into itself or anything below it: walk
+        // up from the target through the `..` entries
+        if entry.is_dir() {
+            let root = self.fs.root_cluster();
+            let mut ancestor = dir;
+            let mut depth = 0;
+            while ancestor != root {
+                if ancestor == entry.first_cluster {
+                    return Err(FsError::InvalidPath);
+                }
+                depth += 1;
+                if depth > MAX_DEPTH {
+                    return Err(FsError::InvalidFs);
+                }
+                ancestor = self.fs.parent_dir(ancestor).map_err(fs_error)?;
+            }
+        }
+
+        let renamed = self.fs.rename_entry(&entry, dir, name.as_bytes()).map_err(fs_error)?;
+
+        // Handles to the file follow it
+        for file in self.open_files.iter_mut().flatten() {
+            if file.entry.is_same(&entry) {
+                file.entry = renamed.clone();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `storage::fat32` error as the nearest `FsError`
+fn fs_error(e: &'static str) -> FsError {
+    match e {
+        "Not mounted" => FsError::NotMounted,
+        "Disk full" | "File too large" | "Root directory full" => FsError::NoSpace,
+        "File exists" => FsError::AlreadyExists,
+        "Directory not empty" => FsError::PermissionDenied,
+        "Bad file name" | "File name too long" | "No free 8.3 name" => FsError::InvalidPath,
+        "Cluster chain loops" | "Cluster chain ends early" | "Bad directory entry" => FsError::InvalidFs,
+        _ => FsError::IoError,
+    }
+}
@@ -0,0 +1,493 @@
+//! ISO 9660 CD-ROM filesystem (read-only), with Joliet long names
+//!
+//! Reads a data CD in an ATAPI drive through `storage::ata`'s READ(12)
+//! path. Mounting walks the volume descriptors from sector 16: the
+//! primary descriptor is required, and a Joliet supplementary descriptor
+//! (UCS-2 names up to 64 characters) is used instead when present.
+//!
+//! - Directories are read from their directory records; the path tables
+//!   aren't used
+//! - Names lose the `;1` version suffix and the `.` of a name with no
+//!   extension, and match ignoring ASCII case
+//! - Without Joliet, names are what the disc records (upper case, 8.3 on
+//!   level 1 discs); Rock Ridge entries are ignored
+//! - Every write returns `FsError::ReadOnly`
+//! - Files split over several extents (4 GB and larger) and interleaved
+//!   files aren't supported
+
+use crate::storage::ata::{self, ATAPI_SECTOR_SIZE};
+use super::{
+    Filesystem, Metadata, FileType, OpenFlags, SeekFrom,
+    FsResult, FsError, DirEntry, ReadDir, Permissions, MAX_FILENAME, dos_time_to_unix,
+};
+
+// =============================================================================
+// On-disc Layout
+// =============================================================================
+
+/// Logical block size; the only one CD media use
+const SECTOR_SIZE: usize = ATAPI_SECTOR_SIZE;
+
+/// The volume descriptor set starts after the 32 KB system area
+const FIRST_DESCRIPTOR: u32 = 16;
+
+/// Descriptors read before giving up on finding the terminator
+const MAX_DESCRIPTORS: u32 = 64;
+
+/// Volume descriptor types
+const DESCRIPTOR_PRIMARY: u8 = 1;
+const DESCRIPTOR_SUPPLEMENTARY: u8 = 2;
+const DESCRIPTOR_TERMINATOR: u8 = 255;
+
+/// Standard identifier in every volume descriptor
+const STANDARD_ID: &[u8; 5] = b"CD001";
+
+/// Volume descriptor fields
+const VD_VOLUME_SPACE: usize = 80;
+const VD_ESCAPES: usize = 88;
+const VD_BLOCK_SIZE: usize = 128;
+const VD_ROOT_RECORD: usize = 156;
+
+/// Directory record fields
+const DR_LENGTH: usize = 0;
+const DR_EXTENT: usize = 2;
+const DR_SIZE: usize = 10;
+const DR_RECORDED: usize = 18;
+const DR_FLAGS: usize = 25;
+const DR_FILE_UNIT: usize = 26;
+const DR_INTERLEAVE: usize = 27;
+const DR_NAME_LEN: usize = 32;
+const DR_NAME: usize = 33;
+
+/// Directory record flags
+const FLAG_HIDDEN: u8 = 0x01;
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_ASSOCIATED: u8 = 0x04;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// Sectors read straight into the caller's buffer per command
+const MAX_SECTORS_PER_READ: usize = 32;
+
+/// Maximum open files
+const MAX_OPEN_FILES: usize = 16;
+
+// =============================================================================
+// Directory Records
+// =============================================================================
+
+/// The parts of a directory record this driver uses
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    /// First sector of the file or directory
+    extent: u32,
+    /// Size in bytes
+    size: u32,
+    flags: u8,
+    /// Years since 1900, month, day, hour, minute, second, GMT offset in
+    /// 15-minute steps
+    recorded: [u8; 7],
+    /// Recorded in interleaved file units rather than one run of sectors
+    interleaved: bool,
+}
+
+impl Record {
+    /// Parse the record at the start of `raw` (at least `DR_NAME` bytes)
+    fn parse(raw: &[u8]) -> Record {
+        let mut recorded = [0; 7];
+        recorded.copy_from_slice(&raw[DR_RECORDED..DR_RECORDED + 7]);
+        Record {
+            extent: le_u32(&raw[DR_EXTENT..]),
+            size: le_u32(&raw[DR_SIZE..]),
+            flags: raw[DR_FLAGS],
+            recorded,
+            interleaved: raw[DR_FILE_UNIT] != 0 || raw[DR_INTERLEAVE] != 0,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+
+    fn file_type(&self) -> FileType {
+        if self.is_dir() { FileType::Directory } else { FileType::Regular }
+    }
+
+    /// Recording time as seconds since 1970 (0 if unset or before 1980)
+    fn modified(&self) -> u64 {
+        let [year, month, day, hour, minute, second, offset] = self.recorded;
+        if year < 80 || month == 0 {
+            return 0;
+        }
+        let dos = ((year as u32 - 80).min(127) << 25)
+            | (month as u32) << 21
+            | (day as u32) << 16
+            | (hour as u32) << 11
+            | (minute as u32) << 5
+            | (second as u32 / 2);
+        let local = dos_time_to_unix(dos, (second % 2) * 100) as i64;
+        (local - offset as i8 as i64 * 900).max(0) as u64
+    }
+}
+
+/// A name decoded from a directory record
+struct Name {
+    buf: [u8; MAX_FILENAME],
+    len: usize,
+}
+
+impl Name {
+    /// Decode a d-character or (Joliet) UCS-2 name and drop the version
+    /// suffix and an empty extension
+    fn decode(raw: &[u8], joliet: bool) -> Name {
+        let mut name = Name { buf: [0; MAX_FILENAME], len: 0 };
+        if joliet {
+            let units = raw.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+            for c in char::decode_utf16(units).map(|c| c.unwrap_or('?')) {
+                if name.len + c.len_utf8() > MAX_FILENAME {
+                    break;
+                }
+                name.len += c.encode_utf8(&mut name.buf[name.len..]).len();
+            }
+        } else {
+            for &c in raw.iter().take(MAX_FILENAME) {
+                name.buf[name.len] = if c.is_ascii() { c } else { b'_' };
+                name.len += 1;
+            }
+        }
+
+        if let Some(semicolon) = name.as_bytes().iter().position(|&c| c == b';') {
+            name.len = semicolon;
+        }
+        if name.len > 1 && name.buf[name.len - 1] == b'.' {
+            name.len -= 1;
+        }
+        name
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// A little-endian u32 at the start of `raw` (both-endian fields store
+/// the little-endian copy first)
+fn le_u32(raw: &[u8]) -> u32 {
+    u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]])
+}
+
+// =============================================================================
+// Filesystem
+// =============================================================================
+
+/// Open file handle
+struct OpenFile {
+    record: Record,
+    /// Current position in file
+    position: u64,
+}
+
+/// An ISO 9660 disc in an ATAPI drive
+pub struct Iso9660Filesystem {
+    /// Index of the drive in `storage::ata`
+    device_index: usize,
+    mounted: bool,
+    /// Names come from a Joliet directory tree
+    joliet: bool,
+    root: Record,
+    /// Volume size in sectors, from the primary descriptor
+    volume_sectors: u32,
+    open_files: [Option<OpenFile>; MAX_OPEN_FILES],
+}
+
+impl Iso9660Filesystem {
+    /// Create a filesystem for the disc in ATAPI device `device_index`
+    /// (read by `mount`)
+    pub fn new(device_index: usize) -> Self {
+        const CLOSED: Option<OpenFile> = None;
+        Self {
+            device_index,
+            mounted: false,
+            joliet: false,
+            root: Record { extent: 0, size: 0, flags: FLAG_DIRECTORY, recorded: [0; 7], interleaved: false },
+            volume_sectors: 0,
+            open_files: [CLOSED; MAX_OPEN_FILES],
+        }
+    }
+
+    /// Read `count` sectors at `lba` into `buf`
+    fn read_sectors(&self, lba: u32, count: usize, buf: &mut [u8]) -> FsResult<()> {
+        let device = ata::get_device(self.device_index).ok_or(FsError::NotMounted)?;
+        if self.mounted && lba as u64 + count as u64 > self.volume_sectors as u64 {
+            return Err(FsError::InvalidFs);
+        }
+        ata::read_atapi_sectors(device, lba, count as u8, buf).map_err(|_| FsError::IoError)?;
+        Ok(())
+    }
+
+    /// Call `f` with each record in `dir` (without `.` and `..`) and its
+    /// decoded name until it returns false
+    fn walk(&self, dir: &Record, mut f: impl FnMut(&Record, &Name) -> bool) -> FsResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let sectors = (dir.size as usize).div_ceil(SECTOR_SIZE) as u32;
+        for lba in dir.extent..dir.extent + sectors {
+            self.read_sectors(lba, 1, &mut sector)?;
+
+            // Records never cross a sector; a zero length pads to the next
+            let mut offset = 0;
+            while offset + DR_NAME <= SECTOR_SIZE && sector[offset + DR_LENGTH] != 0 {
+                let len = sector[offset + DR_LENGTH] as usize;
+                let name_len = sector[offset + DR_NAME_LEN] as usize;
+                if len < DR_NAME + name_len || offset + len > SECTOR_SIZE {
+                    return Err(FsError::InvalidFs);
+                }
+                let raw = &sector[offset..offset + len];
+                offset += len;
+
+                let name = &raw[DR_NAME..DR_NAME + name_len];
+                let record = Record::parse(raw);
+                if name == [0] || name == [1] || record.flags & FLAG_ASSOCIATED != 0 {
+                    continue;
+                }
+                if !f(&record, &Name::decode(name, self.joliet)) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record at `path`; the root's for `/`
+    fn lookup(&self, path: &str) -> FsResult<Record> {
+        if !self.mounted {
+            return Err(FsError::NotMounted);
+        }
+
+        let mut found = self.root;
+        for part in path.split('/').filter(|part| !part.is_empty() && *part != ".") {
+            if !found.is_dir() {
+                return Err(FsError::NotDirectory);
+            }
+            let mut next = None;
+            self.walk(&found, |record, name| {
+                if name.as_bytes().eq_ignore_ascii_case(part.as_bytes()) {
+                    next = Some(*record);
+                }
+                next.is_none()
+            })?;
+            found = next.ok_or(FsError::NotFound)?;
+        }
+        Ok(found)
+    }
+
+    /// Read the volume descriptors and pick the directory tree to use
+    fn read_descriptors(&mut self) -> FsResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut primary = None;
+        let mut joliet = None;
+
+        for lba in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+            self.read_sectors(lba, 1, &mut sector)?;
+            if &sector[1..6] != STANDARD_ID {
+                return Err(FsError::InvalidFs);
+            }
+            match sector[0] {
+                DESCRIPTOR_PRIMARY if primary.is_none() => {
+                    let block_size = u16::from_le_bytes([sector[VD_BLOCK_SIZE], sector[VD_BLOCK_SIZE + 1]]);
+                    if block_size as usize != SECTOR_SIZE {
+                        return Err(FsError::InvalidFs);
+                    }
+                    self.volume_sectors = le_u32(&sector[VD_VOLUME_SPACE..]);
+                    primary = Some(Record::parse(&sector[VD_ROOT_RECORD..]));
+                }
+                // Joliet marks itself with a UCS-2 level 1, 2 or 3 escape
+                DESCRIPTOR_SUPPLEMENTARY if joliet.is_none()
+                    && sector[VD_ESCAPES..VD_ESCAPES + 2] == *b"%/"
+                    && matches!(sector[VD_ESCAPES + 2], b'@' | b'C' | b'E') =>
+                {
+                    joliet = Some(Record::parse(&sector[VD_ROOT_RECORD..]));
+                }
+                DESCRIPTOR_TERMINATOR => break,
+                _ => {}
+            }
+        }
+
+        let primary = primary.ok_or(FsError::InvalidFs)?;
+        self.joliet = joliet.is_some();
+        self.root = joliet.unwrap_or(primary);
+        if !self.root.is_dir() || self.root.extent >= self.volume_sectors {
+            return Err(FsError::InvalidFs);
+        }
+        Ok(())
+    }
+}
+
+/// Get open file by handle
+fn get_file(open_files: &mut [Option<OpenFile>], handle: u64) -> FsResult<&mut OpenFile> {
+    open_files
+        .get_mut(handle as usize)
+        .and_then(Option::as_mut)
+        .ok_or(FsError::IoError)
+}
+
+impl Filesystem for Iso9660Filesystem {
+    fn name(&self) -> &'static str {
+        "iso9660"
+    }
+
+    fn mount(&mut self) -> FsResult<()> {
+        let device = ata::get_device(self.device_index).ok_or(FsError::NotMounted)?;
+        if device.device_type != ata::DeviceType::Atapi {
+            return Err(FsError::InvalidFs);
+        }
+
+        self.mounted = false;
+        self.read_descriptors()?;
+        self.mounted = true;
+        Ok(())
+    }
+
+    fn unmount(&mut self) -> FsResult<()> {
+        if !self.mounted {
+            return Err(FsError::NotMounted);
+        }
+        for file in &mut self.open_files {
+            *file = None;
+        }
+        self.mounted = false;
+        Ok(())
+    }
+
+    fn open(&mut self, path: &str, flags: OpenFlags) -> FsResult<u64> {
+        if flags.write || flags.create || flags.truncate {
+            return Err(FsError::ReadOnly);
+        }
+        let record = self.lookup(path)?;
+        if record.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        if record.flags & FLAG_MULTI_EXTENT != 0 || record.interleaved {
+            return Err(FsError::InvalidFs);
+        }
+
+        let slot = self.open_files.iter().position(Option::is_none).ok_or(FsError::TooManyOpenFiles)?;
+        self.open_files[slot] = Some(OpenFile { record, position: 0 });
+        Ok(slot as u64)
+    }
+
+    fn close(&mut self, handle: u64) -> FsResult<()> {
+        get_file(&mut self.open_files, handle)?;
+        self.open_files[handle as usize] = None;
+        Ok(())
+    }
+
+    fn read(&mut self, handle: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let file = get_file(&mut self.open_files, handle)?;
+        let (record, start) = (file.record, file.position);
+        if start >= record.size as u64 {
+            return Ok(0);
+        }
+        let len = buf.len().min((record.size as u64 - start) as usize);
+
+        let mut done = 0;
+        let mut sector = [0u8; SECTOR_SIZE];
+        while done < len {
+            let position = start as usize + done;
+            let lba = record.extent + (position / SECTOR_SIZE) as u32;
+            let in_sector = position % SECTOR_SIZE;
+            let whole = (len - done) / SECTOR_SIZE;
+
+            if in_sector == 0 && whole > 0 {
+                // Whole sectors go straight into the caller's buffer
+                let count = whole.min(MAX_SECTORS_PER_READ);
+                self.read_sectors(lba, count, &mut buf[done..done + count * SECTOR_SIZE])?;
+                done += count * SECTOR_SIZE;
+            } else {
+                self.read_sectors(lba, 1, &mut sector)?;
+                let part = (SECTOR_SIZE - in_sector).min(len - done);
+                buf[done..done + part].copy_from_slice(&sector[in_sector..in_sector + part]);
+                done += part;
+            }
+        }
+
+        get_file(&mut self.open_files, handle)?.position += done as u64;
+        Ok(done)
+    }
+
+    fn write(&mut self, handle: u64, _buf: &[u8]) -> FsResult<usize> {
+        get_file(&mut self.open_files, handle)?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn seek(&mut self, handle: u64, offset: i64, whence: SeekFrom) -> FsResult<u64> {
+        let file = get_file(&mut self.open_files, handle)?;
+
+        let base = match whence {
+            SeekFrom::Start => 0,
+            SeekFrom::Current => file.position,
+            SeekFrom::End => file.record.size as u64,
+        };
+        file.position = if offset >= 0 {
+            base + offset as u64
+        } else {
+            base.saturating_sub(offset.unsigned_abs())
+        };
+        Ok(file.position)
+    }
+
+    fn stat(&self, path: &str) -> FsResult<Metadata> {
+        let record = self.lookup(path)?;
+        let mut permissions = if record.is_dir() { Permissions::default_dir() } else { Permissions::default_file() };
+        permissions.owner.write = false;
+        let modified = record.modified();
+        Ok(Metadata {
+            file_type: record.file_type(),
+            size: if record.is_dir() { 0 } else { record.size as u64 },
+            permissions,
+            created: modified,
+            modified,
+            accessed: modified,
+        })
+    }
+
+    fn readdir(&mut self, path: &str, offset: usize) -> FsResult<ReadDir> {
+        let dir = self.lookup(path)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+
+        let mut listing = ReadDir::empty();
+        let mut index = 0;
+        self.walk(&dir, |record, name| {
+            index += 1;
+            if index <= offset {
+                return true;
+            }
+            let mut listed = DirEntry {
+                name: [0; MAX_FILENAME],
+                name_len: name.len,
+                file_type: record.file_type(),
+                inode: record.extent as u64,
+                hidden: record.flags & FLAG_HIDDEN != 0,
+            };
+            listed.name[..name.len].copy_from_slice(name.as_bytes());
+            listing.add(listed)
+        })?;
+        Ok(listing)
+    }
+
+    fn mkdir(&mut self, _path: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn remove(&mut self, _path: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rmdir(&mut self, _path: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _from: &str, _to: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+}
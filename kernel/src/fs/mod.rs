@@ -1,9 +1,13 @@
 //! Filesystem Layer
 //!
 //! Rustacean OS filesystem support with Plan 9-style "everything is a file" philosophy.
-//! Primary filesystem is exFAT for USB compatibility.
+//! FAT12/16/32 and exFAT volumes and ISO 9660 discs implement `Filesystem`;
+//! `vfs` mounts them into one tree of `/`-separated paths.
 
 pub mod exfat;
+pub mod fat32;
+pub mod iso9660;
+pub mod vfs;
 
 /// Maximum path length
 pub const MAX_PATH: usize = 256;
@@ -89,6 +93,12 @@ impl OpenFlags {
         self.truncate = true;
         self
     }
+
+    /// Write at the end of the file
+    pub const fn with_append(mut self) -> Self {
+        self.append = true;
+        self
+    }
 }
 
 /// File permissions (Unix-style)
@@ -148,15 +158,17 @@ pub struct Metadata {
 }
 
 /// Directory entry
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DirEntry {
-    /// Entry name
+    /// Entry name (UTF-8)
     pub name: [u8; MAX_FILENAME],
     pub name_len: usize,
     /// File type
     pub file_type: FileType,
     /// Inode number (or cluster for exFAT)
     pub inode: u64,
+    /// Marked hidden or system: listings for the user leave it out
+    pub hidden: bool,
 }
 
 impl DirEntry {
@@ -164,6 +176,16 @@ impl DirEntry {
     pub fn name(&self) -> &str {
         core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("???")
     }
+
+    /// True for a .gb or .gbc file
+    pub fn is_rom(&self) -> bool {
+        let name = self.name().as_bytes();
+        let ext = match name.iter().rposition(|&c| c == b'.') {
+            Some(dot) => &name[dot + 1..],
+            None => return false,
+        };
+        self.file_type == FileType::Regular && (ext.eq_ignore_ascii_case(b"gb") || ext.eq_ignore_ascii_case(b"gbc"))
+    }
 }
 
 /// Filesystem error
@@ -195,6 +217,26 @@ pub enum FsError {
     ReadOnly,
 }
 
+impl FsError {
+    /// Short description for messages on screen
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsError::NotFound => "Not found",
+            FsError::PermissionDenied => "Permission denied",
+            FsError::AlreadyExists => "Already exists",
+            FsError::NotDirectory => "Not a directory",
+            FsError::IsDirectory => "Is a directory",
+            FsError::InvalidPath => "Invalid path",
+            FsError::NoSpace => "Disk full",
+            FsError::TooManyOpenFiles => "Too many open files",
+            FsError::IoError => "Disk error",
+            FsError::NotMounted => "No disk",
+            FsError::InvalidFs => "Bad filesystem",
+            FsError::ReadOnly => "Read-only disk",
+        }
+    }
+}
+
 /// Filesystem result type
 pub type FsResult<T> = Result<T, FsError>;
 
@@ -229,8 +271,9 @@ pub trait Filesystem {
     /// Get file metadata
     fn stat(&self, path: &str) -> FsResult<Metadata>;
     
-    /// Read directory entries
-    fn readdir(&mut self, path: &str) -> FsResult<ReadDir>;
+    /// Read directory entries, from the `offset`th on (as many as a
+    /// `ReadDir` holds; a full one means there may be more)
+    fn readdir(&mut self, path: &str, offset: usize) -> FsResult<ReadDir>;
     
     /// Create a directory
     fn mkdir(&mut self, path: &str) -> FsResult<()>;
@@ -245,6 +288,30 @@ pub trait Filesystem {
     fn rename(&mut self, from: &str, to: &str) -> FsResult<()>;
 }
 
+/// A FAT or exFAT timestamp as seconds since 1970
+///
+/// The date is in the high half (year from 1980, month, day), the time in
+/// the low half (hours, minutes, seconds / 2), both local time with no
+/// zone; `ten_ms` adds up to 1.99 seconds.
+pub fn dos_time_to_unix(timestamp: u32, ten_ms: u8) -> u64 {
+    let seconds = (timestamp & 0x1F) as u64 * 2 + ten_ms as u64 / 100;
+    let minutes = (timestamp >> 5 & 0x3F) as u64;
+    let hours = (timestamp >> 11 & 0x1F) as u64;
+    let day = (timestamp >> 16 & 0x1F).max(1);
+    let month = (timestamp >> 21 & 0x0F).clamp(1, 12);
+    let year = 1980 + (timestamp >> 25) as i64;
+
+    // Days since 1970-01-01 (civil calendar, March-based years)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) as i64 + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    days as u64 * 86400 + hours * 3600 + minutes * 60 + seconds
+}
+
 /// Seek origin
 #[derive(Debug, Clone, Copy)]
 pub enum SeekFrom {
@@ -256,10 +323,13 @@ pub enum SeekFrom {
     End,
 }
 
+/// Entries in one `ReadDir`
+pub const READDIR_ENTRIES: usize = 64;
+
 /// Directory iterator
 pub struct ReadDir {
     /// Entries (fixed size for no_std)
-    entries: [Option<DirEntry>; 64],
+    entries: [Option<DirEntry>; READDIR_ENTRIES],
     /// Number of entries
     count: usize,
     /// Current index
@@ -271,15 +341,25 @@ impl ReadDir {
     pub const fn empty() -> Self {
         const NONE: Option<DirEntry> = None;
         Self {
-            entries: [NONE; 64],
+            entries: [NONE; READDIR_ENTRIES],
             count: 0,
             index: 0,
         }
     }
     
+    /// Entries listed
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// True if no entries were listed
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
     /// Add an entry
     pub fn add(&mut self, entry: DirEntry) -> bool {
-        if self.count < 64 {
+        if self.count < READDIR_ENTRIES {
             self.entries[self.count] = Some(entry);
             self.count += 1;
             true
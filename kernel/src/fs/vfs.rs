@@ -0,0 +1,451 @@
+//! Virtual Filesystem
+//!
+//! Mounts volumes into one tree and hands out file descriptors that work
+//! the same whatever the filesystem underneath.
+//!
+//! # Paths
+//!
+//! Paths are `/`-separated and start at the top of the tree (a missing
+//! leading `/` is assumed). `.` and empty parts are dropped and `..` goes
+//! up a level, never above `/`. The mount with the longest matching mount
+//! point gets the rest of the path.
+//!
+//! A volume mounted in a directory is listed there as a subdirectory, so
+//! `readdir("/")` shows `/disk0p2` beside the root volume's own entries.
+//!
+//! # Usage
+//!
+//! ```ignore
+//! vfs::mount_all();
+//! let read = vfs::read_file("/ROMS/Tetris.gb", &mut buf)?;
+//! vfs::write_file("/ROMS/Tetris.sav", &ram)?;
+//! ```
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec::Vec;
+use core::ptr::addr_of_mut;
+use crate::storage::ata;
+use crate::storage::fat32::Fat32;
+use crate::storage::partition::{self, BlockDevice, Partition};
+use super::{
+    Filesystem, Metadata, FileType, OpenFlags, SeekFrom,
+    FsResult, FsError, DirEntry, ReadDir, MAX_FILENAME, MAX_PATH, READDIR_ENTRIES,
+};
+use super::exfat::ExfatFilesystem;
+use super::fat32::Fat32Filesystem;
+use super::iso9660::Iso9660Filesystem;
+
+// =============================================================================
+// Constants
+// =============================================================================
+
+/// Mount table size
+const MAX_MOUNTS: usize = 8;
+
+/// Open files across all mounts
+const MAX_OPEN_FILES: usize = 32;
+
+// =============================================================================
+// Paths
+// =============================================================================
+
+/// An absolute path without `.`, `..`, empty parts or a trailing `/`
+/// (the top of the tree is `/`)
+#[derive(Clone, Copy)]
+pub struct Path {
+    buf: [u8; MAX_PATH],
+    len: usize,
+}
+
+impl Path {
+    /// Clean up `path` (see the module docs); fails if the result is
+    /// longer than `MAX_PATH`
+    pub fn new(path: &str) -> FsResult<Path> {
+        let mut out = Path { buf: [0; MAX_PATH], len: 0 };
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    let parent = out.as_bytes().iter().rposition(|&c| c == b'/').unwrap_or(0);
+                    out.len = parent;
+                }
+                _ => {
+                    if out.len + 1 + part.len() > MAX_PATH {
+                        return Err(FsError::InvalidPath);
+                    }
+                    out.buf[out.len] = b'/';
+                    out.buf[out.len + 1..out.len + 1 + part.len()].copy_from_slice(part.as_bytes());
+                    out.len += 1 + part.len();
+                }
+            }
+        }
+        if out.len == 0 {
+            out.buf[0] = b'/';
+            out.len = 1;
+        }
+        Ok(out)
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Built from whole `&str` parts, cut only at `/`
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("/")
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Directory holding the path and its last part (`("/", "")` for `/`)
+    fn split_last(&self) -> (&str, &str) {
+        match self.as_str().rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some(split) => split,
+            None => ("/", ""),
+        }
+    }
+
+    /// The rest of the path below `mount_point`, if it is inside it
+    fn below<'a>(&'a self, mount_point: &Path) -> Option<&'a str> {
+        let path = self.as_str();
+        if mount_point.as_str() == "/" {
+            return Some(path);
+        }
+        match path.strip_prefix(mount_point.as_str()) {
+            Some("") => Some("/"),
+            Some(rest) if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+}
+
+// =============================================================================
+// Mount Table
+// =============================================================================
+
+struct Mount {
+    point: Path,
+    fs: &'static mut dyn Filesystem,
+}
+
+/// An open file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fd(usize);
+
+/// Where a descriptor's file is: mount table slot and the filesystem's
+/// own handle
+#[derive(Clone, Copy)]
+struct OpenFile {
+    mount: usize,
+    handle: u64,
+}
+
+const UNMOUNTED: Option<Mount> = None;
+static mut MOUNTS: [Option<Mount>; MAX_MOUNTS] = [UNMOUNTED; MAX_MOUNTS];
+static mut OPEN_FILES: [Option<OpenFile>; MAX_OPEN_FILES] = [None; MAX_OPEN_FILES];
+
+fn mounts() -> &'static mut [Option<Mount>; MAX_MOUNTS] {
+    unsafe { &mut *addr_of_mut!(MOUNTS) }
+}
+
+fn open_files() -> &'static mut [Option<OpenFile>; MAX_OPEN_FILES] {
+    unsafe { &mut *addr_of_mut!(OPEN_FILES) }
+}
+
+/// Mount `fs` at `point` (`/` or a directory path), mounting the
+/// filesystem itself first
+pub fn mount(point: &str, fs: &'static mut dyn Filesystem) -> FsResult<()> {
+    let point = Path::new(point)?;
+    let table = mounts();
+    if table.iter().flatten().any(|m| m.point.as_str() == point.as_str()) {
+        return Err(FsError::AlreadyExists);
+    }
+    let slot = table.iter().position(Option::is_none).ok_or(FsError::NoSpace)?;
+
+    fs.mount()?;
+    table[slot] = Some(Mount { point, fs });
+    Ok(())
+}
+
+/// Unmount the filesystem at `point`, closing its files
+pub fn unmount(point: &str) -> FsResult<()> {
+    let point = Path::new(point)?;
+    let table = mounts();
+    let slot = table
+        .iter()
+        .position(|m| m.as_ref().is_some_and(|m| m.point.as_str() == point.as_str()))
+        .ok_or(FsError::NotMounted)?;
+
+    for file in open_files().iter_mut() {
+        if file.is_some_and(|f| f.mount == slot) {
+            *file = None;
+        }
+    }
+    let mount = table[slot].take().ok_or(FsError::NotMounted)?;
+    mount.fs.unmount()
+}
+
+/// Mount `volume` at `point` as FAT (12, 16 or 32) or else exFAT;
+/// returns the filesystem's name
+pub fn mount_volume(volume: BlockDevice, point: &str) -> FsResult<&'static str> {
+    let mut state = Box::new(Fat32::new());
+    if state.mount(volume).is_ok() {
+        let name = state.fat_type().name();
+        let fat = Box::leak(Box::new(Fat32Filesystem::new(state, volume)));
+        return mount(point, fat).map(|_| name);
+    }
+
+    let exfat = Box::leak(Box::new(ExfatFilesystem::new()));
+    exfat.set_volume(volume);
+    mount(point, exfat).map(|_| "exfat")
+}
+
+/// Mount every FAT or exFAT volume on the ATA disks, the first at `/`
+/// and the others at `/diskNpM` (disk N, partition M; `/diskN` for a
+/// disk without a partition table), then the ISO 9660 disc in each ATAPI
+/// drive at `/cdN` (at `/` when no disk volume mounted); returns how
+/// many were mounted
+pub fn mount_all() -> usize {
+    let mut mounted = 0;
+    for partition in partition::scan_all().iter().filter(|p| p.kind.is_supported()) {
+        let point = if is_mounted("/") { mount_point_for(partition) } else { "/".into() };
+        if mount_volume(partition.volume, &point).is_ok() {
+            mounted += 1;
+        }
+    }
+
+    for index in 0..ata::MAX_DEVICES {
+        if !ata::get_device(index).is_some_and(|d| d.device_type == ata::DeviceType::Atapi) {
+            continue;
+        }
+        let point = if is_mounted("/") { format!("/cd{}", index) } else { "/".into() };
+        let iso = Box::leak(Box::new(Iso9660Filesystem::new(index)));
+        if mount(&point, iso).is_ok() {
+            mounted += 1;
+        }
+    }
+    mounted
+}
+
+/// Where `mount_all` puts a volume that isn't the root
+fn mount_point_for(partition: &Partition) -> alloc::string::String {
+    let disk = partition.volume.device_index();
+    match partition.number {
+        0 => format!("/disk{}", disk),
+        number => format!("/disk{}p{}", disk, number),
+    }
+}
+
+/// True if a filesystem is mounted at `point`
+pub fn is_mounted(point: &str) -> bool {
+    Path::new(point).is_ok_and(|point| mounts().iter().flatten().any(|m| m.point.as_str() == point.as_str()))
+}
+
+/// Call `f` with the filesystem holding `path` and the path within it
+fn with_fs<T>(path: &str, f: impl FnOnce(usize, &mut dyn Filesystem, &str) -> FsResult<T>) -> FsResult<T> {
+    let path = Path::new(path)?;
+    let (slot, mount) = mounts()
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(slot, m)| Some((slot, m.as_mut()?)))
+        .filter(|(_, m)| path.below(&m.point).is_some())
+        .max_by_key(|(_, m)| m.point.len)
+        .ok_or(FsError::NotMounted)?;
+    let rest = path.below(&mount.point).ok_or(FsError::NotMounted)?;
+    f(slot, &mut *mount.fs, rest)
+}
+
+/// The filesystem and handle behind `fd`
+fn with_file<T>(fd: Fd, f: impl FnOnce(&mut dyn Filesystem, u64) -> FsResult<T>) -> FsResult<T> {
+    let file = open_files().get(fd.0).copied().flatten().ok_or(FsError::IoError)?;
+    let mount = mounts()[file.mount].as_mut().ok_or(FsError::NotMounted)?;
+    f(&mut *mount.fs, file.handle)
+}
+
+// =============================================================================
+// Files
+// =============================================================================
+
+/// Open the file at `path`
+pub fn open(path: &str, flags: OpenFlags) -> FsResult<Fd> {
+    let table = open_files();
+    let slot = table.iter().position(Option::is_none).ok_or(FsError::TooManyOpenFiles)?;
+    let (mount, handle) = with_fs(path, |mount, fs, rest| Ok((mount, fs.open(rest, flags)?)))?;
+    table[slot] = Some(OpenFile { mount, handle });
+    Ok(Fd(slot))
+}
+
+/// Close a file
+pub fn close(fd: Fd) -> FsResult<()> {
+    let result = with_file(fd, |fs, handle| fs.close(handle));
+    if let Some(file) = open_files().get_mut(fd.0) {
+        *file = None;
+    }
+    result
+}
+
+/// Read from the file position; returns the bytes read (0 at the end)
+pub fn read(fd: Fd, buf: &mut [u8]) -> FsResult<usize> {
+    with_file(fd, |fs, handle| fs.read(handle, buf))
+}
+
+/// Write at the file position (the end, if opened to append)
+pub fn write(fd: Fd, buf: &[u8]) -> FsResult<usize> {
+    with_file(fd, |fs, handle| fs.write(handle, buf))
+}
+
+/// Move the file position; returns the new one
+pub fn seek(fd: Fd, offset: i64, whence: SeekFrom) -> FsResult<u64> {
+    with_file(fd, |fs, handle| fs.seek(handle, offset, whence))
+}
+
+/// Read the file at `path` into `buf`, as much as fits; returns the
+/// bytes read
+pub fn read_file(path: &str, buf: &mut [u8]) -> FsResult<usize> {
+    let fd = open(path, OpenFlags::read_only())?;
+    let mut done = 0;
+    let result = loop {
+        match read(fd, &mut buf[done..]) {
+            Ok(0) => break Ok(done),
+            Ok(n) => done += n,
+            Err(e) => break Err(e),
+        }
+        if done == buf.len() {
+            break Ok(done);
+        }
+    };
+    close(fd)?;
+    result
+}
+
+/// Make the file at `path` hold `data`, creating it if needed
+///
+/// A file at least as long is written over in place and only cut down
+/// after, so rewriting one of the same size (a save) allocates nothing.
+pub fn write_file(path: &str, data: &[u8]) -> FsResult<()> {
+    let shrinks = stat(path).is_ok_and(|m| m.size > data.len() as u64);
+    let mut flags = OpenFlags::write_only().with_create();
+    if shrinks {
+        flags = flags.with_truncate();
+    }
+
+    let fd = open(path, flags)?;
+    let mut done = 0;
+    let result = loop {
+        if done == data.len() {
+            break Ok(());
+        }
+        match write(fd, &data[done..]) {
+            Ok(0) => break Err(FsError::NoSpace),
+            Ok(n) => done += n,
+            Err(e) => break Err(e),
+        }
+    };
+    close(fd)?;
+    result
+}
+
+// =============================================================================
+// Directories
+// =============================================================================
+
+/// Metadata of the file or directory at `path`
+pub fn stat(path: &str) -> FsResult<Metadata> {
+    with_fs(path, |_, fs, rest| fs.stat(rest))
+}
+
+/// True if there is a file or directory at `path`
+pub fn exists(path: &str) -> bool {
+    stat(path).is_ok()
+}
+
+/// True if `path` is a directory
+pub fn is_dir(path: &str) -> bool {
+    stat(path).is_ok_and(|m| m.file_type == FileType::Directory)
+}
+
+/// Entries of the directory at `path`, from the `offset`th on (up to
+/// `READDIR_ENTRIES`)
+///
+/// Volumes mounted directly in the directory come first.
+pub fn readdir(path: &str, offset: usize) -> FsResult<ReadDir> {
+    let path = Path::new(path)?;
+    let points = mounted_in(&path);
+
+    let mut listing = ReadDir::empty();
+    for entry in points.iter().skip(offset) {
+        listing.add(entry.clone());
+    }
+    let own = with_fs(path.as_str(), |_, fs, rest| fs.readdir(rest, offset.saturating_sub(points.len())))?;
+    for entry in own {
+        if !listing.add(entry) {
+            break;
+        }
+    }
+    Ok(listing)
+}
+
+/// Directory entries for the mount points directly in `dir`
+fn mounted_in(dir: &Path) -> Vec<DirEntry> {
+    mounts()
+        .iter()
+        .flatten()
+        .filter_map(|m| {
+            let (parent, name) = m.point.split_last();
+            (parent == dir.as_str() && !name.is_empty() && name.len() <= MAX_FILENAME).then(|| {
+                let mut entry = DirEntry {
+                    name: [0; MAX_FILENAME],
+                    name_len: name.len(),
+                    file_type: FileType::Directory,
+                    inode: 0,
+                    hidden: false,
+                };
+                entry.name[..name.len()].copy_from_slice(name.as_bytes());
+                entry
+            })
+        })
+        .collect()
+}
+
+/// Call `f` with every entry of the directory at `path`, in directory order
+pub fn for_each_entry(path: &str, mut f: impl FnMut(DirEntry)) -> FsResult<()> {
+    let mut offset = 0;
+    loop {
+        let listing = readdir(path, offset)?;
+        let count = listing.len();
+        listing.for_each(&mut f);
+        if count < READDIR_ENTRIES {
+            return Ok(());
+        }
+        offset += count;
+    }
+}
+
+/// Create a directory
+pub fn mkdir(path: &str) -> FsResult<()> {
+    with_fs(path, |_, fs, rest| fs.mkdir(rest))
+}
+
+/// Delete a file
+pub fn remove(path: &str) -> FsResult<()> {
+    with_fs(path, |_, fs, rest| fs.remove(rest))
+}
+
+/// Delete an empty directory
+pub fn rmdir(path: &str) -> FsResult<()> {
+    with_fs(path, |_, fs, rest| fs.rmdir(rest))
+}
+
+/// Rename or move a file or directory (within one mount)
+pub fn rename(from: &str, to: &str) -> FsResult<()> {
+    let to_path = Path::new(to)?;
+    let (to_mount, to_rest) = with_fs(to_path.as_str(), |mount, _, rest| Ok((mount, Path::new(rest)?)))?;
+    with_fs(from, |mount, fs, rest| {
+        if mount != to_mount {
+            return Err(FsError::InvalidPath);
+        }
+        fs.rename(rest, to_rest.as_str())
+    })
+}
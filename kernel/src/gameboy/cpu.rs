@@ -6,8 +6,10 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use super::gbmode::GbMode;
+use super::jit::Jit;
 use super::mbc;
-use super::mmu::MMU;
+use super::mmu::{MmuState, MMU};
 use super::register::{CpuFlag, Registers};
 use super::register::CpuFlag::{C, H, N, Z};
 use super::StrResult;
@@ -21,6 +23,34 @@ pub struct CPU {
     ime: bool,      // Interrupt master enable
     setdi: u32,     // Delayed DI
     setei: u32,     // Delayed EI
+    poll_jump: u8,  // Length of a tight loop just closed by a taken JR (0 = none)
+    jit: Option<Box<Jit>>,
+    jit_enabled: bool,
+}
+
+/// Saved CPU state (see `CPU::save_state`)
+pub struct CpuState {
+    reg: Registers,
+    halted: bool,
+    halt_bug: bool,
+    ime: bool,
+    setdi: u32,
+    setei: u32,
+    mmu: MmuState,
+}
+
+impl CpuState {
+    pub fn new() -> CpuState {
+        CpuState {
+            reg: Registers::new(GbMode::Classic),
+            halted: false,
+            halt_bug: false,
+            ime: true,
+            setdi: 0,
+            setei: 0,
+            mmu: MmuState::new(),
+        }
+    }
 }
 
 impl CPU {
@@ -35,6 +65,9 @@ impl CPU {
             ime: true,
             setdi: 0,
             setei: 0,
+            poll_jump: 0,
+            jit: None,
+            jit_enabled: false,
             mmu,
         })
     }
@@ -50,10 +83,53 @@ impl CPU {
             ime: true,
             setdi: 0,
             setei: 0,
+            poll_jump: 0,
+            jit: None,
+            jit_enabled: false,
             mmu,
         })
     }
 
+    /// Copy all emulation state into `s`
+    pub fn save_state(&self, s: &mut CpuState) {
+        s.reg = self.reg.clone();
+        s.halted = self.halted;
+        s.halt_bug = self.halt_bug;
+        s.ime = self.ime;
+        s.setdi = self.setdi;
+        s.setei = self.setei;
+        self.mmu.save_state(&mut s.mmu);
+    }
+
+    /// Restore state previously captured with `save_state`
+    pub fn load_state(&mut self, s: &CpuState) {
+        self.reg = s.reg.clone();
+        self.halted = s.halted;
+        self.halt_bug = s.halt_bug;
+        self.ime = s.ime;
+        self.setdi = s.setdi;
+        self.setei = s.setei;
+        self.mmu.load_state(&s.mmu);
+        // RAM may now hold different code than what was compiled from it
+        if let Some(jit) = self.jit.as_mut() {
+            jit.flush_ram(&mut self.mmu);
+        }
+    }
+
+    /// Enable or disable the dynamic recompiler
+    ///
+    /// The code cache is allocated on first use and kept while disabled.
+    pub fn set_jit(&mut self, enabled: bool) {
+        if enabled && self.jit.is_none() {
+            self.jit = Some(Box::new(Jit::new()));
+        }
+        self.jit_enabled = enabled;
+    }
+
+    pub fn jit_enabled(&self) -> bool {
+        self.jit_enabled
+    }
+
     /// Execute one instruction cycle
     pub fn do_cycle(&mut self) -> u32 {
         self.update_ime();
@@ -63,14 +139,94 @@ impl CPU {
         }
 
         let ticks = if self.halted {
-            4
+            self.halt_ticks()
+        } else if let Some(ticks) = self.run_jit() {
+            ticks
         } else {
-            self.execute()
+            let ticks = self.execute();
+            ticks + self.poll_loop_skip(ticks)
         };
 
         self.mmu.do_cycle(ticks)
     }
 
+    /// Run a recompiled block at PC, if there is one
+    ///
+    /// Pending EI/DI and the HALT bug act on the very next instruction, so
+    /// those are always left to the interpreter.
+    fn run_jit(&mut self) -> Option<u32> {
+        if !self.jit_enabled || self.halt_bug || self.setei != 0 || self.setdi != 0 {
+            return None;
+        }
+        self.jit.as_mut()?.run(&mut self.reg, &mut self.mmu)
+    }
+
+    /// Cycles to stay halted: jump straight to the next hardware event
+    ///
+    /// Nothing can wake the CPU before then, so this is equivalent to
+    /// stepping 4 cycles at a time, just without the thousands of calls.
+    fn halt_ticks(&self) -> u32 {
+        let cycles = self.mmu.next_event().cycles;
+        // Keep the 4-cycle granularity of single stepping
+        ((cycles + 3) & !3).max(4)
+    }
+
+    /// Extra cycles to burn after a taken JR that closed a polling loop
+    ///
+    /// The value being polled (LY or STAT) can only change at the next PPU
+    /// event, so every iteration until then is identical. Skips a whole
+    /// number of iterations so the loop exits on the same cycle it would
+    /// have when interpreted.
+    fn poll_loop_skip(&mut self, jr_ticks: u32) -> u32 {
+        let back = core::mem::take(&mut self.poll_jump);
+        if back == 0 || self.setdi != 0 || self.setei != 0 {
+            return 0;
+        }
+        // A pending interrupt must be serviced on the next cycle
+        if self.ime && (self.mmu.inte & self.mmu.intf & 0x1F) != 0 {
+            return 0;
+        }
+
+        let iteration = match self.poll_loop_cycles(back) {
+            Some(cycles) => cycles,
+            None => return 0,
+        };
+        let window = self.mmu.next_event().cycles.saturating_sub(jr_ticks);
+        (window / iteration) * iteration
+    }
+
+    /// Recognise a side-effect-free LY/STAT polling loop starting at PC
+    ///
+    /// Matches `<read> [cp n | and n] jr cc,<start>`, where `<read>` is
+    /// `ldh a,(n)`, `ld a,(nn)`, `ld a,(hl)` or `bit b,(hl)` addressing
+    /// STAT (0xFF41) or LY (0xFF44). Returns the cycles of one iteration.
+    fn poll_loop_cycles(&self, back: u8) -> Option<u32> {
+        let pc = self.reg.pc;
+        let op = |offset: u16| self.mmu.peek(pc.wrapping_add(offset));
+        let is_lcd_status = |addr: u16| addr == 0xFF41 || addr == 0xFF44;
+        let hl = self.reg.hl();
+
+        let (read_len, read_cycles, can_compare) = match op(0) {
+            0xF0 if is_lcd_status(0xFF00 | op(1) as u16) => (2, 12, true),
+            0xFA if is_lcd_status(u16::from_le_bytes([op(1), op(2)])) => (3, 16, true),
+            0x7E if is_lcd_status(hl) => (1, 8, true),
+            0xCB if is_lcd_status(hl) && op(1) & 0xC7 == 0x46 => (2, 16, false),
+            _ => return None,
+        };
+        let (cmp_len, cmp_cycles) = match op(read_len) {
+            0xFE | 0xE6 if can_compare => (2, 8),
+            _ => (0, 0),
+        };
+
+        // The loop must end in a conditional JR straight back to PC
+        let jr = read_len + cmp_len;
+        if !matches!(op(jr), 0x20 | 0x28 | 0x30 | 0x38) || back as u16 != jr + 2 {
+            return None;
+        }
+
+        Some(read_cycles + cmp_cycles + 12)
+    }
+
     fn update_ime(&mut self) {
         self.setdi = match self.setdi {
             2 => 1,
@@ -703,5 +859,10 @@ impl CPU {
     fn cpu_jr(&mut self) {
         let n = self.fetchbyte() as i8;
         self.reg.pc = ((self.reg.pc as i32) + (n as i32)) as u16;
+
+        // Short backward jumps may close a polling loop (see poll_loop_skip)
+        if (-7..=-3).contains(&n) {
+            self.poll_jump = n.unsigned_abs();
+        }
     }
 }
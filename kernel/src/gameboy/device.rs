@@ -9,7 +9,9 @@ use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
 use super::mmu::MMU;
-use super::cpu::CPU;
+use super::cpu::{CpuState, CPU};
+use super::frame_sink::FrameSink;
+use super::gpu::SCREEN_H;
 use super::gbmode::GbMode;
 use super::keypad::KeypadKey;
 use super::mbc;
@@ -20,6 +22,15 @@ pub struct Device {
     cpu: CPU,
 }
 
+/// In-memory snapshot of a `Device`
+///
+/// Holds every piece of mutable emulator state except the ROM and the
+/// rendered framebuffers. Create one with `Device::new_snapshot` and reuse
+/// it; saving and loading only copy into the existing buffers.
+pub struct Snapshot {
+    cpu: CpuState,
+}
+
 impl Device {
     pub fn mmu(&self) -> &MMU {
         &self.cpu.mmu
@@ -37,6 +48,38 @@ impl Device {
         CPU::new_cgb(cart).map(|cpu| Device { cpu })
     }
 
+    /// Allocate an empty snapshot for use with `save_snapshot`
+    pub fn new_snapshot(&self) -> Snapshot {
+        Snapshot { cpu: CpuState::new() }
+    }
+
+    /// Capture the current emulator state into `snapshot`
+    pub fn save_snapshot(&self, snapshot: &mut Snapshot) {
+        self.cpu.save_state(&mut snapshot.cpu);
+    }
+
+    /// Roll the emulator back to a previously saved `snapshot`
+    pub fn load_snapshot(&mut self, snapshot: &Snapshot) {
+        self.cpu.load_state(&snapshot.cpu);
+    }
+
+    /// Enable or disable scanline rendering
+    ///
+    /// Emulation continues normally while disabled; only the framebuffers
+    /// stop being written. Used for frames that will never be displayed.
+    pub fn set_rendering(&mut self, enabled: bool) {
+        self.cpu.mmu.gpu.skip_render = !enabled;
+    }
+
+    /// Switch between the dynamic recompiler and the interpreter
+    pub fn set_jit(&mut self, enabled: bool) {
+        self.cpu.set_jit(enabled);
+    }
+
+    pub fn jit_enabled(&self) -> bool {
+        self.cpu.jit_enabled()
+    }
+
     /// Run one CPU cycle, returns number of cycles executed
     pub fn do_cycle(&mut self) -> u32 {
         self.cpu.do_cycle()
@@ -49,14 +92,9 @@ impl Device {
         result
     }
 
-    /// Get GPU framebuffer data (160x144x3 RGB bytes)
-    pub fn get_gpu_data(&self) -> &[u8] {
-        self.cpu.mmu.gpu.data.as_ref()
-    }
-
-    /// Get GPU palette index buffer for VGA blitting
-    pub fn get_pal_data(&self) -> &[u8] {
-        self.cpu.mmu.gpu.get_pal_data()
+    /// Send rendered scanlines to `sink`; returns the previous sink
+    pub fn set_frame_sink(&mut self, sink: Box<dyn FrameSink>) -> Box<dyn FrameSink> {
+        self.cpu.mmu.gpu.set_frame_sink(sink)
     }
 
     /// Get GBC background palettes for VGA sync
@@ -64,6 +102,17 @@ impl Device {
         self.cpu.mmu.gpu.get_cbgpal()
     }
 
+    /// Get the GBC palettes in effect on each line of the last frame
+    pub fn line_palettes(&self) -> &[[u16; 64]; SCREEN_H] {
+        self.cpu.mmu.gpu.line_palettes()
+    }
+
+    /// Copy the last frame as RGB555 into `out` (160x144, row by row),
+    /// coloring DMG shades with `dmg` (see `GPU::frame_rgb555`)
+    pub fn frame_rgb555(&self, out: &mut [u16], dmg: &[[u16; 4]; 3]) {
+        self.cpu.mmu.gpu.frame_rgb555(out, dmg)
+    }
+
     /// Get GBC sprite palettes for VGA sync
     pub fn get_csprit(&self) -> &[[[u8; 3]; 4]; 8] {
         self.cpu.mmu.gpu.get_csprit()
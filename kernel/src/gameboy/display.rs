@@ -3,6 +3,10 @@
 //! Blits GameBoy's 160x144 screen to Rustacean OS's VESA framebuffer.
 //! Handles the BGRA pixel format used by Rustacean OS.
 //!
+//! Also provides the frame sinks the GPU renders into: `Mode13hSink` for
+//! the Mode 13h back buffer, `VbeSink` for the linear framebuffer and
+//! `FilterSink`, which runs the post-processing filters on the way there.
+//!
 //! # Pixel Format
 //!
 //! Rustacean OS framebuffer uses BGRA format (32-bit):
@@ -22,7 +26,18 @@
 //! 4x scale: 640x576
 //! Centered on 800x600: offset (80, 12)
 
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use super::frame_sink::{FrameSink, RgbLine};
 use super::gpu::{SCREEN_H, SCREEN_W};
+use crate::graphics::double_buffer;
+use crate::graphics::dmg_palettes;
+use crate::graphics::filters;
+use crate::graphics::vga_palette;
+use crate::graphics::vga_mode13h::SCREEN_WIDTH;
+use crate::gui::layout::{GB_X, GB_Y};
 
 /// Scale factor for display
 pub const SCALE: usize = 4;
@@ -35,6 +50,21 @@ pub const SCALED_H: usize = SCREEN_H * SCALE; // 576
 pub const OFFSET_X: usize = (800 - SCALED_W) / 2; // 80
 pub const OFFSET_Y: usize = (600 - SCALED_H) / 2; // 12
 
+/// Position and integer scale of the GameBoy screen on a framebuffer
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    /// Top-left corner in pixels
+    pub x: usize,
+    pub y: usize,
+    /// Integer scale factor
+    pub scale: usize,
+}
+
+impl Placement {
+    /// `SCALE`x, centered on 800x600
+    pub const DEFAULT: Placement = Placement { x: OFFSET_X, y: OFFSET_Y, scale: SCALE };
+}
+
 /// Border color (dark gray) in BGRA format
 pub const BORDER_COLOR_B: u8 = 0x20;
 pub const BORDER_COLOR_G: u8 = 0x20;
@@ -54,99 +84,257 @@ pub const BORDER_COLOR_R: u8 = 0x20;
 /// # Safety
 /// Caller must ensure `fb` points to valid framebuffer memory with sufficient size.
 pub unsafe fn blit_scaled(gb_data: &[u8], fb: *mut u8, pitch: usize, bpp: u32) {
+    for y in 0..SCREEN_H {
+        let row = &gb_data[y * SCREEN_W * 3..(y + 1) * SCREEN_W * 3];
+        blit_row(row, fb, pitch, bpp, y, Placement::DEFAULT);
+    }
+}
+
+/// Blit one RGB row (usually a GameBoy scanline, `y` = 0-143) with scaling
+unsafe fn blit_row(src_row: &[u8], fb: *mut u8, pitch: usize, bpp: u32, y: usize, at: Placement) {
     match bpp {
-        32 => blit_scaled_32bpp(gb_data, fb, pitch),
-        24 => blit_scaled_24bpp(gb_data, fb, pitch),
-        16 => blit_scaled_16bpp(gb_data, fb, pitch),
-        _ => blit_scaled_32bpp(gb_data, fb, pitch), // Default to 32bpp
+        32 => blit_row_32bpp(src_row, fb, pitch, y, at),
+        24 => blit_row_24bpp(src_row, fb, pitch, y, at),
+        16 => blit_row_16bpp(src_row, fb, pitch, y, at),
+        _ => blit_row_32bpp(src_row, fb, pitch, y, at), // Default to 32bpp
     }
 }
 
 /// Blit for 32-bit BGRA framebuffer
-unsafe fn blit_scaled_32bpp(gb_data: &[u8], fb: *mut u8, pitch: usize) {
-    for y in 0..SCREEN_H {
-        let src_row = &gb_data[y * SCREEN_W * 3..(y + 1) * SCREEN_W * 3];
-        let base_y = OFFSET_Y + y * SCALE;
-
-        // Draw SCALE rows for each GameBoy row
-        for sy in 0..SCALE {
-            let row_offset = (base_y + sy) * pitch + OFFSET_X * 4;
-            let mut dst = fb.add(row_offset);
-
-            for x in 0..SCREEN_W {
-                let src_idx = x * 3;
-                // GameBoy GPU outputs RGB
-                let r = src_row[src_idx];
-                let g = src_row[src_idx + 1];
-                let b = src_row[src_idx + 2];
-
-                // Write SCALE pixels horizontally
-                for _ in 0..SCALE {
-                    // BGRA format: B, G, R, A
-                    dst.write_volatile(b);
-                    dst.add(1).write_volatile(g);
-                    dst.add(2).write_volatile(r);
-                    dst.add(3).write_volatile(0xFF);
-                    dst = dst.add(4);
-                }
+unsafe fn blit_row_32bpp(src_row: &[u8], fb: *mut u8, pitch: usize, y: usize, at: Placement) {
+    let base_y = at.y + y * at.scale;
+
+    // Draw `scale` rows for each GameBoy row
+    for sy in 0..at.scale {
+        let row_offset = (base_y + sy) * pitch + at.x * 4;
+        let mut dst = fb.add(row_offset);
+
+        for x in 0..src_row.len() / 3 {
+            let src_idx = x * 3;
+            // GameBoy GPU outputs RGB
+            let r = src_row[src_idx];
+            let g = src_row[src_idx + 1];
+            let b = src_row[src_idx + 2];
+
+            // Write `scale` pixels horizontally
+            for _ in 0..at.scale {
+                // BGRA format: B, G, R, A
+                dst.write_volatile(b);
+                dst.add(1).write_volatile(g);
+                dst.add(2).write_volatile(r);
+                dst.add(3).write_volatile(0xFF);
+                dst = dst.add(4);
             }
         }
     }
 }
 
 /// Blit for 24-bit BGR framebuffer
-unsafe fn blit_scaled_24bpp(gb_data: &[u8], fb: *mut u8, pitch: usize) {
-    for y in 0..SCREEN_H {
-        let src_row = &gb_data[y * SCREEN_W * 3..(y + 1) * SCREEN_W * 3];
-        let base_y = OFFSET_Y + y * SCALE;
-
-        for sy in 0..SCALE {
-            let row_offset = (base_y + sy) * pitch + OFFSET_X * 3;
-            let mut dst = fb.add(row_offset);
-
-            for x in 0..SCREEN_W {
-                let src_idx = x * 3;
-                let r = src_row[src_idx];
-                let g = src_row[src_idx + 1];
-                let b = src_row[src_idx + 2];
-
-                for _ in 0..SCALE {
-                    // BGR format
-                    dst.write_volatile(b);
-                    dst.add(1).write_volatile(g);
-                    dst.add(2).write_volatile(r);
-                    dst = dst.add(3);
-                }
+unsafe fn blit_row_24bpp(src_row: &[u8], fb: *mut u8, pitch: usize, y: usize, at: Placement) {
+    let base_y = at.y + y * at.scale;
+
+    for sy in 0..at.scale {
+        let row_offset = (base_y + sy) * pitch + at.x * 3;
+        let mut dst = fb.add(row_offset);
+
+        for x in 0..src_row.len() / 3 {
+            let src_idx = x * 3;
+            let r = src_row[src_idx];
+            let g = src_row[src_idx + 1];
+            let b = src_row[src_idx + 2];
+
+            for _ in 0..at.scale {
+                // BGR format
+                dst.write_volatile(b);
+                dst.add(1).write_volatile(g);
+                dst.add(2).write_volatile(r);
+                dst = dst.add(3);
             }
         }
     }
 }
 
 /// Blit for 16-bit RGB565 framebuffer
-unsafe fn blit_scaled_16bpp(gb_data: &[u8], fb: *mut u8, pitch: usize) {
-    for y in 0..SCREEN_H {
-        let src_row = &gb_data[y * SCREEN_W * 3..(y + 1) * SCREEN_W * 3];
-        let base_y = OFFSET_Y + y * SCALE;
+unsafe fn blit_row_16bpp(src_row: &[u8], fb: *mut u8, pitch: usize, y: usize, at: Placement) {
+    let base_y = at.y + y * at.scale;
+
+    for sy in 0..at.scale {
+        let row_offset = (base_y + sy) * pitch + at.x * 2;
+        let mut dst = fb.add(row_offset) as *mut u16;
+
+        for x in 0..src_row.len() / 3 {
+            let src_idx = x * 3;
+            let r = src_row[src_idx] as u16;
+            let g = src_row[src_idx + 1] as u16;
+            let b = src_row[src_idx + 2] as u16;
+
+            // RGB565: RRRRRGGGGGGBBBBB
+            let rgb565 = ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3);
+
+            for _ in 0..at.scale {
+                dst.write_volatile(rgb565);
+                dst = dst.add(1);
+            }
+        }
+    }
+}
 
-        for sy in 0..SCALE {
-            let row_offset = (base_y + sy) * pitch + OFFSET_X * 2;
-            let mut dst = fb.add(row_offset) as *mut u16;
+// =============================================================================
+// Frame Sinks
+// =============================================================================
 
-            for x in 0..SCREEN_W {
-                let src_idx = x * 3;
-                let r = src_row[src_idx] as u16;
-                let g = src_row[src_idx + 1] as u16;
-                let b = src_row[src_idx + 2] as u16;
+/// Writes palette indices straight into the Mode 13h back buffer
+pub struct Mode13hSink;
 
-                // RGB565: RRRRRGGGGGGBBBBB
-                let rgb565 = ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3);
+impl FrameSink for Mode13hSink {
+    fn scanline(&mut self, y: usize, pal: &[u8; SCREEN_W], _rgb: Option<&RgbLine>) {
+        let offset = (GB_Y + y) * SCREEN_WIDTH + GB_X;
+        double_buffer::back_buffer()[offset..offset + SCREEN_W].copy_from_slice(pal);
+    }
+}
+
+/// Writes RGB scanlines into a linear framebuffer (VESA or BGA), scaled
+pub struct VbeSink {
+    fb: *mut u8,
+    pitch: usize,
+    bpp: u32,
+    at: Placement,
+}
 
-                for _ in 0..SCALE {
-                    dst.write_volatile(rgb565);
-                    dst = dst.add(1);
+impl VbeSink {
+    /// # Safety
+    /// `fb` must stay mapped for as long as the sink is installed and be
+    /// large enough for the screen at `at`.
+    pub unsafe fn new(fb: *mut u8, pitch: usize, bpp: u32, at: Placement) -> VbeSink {
+        VbeSink { fb, pitch, bpp, at }
+    }
+}
+
+impl FrameSink for VbeSink {
+    fn scanline(&mut self, y: usize, _pal: &[u8; SCREEN_W], rgb: Option<&RgbLine>) {
+        if let Some(rgb) = rgb {
+            unsafe { blit_row(rgb, self.fb, self.pitch, self.bpp, y, self.at) }
+        }
+    }
+
+    fn wants_rgb(&self) -> bool {
+        true
+    }
+}
+
+/// Collects RGB frames and writes them through `graphics::filters`
+///
+/// Upscalers look at the lines above and below, so nothing is drawn until
+/// the last scanline arrives. The frame is then corrected or tinted and
+/// blended with the previous one; each source row is run through the
+/// upscaler, stretched to the placement, masked and written out. Only two
+/// frames and a few lines are buffered, all allocated up front.
+pub struct FilterSink {
+    fb: *mut u8,
+    pitch: usize,
+    bpp: u32,
+    at: Placement,
+    /// Color curve for GBC; presets and tints for DMG
+    cgb: bool,
+    /// RGB of the frame being rendered
+    frame: Vec<u8>,
+    /// What the next frame is blended with (see `filters::blend_frame`)
+    history: Vec<u8>,
+    /// Blending was on last frame, so `history` is current
+    primed: bool,
+    /// Output of one upscaled source row (up to 3 rows of 3x width)
+    epx: Vec<u8>,
+    /// One line of the final scaled image
+    line: Vec<u8>,
+}
+
+impl FilterSink {
+    /// # Safety
+    /// Same requirements as `VbeSink::new`.
+    pub unsafe fn new(fb: *mut u8, pitch: usize, bpp: u32, at: Placement, cgb: bool) -> FilterSink {
+        FilterSink {
+            fb,
+            pitch,
+            bpp,
+            at,
+            cgb,
+            frame: vec![0; SCREEN_W * SCREEN_H * 3],
+            history: vec![0; SCREEN_W * SCREEN_H * 3],
+            primed: false,
+            epx: vec![0; SCREEN_W * 3 * 3 * 3],
+            line: vec![0; SCREEN_W * at.scale * 3],
+        }
+    }
+
+    /// Filter the collected frame and draw it
+    fn present(&mut self) {
+        let settings = filters::settings();
+        if self.cgb {
+            // The curve's table has the vision filter in it
+            vga_palette::correct_frame_rgb8(&mut self.frame);
+        } else {
+            filters::tint_frame(&mut self.frame, settings.tint);
+            vga_palette::vision_filter_rgb8(&mut self.frame);
+        }
+
+        if settings.blend == filters::FrameBlend::Off {
+            self.primed = false;
+        } else {
+            if !self.primed {
+                self.history.copy_from_slice(&self.frame);
+                self.primed = true;
+            }
+            filters::blend_frame(&mut self.frame, &mut self.history, settings.blend);
+        }
+
+        let scale = self.at.scale;
+        let factor = settings.upscaler.factor_for(scale);
+        let repeat = scale / factor;
+        let epx_w = SCREEN_W * factor * 3;
+
+        for y in 0..SCREEN_H {
+            filters::epx_row(&self.frame, SCREEN_W, SCREEN_H, y, factor, &mut self.epx);
+
+            for sy in 0..scale {
+                let src = &self.epx[(sy / repeat) * epx_w..][..epx_w];
+                for (dst, px) in self.line.chunks_exact_mut(3 * repeat).zip(src.chunks_exact(3)) {
+                    for out in dst.chunks_exact_mut(3) {
+                        out.copy_from_slice(px);
+                    }
+                }
+                filters::lcd_mask_row(&mut self.line, sy, scale, settings.lcd);
+
+                let at = Placement { x: self.at.x, y: self.at.y + y * scale + sy, scale: 1 };
+                unsafe { blit_row(&self.line, self.fb, self.pitch, self.bpp, 0, at) }
+            }
+        }
+    }
+}
+
+impl FrameSink for FilterSink {
+    fn scanline(&mut self, y: usize, pal: &[u8; SCREEN_W], rgb: Option<&RgbLine>) {
+        if let Some(rgb) = rgb {
+            let row = &mut self.frame[y * SCREEN_W * 3..(y + 1) * SCREEN_W * 3];
+            if self.cgb {
+                row.copy_from_slice(rgb);
+            } else {
+                // DMG gray levels -> the active preset's colors
+                let preset = dmg_palettes::active();
+                for (x, out) in row.chunks_exact_mut(3).enumerate() {
+                    let shade = dmg_palettes::shade_of(rgb[x * 3]);
+                    let c = preset.color(dmg_palettes::group_of(pal[x]), shade);
+                    let (r, g, b) = ((c & 0x1F) as u8, (c >> 5 & 0x1F) as u8, (c >> 10 & 0x1F) as u8);
+                    out.copy_from_slice(&[r << 3 | r >> 2, g << 3 | g >> 2, b << 3 | b >> 2]);
                 }
             }
         }
+        if y == SCREEN_H - 1 {
+            self.present();
+        }
+    }
+
+    fn wants_rgb(&self) -> bool {
+        true
     }
 }
 
@@ -0,0 +1,85 @@
+//! Frame Sinks
+//!
+//! The GPU renders one scanline at a time into a small line buffer and
+//! hands each finished line to a `FrameSink`, which writes it straight
+//! into its destination surface. There is no intermediate full-frame
+//! buffer to copy from afterwards.
+//!
+//! Every line is delivered as palette indices (see `vga_palette` for the
+//! index layout). RGB is only computed when the sink asks for it through
+//! `wants_rgb`, since the Mode 13h path never looks at it.
+//!
+//! Sinks for the kernel's display surfaces live in `display.rs`;
+//! `BufferSink` keeps a whole frame in memory for tests and tools.
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use super::gpu::{SCREEN_H, SCREEN_W};
+
+/// One RGB scanline (R, G, B per pixel)
+pub type RgbLine = [u8; SCREEN_W * 3];
+
+/// Destination for rendered scanlines
+pub trait FrameSink {
+    /// Receive scanline `y` (0-143)
+    ///
+    /// `rgb` is `Some` only if `wants_rgb` returned true when the sink was
+    /// installed.
+    fn scanline(&mut self, y: usize, pal: &[u8; SCREEN_W], rgb: Option<&RgbLine>);
+
+    /// Whether the GPU should also produce RGB output
+    fn wants_rgb(&self) -> bool {
+        false
+    }
+
+    /// Blank the whole screen (LCD switched off)
+    fn blank(&mut self) {
+        let pal = [0u8; SCREEN_W];
+        let rgb = [0xFFu8; SCREEN_W * 3];
+        let rgb = if self.wants_rgb() { Some(&rgb) } else { None };
+        for y in 0..SCREEN_H {
+            self.scanline(y, &pal, rgb);
+        }
+    }
+}
+
+/// Discards everything (the GPU's sink until a real one is installed)
+pub struct NullSink;
+
+impl FrameSink for NullSink {
+    fn scanline(&mut self, _y: usize, _pal: &[u8; SCREEN_W], _rgb: Option<&RgbLine>) {}
+
+    fn blank(&mut self) {}
+}
+
+/// Keeps the latest frame in memory
+pub struct BufferSink {
+    /// Palette indices, 160x144
+    pub pal: Vec<u8>,
+    /// RGB, 160x144x3 (only when created with `rgb = true`)
+    pub rgb: Option<Vec<u8>>,
+}
+
+impl BufferSink {
+    pub fn new(rgb: bool) -> BufferSink {
+        BufferSink {
+            pal: vec![0; SCREEN_W * SCREEN_H],
+            rgb: if rgb { Some(vec![0; SCREEN_W * SCREEN_H * 3]) } else { None },
+        }
+    }
+}
+
+impl FrameSink for BufferSink {
+    fn scanline(&mut self, y: usize, pal: &[u8; SCREEN_W], rgb: Option<&RgbLine>) {
+        self.pal[y * SCREEN_W..(y + 1) * SCREEN_W].copy_from_slice(pal);
+        if let (Some(dst), Some(src)) = (self.rgb.as_mut(), rgb) {
+            dst[y * SCREEN_W * 3..(y + 1) * SCREEN_W * 3].copy_from_slice(src);
+        }
+    }
+
+    fn wants_rgb(&self) -> bool {
+        self.rgb.is_some()
+    }
+}
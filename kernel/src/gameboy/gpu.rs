@@ -8,10 +8,10 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
-use alloc::vec;
-use alloc::vec::Vec;
 use core::cmp::Ordering;
+use super::frame_sink::{FrameSink, NullSink, RgbLine};
 use super::gbmode::GbMode;
+use super::tile_cache::TileCache;
 
 pub const SCREEN_W: usize = 160;
 pub const SCREEN_H: usize = 144;
@@ -58,23 +58,179 @@ pub struct GPU {
     // VRAM - boxed to avoid stack overflow
     vram: Box<[u8; VRAM_SIZE]>,
     voam: [u8; VOAM_SIZE],
+    // Pre-decoded tile data, invalidated on VRAM writes
+    tile_cache: TileCache,
     cbgpal_inc: bool,
     cbgpal_ind: u8,
     cbgpal: [[[u8; 3]; 4]; 8],
     csprit_inc: bool,
     csprit_ind: u8,
     csprit: [[[u8; 3]; 4]; 8],
+    // CGB colours in effect on each rendered line (RGB555, BG 0-31, OBJ 32-63)
+    line_palettes: Box<[[u16; 64]; SCREEN_H]>,
+    // DMG shades (0 = lightest) in effect on each rendered line (BG, OBJ0, OBJ1)
+    line_dmg: Box<[[u8; 12]; SCREEN_H]>,
+    // Palette indices of the last frame, for screenshots and capture
+    frame_indices: Box<[[u8; SCREEN_W]; SCREEN_H]>,
     vrambank: usize,
-    // RGB framebuffer - boxed to avoid stack overflow
-    pub data: Vec<u8>,
-    // VGA palette index buffer (for mode 13h direct blitting)
-    pub pal_data: Vec<u8>,
+    // Scanline being rendered: palette indices, and RGB if the sink wants it
+    line_pal: [u8; SCREEN_W],
+    line_rgb: RgbLine,
+    rgb_on: bool,
+    // Where finished scanlines go
+    sink: Box<dyn FrameSink>,
     bgprio: [PrioType; SCREEN_W],
     pub updated: bool,
     pub interrupt: u8,
     pub gbmode: GbMode,
     hblanking: bool,
     first_frame: bool,
+    // Skip scanline rendering (run-ahead frames that are never displayed)
+    pub skip_render: bool,
+}
+
+/// GPU state kept in a run-ahead snapshot
+///
+/// Only what emulation needs: no tile cache, line buffers or frame sink.
+pub struct GpuState {
+    mode: u8,
+    modeclock: u32,
+    line: u8,
+    lyc: u8,
+    lcd_on: bool,
+    win_tilemap: u16,
+    win_on: bool,
+    tilebase: u16,
+    bg_tilemap: u16,
+    sprite_size: u32,
+    sprite_on: bool,
+    lcdc0: bool,
+    lyc_inte: bool,
+    m0_inte: bool,
+    m1_inte: bool,
+    m2_inte: bool,
+    scy: u8,
+    scx: u8,
+    winy: u8,
+    winx: u8,
+    wy_trigger: bool,
+    wy_pos: i32,
+    palbr: u8,
+    pal0r: u8,
+    pal1r: u8,
+    palb: [u8; 4],
+    pal0: [u8; 4],
+    pal1: [u8; 4],
+    voam: [u8; VOAM_SIZE],
+    cbgpal_inc: bool,
+    cbgpal_ind: u8,
+    cbgpal: [[[u8; 3]; 4]; 8],
+    csprit_inc: bool,
+    csprit_ind: u8,
+    csprit: [[[u8; 3]; 4]; 8],
+    vrambank: usize,
+    bgprio: [PrioType; SCREEN_W],
+    interrupt: u8,
+    gbmode: GbMode,
+    hblanking: bool,
+    first_frame: bool,
+    vram: Box<[u8; VRAM_SIZE]>,
+}
+
+impl GpuState {
+    pub fn new() -> GpuState {
+        GpuState {
+            mode: 0,
+            modeclock: 0,
+            line: 0,
+            lyc: 0,
+            lcd_on: false,
+            win_tilemap: 0x9C00,
+            win_on: false,
+            tilebase: 0x8000,
+            bg_tilemap: 0x9C00,
+            sprite_size: 8,
+            sprite_on: false,
+            lcdc0: false,
+            lyc_inte: false,
+            m0_inte: false,
+            m1_inte: false,
+            m2_inte: false,
+            scy: 0,
+            scx: 0,
+            winy: 0,
+            winx: 0,
+            wy_trigger: false,
+            wy_pos: -1,
+            palbr: 0,
+            pal0r: 0,
+            pal1r: 1,
+            palb: [0; 4],
+            pal0: [0; 4],
+            pal1: [0; 4],
+            voam: [0; VOAM_SIZE],
+            cbgpal_inc: false,
+            cbgpal_ind: 0,
+            cbgpal: [[[0u8; 3]; 4]; 8],
+            csprit_inc: false,
+            csprit_ind: 0,
+            csprit: [[[0u8; 3]; 4]; 8],
+            vrambank: 0,
+            bgprio: [PrioType::Normal; SCREEN_W],
+            interrupt: 0,
+            gbmode: GbMode::Classic,
+            hblanking: false,
+            first_frame: false,
+            vram: Box::new([0; VRAM_SIZE]),
+        }
+    }
+}
+
+/// Copy the snapshot fields (all but VRAM) between a `GPU` and a `GpuState`
+macro_rules! copy_state {
+    ($dst:expr, $src:expr) => {
+        $dst.mode = $src.mode;
+        $dst.modeclock = $src.modeclock;
+        $dst.line = $src.line;
+        $dst.lyc = $src.lyc;
+        $dst.lcd_on = $src.lcd_on;
+        $dst.win_tilemap = $src.win_tilemap;
+        $dst.win_on = $src.win_on;
+        $dst.tilebase = $src.tilebase;
+        $dst.bg_tilemap = $src.bg_tilemap;
+        $dst.sprite_size = $src.sprite_size;
+        $dst.sprite_on = $src.sprite_on;
+        $dst.lcdc0 = $src.lcdc0;
+        $dst.lyc_inte = $src.lyc_inte;
+        $dst.m0_inte = $src.m0_inte;
+        $dst.m1_inte = $src.m1_inte;
+        $dst.m2_inte = $src.m2_inte;
+        $dst.scy = $src.scy;
+        $dst.scx = $src.scx;
+        $dst.winy = $src.winy;
+        $dst.winx = $src.winx;
+        $dst.wy_trigger = $src.wy_trigger;
+        $dst.wy_pos = $src.wy_pos;
+        $dst.palbr = $src.palbr;
+        $dst.pal0r = $src.pal0r;
+        $dst.pal1r = $src.pal1r;
+        $dst.palb = $src.palb;
+        $dst.pal0 = $src.pal0;
+        $dst.pal1 = $src.pal1;
+        $dst.voam = $src.voam;
+        $dst.cbgpal_inc = $src.cbgpal_inc;
+        $dst.cbgpal_ind = $src.cbgpal_ind;
+        $dst.cbgpal = $src.cbgpal;
+        $dst.csprit_inc = $src.csprit_inc;
+        $dst.csprit_ind = $src.csprit_ind;
+        $dst.csprit = $src.csprit;
+        $dst.vrambank = $src.vrambank;
+        $dst.bgprio = $src.bgprio;
+        $dst.interrupt = $src.interrupt;
+        $dst.gbmode = $src.gbmode;
+        $dst.hblanking = $src.hblanking;
+        $dst.first_frame = $src.first_frame;
+    };
 }
 
 impl GPU {
@@ -110,8 +266,11 @@ impl GPU {
             pal1: [0; 4],
             vram: Box::new([0; VRAM_SIZE]),
             voam: [0; VOAM_SIZE],
-            data: vec![0; SCREEN_W * SCREEN_H * 3],
-            pal_data: vec![0; SCREEN_W * SCREEN_H],
+            tile_cache: TileCache::new(),
+            line_pal: [0; SCREEN_W],
+            line_rgb: [0; SCREEN_W * 3],
+            rgb_on: false,
+            sink: Box::new(NullSink),
             bgprio: [PrioType::Normal; SCREEN_W],
             updated: false,
             interrupt: 0,
@@ -122,9 +281,13 @@ impl GPU {
             csprit_inc: false,
             csprit_ind: 0,
             csprit: [[[0u8; 3]; 4]; 8],
+            line_palettes: Box::new([[0; 64]; SCREEN_H]),
+            line_dmg: Box::new([[0; 12]; SCREEN_H]),
+            frame_indices: Box::new([[0; SCREEN_W]; SCREEN_H]),
             vrambank: 0,
             hblanking: false,
             first_frame: false,
+            skip_render: false,
         }
     }
 
@@ -134,14 +297,16 @@ impl GPU {
         gpu
     }
 
+    /// Send rendered scanlines to `sink`; returns the previous sink
+    pub fn set_frame_sink(&mut self, sink: Box<dyn FrameSink>) -> Box<dyn FrameSink> {
+        self.rgb_on = sink.wants_rgb();
+        core::mem::replace(&mut self.sink, sink)
+    }
+
     // =========================================================================
     // Palette Accessors (for VGA DAC sync)
     // =========================================================================
 
-    pub fn get_pal_data(&self) -> &[u8] {
-        &self.pal_data
-    }
-
     pub fn get_cbgpal(&self) -> &[[[u8; 3]; 4]; 8] {
         &self.cbgpal
     }
@@ -150,6 +315,37 @@ impl GPU {
         &self.csprit
     }
 
+    /// CGB palettes as they were when each line of the last frame was drawn
+    pub fn line_palettes(&self) -> &[[u16; 64]; SCREEN_H] {
+        &self.line_palettes
+    }
+
+    /// The last frame as RGB555, row by row (`SCREEN_W * SCREEN_H` pixels)
+    ///
+    /// CGB colors are the game's own; DMG shades are colored by `dmg`
+    /// (BG, OBJ0, OBJ1; each lightest to darkest). No display filter is
+    /// applied.
+    pub fn frame_rgb555(&self, out: &mut [u16], dmg: &[[u16; 4]; 3]) {
+        for (y, row) in out.chunks_exact_mut(SCREEN_W).take(SCREEN_H).enumerate() {
+            for (x, px) in row.iter_mut().enumerate() {
+                let index = self.frame_indices[y][x];
+                *px = if self.gbmode == GbMode::Color {
+                    self.line_palettes[y][index as usize & 63]
+                } else {
+                    // Anything but a DMG entry is the blank behind a
+                    // disabled background: the lightest BG shade
+                    match index.checked_sub(64) {
+                        Some(slot) if slot < 12 => {
+                            let slot = slot as usize;
+                            dmg[slot / 4][self.line_dmg[y][slot] as usize & 3]
+                        }
+                        _ => dmg[0][0],
+                    }
+                };
+            }
+        }
+    }
+
     pub fn get_palb(&self) -> &[u8; 4] {
         &self.palb
     }
@@ -162,6 +358,27 @@ impl GPU {
         &self.pal1
     }
 
+    // =========================================================================
+    // Snapshots (for run-ahead)
+    // =========================================================================
+
+    /// Capture all emulation state into `s`
+    pub fn save_state(&self, s: &mut GpuState) {
+        copy_state!(s, self);
+        s.vram.copy_from_slice(&self.vram[..]);
+    }
+
+    /// Restore state previously captured with `save_state`
+    ///
+    /// The frame sink, `updated` and `skip_render` belong to the display
+    /// side and are left untouched, so restoring a snapshot never clobbers
+    /// a frame that is already on screen.
+    pub fn load_state(&mut self, s: &GpuState) {
+        copy_state!(self, s);
+        self.vram.copy_from_slice(&s.vram[..]);
+        self.tile_cache.invalidate_all();
+    }
+
     // =========================================================================
     // Cycle processing (matches original rboy)
     // =========================================================================
@@ -212,6 +429,26 @@ impl GPU {
         }
     }
 
+    /// GPU cycles until the next mode change or scanline (None if LCD off)
+    ///
+    /// Mirrors the thresholds in `do_cycle`: mode 2 up to 80, mode 3 up
+    /// to 252, HBlank until 456, where the next line starts.
+    pub fn cycles_to_next_event(&self) -> Option<u32> {
+        if !self.lcd_on {
+            return None;
+        }
+        let next = if self.line >= 144 {
+            456
+        } else if self.modeclock <= 80 {
+            81
+        } else if self.modeclock <= 80 + 172 {
+            80 + 172 + 1
+        } else {
+            456
+        };
+        Some(next - self.modeclock)
+    }
+
     fn check_interrupt_lyc(&mut self) {
         if self.lyc_inte && self.line == self.lyc {
             self.interrupt |= 0x02;
@@ -328,7 +565,10 @@ impl GPU {
 
     pub fn wb(&mut self, a: u16, v: u8) {
         match a {
-            0x8000..=0x9FFF => self.vram[(self.vrambank * 0x2000) | (a as usize & 0x1FFF)] = v,
+            0x8000..=0x9FFF => {
+                self.vram[(self.vrambank * 0x2000) | (a as usize & 0x1FFF)] = v;
+                self.tile_cache.invalidate(self.vrambank, a);
+            }
             0xFE00..=0xFE9F => self.voam[a as usize - 0xFE00] = v,
             0xFF40 => {
                 let orig_lcd_on = self.lcd_on;
@@ -434,12 +674,7 @@ impl GPU {
     // =========================================================================
 
     fn clear_screen(&mut self) {
-        for v in self.data.iter_mut() {
-            *v = 255;
-        }
-        for v in self.pal_data.iter_mut() {
-            *v = 0;
-        }
+        self.sink.blank();
         self.updated = true;
     }
 
@@ -461,7 +696,7 @@ impl GPU {
     }
 
     fn renderscan(&mut self) {
-        if self.first_frame {
+        if self.first_frame || self.skip_render {
             return;
         }
 
@@ -472,27 +707,52 @@ impl GPU {
         }
         self.draw_bg();
         self.draw_sprites();
+
+        if self.gbmode == GbMode::Color {
+            self.capture_line_palette();
+        } else {
+            let out = &mut self.line_dmg[self.line as usize];
+            for (p, pal) in [self.palbr, self.pal0r, self.pal1r].into_iter().enumerate() {
+                for (i, shade) in out[p * 4..p * 4 + 4].iter_mut().enumerate() {
+                    *shade = (pal >> (2 * i)) & 0x03;
+                }
+            }
+        }
+        self.frame_indices[self.line as usize] = self.line_pal;
+
+        let rgb = if self.rgb_on { Some(&self.line_rgb) } else { None };
+        self.sink.scanline(self.line as usize, &self.line_pal, rgb);
+    }
+
+    /// Record the CGB palettes used for the current line
+    fn capture_line_palette(&mut self) {
+        let out = &mut self.line_palettes[self.line as usize];
+        let colors = self.cbgpal.iter().chain(self.csprit.iter()).flatten();
+        for (entry, rgb) in out.iter_mut().zip(colors) {
+            *entry = rgb[0] as u16 | (rgb[1] as u16) << 5 | (rgb[2] as u16) << 10;
+        }
     }
 
     fn setcolor(&mut self, x: usize, color: u8) {
-        self.data[self.line as usize * SCREEN_W * 3 + x * 3 + 0] = color;
-        self.data[self.line as usize * SCREEN_W * 3 + x * 3 + 1] = color;
-        self.data[self.line as usize * SCREEN_W * 3 + x * 3 + 2] = color;
+        if self.rgb_on {
+            self.line_rgb[x * 3..x * 3 + 3].fill(color);
+        }
     }
 
     fn setpal(&mut self, x: usize, pal_idx: u8) {
-        self.pal_data[self.line as usize * SCREEN_W + x] = pal_idx;
+        self.line_pal[x] = pal_idx;
     }
 
     fn setrgb(&mut self, x: usize, r: u8, g: u8, b: u8) {
-        let baseidx = self.line as usize * SCREEN_W * 3 + x * 3;
-        let r = r as u32;
-        let g = g as u32;
-        let b = b as u32;
-        // GBC color correction from Gambatte
-        self.data[baseidx + 0] = ((r * 13 + g * 2 + b) >> 1) as u8;
-        self.data[baseidx + 1] = ((g * 3 + b) << 1) as u8;
-        self.data[baseidx + 2] = ((r * 3 + g * 2 + b * 11) >> 1) as u8;
+        if !self.rgb_on {
+            return;
+        }
+        let baseidx = x * 3;
+        // Plain RGB555 -> RGB888; the color curve is applied on display
+        // (see vga_palette::correct_frame_rgb8)
+        self.line_rgb[baseidx + 0] = (r << 3) | (r >> 2);
+        self.line_rgb[baseidx + 1] = (g << 3) | (g >> 2);
+        self.line_rgb[baseidx + 2] = (b << 3) | (b >> 2);
     }
 
     fn draw_bg(&mut self) {
@@ -515,6 +775,13 @@ impl GPU {
         let bgy = self.scy.wrapping_add(self.line);
         let bgtiley = (bgy as u16 >> 3) & 31;
 
+        // Decoded tile row under the current pixel, reused for the 8 pixels it spans
+        let mut row_key = u32::MAX;
+        let mut row = [0u8; 8];
+        let mut palnr = 0;
+        let mut xflip = false;
+        let mut prio = false;
+
         for x in 0..SCREEN_W {
             let winx = -((self.winx as i32) - 7) + (x as i32);
             let bgx = self.scx as u32 + x as u32;
@@ -539,44 +806,40 @@ impl GPU {
                 continue;
             };
 
-            let tilenr: u8 = self.rbvram0(tilemapbase + tiley * 32 + tilex);
-
-            let (palnr, vram1, xflip, yflip, prio) = if self.gbmode == GbMode::Color {
-                let flags = self.rbvram1(tilemapbase + tiley * 32 + tilex) as usize;
-                (
-                    flags & 0x07,
-                    flags & (1 << 3) != 0,
-                    flags & (1 << 5) != 0,
-                    flags & (1 << 6) != 0,
-                    flags & (1 << 7) != 0,
-                )
-            } else {
-                (0, false, false, false, false)
-            };
-
-            let tileaddress = self.tilebase
-                + (if self.tilebase == 0x8000 {
-                tilenr as u16
-            } else {
-                (tilenr as i8 as i16 + 128) as u16
-            }) * 16;
-
-            let a0 = match yflip {
-                false => tileaddress + (pixely * 2),
-                true => tileaddress + (14 - (pixely * 2)),
-            };
-
-            let (b1, b2) = match vram1 {
-                false => (self.rbvram0(a0), self.rbvram0(a0 + 1)),
-                true => (self.rbvram1(a0), self.rbvram1(a0 + 1)),
-            };
+            let mapaddr = tilemapbase + tiley * 32 + tilex;
+            let key = ((mapaddr as u32) << 3) | pixely as u32;
+            if key != row_key {
+                row_key = key;
+
+                let tilenr: u8 = self.rbvram0(mapaddr);
+
+                let (pal, vram1, xf, yflip, pr) = if self.gbmode == GbMode::Color {
+                    let flags = self.rbvram1(mapaddr) as usize;
+                    (
+                        flags & 0x07,
+                        flags & (1 << 3) != 0,
+                        flags & (1 << 5) != 0,
+                        flags & (1 << 6) != 0,
+                        flags & (1 << 7) != 0,
+                    )
+                } else {
+                    (0, false, false, false, false)
+                };
+                palnr = pal;
+                xflip = xf;
+                prio = pr;
+
+                // Tile index relative to 0x8000 (0x8800 mode uses signed numbers)
+                let tile = if self.tilebase == 0x8000 {
+                    tilenr as usize
+                } else {
+                    (tilenr as i8 as i16 + 256) as usize
+                };
+                let tilerow = if yflip { 7 - pixely } else { pixely } as usize;
+                row = *self.tile_cache.row(&self.vram[..], vram1 as usize, tile, tilerow);
+            }
 
-            let xbit = match xflip {
-                true => pixelx,
-                false => 7 - pixelx,
-            } as u32;
-            let colnr = (if b1 & (1 << xbit) != 0 { 1 } else { 0 })
-                | (if b2 & (1 << xbit) != 0 { 2 } else { 0 });
+            let colnr = row[if xflip { 7 - pixelx } else { pixelx } as usize] as usize;
 
             self.bgprio[x] = if colnr == 0 {
                 PrioType::Color0
@@ -654,21 +917,16 @@ impl GPU {
                 (line - spritey) as u16
             };
 
-            let tileaddress = 0x8000u16 + tilenum * 16 + tiley * 2;
-            let (b1, b2) = if c_vram1 && self.gbmode == GbMode::Color {
-                (self.rbvram1(tileaddress), self.rbvram1(tileaddress + 1))
-            } else {
-                (self.rbvram0(tileaddress), self.rbvram0(tileaddress + 1))
-            };
+            let bank = (c_vram1 && self.gbmode == GbMode::Color) as usize;
+            let tile = (tilenum + (tiley >> 3)) as usize;
+            let row = *self.tile_cache.row(&self.vram[..], bank, tile, (tiley & 7) as usize);
 
             'xloop: for x in 0..8i32 {
                 if spritex + x < 0 || spritex + x >= (SCREEN_W as i32) {
                     continue;
                 }
 
-                let xbit = 1 << (if xflip { x } else { 7 - x } as u32);
-                let colnr =
-                    (if b1 & xbit != 0 { 1 } else { 0 }) | (if b2 & xbit != 0 { 2 } else { 0 });
+                let colnr = row[if xflip { 7 - x } else { x } as usize] as usize;
                 if colnr == 0 {
                     continue;
                 }
@@ -714,3 +972,185 @@ fn dmg_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
 fn cgb_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
     b.2.cmp(&a.2)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// xorshift32, so every run draws the same "random" VRAM
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u8 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 24) as u8
+        }
+    }
+
+    /// Palette indices for the current line, drawn by picking bits straight
+    /// out of VRAM the way the renderer did before the tile cache
+    ///
+    /// `winy` is the window row used for the line, or -1.
+    fn reference_line(gpu: &GPU, winy: i32) -> [u8; SCREEN_W] {
+        let color = gpu.gbmode == GbMode::Color;
+        let mut pal = [0u8; SCREEN_W];
+        let mut prio = [PrioType::Normal; SCREEN_W];
+        let bit = |b1: u8, b2: u8, xbit: u32| ((b1 >> xbit) & 1) | (((b2 >> xbit) & 1) << 1);
+
+        let drawbg = color || gpu.lcdc0;
+        let bgy = gpu.scy.wrapping_add(gpu.line);
+        for x in 0..SCREEN_W {
+            let winx = -((gpu.winx as i32) - 7) + (x as i32);
+            let bgx = gpu.scx as u32 + x as u32;
+            let (tilemapbase, tiley, tilex, pixely, pixelx) = if winy >= 0 && winx >= 0 {
+                (gpu.win_tilemap, (winy as u16 >> 3) & 31, winx as u16 >> 3, winy as u16 & 7, winx as u8 & 7)
+            } else if drawbg {
+                (gpu.bg_tilemap, (bgy as u16 >> 3) & 31, (bgx as u16 >> 3) & 31, bgy as u16 & 7, bgx as u8 & 7)
+            } else {
+                continue;
+            };
+
+            let mapaddr = tilemapbase + tiley * 32 + tilex;
+            let tilenr = gpu.rbvram0(mapaddr);
+            let flags = if color { gpu.rbvram1(mapaddr) } else { 0 };
+            let tileaddress = gpu.tilebase
+                + (if gpu.tilebase == 0x8000 {
+                    tilenr as u16
+                } else {
+                    (tilenr as i8 as i16 + 128) as u16
+                }) * 16;
+            let a0 = if flags & 0x40 != 0 { tileaddress + 14 - pixely * 2 } else { tileaddress + pixely * 2 };
+            let (b1, b2) = if flags & 0x08 != 0 {
+                (gpu.rbvram1(a0), gpu.rbvram1(a0 + 1))
+            } else {
+                (gpu.rbvram0(a0), gpu.rbvram0(a0 + 1))
+            };
+            let colnr = bit(b1, b2, if flags & 0x20 != 0 { pixelx } else { 7 - pixelx } as u32);
+
+            prio[x] = if colnr == 0 {
+                PrioType::Color0
+            } else if flags & 0x80 != 0 {
+                PrioType::PrioFlag
+            } else {
+                PrioType::Normal
+            };
+            pal[x] = if color { (flags & 0x07) * 4 + colnr } else { 64 + colnr };
+        }
+
+        if !gpu.sprite_on {
+            return pal;
+        }
+        let line = gpu.line as i32;
+        let size = gpu.sprite_size as i32;
+        let mut sprites = [(0i32, 0i32, 0u8); 10];
+        let mut count = 0;
+        for index in 0..40u8 {
+            let spritey = gpu.voam[index as usize * 4] as i32 - 16;
+            if line < spritey || line >= spritey + size {
+                continue;
+            }
+            sprites[count] = (gpu.voam[index as usize * 4 + 1] as i32 - 8, spritey, index);
+            count += 1;
+            if count == 10 {
+                break;
+            }
+        }
+        if color {
+            sprites[..count].sort_unstable_by(cgb_sprite_order);
+        } else {
+            sprites[..count].sort_unstable_by(dmg_sprite_order);
+        }
+
+        for &(spritex, spritey, i) in &sprites[..count] {
+            let tilenum = (gpu.voam[i as usize * 4 + 2] & if size == 16 { 0xFE } else { 0xFF }) as u16;
+            let flags = gpu.voam[i as usize * 4 + 3];
+            let tiley = if flags & 0x40 != 0 { size - 1 - (line - spritey) } else { line - spritey } as u16;
+            let tileaddress = 0x8000 + tilenum * 16 + tiley * 2;
+            let (b1, b2) = if color && flags & 0x08 != 0 {
+                (gpu.rbvram1(tileaddress), gpu.rbvram1(tileaddress + 1))
+            } else {
+                (gpu.rbvram0(tileaddress), gpu.rbvram0(tileaddress + 1))
+            };
+
+            for x in 0..8i32 {
+                let sx = spritex + x;
+                if sx < 0 || sx >= SCREEN_W as i32 {
+                    continue;
+                }
+                let colnr = bit(b1, b2, if flags & 0x20 != 0 { x } else { 7 - x } as u32);
+                let under = prio[sx as usize];
+                if colnr == 0 {
+                    continue;
+                }
+                if color {
+                    if gpu.lcdc0 && (under == PrioType::PrioFlag || (flags & 0x80 != 0 && under != PrioType::Color0)) {
+                        continue;
+                    }
+                    pal[sx as usize] = 32 + (flags & 0x07) * 4 + colnr;
+                } else {
+                    if flags & 0x80 != 0 && under != PrioType::Color0 {
+                        continue;
+                    }
+                    pal[sx as usize] = 68 + ((flags >> 4) & 1) * 4 + colnr;
+                }
+            }
+        }
+        pal
+    }
+
+    /// Render frames of random VRAM, OAM and registers, rewriting part of
+    /// the tile data between frames, and compare every line against the
+    /// bit-picking renderer
+    fn check_frames(mut gpu: GPU, seed: u32) {
+        let mut rng = Rng(seed);
+        let banks = if gpu.gbmode == GbMode::Color { 2 } else { 1 };
+        for bank in 0..banks {
+            gpu.wb(0xFF4F, bank);
+            for addr in 0x8000..0xA000u16 {
+                gpu.wb(addr, rng.next());
+            }
+        }
+
+        for frame in 0..16 {
+            gpu.wb(0xFF40, rng.next() | 0x80);
+            for reg in [0xFF42, 0xFF43, 0xFF47, 0xFF48, 0xFF49] {
+                gpu.wb(reg, rng.next());
+            }
+            gpu.wb(0xFF4A, rng.next() % 160);
+            gpu.wb(0xFF4B, rng.next() % 176);
+            for addr in 0xFE00..0xFEA0u16 {
+                gpu.wb(addr, rng.next());
+            }
+            gpu.wy_pos = -1;
+
+            for line in 0..SCREEN_H as u8 {
+                gpu.line = line;
+                gpu.wy_trigger = line >= gpu.winy;
+                gpu.renderscan();
+
+                let winy = if gpu.win_on && gpu.wy_trigger && gpu.winx <= 166 { gpu.wy_pos } else { -1 };
+                assert_eq!(gpu.line_pal, reference_line(&gpu, winy), "frame {} line {}", frame, line);
+            }
+
+            for bank in 0..banks {
+                gpu.wb(0xFF4F, bank);
+                for _ in 0..256 {
+                    let addr = 0x8000 + (rng.next() as u16) * 24 + (rng.next() as u16 % 24);
+                    gpu.wb(addr, rng.next());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cached_render_matches_dmg() {
+        check_frames(GPU::new(), 0x1234_5678);
+    }
+
+    #[test]
+    fn test_cached_render_matches_cgb() {
+        check_frames(GPU::new_cgb(), 0x9E37_79B9);
+    }
+}
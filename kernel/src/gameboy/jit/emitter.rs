@@ -0,0 +1,333 @@
+//! i686 Machine Code Emitter
+//!
+//! Just enough of the x86 encoding to translate SM83 blocks. Guest state
+//! lives in a `JitContext` addressed through ESI, so almost every
+//! instruction here takes a `[esi+disp8]` operand.
+//!
+//! Scratch registers are EAX, ECX and EDX (caller-saved under cdecl, so
+//! helper calls may clobber them freely). ESI is callee-saved and survives
+//! helper calls.
+
+/// ModRM for `[esi+disp8]` with the given reg/opcode field
+const fn modrm_esi(reg: u8) -> u8 {
+    0x40 | (reg << 3) | 0x06
+}
+
+/// x86 condition codes used by `jcc`
+#[derive(Clone, Copy)]
+pub enum Cond {
+    /// ZF = 1
+    Zero = 0x4,
+    /// ZF = 0
+    NotZero = 0x5,
+}
+
+/// 8-bit ALU operations with their `op r8, r/m8` and `op al, imm8` opcodes
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Adc,
+    Sub,
+    Sbb,
+    And,
+    Xor,
+    Or,
+    Cmp,
+}
+
+impl AluOp {
+    fn opcode_rm(self) -> u8 {
+        match self {
+            AluOp::Add => 0x02,
+            AluOp::Or => 0x0A,
+            AluOp::Adc => 0x12,
+            AluOp::Sbb => 0x1A,
+            AluOp::And => 0x22,
+            AluOp::Sub => 0x2A,
+            AluOp::Xor => 0x32,
+            AluOp::Cmp => 0x3A,
+        }
+    }
+
+    fn opcode_imm(self) -> u8 {
+        // `op al, imm8` is always the r/m form + 2
+        self.opcode_rm() + 2
+    }
+}
+
+/// Writes machine code into a fixed buffer
+///
+/// Running out of space sets `overflow` instead of panicking; the caller
+/// discards the block and flushes the cache.
+pub struct Emitter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    overflow: bool,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn new(buf: &'a mut [u8], pos: usize) -> Self {
+        Emitter { buf, pos, overflow: false }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn overflowed(&self) -> bool {
+        self.overflow
+    }
+
+    fn byte(&mut self, b: u8) {
+        if self.pos < self.buf.len() {
+            self.buf[self.pos] = b;
+            self.pos += 1;
+        } else {
+            self.overflow = true;
+        }
+    }
+
+    fn bytes(&mut self, bs: &[u8]) {
+        for &b in bs {
+            self.byte(b);
+        }
+    }
+
+    fn word(&mut self, w: u16) {
+        self.bytes(&w.to_le_bytes());
+    }
+
+    fn dword(&mut self, d: u32) {
+        self.bytes(&d.to_le_bytes());
+    }
+
+    // =========================================================================
+    // Prologue / epilogue
+    // =========================================================================
+
+    /// `push esi; mov esi, [esp+8]` - load the context pointer argument
+    pub fn prologue(&mut self) {
+        self.bytes(&[0x56, 0x8B, 0x74, 0x24, 0x08]);
+    }
+
+    /// `pop esi; ret`
+    pub fn epilogue(&mut self) {
+        self.bytes(&[0x5E, 0xC3]);
+    }
+
+    // =========================================================================
+    // Moves between guest state and scratch registers
+    // =========================================================================
+
+    /// `mov al, [esi+off]`
+    pub fn load_al(&mut self, off: u8) {
+        self.bytes(&[0x8A, modrm_esi(0), off]);
+    }
+
+    /// `mov [esi+off], al`
+    pub fn store_al(&mut self, off: u8) {
+        self.bytes(&[0x88, modrm_esi(0), off]);
+    }
+
+    /// `mov [esi+off], dl`
+    pub fn store_dl(&mut self, off: u8) {
+        self.bytes(&[0x88, modrm_esi(2), off]);
+    }
+
+    /// `mov cl, [esi+off]`
+    pub fn load_cl(&mut self, off: u8) {
+        self.bytes(&[0x8A, modrm_esi(1), off]);
+    }
+
+    /// `movzx eax, byte [esi+off]`
+    pub fn load_eax_u8(&mut self, off: u8) {
+        self.bytes(&[0x0F, 0xB6, modrm_esi(0), off]);
+    }
+
+    /// `movzx eax, word [esi+off]`
+    pub fn load_eax_u16(&mut self, off: u8) {
+        self.bytes(&[0x0F, 0xB7, modrm_esi(0), off]);
+    }
+
+    /// `mov byte [esi+off], imm8`
+    pub fn store_imm8(&mut self, off: u8, v: u8) {
+        self.bytes(&[0xC6, modrm_esi(0), off, v]);
+    }
+
+    /// `mov word [esi+off], imm16`
+    pub fn store_imm16(&mut self, off: u8, v: u16) {
+        self.bytes(&[0x66, 0xC7, modrm_esi(0), off]);
+        self.word(v);
+    }
+
+    /// `mov dword [esi+off], imm32`
+    pub fn store_imm32(&mut self, off: u8, v: u32) {
+        self.bytes(&[0xC7, modrm_esi(0), off]);
+        self.dword(v);
+    }
+
+    /// `mov [esi+off], ax`
+    pub fn store_ax(&mut self, off: u8) {
+        self.bytes(&[0x66, 0x89, modrm_esi(0), off]);
+    }
+
+    // =========================================================================
+    // Arithmetic on guest state
+    // =========================================================================
+
+    /// `op al, [esi+off]`
+    pub fn alu_al_mem(&mut self, op: AluOp, off: u8) {
+        self.bytes(&[op.opcode_rm(), modrm_esi(0), off]);
+    }
+
+    /// `op al, imm8`
+    pub fn alu_al_imm(&mut self, op: AluOp, v: u8) {
+        self.bytes(&[op.opcode_imm(), v]);
+    }
+
+    /// `inc byte [esi+off]`
+    pub fn inc8(&mut self, off: u8) {
+        self.bytes(&[0xFE, modrm_esi(0), off]);
+    }
+
+    /// `dec byte [esi+off]`
+    pub fn dec8(&mut self, off: u8) {
+        self.bytes(&[0xFE, modrm_esi(1), off]);
+    }
+
+    /// `inc al`
+    pub fn inc_al(&mut self) {
+        self.bytes(&[0xFE, 0xC0]);
+    }
+
+    /// `dec al`
+    pub fn dec_al(&mut self) {
+        self.bytes(&[0xFE, 0xC8]);
+    }
+
+    /// `inc word [esi+off]`
+    pub fn inc16(&mut self, off: u8) {
+        self.bytes(&[0x66, 0xFF, modrm_esi(0), off]);
+    }
+
+    /// `dec word [esi+off]`
+    pub fn dec16(&mut self, off: u8) {
+        self.bytes(&[0x66, 0xFF, modrm_esi(1), off]);
+    }
+
+    /// `sub word [esi+off], imm8` (sign-extended)
+    pub fn sub16_imm8(&mut self, off: u8, v: i8) {
+        self.bytes(&[0x66, 0x83, modrm_esi(5), off, v as u8]);
+    }
+
+    /// `add word [esi+off], imm8` (sign-extended)
+    pub fn add16_imm8(&mut self, off: u8, v: i8) {
+        self.bytes(&[0x66, 0x83, modrm_esi(0), off, v as u8]);
+    }
+
+    /// `not byte [esi+off]`
+    pub fn not8(&mut self, off: u8) {
+        self.bytes(&[0xF6, modrm_esi(2), off]);
+    }
+
+    /// `and byte [esi+off], imm8`
+    pub fn and8_imm(&mut self, off: u8, v: u8) {
+        self.bytes(&[0x80, modrm_esi(4), off, v]);
+    }
+
+    /// `or byte [esi+off], imm8`
+    pub fn or8_imm(&mut self, off: u8, v: u8) {
+        self.bytes(&[0x80, modrm_esi(1), off, v]);
+    }
+
+    /// `xor byte [esi+off], imm8`
+    pub fn xor8_imm(&mut self, off: u8, v: u8) {
+        self.bytes(&[0x80, modrm_esi(6), off, v]);
+    }
+
+    /// `test byte [esi+off], imm8`
+    pub fn test8_imm(&mut self, off: u8, v: u8) {
+        self.bytes(&[0xF6, modrm_esi(0), off, v]);
+    }
+
+    /// `bt dword [esi+off], bit` - copy a guest bit into CF
+    pub fn bt_mem(&mut self, off: u8, bit: u8) {
+        self.bytes(&[0x0F, 0xBA, modrm_esi(4), off, bit]);
+    }
+
+    // =========================================================================
+    // Flag translation
+    // =========================================================================
+
+    /// `lahf; movzx ecx, ah; mov dl, [ecx+table]`
+    ///
+    /// Leaves the translated flags in DL.
+    pub fn flags_to_dl(&mut self, table: u32) {
+        self.bytes(&[0x9F, 0x0F, 0xB6, 0xCC, 0x8A, 0x91]);
+        self.dword(table);
+    }
+
+    /// `and dl, imm8`
+    pub fn and_dl(&mut self, v: u8) {
+        self.bytes(&[0x80, 0xE2, v]);
+    }
+
+    /// `or dl, imm8`
+    pub fn or_dl(&mut self, v: u8) {
+        self.bytes(&[0x80, 0xCA, v]);
+    }
+
+    /// `and cl, imm8`
+    pub fn and_cl(&mut self, v: u8) {
+        self.bytes(&[0x80, 0xE1, v]);
+    }
+
+    /// `or dl, cl`
+    pub fn or_dl_cl(&mut self) {
+        self.bytes(&[0x08, 0xCA]);
+    }
+
+    // =========================================================================
+    // Calls and control flow
+    // =========================================================================
+
+    /// `push eax`
+    pub fn push_eax(&mut self) {
+        self.byte(0x50);
+    }
+
+    /// `push esi`
+    pub fn push_esi(&mut self) {
+        self.byte(0x56);
+    }
+
+    /// `push imm32`
+    pub fn push_imm32(&mut self, v: u32) {
+        self.byte(0x68);
+        self.dword(v);
+    }
+
+    /// `mov eax, target; call eax; add esp, args*4`
+    pub fn call_abs(&mut self, target: u32, args: u8) {
+        self.byte(0xB8);
+        self.dword(target);
+        self.bytes(&[0xFF, 0xD0, 0x83, 0xC4, args * 4]);
+    }
+
+    /// `jcc rel32` with a placeholder target; returns the patch position
+    pub fn jcc_forward(&mut self, cond: Cond) -> usize {
+        self.bytes(&[0x0F, 0x80 | cond as u8]);
+        let at = self.pos;
+        self.dword(0);
+        at
+    }
+
+    /// Point a `jcc_forward` placeholder at the current position
+    pub fn patch_here(&mut self, at: usize) {
+        if self.overflow {
+            return;
+        }
+        let rel = (self.pos as i32 - (at as i32 + 4)) as u32;
+        self.buf[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+}
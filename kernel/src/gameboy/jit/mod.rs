@@ -0,0 +1,344 @@
+//! Dynamic Recompiler (SM83 -> i686)
+//!
+//! Translates basic blocks of Game Boy code into native i686 code and
+//! runs them instead of interpreting instruction by instruction. The
+//! interpreter in `cpu.rs` stays the reference: anything the translator
+//! does not handle simply ends the block, and the CPU falls back to the
+//! interpreter for that instruction.
+//!
+//! # Design
+//!
+//! - Guest registers live in a `JitContext`; generated code addresses it
+//!   through ESI and keeps nothing in host registers between instructions
+//! - Memory operands call back into `MMU::rb`/`MMU::wb`
+//! - Each block exit stores the next PC and the cycles spent on that path;
+//!   the CPU then advances the rest of the hardware by that amount in one
+//!   `MMU::do_cycle` call (cycle accounting at block exits). A block whose
+//!   longest path would run past the next hardware event is interpreted
+//!   instead, so timer edges and PPU mode changes are never skipped.
+//!   Stores that may reach an I/O register end their block, so the next
+//!   event cannot move while a block is running
+//! - Blocks are cached by (ROM bank, PC). Blocks compiled from RAM are
+//!   watched through `CodeWatch` and dropped when their bytes are written
+//! - The code buffer is allocated once; when it fills up the whole cache
+//!   is flushed and translation starts over
+//!
+//! Only code in ROM and WRAM bank 0 (0xC000-0xCFFF) is compiled. HRAM
+//! shares its page with the I/O registers and usually holds nothing but
+//! the short OAM DMA routine, so it is left to the interpreter.
+//!
+//! The generated code is i686 only and assumes the flat, identity-mapped,
+//! non-NX memory the kernel runs in.
+
+extern crate alloc;
+
+mod emitter;
+mod translate;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use super::mmu::MMU;
+use super::register::Registers;
+use emitter::Emitter;
+use translate::Translator;
+
+/// Size of the native code buffer
+const CODE_SIZE: usize = 128 * 1024;
+
+/// Block cache slots (direct-mapped)
+const BLOCK_SLOTS: usize = 4096;
+
+/// Worst-case native size of one block, checked before translating
+const MAX_BLOCK_BYTES: usize = 2048;
+
+/// Byte offsets of the guest registers inside `JitContext`
+///
+/// Register pairs are stored low byte first so 16-bit operations can
+/// address them as little-endian words.
+pub mod ctx {
+    pub const F: u8 = 0;
+    pub const A: u8 = 1;
+    pub const C: u8 = 2;
+    pub const B: u8 = 3;
+    pub const E: u8 = 4;
+    pub const D: u8 = 5;
+    pub const L: u8 = 6;
+    pub const H: u8 = 7;
+    pub const BC: u8 = 2;
+    pub const DE: u8 = 4;
+    pub const HL: u8 = 6;
+    pub const SP: u8 = 8;
+    pub const PC: u8 = 10;
+    pub const CYCLES: u8 = 12;
+    pub const SCRATCH: u8 = 20;
+}
+
+/// Guest state shared with generated code (layout fixed by `ctx`)
+#[repr(C)]
+pub struct JitContext {
+    f: u8,
+    a: u8,
+    c: u8,
+    b: u8,
+    e: u8,
+    d: u8,
+    l: u8,
+    h: u8,
+    sp: u16,
+    pc: u16,
+    cycles: u32,
+    mmu: *mut MMU,
+    scratch: u8,
+}
+
+impl JitContext {
+    fn load(reg: &Registers, mmu: *mut MMU) -> JitContext {
+        JitContext {
+            f: reg.f,
+            a: reg.a,
+            c: reg.c,
+            b: reg.b,
+            e: reg.e,
+            d: reg.d,
+            l: reg.l,
+            h: reg.h,
+            sp: reg.sp,
+            pc: reg.pc,
+            cycles: 0,
+            mmu,
+            scratch: 0,
+        }
+    }
+
+    fn store(&self, reg: &mut Registers) {
+        reg.f = self.f & 0xF0;
+        reg.a = self.a;
+        reg.c = self.c;
+        reg.b = self.b;
+        reg.e = self.e;
+        reg.d = self.d;
+        reg.l = self.l;
+        reg.h = self.h;
+        reg.sp = self.sp;
+        reg.pc = self.pc;
+    }
+}
+
+/// Guest F value for every possible x86 `lahf` result
+///
+/// AH = SF ZF 0 AF 0 PF 1 CF; the guest wants Z at bit 7, H at bit 5
+/// and C at bit 4. N is added separately by the translator.
+static LAHF_TO_F: [u8; 256] = build_lahf_table();
+
+const fn build_lahf_table() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut ah = 0;
+    while ah < 256 {
+        let mut f = 0u8;
+        if ah & 0x40 != 0 {
+            f |= 0x80;
+        }
+        if ah & 0x10 != 0 {
+            f |= 0x20;
+        }
+        if ah & 0x01 != 0 {
+            f |= 0x10;
+        }
+        table[ah] = f;
+        ah += 1;
+    }
+    table
+}
+
+/// Memory read helper called from generated code
+extern "C" fn jit_read(ctx: *mut JitContext, addr: u32) -> u32 {
+    unsafe { (*(*ctx).mmu).rb(addr as u16) as u32 }
+}
+
+/// Memory write helper called from generated code
+extern "C" fn jit_write(ctx: *mut JitContext, addr: u32, value: u32) {
+    unsafe { (*(*ctx).mmu).wb(addr as u16, value as u8) }
+}
+
+// =============================================================================
+// RAM code invalidation
+// =============================================================================
+
+/// Size of the unit `CodeWatch` tracks
+const WATCH_LINE: u32 = 16;
+
+/// Tracks which 16-byte lines of 0x8000-0xFFFF hold compiled code
+///
+/// Lives in the MMU so every write (from the interpreter or from a block)
+/// is checked. A hit only sets a flag; the JIT drops its RAM blocks before
+/// the next lookup. Lines are small so data stored next to code (a loop
+/// counter, a stack frame) doesn't keep throwing the code away.
+pub struct CodeWatch {
+    lines: [u32; 64],
+    pub hit: bool,
+}
+
+impl CodeWatch {
+    pub fn new() -> CodeWatch {
+        CodeWatch { lines: [0; 64], hit: false }
+    }
+
+    /// Check a guest write
+    #[inline]
+    pub fn check(&mut self, addr: u16) {
+        // Echo RAM aliases 0xC000-0xDDFF
+        let addr = match addr {
+            0xE000..=0xFDFF => addr - 0x2000,
+            _ => addr,
+        };
+        if addr >= 0x8000 {
+            let line = ((addr as u32 - 0x8000) / WATCH_LINE) as usize;
+            if self.lines[line >> 5] & (1 << (line & 31)) != 0 {
+                self.hit = true;
+            }
+        }
+    }
+
+    /// Start watching the lines covering `start..end`
+    fn watch(&mut self, start: u16, end: u32) {
+        let mut addr = start as u32 & !(WATCH_LINE - 1);
+        while addr < end {
+            let line = ((addr - 0x8000) / WATCH_LINE) as usize;
+            self.lines[line >> 5] |= 1 << (line & 31);
+            addr += WATCH_LINE;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.lines = [0; 64];
+        self.hit = false;
+    }
+}
+
+// =============================================================================
+// Block cache
+// =============================================================================
+
+#[derive(Clone, Copy)]
+enum Block {
+    Empty,
+    /// Native code at this offset in the code buffer, taking at most
+    /// `cycles` guest cycles
+    Native { key: u32, offset: u32, cycles: u32 },
+    /// First instruction can't be translated; always interpret
+    Interpret { key: u32 },
+}
+
+/// Dynamic recompiler state
+pub struct Jit {
+    code: Box<[u8]>,
+    used: usize,
+    blocks: Box<[Block]>,
+}
+
+impl Jit {
+    pub fn new() -> Jit {
+        Jit {
+            code: vec![0u8; CODE_SIZE].into_boxed_slice(),
+            used: 0,
+            blocks: vec![Block::Empty; BLOCK_SLOTS].into_boxed_slice(),
+        }
+    }
+
+    /// Drop every compiled block
+    pub fn flush(&mut self, mmu: &mut MMU) {
+        self.blocks.fill(Block::Empty);
+        self.used = 0;
+        mmu.code_watch.clear();
+    }
+
+    /// Drop blocks compiled from RAM (after a write to a watched line)
+    pub fn flush_ram(&mut self, mmu: &mut MMU) {
+        for block in self.blocks.iter_mut() {
+            let key = match *block {
+                Block::Native { key, .. } | Block::Interpret { key } => key,
+                Block::Empty => continue,
+            };
+            if key & 0xFFFF >= 0x8000 {
+                *block = Block::Empty;
+            }
+        }
+        mmu.code_watch.clear();
+    }
+
+    /// Run the block at PC
+    ///
+    /// Returns the guest cycles spent, or `None` if the instruction at PC
+    /// must be interpreted instead. That includes blocks that could run
+    /// past the next hardware event: the MMU is only advanced after the
+    /// block and must not step over an event in one go.
+    pub fn run(&mut self, reg: &mut Registers, mmu: &mut MMU) -> Option<u32> {
+        if mmu.code_watch.hit {
+            self.flush_ram(mmu);
+        }
+
+        let pc = reg.pc;
+        let limit = code_region_end(pc)?;
+        let key = ((mmu.mbc.rom_bank_at(pc) as u32) << 16) | pc as u32;
+        let slot = (key ^ (key >> 12)) as usize % BLOCK_SLOTS;
+
+        let (offset, cycles) = match self.blocks[slot] {
+            Block::Native { key: k, offset, cycles } if k == key => (offset, cycles),
+            Block::Interpret { key: k } if k == key => return None,
+            _ => {
+                let block = self.compile(mmu, pc, key, limit);
+                self.blocks[slot] = block;
+                match block {
+                    Block::Native { offset, cycles, .. } => (offset, cycles),
+                    _ => return None,
+                }
+            }
+        };
+        if cycles > mmu.next_event().cycles {
+            return None;
+        }
+
+        let mut context = JitContext::load(reg, mmu as *mut MMU);
+        unsafe {
+            let entry: extern "C" fn(*mut JitContext) =
+                core::mem::transmute(self.code.as_ptr().add(offset as usize));
+            entry(&mut context);
+        }
+        context.store(reg);
+        Some(context.cycles)
+    }
+
+    fn compile(&mut self, mmu: &mut MMU, pc: u16, key: u32, limit: u32) -> Block {
+        if self.used + MAX_BLOCK_BYTES > CODE_SIZE {
+            self.flush(mmu);
+        }
+
+        let start = self.used;
+        let mut e = Emitter::new(&mut self.code[..], start);
+        let (count, end, cycles) = {
+            let mut t = Translator::new(&mut e, mmu, pc, limit);
+            let count = t.translate();
+            (count, t.end(), t.worst_cycles())
+        };
+        if count == 0 || e.overflowed() {
+            return Block::Interpret { key };
+        }
+        self.used = e.pos();
+
+        if pc >= 0x8000 {
+            // Include the last instruction's bytes (end points past them)
+            mmu.code_watch.watch(pc, (end as u32).max(pc as u32 + 1).min(limit));
+        }
+        Block::Native { key, offset: start as u32, cycles }
+    }
+}
+
+/// End of the code region containing `pc`, or `None` if it isn't compiled
+fn code_region_end(pc: u16) -> Option<u32> {
+    match pc {
+        0x0000..=0x3FFF => Some(0x4000),
+        0x4000..=0x7FFF => Some(0x8000),
+        0xC000..=0xCFFF => Some(0xD000),
+        _ => None,
+    }
+}
@@ -0,0 +1,588 @@
+//! SM83 Block Translator
+//!
+//! Decodes one basic block of SM83 code and emits equivalent i686 code.
+//! Translation stops at the first branch (which becomes the block exit),
+//! at the first instruction the translator does not handle, or after
+//! `MAX_BLOCK_INSTRS` instructions.
+//!
+//! Handled natively: register/immediate loads, 8-bit ALU, INC/DEC,
+//! 16-bit INC/DEC, CPL/SCF/CCF, JP/JR (plain and conditional), CALL,
+//! RET and RST. Memory operands go through `jit_read`/`jit_write`, except
+//! for statically addressed I/O registers, which are left to the
+//! interpreter. Everything else (CB prefix, EI/DI/HALT/STOP, rotates,
+//! 16-bit ADD, ...) ends the block.
+//!
+//! The rest of the hardware only catches up at block exits, so I/O
+//! registers are current only for the first instruction. Loads through a
+//! register pair may hit LY/STAT and are translated only in that position.
+//! A store through a register pair may hit an MBC or I/O register (TAC,
+//! DIV, IF, IE, LCDC, ...) and move the next event, so the block ends
+//! right after it.
+
+use super::emitter::{AluOp, Cond, Emitter};
+use super::{ctx, jit_read, jit_write, JitContext, LAHF_TO_F};
+use crate::gameboy::mmu::MMU;
+
+/// Longest block in guest instructions (bounds interrupt latency)
+pub const MAX_BLOCK_INSTRS: u32 = 24;
+
+/// Guest flag bits in F
+const FLAG_Z: u8 = 0x80;
+const FLAG_N: u8 = 0x40;
+const FLAG_H: u8 = 0x20;
+const FLAG_C: u8 = 0x10;
+
+/// Context offset of each SM83 register index (B, C, D, E, H, L, -, A)
+const REG8: [u8; 8] = [ctx::B, ctx::C, ctx::D, ctx::E, ctx::H, ctx::L, 0xFF, ctx::A];
+
+/// Context offset of each 16-bit register index (BC, DE, HL, SP)
+const REG16: [u8; 4] = [ctx::BC, ctx::DE, ctx::HL, ctx::SP];
+
+/// How an ALU result maps onto the guest flags
+#[derive(Clone, Copy)]
+enum FlagMode {
+    /// Z, H, C straight from x86 (ADD/ADC/SUB/SBC/CP)
+    Arith,
+    /// Z and H from x86, guest C preserved (INC/DEC)
+    KeepCarry,
+    /// Z only; H forced to the given value, C cleared (AND/OR/XOR)
+    Logic(bool),
+}
+
+/// Result of translating one instruction
+enum Step {
+    /// Instruction emitted, keep going
+    Next,
+    /// Block exit emitted
+    End,
+    /// Instruction emitted; end the block right after it
+    Last,
+    /// Instruction not handled; exit before it
+    Stop,
+}
+
+pub struct Translator<'a, 'b> {
+    e: &'a mut Emitter<'b>,
+    mmu: &'a MMU,
+    /// Address of the instruction being translated
+    pc: u16,
+    /// First address past the code region the block may read from
+    limit: u32,
+    /// Guest cycles of everything emitted so far
+    cycles: u32,
+    /// Most cycles any exit emitted so far reports
+    worst_cycles: u32,
+}
+
+impl<'a, 'b> Translator<'a, 'b> {
+    pub fn new(e: &'a mut Emitter<'b>, mmu: &'a MMU, pc: u16, limit: u32) -> Self {
+        Translator { e, mmu, pc, limit, cycles: 0, worst_cycles: 0 }
+    }
+
+    /// Translate the block at `pc`; returns the number of guest instructions
+    ///
+    /// Returns 0 when the first instruction is unsupported, in which case
+    /// the emitted code must be discarded.
+    pub fn translate(&mut self) -> u32 {
+        self.e.prologue();
+
+        let mut count = 0;
+        loop {
+            if count == MAX_BLOCK_INSTRS {
+                self.exit(self.pc, self.cycles);
+                break;
+            }
+            match self.step() {
+                Step::Next => count += 1,
+                Step::End => {
+                    count += 1;
+                    break;
+                }
+                Step::Last => {
+                    count += 1;
+                    self.exit(self.pc, self.cycles);
+                    break;
+                }
+                Step::Stop => {
+                    if count > 0 {
+                        self.exit(self.pc, self.cycles);
+                    }
+                    break;
+                }
+            }
+        }
+        count
+    }
+
+    /// First address after the last instruction translated so far
+    pub fn end(&self) -> u16 {
+        self.pc
+    }
+
+    /// Guest cycles of the longest path through the block
+    pub fn worst_cycles(&self) -> u32 {
+        self.worst_cycles
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.mmu.peek(self.pc.wrapping_add(offset))
+    }
+
+    /// Emit a block exit: store the next PC and the cycles spent
+    fn exit(&mut self, pc: u16, cycles: u32) {
+        self.e.store_imm16(ctx::PC, pc);
+        self.e.store_imm32(ctx::CYCLES, cycles);
+        self.e.epilogue();
+        self.worst_cycles = self.worst_cycles.max(cycles);
+    }
+
+    /// Translate the instruction at `self.pc`
+    fn step(&mut self) -> Step {
+        let op = self.peek(0);
+        let len = instr_len(op);
+        if self.pc as u32 + len as u32 > self.limit {
+            return Step::Stop;
+        }
+        let n = self.peek(1);
+        let nn = u16::from_le_bytes([n, self.peek(2)]);
+        let next = self.pc.wrapping_add(len);
+
+        // After the first instruction the I/O registers are stale
+        if self.cycles != 0 && reads_indirect(op) {
+            return Step::Stop;
+        }
+        let (step, cycles) = match op {
+            0x00 => (Step::Next, 4), // NOP
+
+            // LD rr,nn
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                self.e.store_imm16(REG16[(op >> 4) as usize], nn);
+                (Step::Next, 12)
+            }
+
+            // INC rr / DEC rr
+            0x03 | 0x13 | 0x23 | 0x33 => {
+                self.e.inc16(REG16[(op >> 4) as usize]);
+                (Step::Next, 8)
+            }
+            0x0B | 0x1B | 0x2B | 0x3B => {
+                self.e.dec16(REG16[(op >> 4) as usize]);
+                (Step::Next, 8)
+            }
+
+            // INC r / DEC r
+            0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x3C => {
+                self.e.inc8(REG8[(op >> 3) as usize]);
+                self.flags(FlagMode::KeepCarry, false);
+                (Step::Next, 4)
+            }
+            0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x3D => {
+                self.e.dec8(REG8[(op >> 3) as usize]);
+                self.flags(FlagMode::KeepCarry, true);
+                (Step::Next, 4)
+            }
+
+            // INC (HL) / DEC (HL)
+            0x34 | 0x35 => {
+                self.read_hl();
+                if op == 0x34 {
+                    self.e.inc_al();
+                } else {
+                    self.e.dec_al();
+                }
+                self.flags(FlagMode::KeepCarry, op == 0x35);
+                // Result byte is still in AL; write it back through the MMU
+                self.e.push_eax();
+                self.e.load_eax_u16(ctx::HL);
+                self.e.push_eax();
+                self.e.push_esi();
+                self.e.call_abs(write_helper(jit_write), 3);
+                (Step::Last, 12)
+            }
+
+            // LD r,n
+            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x3E => {
+                self.e.store_imm8(REG8[(op >> 3) as usize], n);
+                (Step::Next, 8)
+            }
+
+            // LD (HL),n
+            0x36 => {
+                self.e.push_imm32(n as u32);
+                self.write_to_hl_pushed();
+                (Step::Last, 12)
+            }
+
+            // LD (BC),A / LD (DE),A / LD (HL+),A / LD (HL-),A
+            0x02 | 0x12 | 0x22 | 0x32 => {
+                self.e.load_eax_u8(ctx::A);
+                self.e.push_eax();
+                self.e.load_eax_u16(REG16[((op >> 4) as usize).min(2)]);
+                self.e.push_eax();
+                self.e.push_esi();
+                self.e.call_abs(write_helper(jit_write), 3);
+                self.post_hl(op);
+                (Step::Last, 8)
+            }
+
+            // LD A,(BC) / LD A,(DE) / LD A,(HL+) / LD A,(HL-)
+            0x0A | 0x1A | 0x2A | 0x3A => {
+                self.e.load_eax_u16(REG16[((op >> 4) as usize).min(2)]);
+                self.call_read();
+                self.e.store_al(ctx::A);
+                self.post_hl(op);
+                (Step::Next, 8)
+            }
+
+            // CPL / SCF / CCF
+            0x2F => {
+                self.e.not8(ctx::A);
+                self.e.or8_imm(ctx::F, FLAG_N | FLAG_H);
+                (Step::Next, 4)
+            }
+            0x37 => {
+                self.e.and8_imm(ctx::F, FLAG_Z);
+                self.e.or8_imm(ctx::F, FLAG_C);
+                (Step::Next, 4)
+            }
+            0x3F => {
+                self.e.and8_imm(ctx::F, FLAG_Z | FLAG_C);
+                self.e.xor8_imm(ctx::F, FLAG_C);
+                (Step::Next, 4)
+            }
+
+            // HALT sits in the middle of the LD block; leave it to the interpreter
+            0x76 => (Step::Stop, 0),
+
+            // LD (HL),r
+            0x70..=0x77 => {
+                self.e.load_eax_u8(REG8[(op & 7) as usize]);
+                self.e.push_eax();
+                self.write_to_hl_pushed();
+                (Step::Last, 8)
+            }
+
+            // LD r,(HL)
+            0x46 | 0x4E | 0x56 | 0x5E | 0x66 | 0x6E | 0x7E => {
+                self.read_hl();
+                self.e.store_al(REG8[((op >> 3) & 7) as usize]);
+                (Step::Next, 8)
+            }
+
+            // LD r,r'
+            0x40..=0x7F => {
+                let dst = REG8[((op >> 3) & 7) as usize];
+                let src = REG8[(op & 7) as usize];
+                if dst != src {
+                    self.e.load_al(src);
+                    self.e.store_al(dst);
+                }
+                (Step::Next, 4)
+            }
+
+            // ALU A,r / ALU A,(HL)
+            0x80..=0xBF => {
+                let alu = alu_op(op >> 3);
+                if op & 7 == 6 {
+                    self.read_hl();
+                    // Park the operand in the context so A can be loaded into AL
+                    self.e.store_al(ctx::SCRATCH);
+                    self.alu(alu, Operand::Mem(ctx::SCRATCH));
+                    (Step::Next, 8)
+                } else {
+                    self.alu(alu, Operand::Mem(REG8[(op & 7) as usize]));
+                    (Step::Next, 4)
+                }
+            }
+
+            // ALU A,n
+            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+                self.alu(alu_op((op >> 3) & 7), Operand::Imm(n));
+                (Step::Next, 8)
+            }
+
+            // LDH (n),A / LDH A,(n) - HRAM only, I/O and IE go to the interpreter
+            0xE0 if (0x80..0xFF).contains(&n) => {
+                self.e.load_eax_u8(ctx::A);
+                self.e.push_eax();
+                self.e.push_imm32(0xFF00 | n as u32);
+                self.e.push_esi();
+                self.e.call_abs(write_helper(jit_write), 3);
+                (Step::Next, 12)
+            }
+            0xF0 if (0x80..0xFF).contains(&n) => {
+                self.e.push_imm32(0xFF00 | n as u32);
+                self.e.push_esi();
+                self.e.call_abs(read_helper(jit_read), 2);
+                self.e.store_al(ctx::A);
+                (Step::Next, 12)
+            }
+
+            // LD (nn),A / LD A,(nn) - RAM only (no MBC or I/O registers)
+            0xEA if (0x8000..0xFE00).contains(&nn) => {
+                self.e.load_eax_u8(ctx::A);
+                self.e.push_eax();
+                self.e.push_imm32(nn as u32);
+                self.e.push_esi();
+                self.e.call_abs(write_helper(jit_write), 3);
+                (Step::Next, 16)
+            }
+            0xFA if (0x8000..0xFE00).contains(&nn) => {
+                self.e.push_imm32(nn as u32);
+                self.e.push_esi();
+                self.e.call_abs(read_helper(jit_read), 2);
+                self.e.store_al(ctx::A);
+                (Step::Next, 16)
+            }
+
+            // JP nn / JR e
+            0xC3 => {
+                self.exit(nn, self.cycles + 16);
+                (Step::End, 0)
+            }
+            0x18 => {
+                self.exit(jr_target(next, n), self.cycles + 12);
+                (Step::End, 0)
+            }
+
+            // JR cc,e / JP cc,nn
+            0x20 | 0x28 | 0x30 | 0x38 => {
+                self.branch(op, jr_target(next, n), next, 12, 8);
+                (Step::End, 0)
+            }
+            0xC2 | 0xCA | 0xD2 | 0xDA => {
+                self.branch(op, nn, next, 16, 12);
+                (Step::End, 0)
+            }
+
+            // CALL nn / RST
+            0xCD => {
+                self.push_word(next);
+                self.exit(nn, self.cycles + 24);
+                (Step::End, 0)
+            }
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+                self.push_word(next);
+                self.exit((op & 0x38) as u16, self.cycles + 16);
+                (Step::End, 0)
+            }
+
+            // RET - target is only known at run time
+            0xC9 => {
+                self.pop_to_pc();
+                self.e.store_imm32(ctx::CYCLES, self.cycles + 16);
+                self.e.epilogue();
+                self.worst_cycles = self.worst_cycles.max(self.cycles + 16);
+                (Step::End, 0)
+            }
+
+            _ => (Step::Stop, 0),
+        };
+
+        if let Step::Stop = step {
+            return step;
+        }
+        self.cycles += cycles;
+        self.pc = next;
+        step
+    }
+
+    // =========================================================================
+    // Helpers
+    // =========================================================================
+
+    /// Call `jit_read(ctx, eax)`; the byte read ends up in AL
+    fn call_read(&mut self) {
+        self.e.push_eax();
+        self.e.push_esi();
+        self.e.call_abs(read_helper(jit_read), 2);
+    }
+
+    /// Read the byte at (HL) into AL
+    fn read_hl(&mut self) {
+        self.e.load_eax_u16(ctx::HL);
+        self.call_read();
+    }
+
+    /// Write the already pushed value to (HL)
+    fn write_to_hl_pushed(&mut self) {
+        self.e.load_eax_u16(ctx::HL);
+        self.e.push_eax();
+        self.e.push_esi();
+        self.e.call_abs(write_helper(jit_write), 3);
+    }
+
+    /// HL post-increment/decrement for the (HL+)/(HL-) forms
+    fn post_hl(&mut self, op: u8) {
+        match op >> 4 {
+            2 => self.e.inc16(ctx::HL),
+            3 => self.e.dec16(ctx::HL),
+            _ => {}
+        }
+    }
+
+    /// Push a constant word onto the guest stack (low byte at SP, like `MMU::ww`)
+    fn push_word(&mut self, value: u16) {
+        self.e.sub16_imm8(ctx::SP, 2);
+        for (i, byte) in value.to_le_bytes().iter().enumerate() {
+            self.e.push_imm32(*byte as u32);
+            self.e.load_eax_u16(ctx::SP);
+            if i == 1 {
+                // SP+1, wrapped to 16 bits by the read/write helpers
+                self.e.push_eax();
+                self.e.push_esi();
+                self.e.call_abs(write_helper(jit_write_next), 3);
+            } else {
+                self.e.push_eax();
+                self.e.push_esi();
+                self.e.call_abs(write_helper(jit_write), 3);
+            }
+        }
+    }
+
+    /// Pop a word from the guest stack into PC
+    fn pop_to_pc(&mut self) {
+        self.e.load_eax_u16(ctx::SP);
+        self.call_read();
+        self.e.store_al(ctx::PC);
+        self.e.load_eax_u16(ctx::SP);
+        self.e.push_eax();
+        self.e.push_esi();
+        self.e.call_abs(read_helper(jit_read_next), 2);
+        self.e.store_al(ctx::PC + 1);
+        self.e.add16_imm8(ctx::SP, 2);
+    }
+
+    /// Conditional branch: two exits selected by a guest flag
+    fn branch(&mut self, op: u8, taken: u16, not_taken: u16, taken_cycles: u32, skip_cycles: u32) {
+        // Bits 4-3 of the opcode: NZ, Z, NC, C
+        let (flag, jump_if_set) = match (op >> 3) & 3 {
+            0 => (FLAG_Z, false),
+            1 => (FLAG_Z, true),
+            2 => (FLAG_C, false),
+            _ => (FLAG_C, true),
+        };
+
+        self.e.test8_imm(ctx::F, flag);
+        // Skip the "taken" exit when the condition fails
+        let cond = if jump_if_set { Cond::Zero } else { Cond::NotZero };
+        let patch = self.e.jcc_forward(cond);
+        self.exit(taken, self.cycles + taken_cycles);
+        self.e.patch_here(patch);
+        self.exit(not_taken, self.cycles + skip_cycles);
+    }
+
+    /// `A = A op operand` (or just compare) with guest flag update
+    fn alu(&mut self, op: AluOp, operand: Operand) {
+        // ADC/SBC consume the guest carry
+        if matches!(op, AluOp::Adc | AluOp::Sbb) {
+            self.e.bt_mem(ctx::F, 4);
+        }
+        self.e.load_al(ctx::A);
+        match operand {
+            Operand::Mem(off) => self.e.alu_al_mem(op, off),
+            Operand::Imm(v) => self.e.alu_al_imm(op, v),
+        }
+        let mode = match op {
+            AluOp::And => FlagMode::Logic(true),
+            AluOp::Or | AluOp::Xor => FlagMode::Logic(false),
+            _ => FlagMode::Arith,
+        };
+        let subtract = matches!(op, AluOp::Sub | AluOp::Sbb | AluOp::Cmp);
+        self.flags(mode, subtract);
+        if op != AluOp::Cmp {
+            self.e.store_al(ctx::A);
+        }
+    }
+
+    /// Translate the x86 flags of the last operation into guest F
+    fn flags(&mut self, mode: FlagMode, subtract: bool) {
+        self.e.flags_to_dl(LAHF_TO_F.as_ptr() as usize as u32);
+        match mode {
+            FlagMode::Arith => {}
+            FlagMode::KeepCarry => {
+                self.e.and_dl(FLAG_Z | FLAG_H);
+                self.e.load_cl(ctx::F);
+                self.e.and_cl(FLAG_C);
+                self.e.or_dl_cl();
+            }
+            FlagMode::Logic(half) => {
+                self.e.and_dl(FLAG_Z);
+                if half {
+                    self.e.or_dl(FLAG_H);
+                }
+            }
+        }
+        if subtract {
+            self.e.or_dl(FLAG_N);
+        }
+        self.e.store_dl(ctx::F);
+    }
+}
+
+/// Second ALU operand
+#[derive(Clone, Copy)]
+enum Operand {
+    Mem(u8),
+    Imm(u8),
+}
+
+/// ALU operation from opcode bits 5-3
+fn alu_op(bits: u8) -> AluOp {
+    match bits & 7 {
+        0 => AluOp::Add,
+        1 => AluOp::Adc,
+        2 => AluOp::Sub,
+        3 => AluOp::Sbb,
+        4 => AluOp::And,
+        5 => AluOp::Xor,
+        6 => AluOp::Or,
+        _ => AluOp::Cmp,
+    }
+}
+
+/// True for the loads through HL, BC or DE (including INC/DEC (HL))
+fn reads_indirect(op: u8) -> bool {
+    match op {
+        0x0A | 0x1A | 0x2A | 0x3A | 0x34 | 0x35 => true,
+        0x46 | 0x4E | 0x56 | 0x5E | 0x66 | 0x6E | 0x7E => true,
+        0x80..=0xBF => op & 7 == 6,
+        _ => false,
+    }
+}
+
+fn jr_target(next: u16, n: u8) -> u16 {
+    next.wrapping_add(n as i8 as u16)
+}
+
+/// Address of a read helper, as called by generated code
+fn read_helper(f: extern "C" fn(*mut JitContext, u32) -> u32) -> u32 {
+    f as usize as u32
+}
+
+/// Address of a write helper, as called by generated code
+fn write_helper(f: extern "C" fn(*mut JitContext, u32, u32)) -> u32 {
+    f as usize as u32
+}
+
+/// Read the byte after `addr` (for the high byte of a stack pop)
+extern "C" fn jit_read_next(ctx: *mut JitContext, addr: u32) -> u32 {
+    jit_read(ctx, addr.wrapping_add(1) & 0xFFFF)
+}
+
+/// Write the byte after `addr` (for the high byte of a stack push)
+extern "C" fn jit_write_next(ctx: *mut JitContext, addr: u32, value: u32) {
+    jit_write(ctx, addr.wrapping_add(1) & 0xFFFF, value)
+}
+
+/// Instruction length in bytes, from the opcode alone
+pub fn instr_len(op: u8) -> u16 {
+    match op {
+        0x01 | 0x11 | 0x21 | 0x31 | 0x08 | 0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCC | 0xCD | 0xD2
+        | 0xD4 | 0xDA | 0xDC | 0xEA | 0xFA => 3,
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E | 0x10 | 0x18 | 0x20 | 0x28
+        | 0x30 | 0x38 | 0xC6 | 0xCB | 0xCE | 0xD6 | 0xDE | 0xE0 | 0xE6 | 0xE8 | 0xEE | 0xF0
+        | 0xF6 | 0xF8 | 0xFE => 2,
+        _ => 1,
+    }
+}
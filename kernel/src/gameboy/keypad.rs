@@ -16,6 +16,7 @@ pub enum KeypadKey {
 }
 
 /// Keypad state
+#[derive(Clone)]
 pub struct Keypad {
     /// Direction buttons (right, left, up, down)
     row0: u8,
@@ -23,6 +23,10 @@ impl MBC for MBC0 {
         *self.rom.get(addr as usize).unwrap_or(&0xFF)
     }
 
+    fn rom_bank_at(&self, _addr: u16) -> usize {
+        0
+    }
+
     fn readram(&self, _addr: u16) -> u8 {
         0xFF
     }
@@ -47,6 +51,15 @@ impl MBC for MBC0 {
         Vec::new()
     }
 
+    fn save_state(&self, out: &mut Vec<u8>) {
+        // No banking state
+        out.clear();
+    }
+
+    fn load_state(&mut self, _data: &[u8]) {
+        // No banking state
+    }
+
     fn check_and_reset_ram_updated(&mut self) -> bool {
         false
     }
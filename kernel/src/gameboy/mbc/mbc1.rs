@@ -65,6 +65,15 @@ impl MBC for MBC1 {
         *self.rom.get(idx).unwrap_or(&0xFF)
     }
 
+    fn rom_bank_at(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3FFF if self.banking_mode == 0 => 0,
+            0x0000..=0x3FFF => self.rombank & 0xE0,
+            0x4000..=0x7FFF => self.rombank,
+            _ => 0,
+        }
+    }
+
     fn readram(&self, addr: u16) -> u8 {
         if !self.ram_on || self.rambanks == 0 {
             return 0xFF;
@@ -138,6 +147,28 @@ impl MBC for MBC1 {
         self.ram.clone()
     }
 
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.clear();
+        out.push(self.ram_on as u8);
+        out.push(self.ram_updated as u8);
+        out.push(self.banking_mode);
+        out.push(self.rombank as u8);
+        out.push(self.rambank as u8);
+        out.extend_from_slice(&self.ram);
+    }
+
+    fn load_state(&mut self, data: &[u8]) {
+        if data.len() != 5 + self.ram.len() {
+            return;
+        }
+        self.ram_on = data[0] != 0;
+        self.ram_updated = data[1] != 0;
+        self.banking_mode = data[2];
+        self.rombank = data[3] as usize;
+        self.rambank = data[4] as usize;
+        self.ram.copy_from_slice(&data[5..]);
+    }
+
     fn check_and_reset_ram_updated(&mut self) -> bool {
         let result = self.ram_updated;
         self.ram_updated = false;
@@ -76,6 +76,13 @@ impl MBC for MBC3 {
         *self.rom.get(idx).unwrap_or(&0xFF)
     }
 
+    fn rom_bank_at(&self, addr: u16) -> usize {
+        match addr {
+            0x4000..=0x7FFF => self.rombank,
+            _ => 0,
+        }
+    }
+
     fn readram(&self, addr: u16) -> u8 {
         if !self.ram_on {
             return 0xFF;
@@ -145,6 +152,34 @@ impl MBC for MBC3 {
         self.ram.clone()
     }
 
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.clear();
+        out.push(self.ram_on as u8);
+        out.push(self.ram_updated as u8);
+        out.push(self.rombank as u8);
+        out.push(self.rambank as u8);
+        out.push(self.selectrtc as u8);
+        out.push(self.rtc_latch);
+        out.extend_from_slice(&self.rtc_ram);
+        out.extend_from_slice(&self.rtc_ram_latch);
+        out.extend_from_slice(&self.ram);
+    }
+
+    fn load_state(&mut self, data: &[u8]) {
+        if data.len() != 16 + self.ram.len() {
+            return;
+        }
+        self.ram_on = data[0] != 0;
+        self.ram_updated = data[1] != 0;
+        self.rombank = data[2] as usize;
+        self.rambank = data[3] as usize;
+        self.selectrtc = data[4] != 0;
+        self.rtc_latch = data[5];
+        self.rtc_ram.copy_from_slice(&data[6..11]);
+        self.rtc_ram_latch.copy_from_slice(&data[11..16]);
+        self.ram.copy_from_slice(&data[16..]);
+    }
+
     fn check_and_reset_ram_updated(&mut self) -> bool {
         let result = self.ram_updated;
         self.ram_updated = false;
@@ -25,6 +25,11 @@ mod mbc5;
 pub trait MBC: Send {
     /// Read from ROM address space (0x0000-0x7FFF)
     fn readrom(&self, addr: u16) -> u8;
+
+    /// ROM bank currently mapped at `addr` (0 outside 0x0000-0x7FFF)
+    ///
+    /// Used by the recompiler to key translated blocks.
+    fn rom_bank_at(&self, addr: u16) -> usize;
     
     /// Read from external RAM (0xA000-0xBFFF)
     fn readram(&self, addr: u16) -> u8;
@@ -46,6 +51,14 @@ pub trait MBC: Send {
     
     /// Dump RAM contents (for saves)
     fn dumpram(&self) -> Vec<u8>;
+
+    /// Save banking registers and RAM into `out` (for in-memory snapshots)
+    ///
+    /// `out` is cleared first so the same buffer can be reused every frame.
+    fn save_state(&self, out: &mut Vec<u8>);
+
+    /// Restore state previously written by `save_state`
+    fn load_state(&mut self, data: &[u8]);
     
     /// Get ROM title from header
     fn romname(&self) -> String {
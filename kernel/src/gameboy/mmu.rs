@@ -14,10 +14,13 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use super::gbmode::{GbMode, GbSpeed};
-use super::gpu::GPU;
+use super::gpu::{GpuState, GPU};
+use super::jit::CodeWatch;
 use super::keypad::Keypad;
 use super::mbc;
+use super::scheduler::{Event, EventKind};
 use super::serial::Serial;
 use super::timer::Timer;
 use super::StrResult;
@@ -25,7 +28,7 @@ use super::StrResult;
 const WRAM_SIZE: usize = 0x8000;
 const ZRAM_SIZE: usize = 0x7F;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 enum DMAType {
     NoDMA,
     GDMA,
@@ -68,6 +71,60 @@ pub struct MMU {
     hdma_len: u8,
     // Undocumented CGB registers
     undocumented_cgb_regs: [u8; 3],
+    // Pages holding recompiled code
+    pub code_watch: CodeWatch,
+}
+
+/// Saved MMU state (see `MMU::save_state`)
+///
+/// Buffers are allocated once and reused, so taking a snapshot every frame
+/// does not touch the heap.
+pub struct MmuState {
+    wram: Box<[u8; WRAM_SIZE]>,
+    zram: [u8; ZRAM_SIZE],
+    wrambank: usize,
+    hdma: [u8; 4],
+    inte: u8,
+    intf: u8,
+    serial: Serial,
+    timer: Timer,
+    keypad: Keypad,
+    gpu: GpuState,
+    mbc: Vec<u8>,
+    gbmode: GbMode,
+    gbspeed: GbSpeed,
+    speed_switch_req: bool,
+    hdma_src: u16,
+    hdma_dst: u16,
+    hdma_status: DMAType,
+    hdma_len: u8,
+    undocumented_cgb_regs: [u8; 3],
+}
+
+impl MmuState {
+    pub fn new() -> MmuState {
+        MmuState {
+            wram: Box::new([0u8; WRAM_SIZE]),
+            zram: [0; ZRAM_SIZE],
+            wrambank: 1,
+            hdma: [0; 4],
+            inte: 0,
+            intf: 0,
+            serial: Serial::new(),
+            timer: Timer::new(),
+            keypad: Keypad::new(),
+            gpu: GpuState::new(),
+            mbc: Vec::new(),
+            gbmode: GbMode::Classic,
+            gbspeed: GbSpeed::Single,
+            speed_switch_req: false,
+            hdma_src: 0,
+            hdma_dst: 0,
+            hdma_status: DMAType::NoDMA,
+            hdma_len: 0xFF,
+            undocumented_cgb_regs: [0; 3],
+        }
+    }
 }
 
 /// Simple LCG for initializing RAM with "random" values
@@ -107,6 +164,7 @@ impl MMU {
             hdma_status: DMAType::NoDMA,
             hdma_len: 0xFF,
             undocumented_cgb_regs: [0; 3],
+            code_watch: CodeWatch::new(),
         };
 
         if res.rb(0x0143) == 0xC0 {
@@ -141,6 +199,7 @@ impl MMU {
             hdma_status: DMAType::NoDMA,
             hdma_len: 0xFF,
             undocumented_cgb_regs: [0; 3],
+            code_watch: CodeWatch::new(),
         };
         res.determine_mode();
         res.set_initial();
@@ -214,6 +273,21 @@ impl MMU {
         gputicks
     }
 
+    /// Find the next hardware event, in CPU cycles from now
+    pub fn next_event(&self) -> Event {
+        // GDMA runs on the very next cycle; never skip over it
+        if self.hdma_status == DMAType::GDMA {
+            return Event { kind: EventKind::Dma, cycles: 0 };
+        }
+
+        // PPU and serial count GPU cycles, which are half as long in double speed
+        let cpudivider = self.gbspeed as u32;
+        Event::idle()
+            .earliest(EventKind::Timer, self.timer.cycles_to_next_event())
+            .earliest(EventKind::Ppu, self.gpu.cycles_to_next_event().map(|c| c * cpudivider))
+            .earliest(EventKind::Serial, self.serial.cycles_to_next_event().map(|c| c * cpudivider))
+    }
+
     /// Read byte from memory
     pub fn rb(&mut self, a: u16) -> u8 {
         match a {
@@ -257,6 +331,7 @@ impl MMU {
 
     /// Write byte to memory
     pub fn wb(&mut self, a: u16, v: u8) {
+        self.code_watch.check(a);
         match a {
             0x0000..=0x7FFF => self.mbc.writerom(a, v),
             0x8000..=0x9FFF => self.gpu.wb(a, v),
@@ -426,6 +501,52 @@ impl MMU {
         self.speed_switch_req = false;
     }
 
+    /// Copy all emulation state into `s`
+    pub fn save_state(&self, s: &mut MmuState) {
+        s.wram.copy_from_slice(&self.wram[..]);
+        s.zram = self.zram;
+        s.wrambank = self.wrambank;
+        s.hdma = self.hdma;
+        s.inte = self.inte;
+        s.intf = self.intf;
+        s.serial = self.serial.clone();
+        s.timer = self.timer.clone();
+        s.keypad = self.keypad.clone();
+        self.gpu.save_state(&mut s.gpu);
+        self.mbc.save_state(&mut s.mbc);
+        s.gbmode = self.gbmode;
+        s.gbspeed = self.gbspeed;
+        s.speed_switch_req = self.speed_switch_req;
+        s.hdma_src = self.hdma_src;
+        s.hdma_dst = self.hdma_dst;
+        s.hdma_status = self.hdma_status;
+        s.hdma_len = self.hdma_len;
+        s.undocumented_cgb_regs = self.undocumented_cgb_regs;
+    }
+
+    /// Restore state previously captured with `save_state`
+    pub fn load_state(&mut self, s: &MmuState) {
+        self.wram.copy_from_slice(&s.wram[..]);
+        self.zram = s.zram;
+        self.wrambank = s.wrambank;
+        self.hdma = s.hdma;
+        self.inte = s.inte;
+        self.intf = s.intf;
+        self.serial = s.serial.clone();
+        self.timer = s.timer.clone();
+        self.keypad = s.keypad.clone();
+        self.gpu.load_state(&s.gpu);
+        self.mbc.load_state(&s.mbc);
+        self.gbmode = s.gbmode;
+        self.gbspeed = s.gbspeed;
+        self.speed_switch_req = s.speed_switch_req;
+        self.hdma_src = s.hdma_src;
+        self.hdma_dst = s.hdma_dst;
+        self.hdma_status = s.hdma_status;
+        self.hdma_len = s.hdma_len;
+        self.undocumented_cgb_regs = s.undocumented_cgb_regs;
+    }
+
     /// Read byte from memory without side effects (for debugging/overlay)
     pub fn peek(&self, addr: u16) -> u8 {
         match addr {
@@ -15,13 +15,18 @@ extern crate alloc;
 // Core emulator components (ported from rboy)
 pub mod cpu;
 pub mod device;
+pub mod frame_sink;
 pub mod gbmode;
 pub mod gpu;
+pub mod jit;
 pub mod keypad;
 pub mod mbc;
 pub mod mmu;
 pub mod register;
+pub mod runahead;
+pub mod scheduler;
 pub mod serial;
+pub mod tile_cache;
 pub mod timer;
 
 // Rustacean OS integration layer
@@ -18,6 +18,7 @@ pub enum CpuFlag {
 }
 
 /// CPU register file
+#[derive(Clone)]
 pub struct Registers {
     pub a: u8,
     pub f: u8,
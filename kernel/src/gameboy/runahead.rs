@@ -0,0 +1,105 @@
+//! Run-Ahead Input Latency Reduction
+//!
+//! Most games read the joypad once per frame and react a frame or two
+//! later. Run-ahead hides that internal lag by emulating a few frames into
+//! the future with the current input, displaying that result, and then
+//! rolling back to the real timeline.
+//!
+//! # Per-frame sequence (N frames ahead)
+//!
+//! 1. Run the real frame with rendering disabled
+//! 2. Snapshot the device
+//! 3. Run N-1 hidden frames, then one rendered frame
+//! 4. Caller displays the rendered frame
+//! 5. Restore the snapshot (`rollback`)
+//!
+//! With N = 0 the device runs exactly one rendered frame and `rollback`
+//! does nothing, so the cost is identical to plain emulation. Each extra
+//! frame costs a full frame of CPU time, so keep N small on slow machines.
+
+use super::device::{Device, Snapshot};
+use super::CYCLES_PER_FRAME;
+
+/// Maximum number of frames to run ahead
+pub const MAX_FRAMES: u8 = 4;
+
+/// Run-ahead state
+pub struct RunAhead {
+    /// Frames to run ahead (0 = disabled)
+    frames: u8,
+    /// Snapshot of the real timeline (allocated on first use)
+    snapshot: Option<Snapshot>,
+    /// A rollback is pending for the current frame
+    pending: bool,
+}
+
+impl RunAhead {
+    pub fn new(frames: u8) -> Self {
+        RunAhead {
+            frames: frames.min(MAX_FRAMES),
+            snapshot: None,
+            pending: false,
+        }
+    }
+
+    /// Number of frames currently run ahead
+    pub fn frames(&self) -> u8 {
+        self.frames
+    }
+
+    /// Change the number of frames to run ahead (clamped to `MAX_FRAMES`)
+    pub fn set_frames(&mut self, frames: u8) {
+        self.frames = frames.min(MAX_FRAMES);
+    }
+
+    /// Cycle 0 -> 1 -> ... -> MAX_FRAMES -> 0 (for a hotkey)
+    pub fn cycle_frames(&mut self) {
+        self.frames = if self.frames >= MAX_FRAMES { 0 } else { self.frames + 1 };
+    }
+
+    /// Run one frame, leaving the run-ahead result in the GPU buffers
+    ///
+    /// Input must already be applied to `device`. Call `rollback` once
+    /// the frame has been displayed.
+    pub fn run_frame(&mut self, device: &mut Device) {
+        if self.frames == 0 {
+            run_one_frame(device);
+            return;
+        }
+
+        // Real frame - emulated but never shown
+        device.set_rendering(false);
+        run_one_frame(device);
+
+        let snapshot = self.snapshot.get_or_insert_with(|| device.new_snapshot());
+        device.save_snapshot(snapshot);
+        self.pending = true;
+
+        // Speculative frames - only the last one is rendered
+        for _ in 1..self.frames {
+            run_one_frame(device);
+        }
+        device.set_rendering(true);
+        run_one_frame(device);
+    }
+
+    /// Return to the real timeline after the frame has been displayed
+    pub fn rollback(&mut self, device: &mut Device) {
+        if !self.pending {
+            return;
+        }
+        self.pending = false;
+
+        if let Some(snapshot) = &self.snapshot {
+            device.load_snapshot(snapshot);
+        }
+    }
+}
+
+/// Run one frame worth of CPU cycles
+fn run_one_frame(device: &mut Device) {
+    let mut cycles: u32 = 0;
+    while cycles < CYCLES_PER_FRAME {
+        cycles += device.do_cycle();
+    }
+}
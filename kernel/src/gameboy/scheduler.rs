@@ -0,0 +1,55 @@
+//! Event Scheduler
+//!
+//! Works out how many CPU cycles can pass before any hardware block does
+//! something the CPU could observe: a TIMA increment, a PPU mode or line
+//! change, a serial transfer completing, or a VRAM DMA step.
+//!
+//! Between two events the machine is fully predictable, so a halted CPU
+//! (or one spinning in a LY/STAT polling loop) can jump straight to the
+//! next event instead of stepping four cycles at a time. On the Armada
+//! E500 this turns most of a typical frame's idle time into a handful of
+//! calls instead of thousands.
+
+/// Upper bound on a single skip, in CPU cycles (ten scanlines)
+///
+/// Applies when nothing is scheduled at all (LCD off, timer stopped),
+/// so the frame loop in `main.rs` still regains control regularly.
+pub const MAX_SKIP: u32 = 456 * 10;
+
+/// Hardware block that owns the next event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Nothing scheduled within `MAX_SKIP`
+    Idle,
+    /// Timer (TIMA increment)
+    Timer,
+    /// PPU mode change or new scanline
+    Ppu,
+    /// Serial transfer completion
+    Serial,
+    /// General-purpose VRAM DMA pending
+    Dma,
+}
+
+/// The earliest upcoming event
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub kind: EventKind,
+    /// CPU cycles until the event fires
+    pub cycles: u32,
+}
+
+impl Event {
+    /// Nothing scheduled yet
+    pub fn idle() -> Event {
+        Event { kind: EventKind::Idle, cycles: MAX_SKIP }
+    }
+
+    /// Keep whichever of `self` and the candidate fires first
+    pub fn earliest(self, kind: EventKind, cycles: Option<u32>) -> Event {
+        match cycles {
+            Some(c) if c < self.cycles => Event { kind, cycles: c },
+            _ => self,
+        }
+    }
+}
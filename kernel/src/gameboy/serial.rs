@@ -9,6 +9,7 @@ pub trait SerialCallback {
 }
 
 /// Serial port state
+#[derive(Clone)]
 pub struct Serial {
     /// Serial transfer data
     data: u8,
@@ -58,6 +59,15 @@ impl Serial {
         }
     }
 
+    /// GPU-speed cycles until the current transfer completes
+    pub fn cycles_to_next_event(&self) -> Option<u32> {
+        if self.transferring {
+            Some(self.cycles)
+        } else {
+            None
+        }
+    }
+
     /// Advance serial transfer (called each frame or so)
     pub fn do_cycle(&mut self, cycles: u32) {
         if !self.transferring {
@@ -0,0 +1,80 @@
+//! Decoded Tile Cache
+//!
+//! Game Boy tiles are stored as 2 bitplanes per row, so drawing a pixel
+//! normally means picking one bit out of each of two VRAM bytes. The cache
+//! keeps every tile pre-decoded to one colour number (0-3) per byte, so
+//! rendering a scanline becomes row lookups and copies.
+//!
+//! Tiles are decoded lazily: VRAM writes only mark the tile dirty, and it
+//! is re-decoded the next time the renderer asks for one of its rows.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec;
+
+/// Tiles per VRAM bank (0x8000-0x97FF, 16 bytes each)
+const TILES_PER_BANK: usize = 384;
+
+/// Both CGB VRAM banks
+const TILE_COUNT: usize = TILES_PER_BANK * 2;
+
+/// Size of one VRAM bank in bytes
+const BANK_SIZE: usize = 0x2000;
+
+pub struct TileCache {
+    /// Colour numbers, 8 rows of 8 pixels per tile, leftmost pixel first
+    pixels: Box<[[[u8; 8]; 8]]>,
+    /// Tile needs decoding before use
+    dirty: Box<[bool]>,
+}
+
+impl TileCache {
+    pub fn new() -> TileCache {
+        TileCache {
+            // Built on the heap: 48 KB is too much for the kernel stack
+            pixels: vec![[[0; 8]; 8]; TILE_COUNT].into_boxed_slice(),
+            dirty: vec![true; TILE_COUNT].into_boxed_slice(),
+        }
+    }
+
+    /// Mark the tile containing VRAM address `addr` (0x8000-0x9FFF) dirty
+    ///
+    /// Writes to the tile maps (0x9800 and up) don't affect tile data and
+    /// are ignored.
+    pub fn invalidate(&mut self, bank: usize, addr: u16) {
+        let offset = addr as usize & 0x1FFF;
+        if offset < TILES_PER_BANK * 16 {
+            self.dirty[bank * TILES_PER_BANK + (offset >> 4)] = true;
+        }
+    }
+
+    /// Mark every tile dirty (after VRAM was replaced wholesale)
+    pub fn invalidate_all(&mut self) {
+        self.dirty.fill(true);
+    }
+
+    /// Decoded row `row` (0-7) of `tile` (0-383) in `bank`
+    pub fn row(&mut self, vram: &[u8], bank: usize, tile: usize, row: usize) -> &[u8; 8] {
+        let index = bank * TILES_PER_BANK + tile;
+        if self.dirty[index] {
+            self.decode(vram, index);
+        }
+        &self.pixels[index][row]
+    }
+
+    fn decode(&mut self, vram: &[u8], index: usize) {
+        let bank = index / TILES_PER_BANK;
+        let base = bank * BANK_SIZE + (index % TILES_PER_BANK) * 16;
+
+        for (y, row) in self.pixels[index].iter_mut().enumerate() {
+            let b1 = vram[base + y * 2];
+            let b2 = vram[base + y * 2 + 1];
+            for (x, px) in row.iter_mut().enumerate() {
+                let bit = 7 - x;
+                *px = ((b1 >> bit) & 1) | (((b2 >> bit) & 1) << 1);
+            }
+        }
+        self.dirty[index] = false;
+    }
+}
@@ -3,6 +3,7 @@
 //! Emulates DIV (0xFF04), TIMA (0xFF05), TMA (0xFF06), TAC (0xFF07)
 
 /// Timer state
+#[derive(Clone)]
 pub struct Timer {
     /// Divider register (increments at 16384 Hz)
     div: u16,
@@ -52,6 +53,23 @@ impl Timer {
         }
     }
 
+    /// CPU cycles until TIMA next increments (None if the timer is stopped)
+    ///
+    /// `do_cycle` only detects one falling edge per call, so callers must
+    /// never advance the timer past this point in one step.
+    pub fn cycles_to_next_event(&self) -> Option<u32> {
+        if self.tac & 0x04 == 0 {
+            return None;
+        }
+        let period: u32 = match self.tac & 0x03 {
+            0 => 1024,
+            1 => 16,
+            2 => 64,
+            _ => 256,
+        };
+        Some(period - (self.div as u32 & (period - 1)))
+    }
+
     /// Advance timer by given CPU cycles
     pub fn do_cycle(&mut self, cycles: u32) {
         // Update DIV (always runs)
@@ -0,0 +1,330 @@
+//! DMG Palette Presets
+//!
+//! Colors for original Game Boy games, which only ever produce four
+//! shades per palette. A preset gives four RGB555 colors to each of the
+//! background and the two sprite palettes, lightest first.
+//!
+//! Built in are plain gray, the DMG's green and the Pocket's olive, and
+//! the twelve palettes the CGB boot ROM offers when a direction (alone
+//! or with A or B) is held while its logo shows. More can be added in
+//! `PALETTES.TXT` in the root of the disk, one per line:
+//!
+//! ```text
+//! # name  BG (4 colors)               [OBJ0 (4 colors)  [OBJ1 (4 colors)]]
+//! SEPIA   FFF0D8 C8A880 806040 302010
+//! ```
+//!
+//! Colors are RRGGBB hex. Missing sprite palettes copy the one before.
+//! Names are cut to `NAME_LEN` characters.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::gameboy::KeypadKey;
+use crate::fs::{vfs, FsError};
+
+// =============================================================================
+// Presets
+// =============================================================================
+
+/// Longest preset name (fits the settings menu)
+pub const NAME_LEN: usize = 8;
+
+/// Most presets loaded from `PALETTES.TXT`
+const MAX_CUSTOM: usize = 32;
+
+/// Where custom presets are read from
+const PALETTES_PATH: &str = "/PALETTES.TXT";
+
+/// Largest `PALETTES.TXT` read
+const MAX_FILE_SIZE: usize = 8192;
+
+/// Background, sprite 0 and sprite 1 palettes of one preset
+#[derive(Debug, Clone, Copy)]
+pub struct DmgPalette {
+    pub name: &'static str,
+    /// BG, OBJ0, OBJ1; each lightest to darkest, RGB555
+    pub colors: [[u16; 4]; 3],
+}
+
+impl DmgPalette {
+    /// Color of `shade` (0 = lightest) in palette `group` (0 = BG, 1-2 = OBJ)
+    #[inline]
+    pub fn color(&self, group: usize, shade: usize) -> u16 {
+        self.colors[group.min(2)][shade & 3]
+    }
+}
+
+/// 0xRRGGBB -> RGB555
+const fn rgb(c: u32) -> u16 {
+    let r = (c >> 19) & 0x1F;
+    let g = (c >> 11) & 0x1F;
+    let b = (c >> 3) & 0x1F;
+    (r | g << 5 | b << 10) as u16
+}
+
+const fn ramp(c: [u32; 4]) -> [u16; 4] {
+    [rgb(c[0]), rgb(c[1]), rgb(c[2]), rgb(c[3])]
+}
+
+/// One ramp for all three palettes
+const fn mono(name: &'static str, c: [u32; 4]) -> DmgPalette {
+    DmgPalette { name, colors: [ramp(c), ramp(c), ramp(c)] }
+}
+
+const fn split(name: &'static str, bg: [u32; 4], obj0: [u32; 4], obj1: [u32; 4]) -> DmgPalette {
+    DmgPalette { name, colors: [ramp(bg), ramp(obj0), ramp(obj1)] }
+}
+
+const BROWN: [u32; 4] = [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000];
+const RED: [u32; 4] = [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000];
+const GREEN: [u32; 4] = [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000];
+const BLUE: [u32; 4] = [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000];
+
+/// Built-in presets; the first is the default
+pub const BUILTIN: [DmgPalette; 15] = [
+    mono("GRAY", [0xFFFFFF, 0xC0C0C0, 0x606060, 0x000000]),
+    mono("GREEN", [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+    mono("POCKET", [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]),
+    // CGB boot ROM manual selections
+    mono("UP", BROWN),
+    split("UP+A", RED, GREEN, BLUE),
+    mono("UP+B", [0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108]),
+    split("LEFT", BLUE, RED, GREEN),
+    split("LEFT+A", [0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000], RED, BROWN),
+    mono("LEFT+B", [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000]),
+    mono("DOWN", [0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000]),
+    mono("DOWN+A", [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000]),
+    split("DOWN+B", [0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000], BLUE, GREEN),
+    mono("RIGHT", [0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000]),
+    split("RIGHT+A", [0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000], RED, RED),
+    mono("RIGHT+B", [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF]),
+];
+
+/// Index of the first boot ROM selection in `BUILTIN` ("UP")
+const FIRST_COMBO: usize = 3;
+
+/// Presets from `PALETTES.TXT`, after the built-in ones
+static mut CUSTOM: Vec<DmgPalette> = Vec::new();
+
+/// Index of the active preset
+static mut SELECTED: usize = 0;
+
+fn custom() -> &'static Vec<DmgPalette> {
+    unsafe { &*core::ptr::addr_of!(CUSTOM) }
+}
+
+/// Number of presets (built-in and custom)
+pub fn count() -> usize {
+    BUILTIN.len() + custom().len()
+}
+
+/// Preset `index` (built-in first, then custom)
+pub fn get(index: usize) -> DmgPalette {
+    match index.checked_sub(BUILTIN.len()) {
+        None => BUILTIN[index],
+        Some(i) => custom().get(i).copied().unwrap_or(BUILTIN[0]),
+    }
+}
+
+/// Index of the preset named `name`
+pub fn find(name: &str) -> Option<usize> {
+    (0..count()).find(|&i| get(i).name == name)
+}
+
+/// Index of the active preset
+pub fn selected() -> usize {
+    unsafe { SELECTED }
+}
+
+/// The active preset
+pub fn active() -> DmgPalette {
+    get(selected())
+}
+
+/// Make preset `index` active (taking effect at the next palette sync)
+pub fn select(index: usize) {
+    unsafe { SELECTED = if index < count() { index } else { 0 }; }
+}
+
+/// Step the active preset forwards (`delta` = 1) or backwards (-1)
+pub fn cycle(delta: i32) {
+    let n = count() as i32;
+    select((selected() as i32 + delta).rem_euclid(n) as usize);
+}
+
+// =============================================================================
+// Shade Mapping
+// =============================================================================
+
+/// Shade (0 = lightest) of a gray level produced by the GPU (255/192/96/0)
+#[inline]
+pub fn shade_of(gray: u8) -> usize {
+    match gray {
+        224..=255 => 0,
+        144..=223 => 1,
+        48..=143 => 2,
+        _ => 3,
+    }
+}
+
+/// Palette group of a DMG output index (64-67 BG, 68-71 OBJ0, 72-75 OBJ1)
+///
+/// Anything else (the blank color behind a disabled background) counts
+/// as background.
+#[inline]
+pub fn group_of(index: u8) -> usize {
+    match index {
+        68..=71 => 1,
+        72..=75 => 2,
+        _ => 0,
+    }
+}
+
+// =============================================================================
+// Boot-Time Selection
+// =============================================================================
+
+/// Frames after power-on during which a combo picks a preset (the
+/// length of the CGB boot logo)
+pub const COMBO_FRAMES: u32 = 120;
+
+/// Keys that take part in a boot ROM combo
+const COMBO_KEYS: [KeypadKey; 6] = [
+    KeypadKey::A,
+    KeypadKey::B,
+    KeypadKey::Up,
+    KeypadKey::Left,
+    KeypadKey::Down,
+    KeypadKey::Right,
+];
+
+/// True for A, B and the directions (Start and Select pick nothing)
+pub fn is_combo_key(key: KeypadKey) -> bool {
+    COMBO_KEYS.contains(&key)
+}
+
+/// Watches the keypad for a boot ROM combo
+pub struct ComboPicker {
+    /// Bit `i` set while `COMBO_KEYS[i]` is held
+    held: u8,
+    /// Direction pressed last (still held)
+    direction: Option<KeypadKey>,
+}
+
+impl ComboPicker {
+    pub const fn new() -> ComboPicker {
+        ComboPicker { held: 0, direction: None }
+    }
+
+    /// Track a key press or release; returns the preset the keys held
+    /// now select, if a direction is among them
+    pub fn key(&mut self, key: KeypadKey, pressed: bool) -> Option<usize> {
+        let bit = 1 << COMBO_KEYS.iter().position(|&k| k == key)?;
+        if pressed {
+            self.held |= bit;
+        } else {
+            self.held &= !bit;
+        }
+        if key != KeypadKey::A && key != KeypadKey::B {
+            if pressed {
+                self.direction = Some(key);
+            } else if self.direction == Some(key) {
+                // Fall back to another direction still held
+                let other = self.held().find(|&k| k != KeypadKey::A && k != KeypadKey::B);
+                self.direction = other;
+            }
+        }
+        if !pressed {
+            return None;
+        }
+        combo(self.direction?, self.is_held(KeypadKey::A), self.is_held(KeypadKey::B))
+    }
+
+    /// Combo keys held right now
+    pub fn held(&self) -> impl Iterator<Item = KeypadKey> + '_ {
+        COMBO_KEYS.iter().copied().filter(move |&k| self.is_held(k))
+    }
+
+    fn is_held(&self, key: KeypadKey) -> bool {
+        COMBO_KEYS.iter().position(|&k| k == key).is_some_and(|i| self.held & 1 << i != 0)
+    }
+}
+
+/// CGB boot ROM preset for a direction, held alone or with A or B
+///
+/// A wins if both buttons are held. Non-direction keys give `None`.
+pub fn combo(direction: KeypadKey, a: bool, b: bool) -> Option<usize> {
+    let base = match direction {
+        KeypadKey::Up => 0,
+        KeypadKey::Left => 3,
+        KeypadKey::Down => 6,
+        KeypadKey::Right => 9,
+        _ => return None,
+    };
+    let button = if a { 1 } else if b { 2 } else { 0 };
+    Some(FIRST_COMBO + base + button)
+}
+
+// =============================================================================
+// Custom Palettes
+// =============================================================================
+
+/// Load `PALETTES.TXT` from the root directory, adding its presets
+///
+/// Returns the number of presets added (0 if there is no file). Call
+/// once: presets are never removed.
+pub fn load_custom() -> Result<usize, &'static str> {
+    let size = match vfs::stat(PALETTES_PATH) {
+        Ok(metadata) => metadata.size,
+        Err(FsError::NotFound) => return Ok(0),
+        Err(e) => return Err(e.as_str()),
+    };
+
+    let mut data = alloc::vec![0u8; (size as usize).min(MAX_FILE_SIZE)];
+    let len = vfs::read_file(PALETTES_PATH, &mut data).map_err(|e| e.as_str())?;
+    let text = core::str::from_utf8(&data[..len]).map_err(|_| "PALETTES.TXT is not text")?;
+
+    let custom = unsafe { &mut *core::ptr::addr_of_mut!(CUSTOM) };
+    let before = custom.len();
+    for line in text.lines() {
+        if custom.len() >= MAX_CUSTOM {
+            break;
+        }
+        if let Some(palette) = parse_line(line) {
+            custom.push(palette);
+        }
+    }
+    Ok(custom.len() - before)
+}
+
+/// Parse one `PALETTES.TXT` line (`None` for blanks, comments and errors)
+fn parse_line(line: &str) -> Option<DmgPalette> {
+    let line = line.split('#').next()?.trim();
+    let mut fields = line.split_whitespace();
+    let name = fields.next()?;
+
+    let mut colors = [[0u16; 4]; 3];
+    let mut count = 0;
+    for field in fields {
+        if count == 12 {
+            return None;
+        }
+        let c = u32::from_str_radix(field, 16).ok().filter(|_| field.len() == 6)?;
+        colors[count / 4][count % 4] = rgb(c);
+        count += 1;
+    }
+    if count == 0 || count % 4 != 0 {
+        return None;
+    }
+    for group in count / 4..3 {
+        colors[group] = colors[group - 1];
+    }
+
+    // Upper case, like the built-in names
+    let mut upper = String::with_capacity(NAME_LEN);
+    upper.extend(name.chars().take(NAME_LEN).map(|c| c.to_ascii_uppercase()));
+    Some(DmgPalette { name: Box::leak(upper.into_boxed_str()), colors })
+}
@@ -162,24 +162,11 @@ pub fn flip_overlay_regions() {
 }
 
 // =============================================================================
-// Helper: Blit Game Boy Screen to Back Buffer
+// Helper: Overlay Areas
 // =============================================================================
-
-/// Blit the Game Boy's rendered frame to the back buffer
-/// This replaces the direct-to-VGA blit
-#[inline]
-pub fn blit_gb_to_backbuffer(pal_data: &[u8]) {
-    let buffer = back_buffer();
-
-    for y in 0..GB_HEIGHT {
-        let src_offset = y * GB_WIDTH;
-        let dst_offset = (GB_Y + y) * SCREEN_WIDTH + GB_X;
-
-        // Copy one scanline
-        buffer[dst_offset..dst_offset + GB_WIDTH]
-            .copy_from_slice(&pal_data[src_offset..src_offset + GB_WIDTH]);
-    }
-}
+//
+// The Game Boy screen itself is written straight into the back buffer by
+// the GPU (see `gameboy::display::Mode13hSink`).
 
 /// Clear the overlay areas in the back buffer
 /// Only needed on first frame or when overlay is toggled
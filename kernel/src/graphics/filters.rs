@@ -0,0 +1,369 @@
+//! Post-Processing Filters for True-Color Output
+//!
+//! Functions over the GPU's RGB output (160x144, R, G, B per pixel):
+//!
+//! - DMG green and "pocket" tints for monochrome games
+//! - Scale2x/Scale3x (EPX / AdvMAME) pixel-art upscaling
+//! - LCD grid and subpixel masks for the scaled image
+//! - LCD ghosting and 50/50 frame blending against the previous frame
+//!
+//! GBC color correction is not here: it is `vga_palette`'s color curve,
+//! which the palette modes and true-color output share.
+//!
+//! Most only apply in a true-color mode; Mode 13h shows palette indices
+//! and never sees the RGB frame. Frame blending is the exception: the
+//! palette modes blend RGB555 colors with `blend_rgb555` and give the
+//! results DAC entries (`frame_blend`). The active selection is a global
+//! `FilterSettings`, changed from the settings menu and read once per
+//! frame.
+
+// =============================================================================
+// Settings
+// =============================================================================
+
+/// Pixel-art upscaler run before the integer scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upscaler {
+    None,
+    Scale2x,
+    Scale3x,
+}
+
+/// Mask simulating the LCD's pixel structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LcdMask {
+    Off,
+    /// Dark gaps between pixels
+    Grid,
+    /// R/G/B stripes within each pixel, plus row gaps
+    Subpixel,
+}
+
+/// Tint for monochrome (DMG) games
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tint {
+    None,
+    /// Original DMG pea-green screen
+    DmgGreen,
+    /// Game Boy Pocket's grey-olive screen
+    Pocket,
+}
+
+/// Mixing with the previous frame (for games that flicker sprites)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBlend {
+    Off,
+    /// Slow LCD response: each frame fades into the next
+    Ghosting,
+    /// Average of this frame and the last
+    Blend,
+}
+
+/// Active filter selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterSettings {
+    pub upscaler: Upscaler,
+    pub lcd: LcdMask,
+    pub tint: Tint,
+    pub blend: FrameBlend,
+}
+
+impl FilterSettings {
+    /// Plain scaling (the look before filters existed)
+    pub const DEFAULT: FilterSettings = FilterSettings {
+        upscaler: Upscaler::None,
+        lcd: LcdMask::Off,
+        tint: Tint::None,
+        blend: FrameBlend::Off,
+    };
+}
+
+static mut SETTINGS: FilterSettings = FilterSettings::DEFAULT;
+
+/// Current filter selection
+pub fn settings() -> FilterSettings {
+    unsafe { SETTINGS }
+}
+
+/// Replace the filter selection (takes effect on the next frame)
+pub fn set_settings(settings: FilterSettings) {
+    unsafe { SETTINGS = settings; }
+}
+
+impl Upscaler {
+    pub const ALL: [Upscaler; 3] = [Upscaler::None, Upscaler::Scale2x, Upscaler::Scale3x];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Upscaler::None => "NONE",
+            Upscaler::Scale2x => "SCALE2X",
+            Upscaler::Scale3x => "SCALE3X",
+        }
+    }
+
+    /// Output pixels per source pixel, per axis
+    pub fn factor(self) -> usize {
+        match self {
+            Upscaler::None => 1,
+            Upscaler::Scale2x => 2,
+            Upscaler::Scale3x => 3,
+        }
+    }
+
+    /// Largest EPX factor up to ours that divides the integer `scale`
+    ///
+    /// Scale3x at 4x falls back to Scale2x; Scale2x at 3x to plain pixels.
+    pub fn factor_for(self, scale: usize) -> usize {
+        (1..=self.factor()).rev().find(|&f| scale % f == 0).unwrap_or(1)
+    }
+}
+
+impl LcdMask {
+    pub const ALL: [LcdMask; 3] = [LcdMask::Off, LcdMask::Grid, LcdMask::Subpixel];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LcdMask::Off => "OFF",
+            LcdMask::Grid => "GRID",
+            LcdMask::Subpixel => "SUBPIXEL",
+        }
+    }
+}
+
+impl Tint {
+    pub const ALL: [Tint; 3] = [Tint::None, Tint::DmgGreen, Tint::Pocket];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tint::None => "NONE",
+            Tint::DmgGreen => "DMG",
+            Tint::Pocket => "POCKET",
+        }
+    }
+}
+
+impl FrameBlend {
+    pub const ALL: [FrameBlend; 3] = [FrameBlend::Off, FrameBlend::Ghosting, FrameBlend::Blend];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FrameBlend::Off => "OFF",
+            FrameBlend::Ghosting => "GHOSTING",
+            FrameBlend::Blend => "50/50",
+        }
+    }
+}
+
+/// Step `current` through `all` by `delta`, wrapping
+pub fn cycle<T: Copy + PartialEq>(all: &[T], current: T, delta: i32) -> T {
+    let pos = all.iter().position(|&v| v == current).unwrap_or(0) as i32;
+    let len = all.len() as i32;
+    all[(pos + delta).rem_euclid(len) as usize]
+}
+
+// =============================================================================
+// Monochrome Tints
+// =============================================================================
+
+/// Tint ramps, darkest to lightest
+const DMG_GREEN_RAMP: [[u8; 3]; 4] =
+    [[0x0F, 0x38, 0x0F], [0x30, 0x62, 0x30], [0x8B, 0xAC, 0x0F], [0x9B, 0xBC, 0x0F]];
+const POCKET_RAMP: [[u8; 3]; 4] =
+    [[0x1F, 0x1F, 0x1F], [0x4D, 0x53, 0x3C], [0x8B, 0x95, 0x6D], [0xC4, 0xCF, 0xA1]];
+
+/// Map a pixel's brightness onto the tint's ramp
+pub fn tint_color(tint: Tint, r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let ramp = match tint {
+        Tint::None => return (r, g, b),
+        Tint::DmgGreen => &DMG_GREEN_RAMP,
+        Tint::Pocket => &POCKET_RAMP,
+    };
+
+    // Luma 0-255 spread over three ramp segments of 85 steps each
+    let luma = (r as u32 * 77 + g as u32 * 150 + b as u32 * 29) >> 8;
+    let seg = (luma / 85).min(2) as usize;
+    let t = luma - seg as u32 * 85;
+    let lerp = |c: usize| {
+        let lo = ramp[seg][c] as u32;
+        let hi = ramp[seg + 1][c] as u32;
+        ((lo * (85 - t) + hi * t) / 85) as u8
+    };
+    (lerp(0), lerp(1), lerp(2))
+}
+
+/// Tint a whole RGB frame in place
+pub fn tint_frame(rgb: &mut [u8], tint: Tint) {
+    if tint == Tint::None {
+        return;
+    }
+    for px in rgb.chunks_exact_mut(3) {
+        let (r, g, b) = tint_color(tint, px[0], px[1], px[2]);
+        px.copy_from_slice(&[r, g, b]);
+    }
+}
+
+// =============================================================================
+// Frame Blending
+// =============================================================================
+
+/// Mix one channel of the current frame with the history
+///
+/// Ghosting keeps 3/8 of what was on screen, so a sprite shown every
+/// other frame settles between the two frames' colors rather than
+/// strobing.
+#[inline]
+fn blend_channel(mode: FrameBlend, cur: u32, history: u32) -> u32 {
+    match mode {
+        FrameBlend::Off => cur,
+        FrameBlend::Ghosting => (cur * 5 + history * 3 + 4) / 8,
+        FrameBlend::Blend => (cur + history + 1) / 2,
+    }
+}
+
+/// Blend an RGB frame with `history` (same size) in place
+///
+/// `history` is the previous frame for `Blend` and the previous output
+/// for `Ghosting`; it is updated for the next call.
+pub fn blend_frame(rgb: &mut [u8], history: &mut [u8], mode: FrameBlend) {
+    for (c, h) in rgb.iter_mut().zip(history.iter_mut()) {
+        let out = blend_channel(mode, *c as u32, *h as u32) as u8;
+        *h = if mode == FrameBlend::Ghosting { out } else { *c };
+        *c = out;
+    }
+}
+
+/// Blend one RGB555 pixel with its history, updating the history
+pub fn blend_rgb555(cur: u16, history: &mut u16, mode: FrameBlend) -> u16 {
+    let mut out = 0;
+    for shift in [0, 5, 10] {
+        let c = ((cur >> shift) & 0x1F) as u32;
+        let h = ((*history >> shift) & 0x1F) as u32;
+        out |= (blend_channel(mode, c, h) as u16) << shift;
+    }
+    *history = if mode == FrameBlend::Ghosting { out } else { cur };
+    out
+}
+
+// =============================================================================
+// EPX Upscaling
+// =============================================================================
+
+/// Pixel (x, y) of a `width` x `height` RGB image, clamped to the edges
+#[inline]
+fn pixel(src: &[u8], width: usize, height: usize, x: isize, y: isize) -> u32 {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    let i = (y * width + x) * 3;
+    (src[i] as u32) << 16 | (src[i + 1] as u32) << 8 | src[i + 2] as u32
+}
+
+#[inline]
+fn put(out: &mut [u8], i: usize, px: u32) {
+    out[i * 3] = (px >> 16) as u8;
+    out[i * 3 + 1] = (px >> 8) as u8;
+    out[i * 3 + 2] = px as u8;
+}
+
+/// Upscale source row `y` by `factor` (1, 2 or 3)
+///
+/// Writes `factor` output rows of `width * factor` pixels, one after the
+/// other, to `out`. Working a row at a time lets callers stream the
+/// result without holding the whole upscaled image.
+pub fn epx_row(src: &[u8], width: usize, height: usize, y: usize, factor: usize, out: &mut [u8]) {
+    let out_w = width * factor;
+    let y = y as isize;
+
+    for x in 0..width {
+        let xi = x as isize;
+        let at = |dx: isize, dy: isize| pixel(src, width, height, xi + dx, y + dy);
+        let e = at(0, 0);
+        let (b, d, f, h) = (at(0, -1), at(-1, 0), at(1, 0), at(0, 1));
+        let edge = b != h && d != f;
+
+        match factor {
+            2 => {
+                let o = [
+                    if edge && d == b { d } else { e },
+                    if edge && b == f { f } else { e },
+                    if edge && d == h { d } else { e },
+                    if edge && h == f { f } else { e },
+                ];
+                for (i, &px) in o.iter().enumerate() {
+                    put(out, (i / 2) * out_w + x * 2 + i % 2, px);
+                }
+            }
+            3 => {
+                let (a, c, g, i) = (at(-1, -1), at(1, -1), at(-1, 1), at(1, 1));
+                let o = if edge {
+                    [
+                        if d == b { d } else { e },
+                        if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                        if b == f { f } else { e },
+                        if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                        e,
+                        if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                        if d == h { d } else { e },
+                        if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                        if h == f { f } else { e },
+                    ]
+                } else {
+                    [e; 9]
+                };
+                for (k, &px) in o.iter().enumerate() {
+                    put(out, (k / 3) * out_w + x * 3 + k % 3, px);
+                }
+            }
+            _ => put(out, x, e),
+        }
+    }
+}
+
+/// Scale2x a whole `width` x `height` RGB image into `dst` (4x the size)
+pub fn scale2x(src: &[u8], width: usize, height: usize, dst: &mut [u8]) {
+    let row = width * 2 * 2 * 3;
+    for y in 0..height {
+        epx_row(src, width, height, y, 2, &mut dst[y * row..(y + 1) * row]);
+    }
+}
+
+/// Scale3x a whole `width` x `height` RGB image into `dst` (9x the size)
+pub fn scale3x(src: &[u8], width: usize, height: usize, dst: &mut [u8]) {
+    let row = width * 3 * 3 * 3;
+    for y in 0..height {
+        epx_row(src, width, height, y, 3, &mut dst[y * row..(y + 1) * row]);
+    }
+}
+
+// =============================================================================
+// LCD Masks
+// =============================================================================
+
+/// Darken an output row to look like an LCD with `cell` x `cell` pixels
+///
+/// `row` is one line of the final scaled image and `sub_y` the line's
+/// offset within its cell. The last line and column of each cell become
+/// the gap; `Subpixel` also splits each cell into R, G and B stripes.
+pub fn lcd_mask_row(row: &mut [u8], sub_y: usize, cell: usize, mask: LcdMask) {
+    if mask == LcdMask::Off || cell < 2 {
+        return;
+    }
+    let gap_row = sub_y == cell - 1;
+
+    for (x, px) in row.chunks_exact_mut(3).enumerate() {
+        let sub_x = x % cell;
+        if gap_row || sub_x == cell - 1 {
+            for c in px.iter_mut() {
+                *c = (*c as u16 * 3 / 4) as u8;
+            }
+        }
+        if mask == LcdMask::Subpixel {
+            // Stripe across the lit part of the cell
+            let stripe = sub_x * 3 / (cell - 1).max(1);
+            for (channel, c) in px.iter_mut().enumerate() {
+                if channel != stripe.min(2) {
+                    *c /= 2;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Frame Blending in the 256-Color Modes
+//!
+//! A blend of two palette colours is usually in no palette, so blending
+//! can't be done on indices. Instead each Game Boy pixel is turned back
+//! into RGB555 (CGB: the line's palette; DMG: the shade its entry was
+//! last synced to), mixed with the history kept for that pixel, and the
+//! resulting colours are given DAC entries 96-255 by the frame quantizer,
+//! which rewrites the Game Boy area with them.
+//!
+//! True-color output blends RGB directly in `display::FilterSink`.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use crate::graphics::filters::{self, FrameBlend};
+use crate::graphics::palette_quantizer::FrameQuantizer;
+use crate::graphics::vga_mode13h::SCREEN_WIDTH;
+use crate::graphics::vga_palette::{dmg_rgb555, palette_rgb8};
+use crate::gui::layout::{GB_HEIGHT, GB_WIDTH, GB_X, GB_Y};
+
+const PIXELS: usize = GB_WIDTH * GB_HEIGHT;
+
+/// Per-pixel blending state for the palette modes
+pub struct PaletteBlender {
+    /// What each pixel is blended with next frame
+    history: Box<[u16; PIXELS]>,
+    /// This frame's blended colours
+    blended: Box<[u16; PIXELS]>,
+    /// `history` holds the previous frame
+    primed: bool,
+}
+
+impl PaletteBlender {
+    pub fn new() -> PaletteBlender {
+        PaletteBlender {
+            history: Box::new([0; PIXELS]),
+            blended: Box::new([0; PIXELS]),
+            primed: false,
+        }
+    }
+
+    /// Forget the history (call on frames that aren't blended)
+    pub fn reset(&mut self) {
+        self.primed = false;
+    }
+
+    /// Blend the Game Boy area of `screen` with the previous frame
+    ///
+    /// `palettes` are the GPU's per-line CGB palettes, or `None` for DMG
+    /// (whose palettes must already be in the DAC).
+    pub fn apply(
+        &mut self,
+        mode: FrameBlend,
+        palettes: Option<&[[u16; 64]; GB_HEIGHT]>,
+        screen: &mut [u8],
+        quantizer: &mut FrameQuantizer,
+    ) {
+        for y in 0..GB_HEIGHT {
+            let row = (GB_Y + y) * SCREEN_WIDTH + GB_X;
+            for x in 0..GB_WIDTH {
+                let index = screen[row + x];
+                let color = match palettes {
+                    Some(pal) if (index as usize) < 64 => pal[y][index as usize],
+                    _ => dmg_rgb555(index).unwrap_or_else(|| dac_rgb555(index)),
+                };
+
+                let i = y * GB_WIDTH + x;
+                if !self.primed {
+                    self.history[i] = color;
+                }
+                self.blended[i] = filters::blend_rgb555(color, &mut self.history[i], mode);
+            }
+        }
+        self.primed = true;
+
+        quantizer.apply_pixels(&self.blended[..], screen);
+    }
+}
+
+/// A DAC entry as RGB555 (for anything that isn't a Game Boy color)
+fn dac_rgb555(index: u8) -> u16 {
+    let (r, g, b) = palette_rgb8(index);
+    (r as u16 >> 3) | (g as u16 >> 3) << 5 | (b as u16 >> 3) << 10
+}
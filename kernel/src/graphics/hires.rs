@@ -0,0 +1,233 @@
+//! High-Resolution Output on BGA
+//!
+//! When a Bochs Graphics Adapter is present, the emulator switches to a
+//! true-color linear mode and draws the Game Boy screen at 3x or 4x
+//! straight from the GPU, through a `FilterSink` so the post-processing
+//! filters apply.
+//!
+//! The overlay is unchanged: it still draws into the 320x200 back buffer.
+//! Each frame its three panels are converted through the DAC shadow and
+//! copied, at 1x or 2x, to the left, right and bottom of the scaled
+//! screen; only rows that changed since the last copy are drawn, unless
+//! the DAC did. Without BGA, `init` returns `None` and the caller stays
+//! in Mode 13h.
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::drivers::bga::{self, BgaMode};
+use crate::gameboy::display::{FilterSink, Placement};
+use crate::gameboy::{SCREEN_H, SCREEN_W};
+use crate::graphics::vga_mode13h::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::graphics::vga_palette::palette_rgb8;
+use crate::gui::layout::{Region, GB_BORDER, GB_BORDER_COLOR, GB_HEIGHT, GB_WIDTH, GB_X, GB_Y};
+use crate::gui::{Color, Framebuffer};
+
+/// Modes to try, largest first
+const MODES: [(u32, u32); 3] = [(1024, 768), (800, 600), (640, 480)];
+
+/// Framebuffer depth
+const BPP: u32 = 32;
+
+/// Game Boy scale factors to try, largest first
+const SCALES: [usize; 2] = [4, 3];
+
+/// Panel scale factors to try, largest first
+const PANEL_SCALES: [usize; 2] = [2, 1];
+
+/// A rectangle of the back buffer shown on the high-resolution screen
+#[derive(Clone, Copy)]
+struct Panel {
+    src: Region,
+    dst_x: usize,
+    dst_y: usize,
+}
+
+/// Active high-resolution layout
+pub struct HiResScreen {
+    mode: BgaMode,
+    fb: Framebuffer,
+    gb: Placement,
+    panel_scale: usize,
+    panels: [Panel; 3],
+    /// Back buffer as the panels were last drawn from it
+    shown: Vec<u8>,
+    /// DAC colors they were drawn with (`None` before the first time)
+    shown_lut: Option<[Color; 256]>,
+}
+
+impl HiResScreen {
+    /// Switch to the largest BGA mode the layout fits in
+    ///
+    /// Returns `None` (leaving the display in Mode 13h) if there is no
+    /// BGA adapter or no mode could be set.
+    pub fn init() -> Option<HiResScreen> {
+        if !bga::is_present() {
+            return None;
+        }
+
+        for &(width, height) in MODES.iter() {
+            let (gb, panel_scale, panels) = match layout(width as usize, height as usize) {
+                Some(l) => l,
+                None => continue,
+            };
+            let mode = match bga::set_mode(width, height, BPP) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+
+            let fb = unsafe {
+                Framebuffer::new(mode.lfb, mode.width, mode.height, mode.bpp / 8, mode.pitch)
+            };
+            let mut screen = HiResScreen {
+                mode,
+                fb,
+                gb,
+                panel_scale,
+                panels,
+                shown: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+                shown_lut: None,
+            };
+            screen.draw_background();
+            return Some(screen);
+        }
+        None
+    }
+
+    /// Frame sink that renders the Game Boy screen into this mode
+    ///
+    /// `cgb` picks which filters apply (color curve or DMG tints).
+    pub fn frame_sink(&self, cgb: bool) -> FilterSink {
+        unsafe {
+            FilterSink::new(self.mode.lfb, self.mode.pitch as usize, self.mode.bpp, self.gb, cgb)
+        }
+    }
+
+    /// Clear the screen and frame the Game Boy area
+    fn draw_background(&mut self) {
+        self.fb.clear(Color::BLACK);
+
+        let (r, g, b) = palette_rgb8(GB_BORDER_COLOR);
+        let color = Color::rgb(r, g, b);
+        let border = (GB_BORDER * self.panel_scale) as u32;
+        let x = (self.gb.x as u32 - border) as i32;
+        let y = (self.gb.y as u32 - border) as i32;
+        let w = (SCREEN_W * self.gb.scale) as u32 + 2 * border;
+        let h = (SCREEN_H * self.gb.scale) as u32 + 2 * border;
+
+        self.fb.fill_rect(x, y, w, border, color);
+        self.fb.fill_rect(x, y + (h - border) as i32, w, border, color);
+        self.fb.fill_rect(x, y, border, h, color);
+        self.fb.fill_rect(x + (w - border) as i32, y, border, h, color);
+    }
+
+    /// Copy the overlay panels from the back buffer to the screen
+    ///
+    /// Rows that are the same as last time are skipped; everything is
+    /// redrawn when a DAC color changed.
+    pub fn present_panels(&mut self, back: &[u8]) {
+        let lut = dac_lut();
+        let recolored = self.shown_lut != Some(lut);
+        for panel in self.panels {
+            let src = panel.src;
+            for sy in 0..src.height {
+                let at = (src.y + sy) * SCREEN_WIDTH + src.x;
+                let row = &back[at..][..src.width];
+                let shown = &mut self.shown[at..][..src.width];
+                if !recolored && shown == row {
+                    continue;
+                }
+                shown.copy_from_slice(row);
+                self.draw_row(row, panel, sy, self.panel_scale, &lut);
+            }
+        }
+        self.shown_lut = Some(lut);
+    }
+
+    /// Copy the Game Boy area of the back buffer over the scaled screen
+    ///
+    /// For UI drawn in place of the game (the settings menu); the GPU
+    /// overwrites it with the next frame.
+    pub fn present_gb_area(&mut self, back: &[u8]) {
+        let panel = Panel {
+            src: Region::new(GB_X, GB_Y, GB_WIDTH, GB_HEIGHT),
+            dst_x: self.gb.x,
+            dst_y: self.gb.y,
+        };
+        self.copy_region(back, panel, self.gb.scale, &dac_lut());
+    }
+
+    /// Copy one back buffer region to the screen at `scale`
+    fn copy_region(&mut self, back: &[u8], panel: Panel, scale: usize, lut: &[Color; 256]) {
+        let src = panel.src;
+        for sy in 0..src.height {
+            let row = &back[(src.y + sy) * SCREEN_WIDTH + src.x..][..src.width];
+            self.draw_row(row, panel, sy, scale, lut);
+        }
+    }
+
+    /// Draw `row`, line `sy` of `panel`'s region, at `scale`
+    fn draw_row(&mut self, row: &[u8], panel: Panel, sy: usize, scale: usize, lut: &[Color; 256]) {
+        let dy = (panel.dst_y + sy * scale) as i32;
+        for (sx, &px) in row.iter().enumerate() {
+            let dx = (panel.dst_x + sx * scale) as i32;
+            self.fb.fill_rect(dx, dy, scale as u32, scale as u32, lut[px as usize]);
+        }
+    }
+}
+
+/// Current DAC contents as true colors
+fn dac_lut() -> [Color; 256] {
+    let mut lut = [Color::BLACK; 256];
+    for (index, color) in lut.iter_mut().enumerate() {
+        let (r, g, b) = palette_rgb8(index as u8);
+        *color = Color::rgb(r, g, b);
+    }
+    lut
+}
+
+/// Fit the Game Boy screen and the panels into `width` x `height`
+///
+/// Prefers the largest Game Boy scale, then the largest panel scale.
+/// The panels keep their Mode 13h sizes (times the panel scale): the side
+/// panels flank the border, the bottom panel sits centered below it. This
+/// gives 3x/1x at 640x480, 3x/2x at 800x600 and 4x/2x at 1024x768.
+fn layout(width: usize, height: usize) -> Option<(Placement, usize, [Panel; 3])> {
+    let left = Region::left_sidebar();
+    let right = Region::right_sidebar();
+    let bottom_bar = Region::bottom_sidebar();
+    let bottom = Region::new(GB_X, bottom_bar.y, GB_WIDTH, bottom_bar.height);
+
+    for &scale in SCALES.iter() {
+        for &p in PANEL_SCALES.iter() {
+            let gb_w = SCREEN_W * scale;
+            let gb_h = SCREEN_H * scale;
+            let border = GB_BORDER * p;
+
+            let side = left.width.max(right.width) * p;
+            let total_w = gb_w + 2 * (side + border);
+            let column_h = border + gb_h + border + bottom.height * p;
+            let total_h = column_h.max(left.height * p);
+            if total_w > width || total_h > height {
+                continue;
+            }
+
+            let x0 = (width - total_w) / 2;
+            let y0 = (height - total_h) / 2;
+            let gb = Placement { x: x0 + side + border, y: y0 + border, scale };
+
+            let panels = [
+                Panel { src: left, dst_x: x0, dst_y: y0 },
+                Panel { src: right, dst_x: gb.x + gb_w + border, dst_y: y0 },
+                Panel {
+                    src: bottom,
+                    dst_x: gb.x + (gb_w - bottom.width * p) / 2,
+                    dst_y: gb.y + gb_h + border,
+                },
+            ];
+            return Some((gb, p, panels));
+        }
+    }
+    None
+}
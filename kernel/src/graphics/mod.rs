@@ -2,9 +2,26 @@
 //!
 //! Provides VGA Mode 13h graphics support including:
 //! - Low-level drawing primitives (vga_mode13h)
+//! - Planar Mode X (320x240) primitives (vga_modex) and page flipping (page_flip)
 //! - Palette management (vga_palette)
+//! - Per-frame GBC color allocation (palette_quantizer)
+//! - Upscaling, LCD and color filters for true-color output (filters)
+//! - Frame blending through per-frame palettes (frame_blend)
+//! - DMG palette presets, built in and from PALETTES.TXT (dmg_palettes)
+//! - BMP screenshots to the ROM disk (screenshot)
+//! - Gameplay recording to run-length encoded video files (video_capture)
+//! - High-resolution BGA output with the overlay around it (hires)
 //! - Double buffering with VSync (double_buffer) - NEW
 
 pub mod vga_mode13h;
+pub mod vga_modex;
 pub mod vga_palette;
+pub mod palette_quantizer;
+pub mod filters;
+pub mod frame_blend;
+pub mod dmg_palettes;
+pub mod screenshot;
+pub mod video_capture;
+pub mod hires;
 pub mod double_buffer;
+pub mod page_flip;
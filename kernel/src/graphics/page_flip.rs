@@ -0,0 +1,141 @@
+//! Page-Flipped Mode X Output
+//!
+//! Replaces `double_buffer::flip_vsync` (a full 64KB copy to 0xA0000 every
+//! frame) when the display is in Mode X. Everything is still drawn into
+//! the 320x200 back buffer, but each page remembers a hash of every row
+//! segment (left of, across and right of the Game Boy screen) as it last
+//! received it, and only segments that differ are written. A static
+//! overlay costs nothing; a static game screen neither. Showing the page
+//! is a CRTC start address change instead of a copy.
+//!
+//! With three pages the page being drawn is never the one on screen nor
+//! the one waiting to be latched, so `flip` doesn't wait for retrace.
+//!
+//! The 320x200 layout sits at the top of the 240-line screen; the 40 lines
+//! below it hold a status strip.
+
+use crate::graphics::vga_mode13h::{SCREEN_HEIGHT as BACK_HEIGHT, SCREEN_WIDTH as BACK_WIDTH};
+use crate::graphics::vga_modex::{self, PAGE_COUNT, SCREEN_HEIGHT};
+use crate::graphics::vga_palette::{COLOR_GB_BORDER, COLOR_OVERLAY_BG, COLOR_OVERLAY_TEXT};
+use crate::gui::font_8x8;
+use crate::gui::layout::{GB_HEIGHT, GB_WIDTH, GB_X, GB_Y};
+
+/// First line below the 320x200 layout
+pub const STATUS_Y: usize = BACK_HEIGHT;
+
+/// Height of the status strip
+pub const STATUS_HEIGHT: usize = SCREEN_HEIGHT - BACK_HEIGHT;
+
+/// Longest status text (one 8x8 line)
+const STATUS_LEN: usize = BACK_WIDTH / font_8x8::CHAR_WIDTH;
+
+/// Row segments hashed separately: left of, across and right of the GB screen
+const SEGMENTS: usize = 3;
+
+/// Mode X chosen in the settings menu (off: plain Mode 13h)
+///
+/// Off by default: every frame still hashes the whole back buffer, which
+/// costs about as much as the Mode 13h copy it replaces.
+static mut ENABLED: bool = false;
+
+/// True if Mode X should be used when there is no BGA
+pub fn enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+/// Choose Mode X or Mode 13h; takes effect when the caller next switches
+pub fn set_enabled(enabled: bool) {
+    unsafe { ENABLED = enabled; }
+}
+
+/// Mode X display with one page shown and the next one being drawn
+pub struct ModeXScreen {
+    /// Page being drawn
+    draw: usize,
+    /// Hash of each row segment as last copied into each page
+    row_hash: [[[u32; SEGMENTS]; BACK_HEIGHT]; PAGE_COUNT],
+    /// Page has never received the back buffer
+    stale: [bool; PAGE_COUNT],
+    /// Status text and length as last drawn into each page
+    status: [([u8; STATUS_LEN], usize); PAGE_COUNT],
+}
+
+impl ModeXScreen {
+    /// Switch to Mode X, showing page 0 and drawing page 1
+    pub fn init() -> ModeXScreen {
+        vga_modex::set_mode();
+        vga_modex::set_draw_page(1);
+        ModeXScreen {
+            draw: 1,
+            row_hash: [[[0; SEGMENTS]; BACK_HEIGHT]; PAGE_COUNT],
+            stale: [true; PAGE_COUNT],
+            status: [([0; STATUS_LEN], usize::MAX); PAGE_COUNT],
+        }
+    }
+
+    /// Go back to Mode 13h (the next `double_buffer::flip_vsync` redraws
+    /// the whole screen)
+    pub fn leave(self) {
+        vga_modex::restore_mode_13h();
+    }
+
+    /// Copy the parts of the back buffer the draw page doesn't have yet
+    pub fn present(&mut self, back: &[u8]) {
+        let page = self.draw;
+        let stale = core::mem::replace(&mut self.stale[page], false);
+
+        for y in 0..BACK_HEIGHT {
+            let row = &back[y * BACK_WIDTH..(y + 1) * BACK_WIDTH];
+            let bounds: &[(usize, usize)] = if (GB_Y..GB_Y + GB_HEIGHT).contains(&y) {
+                &[(0, GB_X), (GB_X, GB_X + GB_WIDTH), (GB_X + GB_WIDTH, BACK_WIDTH)]
+            } else {
+                &[(0, BACK_WIDTH)]
+            };
+
+            for (seg, &(x0, x1)) in bounds.iter().enumerate() {
+                let hash = fnv1a(&row[x0..x1]);
+                if !stale && self.row_hash[page][y][seg] == hash {
+                    continue;
+                }
+                self.row_hash[page][y][seg] = hash;
+                vga_modex::write_row(x0, y, &row[x0..x1]);
+            }
+        }
+    }
+
+    /// Show `text` in the status strip of the draw page (if it changed)
+    pub fn draw_status(&mut self, text: &str) {
+        let bytes = &text.as_bytes()[..text.len().min(STATUS_LEN)];
+        let (last, last_len) = &mut self.status[self.draw];
+        if *last_len == bytes.len() && &last[..bytes.len()] == bytes {
+            return;
+        }
+        last[..bytes.len()].copy_from_slice(bytes);
+        *last_len = bytes.len();
+
+        vga_modex::fill_rect(0, STATUS_Y, BACK_WIDTH, STATUS_HEIGHT, COLOR_OVERLAY_BG);
+        vga_modex::draw_hline(0, STATUS_Y, BACK_WIDTH, COLOR_GB_BORDER);
+        let text = core::str::from_utf8(bytes).unwrap_or("");
+        let y = STATUS_Y + (STATUS_HEIGHT - font_8x8::CHAR_HEIGHT) / 2;
+        font_8x8::draw_string_centered_modex(y, text, COLOR_OVERLAY_TEXT);
+    }
+
+    /// Show the draw page and move on to the next one
+    pub fn flip(&mut self) {
+        vga_modex::show_page(self.draw);
+        self.draw = (self.draw + 1) % PAGE_COUNT;
+        vga_modex::set_draw_page(self.draw);
+    }
+}
+
+const FNV_OFFSET: u32 = 0x811C_9DC5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// FNV-1a hash of `bytes`
+fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash = FNV_OFFSET;
+    for &b in bytes {
+        hash = (hash ^ b as u32).wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
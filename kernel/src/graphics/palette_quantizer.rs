@@ -0,0 +1,213 @@
+//! Per-Frame CGB Colour Allocation
+//!
+//! The GPU writes CGB pixels into the back buffer as raw palette indices
+//! (0-31 BG, 32-63 OBJ) and records which colours those indices meant on
+//! each scanline. Mapping them to fixed DAC entries only shows one palette
+//! per frame, so mid-frame palette changes (HBlank tricks, HDMA-driven
+//! gradients) come out wrong.
+//!
+//! Once a frame is complete, `FrameQuantizer::apply` gathers every distinct
+//! colour actually used on screen, gives each one of the 160 free DAC
+//! entries (96-255) and rewrites the Game Boy area of the back buffer to
+//! point at them. If the frame uses more than 160 colours, precision is
+//! dropped one bit per channel at a time until they fit; at 2 bits per
+//! channel there are only 64 possible colours, so allocation always
+//! succeeds.
+//!
+//! `apply_pixels` does the same for a frame given as one RGB555 colour
+//! per pixel, for colours that aren't in any palette (frame blending).
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use crate::graphics::vga_mode13h::SCREEN_WIDTH;
+use crate::graphics::vga_palette::{set_palette_entry_gbc, PAL_DYNAMIC_START};
+use crate::gui::layout::{GB_HEIGHT, GB_WIDTH, GB_X, GB_Y};
+
+/// DAC entries available for dynamic allocation (96-255)
+const DYNAMIC_COLORS: usize = 160;
+
+/// Palette indices the GPU emits for CGB pixels
+const CGB_INDICES: usize = 64;
+
+/// Colour space of RGB555 values
+const COLOR_SPACE: usize = 1 << 15;
+
+/// Maps the colours of one frame onto DAC entries 96-255
+pub struct FrameQuantizer {
+    /// Reduced RGB555 colour -> allocated slot + 1 (0 = not allocated)
+    slot_of: Box<[u8; COLOR_SPACE]>,
+    /// Colours allocated this frame, by slot
+    colors: [u16; DYNAMIC_COLORS],
+    count: usize,
+    /// What each DAC entry currently holds (`u16::MAX` = unknown)
+    programmed: [u16; DYNAMIC_COLORS],
+    /// Palette indices used on each line (bit n = index n)
+    used: [u64; GB_HEIGHT],
+    /// DAC entry for each palette index on each line
+    remap: Box<[[u8; CGB_INDICES]; GB_HEIGHT]>,
+}
+
+impl FrameQuantizer {
+    pub fn new() -> FrameQuantizer {
+        FrameQuantizer {
+            slot_of: Box::new([0; COLOR_SPACE]),
+            colors: [0; DYNAMIC_COLORS],
+            count: 0,
+            programmed: [u16::MAX; DYNAMIC_COLORS],
+            used: [0; GB_HEIGHT],
+            remap: Box::new([[0; CGB_INDICES]; GB_HEIGHT]),
+        }
+    }
+
+    /// Forget what the DAC holds (after something else reprogrammed it)
+    pub fn invalidate_dac(&mut self) {
+        self.programmed = [u16::MAX; DYNAMIC_COLORS];
+    }
+
+    /// Allocate DAC entries for per-pixel colours and write them to `screen`
+    ///
+    /// `pixels` holds `GB_WIDTH * GB_HEIGHT` RGB555 colours, row by row;
+    /// every pixel of the Game Boy area of `screen` is overwritten.
+    pub fn apply_pixels(&mut self, pixels: &[u16], screen: &mut [u8]) {
+        let mut bits = 5;
+        while !self.allocate_pixels(pixels, bits) {
+            bits -= 1;
+        }
+
+        self.program_dac();
+
+        for (y, line) in pixels.chunks_exact(GB_WIDTH).enumerate() {
+            let row = (GB_Y + y) * SCREEN_WIDTH + GB_X;
+            for (px, &color) in screen[row..row + GB_WIDTH].iter_mut().zip(line) {
+                let slot = self.slot_of[reduce(color, bits) as usize];
+                *px = PAL_DYNAMIC_START + slot - 1;
+            }
+        }
+    }
+
+    /// Give every colour in `pixels` a slot, at `bits` bits per channel
+    fn allocate_pixels(&mut self, pixels: &[u16], bits: u32) -> bool {
+        self.clear_slots();
+        pixels.iter().all(|&color| self.slot_for(reduce(color, bits)).is_some())
+    }
+
+    /// Forget the previous allocation
+    fn clear_slots(&mut self) {
+        for &c in &self.colors[..self.count] {
+            self.slot_of[c as usize] = 0;
+        }
+        self.count = 0;
+    }
+
+    /// Slot (1-based) holding `color`, allocating one if needed
+    ///
+    /// Returns `None` once all `DYNAMIC_COLORS` are taken.
+    fn slot_for(&mut self, color: u16) -> Option<u8> {
+        let slot = self.slot_of[color as usize];
+        if slot != 0 {
+            return Some(slot);
+        }
+        if self.count == DYNAMIC_COLORS {
+            return None;
+        }
+        self.colors[self.count] = color;
+        self.count += 1;
+        self.slot_of[color as usize] = self.count as u8;
+        Some(self.count as u8)
+    }
+
+    /// Allocate DAC entries for the frame in `screen` and remap its pixels
+    ///
+    /// `palettes[y]` holds the 64 RGB555 colours in effect on line `y`;
+    /// `screen` is the 320x200 back buffer.
+    pub fn apply(&mut self, palettes: &[[u16; CGB_INDICES]; GB_HEIGHT], screen: &mut [u8]) {
+        self.collect_used(screen);
+
+        let mut bits = 5;
+        while !self.allocate(palettes, bits) {
+            bits -= 1;
+        }
+
+        self.program_dac();
+        self.remap_pixels(screen);
+    }
+
+    /// Note which palette indices appear on each line
+    fn collect_used(&mut self, screen: &[u8]) {
+        for (y, used) in self.used.iter_mut().enumerate() {
+            let row = (GB_Y + y) * SCREEN_WIDTH + GB_X;
+            *used = 0;
+            for &px in &screen[row..row + GB_WIDTH] {
+                if (px as usize) < CGB_INDICES {
+                    *used |= 1u64 << px;
+                }
+            }
+        }
+    }
+
+    /// Give every used colour a slot, at `bits` bits per channel
+    ///
+    /// Returns false if the colours don't fit in `DYNAMIC_COLORS`.
+    fn allocate(&mut self, palettes: &[[u16; CGB_INDICES]; GB_HEIGHT], bits: u32) -> bool {
+        self.clear_slots();
+
+        for y in 0..GB_HEIGHT {
+            let mut used = self.used[y];
+            while used != 0 {
+                let index = used.trailing_zeros() as usize;
+                used &= used - 1;
+
+                let slot = match self.slot_for(reduce(palettes[y][index], bits)) {
+                    Some(slot) => slot,
+                    None => return false,
+                };
+                self.remap[y][index] = PAL_DYNAMIC_START + slot - 1;
+            }
+        }
+        true
+    }
+
+    /// Write changed entries to the DAC
+    fn program_dac(&mut self) {
+        for slot in 0..self.count {
+            let color = self.colors[slot];
+            if self.programmed[slot] != color {
+                self.programmed[slot] = color;
+                let r = (color & 0x1F) as u8;
+                let g = ((color >> 5) & 0x1F) as u8;
+                let b = ((color >> 10) & 0x1F) as u8;
+                set_palette_entry_gbc(PAL_DYNAMIC_START + slot as u8, r, g, b);
+            }
+        }
+    }
+
+    /// Point every Game Boy pixel at its allocated DAC entry
+    fn remap_pixels(&self, screen: &mut [u8]) {
+        for y in 0..GB_HEIGHT {
+            let row = (GB_Y + y) * SCREEN_WIDTH + GB_X;
+            let remap = &self.remap[y];
+            for px in &mut screen[row..row + GB_WIDTH] {
+                if (*px as usize) < CGB_INDICES {
+                    *px = remap[*px as usize];
+                }
+            }
+        }
+    }
+}
+
+/// Keep the top `bits` bits of each 5-bit channel, centred in its bucket
+fn reduce(color: u16, bits: u32) -> u16 {
+    if bits >= 5 {
+        return color & 0x7FFF;
+    }
+    let drop = 5 - bits;
+    let mask = (0x1F >> drop) << drop;
+    let half = 1 << (drop - 1);
+    let mut out = 0;
+    for shift in [0, 5, 10] {
+        let c = (color >> shift) & 0x1F;
+        out |= ((c & mask) | half) << shift;
+    }
+    out
+}
@@ -0,0 +1,164 @@
+//! Screenshots
+//!
+//! Saves the screen as 24-bit BMP files in the root directory of the ROM
+//! disk, numbered `SHOT0000.BMP`, `SHOT0001.BMP` and so on. Two kinds:
+//!
+//! - **Game Boy**: the raw 160x144 frame in the game's own colors, before
+//!   color curves, filters and blending.
+//! - **Screen**: the composited 320x200 layout (overlay included) as the
+//!   DAC shows it. In high resolution the Game Boy screen isn't in the
+//!   back buffer, so the raw frame is put in its place.
+//!
+//! The file buffer is allocated on the first capture and reused.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crate::gameboy::Device;
+use crate::graphics::dmg_palettes;
+use crate::graphics::vga_mode13h::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::graphics::vga_palette::palette_rgb8;
+use crate::gui::layout::{GB_HEIGHT, GB_WIDTH, GB_X, GB_Y};
+use crate::fs::{vfs, FsError};
+
+/// BITMAPFILEHEADER + BITMAPINFOHEADER
+const HEADER_SIZE: usize = 14 + 40;
+
+/// Largest file (a screen capture), reserved up front so the buffer is
+/// never reallocated
+const MAX_FILE_SIZE: usize = HEADER_SIZE + SCREEN_WIDTH * 3 * SCREEN_HEIGHT;
+
+/// Highest file number (`SHOT9999.BMP`)
+const MAX_SHOTS: u32 = 10000;
+
+/// What to capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    GameBoy,
+    Screen,
+}
+
+/// Screenshot writer; remembers the next free file number
+pub struct Screenshots {
+    /// Raw Game Boy frame (RGB555)
+    frame: Box<[u16; GB_WIDTH * GB_HEIGHT]>,
+    /// BMP file being built
+    file: Vec<u8>,
+    /// Next number to try
+    next: u32,
+}
+
+impl Screenshots {
+    pub fn new() -> Screenshots {
+        Screenshots {
+            frame: Box::new([0; GB_WIDTH * GB_HEIGHT]),
+            file: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Capture and save; returns the file name (`SHOTnnnn.BMP`)
+    ///
+    /// `back` is the 320x200 back buffer; `hires` is true when the Game
+    /// Boy screen is drawn straight to a BGA framebuffer instead.
+    pub fn capture(
+        &mut self,
+        kind: Capture,
+        device: &Device,
+        back: &[u8],
+        hires: bool,
+    ) -> Result<[u8; 12], &'static str> {
+        if self.file.capacity() == 0 {
+            self.file.reserve_exact(MAX_FILE_SIZE);
+        }
+        device.frame_rgb555(&mut self.frame[..], &dmg_palettes::active().colors);
+
+        match kind {
+            Capture::GameBoy => {
+                let frame = &self.frame;
+                encode_bmp(&mut self.file, GB_WIDTH, GB_HEIGHT, |x, y| {
+                    rgb555_to_rgb8(frame[y * GB_WIDTH + x])
+                });
+            }
+            Capture::Screen => {
+                let frame = &self.frame;
+                encode_bmp(&mut self.file, SCREEN_WIDTH, SCREEN_HEIGHT, |x, y| {
+                    let in_gb = (GB_X..GB_X + GB_WIDTH).contains(&x)
+                        && (GB_Y..GB_Y + GB_HEIGHT).contains(&y);
+                    if hires && in_gb {
+                        rgb555_to_rgb8(frame[(y - GB_Y) * GB_WIDTH + x - GB_X])
+                    } else {
+                        palette_rgb8(back[y * SCREEN_WIDTH + x])
+                    }
+                });
+            }
+        }
+
+        while self.next < MAX_SHOTS {
+            let path = shot_path(self.next);
+            self.next += 1;
+            // Written as ASCII digits
+            let path_str = core::str::from_utf8(&path).unwrap_or("");
+            match vfs::stat(path_str) {
+                Err(FsError::NotFound) => {}
+                Err(e) => return Err(e.as_str()),
+                Ok(_) => continue,
+            }
+            vfs::write_file(path_str, &self.file).map_err(|e| e.as_str())?;
+            let mut name = [0u8; 12];
+            name.copy_from_slice(&path[1..]);
+            return Ok(name);
+        }
+        Err("No free screenshot number")
+    }
+}
+
+/// Path of screenshot `n` (`/SHOTnnnn.BMP`)
+fn shot_path(n: u32) -> [u8; 13] {
+    let mut path = *b"/SHOT0000.BMP";
+    for (i, place) in [1000, 100, 10, 1].iter().enumerate() {
+        path[5 + i] = b'0' + (n / place % 10) as u8;
+    }
+    path
+}
+
+#[inline]
+fn rgb555_to_rgb8(c: u16) -> (u8, u8, u8) {
+    let (r, g, b) = ((c & 0x1F) as u8, (c >> 5 & 0x1F) as u8, (c >> 10 & 0x1F) as u8);
+    (r << 3 | r >> 2, g << 3 | g >> 2, b << 3 | b >> 2)
+}
+
+/// Build a 24-bit bottom-up BMP in `out` from `pixel(x, y)`
+fn encode_bmp(out: &mut Vec<u8>, width: usize, height: usize, pixel: impl Fn(usize, usize) -> (u8, u8, u8)) {
+    let row_size = (width * 3).next_multiple_of(4);
+    let file_size = HEADER_SIZE + row_size * height;
+    out.clear();
+    out.reserve_exact(file_size);
+
+    // BITMAPFILEHEADER
+    out.extend_from_slice(b"BM");
+    out.extend_from_slice(&(file_size as u32).to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+
+    // BITMAPINFOHEADER
+    out.extend_from_slice(&40u32.to_le_bytes());
+    out.extend_from_slice(&(width as i32).to_le_bytes());
+    out.extend_from_slice(&(height as i32).to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // planes
+    out.extend_from_slice(&24u16.to_le_bytes()); // bits per pixel
+    out.extend_from_slice(&0u32.to_le_bytes()); // BI_RGB
+    out.extend_from_slice(&((row_size * height) as u32).to_le_bytes());
+    out.extend_from_slice(&2835u32.to_le_bytes()); // 72 DPI
+    out.extend_from_slice(&2835u32.to_le_bytes());
+    out.extend_from_slice(&[0; 8]); // palette sizes
+
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let (r, g, b) = pixel(x, y);
+            out.extend_from_slice(&[b, g, r]);
+        }
+        out.resize(out.len() + row_size - width * 3, 0);
+    }
+}
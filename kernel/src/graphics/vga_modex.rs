@@ -0,0 +1,394 @@
+//! VGA Mode X Graphics Driver
+//!
+//! Provides planar drawing primitives for Mode X (320x240, 256 colors,
+//! unchained). Pixel x lives in plane `x % 4` at byte `x / 4` of its row,
+//! so each row is 80 bytes and a whole screen ("page") is 19200 bytes.
+//! Three pages fit in the 64KB window at 0xA0000; one is shown (CRTC start
+//! address) while the others are drawn.
+//!
+//! Primitives draw to the current *draw page* (`set_draw_page`), the
+//! planar counterpart of `vga_mode13h` writing to the visible screen.
+
+use crate::arch::x86::io::{inb, outb};
+
+// ============================================================================
+// Constants
+// ============================================================================
+
+/// Screen dimensions for Mode X
+pub const SCREEN_WIDTH: usize = 320;
+pub const SCREEN_HEIGHT: usize = 240;
+
+/// Bytes per row in each plane
+pub const BYTES_PER_ROW: usize = SCREEN_WIDTH / 4;
+
+/// Bytes per page in each plane
+pub const PAGE_SIZE: usize = BYTES_PER_ROW * SCREEN_HEIGHT;
+
+/// Pages that fit in the 64KB window
+pub const PAGE_COUNT: usize = 3;
+
+/// VGA framebuffer base address
+pub const VGA_ADDR: *mut u8 = 0xA0000 as *mut u8;
+
+/// VGA registers
+const MISC_OUTPUT: u16 = 0x3C2;
+const SC_INDEX: u16 = 0x3C4;
+const SC_DATA: u16 = 0x3C5;
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+const INPUT_STATUS_1: u16 = 0x3DA;
+
+/// Sequencer registers
+const SC_RESET: u8 = 0x00;
+const SC_MAP_MASK: u8 = 0x02;
+const SC_MEMORY_MODE: u8 = 0x04;
+
+/// CRTC registers
+const CRTC_START_HIGH: u8 = 0x0C;
+const CRTC_START_LOW: u8 = 0x0D;
+const CRTC_VRETRACE_END: u8 = 0x11;
+
+/// CRTC timing for 240 lines (from Mode 13h's 400-line base timing)
+const CRTC_240_LINES: [(u8, u8); 10] = [
+    (0x06, 0x0D), // vertical total
+    (0x07, 0x3E), // overflow
+    (0x09, 0x41), // max scan line (double scan)
+    (0x10, 0xEA), // vertical retrace start
+    (0x11, 0xAC), // vertical retrace end (and write protect off)
+    (0x12, 0xDF), // vertical display end
+    (0x14, 0x00), // underline location: doubleword mode off
+    (0x15, 0xE7), // vertical blank start
+    (0x16, 0x06), // vertical blank end
+    (0x17, 0xE3), // mode control: byte mode on
+];
+
+/// The same registers as Mode 13h has them (the BIOS values)
+const CRTC_MODE_13H: [(u8, u8); 10] = [
+    (0x06, 0xBF),
+    (0x07, 0x1F),
+    (0x09, 0x41),
+    (0x10, 0x9C),
+    (0x11, 0x8E), // write protect back on, so last of the protected ones
+    (0x12, 0x8F),
+    (0x14, 0x40),
+    (0x15, 0x96),
+    (0x16, 0xB9),
+    (0x17, 0xA3),
+];
+
+/// Offset of the page drawing primitives write to
+static mut DRAW_OFFSET: usize = 0;
+
+// ============================================================================
+// Mode and Page Control
+// ============================================================================
+
+#[inline]
+fn write_sc(index: u8, value: u8) {
+    unsafe {
+        outb(SC_INDEX, index);
+        outb(SC_DATA, value);
+    }
+}
+
+#[inline]
+fn write_crtc(index: u8, value: u8) {
+    unsafe {
+        outb(CRTC_INDEX, index);
+        outb(CRTC_DATA, value);
+    }
+}
+
+/// Enable writes to the planes in `mask` (bit n = plane n)
+#[inline]
+fn select_planes(mask: u8) {
+    write_sc(SC_MAP_MASK, mask);
+}
+
+/// Switch from Mode 13h to Mode X and clear all pages
+///
+/// Expects Mode 13h (set by the bootloader) as the starting point; only
+/// the registers that differ are reprogrammed.
+pub fn set_mode() {
+    unsafe {
+        // Unchain: chain-4 and odd/even off, extended memory on
+        write_sc(SC_MEMORY_MODE, 0x06);
+
+        // 25MHz dot clock, 480-line sync polarity
+        write_sc(SC_RESET, 0x01);
+        outb(MISC_OUTPUT, 0xE3);
+        write_sc(SC_RESET, 0x03);
+
+        // Lift the CRTC write protect, then program 240-line timing
+        outb(CRTC_INDEX, CRTC_VRETRACE_END);
+        let v = inb(CRTC_DATA);
+        outb(CRTC_DATA, v & 0x7F);
+        for &(index, value) in CRTC_240_LINES.iter() {
+            write_crtc(index, value);
+        }
+
+        // Clear every page in all planes at once
+        select_planes(0x0F);
+        for i in 0..PAGE_SIZE * PAGE_COUNT {
+            core::ptr::write_volatile(VGA_ADDR.add(i), 0);
+        }
+
+        DRAW_OFFSET = 0;
+    }
+    show_page(0);
+}
+
+/// Switch from Mode X back to Mode 13h
+///
+/// The screen keeps whatever the pages held until the next full copy of
+/// the back buffer.
+pub fn restore_mode_13h() {
+    show_page(0);
+    unsafe {
+        // Chain-4 on, all planes written (chained writes pick the plane)
+        write_sc(SC_MEMORY_MODE, 0x0E);
+        select_planes(0x0F);
+
+        write_sc(SC_RESET, 0x01);
+        outb(MISC_OUTPUT, 0x63);
+        write_sc(SC_RESET, 0x03);
+
+        outb(CRTC_INDEX, CRTC_VRETRACE_END);
+        let v = inb(CRTC_DATA);
+        outb(CRTC_DATA, v & 0x7F);
+        for &(index, value) in CRTC_MODE_13H.iter() {
+            write_crtc(index, value);
+        }
+
+        DRAW_OFFSET = 0;
+    }
+}
+
+/// Direct drawing to page `page` (0 to PAGE_COUNT - 1)
+pub fn set_draw_page(page: usize) {
+    unsafe { DRAW_OFFSET = (page % PAGE_COUNT) * PAGE_SIZE; }
+}
+
+/// Display page `page`
+///
+/// The CRTC latches the new start address at the next vertical retrace;
+/// until then the old page stays on screen.
+pub fn show_page(page: usize) {
+    let offset = (page % PAGE_COUNT) * PAGE_SIZE;
+    write_crtc(CRTC_START_HIGH, (offset >> 8) as u8);
+    write_crtc(CRTC_START_LOW, offset as u8);
+}
+
+/// Wait for the start of the next vertical retrace
+pub fn wait_vsync() {
+    unsafe {
+        while inb(INPUT_STATUS_1) & 0x08 != 0 {
+            core::hint::spin_loop();
+        }
+        while inb(INPUT_STATUS_1) & 0x08 == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Byte address of pixel column group `x / 4` on row `y` of the draw page
+#[inline]
+fn row_addr(x: usize, y: usize) -> *mut u8 {
+    unsafe { VGA_ADDR.add(DRAW_OFFSET + y * BYTES_PER_ROW + x / 4) }
+}
+
+// ============================================================================
+// Drawing Primitives
+// ============================================================================
+
+/// Fill the entire draw page with a single color
+///
+/// # Arguments
+/// * `color` - VGA palette index (0-255)
+#[inline(never)]
+pub fn fill_screen(color: u8) {
+    fill_rect(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT, color);
+}
+
+/// Fill a rectangular region with a color
+///
+/// Columns are written one plane at a time, so each byte written covers
+/// every fourth pixel of the span.
+///
+/// # Arguments
+/// * `x` - Left edge X coordinate
+/// * `y` - Top edge Y coordinate
+/// * `w` - Width in pixels
+/// * `h` - Height in pixels
+/// * `color` - VGA palette index (0-255)
+#[inline(never)]
+pub fn fill_rect(x: usize, y: usize, w: usize, h: usize, color: u8) {
+    let x_end = (x + w).min(SCREEN_WIDTH);
+    let y_end = (y + h).min(SCREEN_HEIGHT);
+    if x >= x_end || y >= y_end {
+        return;
+    }
+
+    for plane in 0..4 {
+        // First column at or after x that lives in this plane
+        let first = x + (plane + 4 - x % 4) % 4;
+        if first >= x_end {
+            continue;
+        }
+        select_planes(1 << plane);
+        for py in y..y_end {
+            let mut px = first;
+            while px < x_end {
+                unsafe { core::ptr::write_volatile(row_addr(px, py), color); }
+                px += 4;
+            }
+        }
+    }
+}
+
+/// Set a single pixel
+///
+/// # Arguments
+/// * `x` - X coordinate
+/// * `y` - Y coordinate
+/// * `color` - VGA palette index (0-255)
+#[inline(never)]
+pub fn set_pixel(x: usize, y: usize, color: u8) {
+    if x < SCREEN_WIDTH && y < SCREEN_HEIGHT {
+        select_planes(1 << (x % 4));
+        unsafe { core::ptr::write_volatile(row_addr(x, y), color); }
+    }
+}
+
+/// Copy a row of pixels (one palette index each) to (`x`, `y`)
+///
+/// Four passes, one per plane, with a single map mask change each.
+#[inline(never)]
+pub fn write_row(x: usize, y: usize, pixels: &[u8]) {
+    if y >= SCREEN_HEIGHT || x >= SCREEN_WIDTH {
+        return;
+    }
+    let len = pixels.len().min(SCREEN_WIDTH - x);
+
+    for plane in 0..4 {
+        let skip = (plane + 4 - x % 4) % 4;
+        if skip >= len {
+            continue;
+        }
+        select_planes(1 << plane);
+        let mut i = skip;
+        while i < len {
+            unsafe { core::ptr::write_volatile(row_addr(x + i, y), pixels[i]); }
+            i += 4;
+        }
+    }
+}
+
+/// Draw a horizontal line
+///
+/// # Arguments
+/// * `x` - Starting X coordinate
+/// * `y` - Y coordinate
+/// * `length` - Length in pixels
+/// * `color` - VGA palette index (0-255)
+#[inline(never)]
+pub fn draw_hline(x: usize, y: usize, length: usize, color: u8) {
+    fill_rect(x, y, length, 1, color);
+}
+
+/// Draw a vertical line
+///
+/// # Arguments
+/// * `x` - X coordinate
+/// * `y` - Starting Y coordinate
+/// * `length` - Length in pixels
+/// * `color` - VGA palette index (0-255)
+#[inline(never)]
+pub fn draw_vline(x: usize, y: usize, length: usize, color: u8) {
+    fill_rect(x, y, 1, length, color);
+}
+
+/// Draw a rectangle outline (not filled)
+///
+/// # Arguments
+/// * `x` - Left edge X coordinate
+/// * `y` - Top edge Y coordinate
+/// * `w` - Width in pixels
+/// * `h` - Height in pixels
+/// * `color` - VGA palette index (0-255)
+#[inline(never)]
+pub fn draw_rect(x: usize, y: usize, w: usize, h: usize, color: u8) {
+    draw_hline(x, y, w, color); // Top
+    draw_hline(x, y + h - 1, w, color); // Bottom
+    draw_vline(x, y, h, color); // Left
+    draw_vline(x + w - 1, y, h, color); // Right
+}
+
+/// Draw a thick border (multiple pixel width)
+///
+/// # Arguments
+/// * `x` - Left edge X coordinate
+/// * `y` - Top edge Y coordinate
+/// * `w` - Width in pixels
+/// * `h` - Height in pixels
+/// * `thickness` - Border thickness in pixels
+/// * `color` - VGA palette index (0-255)
+#[inline(never)]
+pub fn draw_thick_border(x: usize, y: usize, w: usize, h: usize, thickness: usize, color: u8) {
+    fill_rect(x, y, w, thickness, color);
+    fill_rect(x, y + h - thickness, w, thickness, color);
+    fill_rect(x, y, thickness, h, color);
+    fill_rect(x + w - thickness, y, thickness, h, color);
+}
+
+/// Draw the set pixels of a bitmap, `width` columns wide
+///
+/// Groups the writes by plane so the map mask changes at most four times.
+fn draw_bitmap(x: usize, y: usize, rows: &[u8], width: usize, color: u8) {
+    for plane in 0..4 {
+        let skip = (plane + 4 - x % 4) % 4;
+        if skip >= width {
+            continue;
+        }
+        select_planes(1 << plane);
+        for (row, bits) in rows.iter().enumerate() {
+            let py = y + row;
+            if py >= SCREEN_HEIGHT {
+                break;
+            }
+            let mut col = skip;
+            while col < width {
+                let px = x + col;
+                if px < SCREEN_WIDTH && (bits >> (7 - col)) & 1 != 0 {
+                    unsafe { core::ptr::write_volatile(row_addr(px, py), color); }
+                }
+                col += 4;
+            }
+        }
+    }
+}
+
+/// Draw an 8x8 bitmap (for font rendering, sprites, etc.)
+///
+/// # Arguments
+/// * `x` - Left edge X coordinate
+/// * `y` - Top edge Y coordinate
+/// * `bitmap` - Array of 8 bytes, one per row, MSB is leftmost pixel
+/// * `color` - VGA palette index for set pixels (0-255)
+#[inline(never)]
+pub fn draw_bitmap_8x8(x: usize, y: usize, bitmap: [u8; 8], color: u8) {
+    draw_bitmap(x, y, &bitmap, 8, color);
+}
+
+/// Draw an 4x6 bitmap (for font rendering, sprites, etc.)
+///
+/// # Arguments
+/// * `x` - Left edge X coordinate
+/// * `y` - Top edge Y coordinate
+/// * `bitmap` - Array of 6 bytes, one per row, MSB is leftmost pixel
+/// * `color` - VGA palette index for set pixels (0-255)
+#[inline(never)]
+pub fn draw_bitmap_4x6(x: usize, y: usize, bitmap: [u8; 6], color: u8) {
+    draw_bitmap(x, y, &bitmap, 4, color);
+}
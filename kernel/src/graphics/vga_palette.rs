@@ -9,18 +9,31 @@
 //! Index   Purpose
 //! 0-31    GBC Background palettes (8 palettes × 4 colors)
 //! 32-63   GBC Sprite palettes (8 palettes × 4 colors)
-//! 64-79   DMG grayscale (16 shades for Classic mode)
+//! 64-79   DMG palettes (BG, OBJ0, OBJ1 from the active preset)
 //! 80-95   UI colors (border, overlay text, etc.)
-//! 96-255  Reserved / unused
+//! 96-255  GBC colors allocated per frame (see `palette_quantizer`)
 //! ```
 //!
+//! In GBC mode, entries 0-63 are only the GPU's raw output indices; the
+//! quantizer rewrites them to 96-255 before the frame is shown.
+//!
 //! # GBC to VGA Color Conversion
 //!
 //! GBC uses 5-bit RGB (0-31 per channel)
 //! VGA DAC uses 6-bit RGB (0-63 per channel)
-//! Conversion: vga_component = gbc_component * 2
+//!
+//! Game Boy colors (CGB palettes and DMG shades alike) go through a
+//! selectable `ColorCurve`: raw scaling, an emulation of the GBC's LCD,
+//! a GBA SP front-lit screen, or a user gamma. Each curve is baked into a
+//! 32K-entry RGB555 -> RGB lookup table when selected; the DAC takes its
+//! top 6 bits, and true-color output uses it for GBC frames as they are.
+//!
+//! A `VisionFilter` for color vision deficiencies (daltonization) or high
+//! contrast is folded into the same table, and also applied to the UI
+//! colors, so overlay HP bars and type colors stay distinguishable.
 
 use crate::arch::x86::io::{outb, inb};
+use crate::graphics::dmg_palettes;
 
 // VGA DAC ports
 const DAC_WRITE_INDEX: u16 = 0x3C8;  // Write: set palette index for writing
@@ -32,6 +45,14 @@ pub const PAL_GBC_BG_START: u8 = 0;      // GBC BG palettes 0-7
 pub const PAL_GBC_SPRITE_START: u8 = 32; // GBC sprite palettes 0-7
 pub const PAL_DMG_START: u8 = 64;        // DMG grayscale
 pub const PAL_UI_START: u8 = 80;         // UI elements
+pub const PAL_DYNAMIC_START: u8 = 96;    // Per-frame GBC colors
+
+/// Copy of every DAC entry (6-bit RGB), for converting the 8-bit back
+/// buffer when the screen is in a true-color mode
+static mut DAC_SHADOW: [[u8; 3]; 256] = [[0; 3]; 256];
+
+/// DMG entries before the color curve (BG, OBJ0, OBJ1 x 4 shades)
+static mut DMG_RGB555: [u16; 12] = [0; 12];
 
 // UI color indices (for easy reference)
 pub const COLOR_BLACK: u8 = PAL_UI_START;
@@ -40,6 +61,16 @@ pub const COLOR_GB_BORDER: u8 = PAL_UI_START + 2;
 pub const COLOR_OVERLAY_BG: u8 = PAL_UI_START + 3;
 pub const COLOR_OVERLAY_TEXT: u8 = PAL_UI_START + 4;
 pub const COLOR_OVERLAY_SHADOW: u8 = PAL_UI_START + 5;
+pub const COLOR_ERROR: u8 = PAL_UI_START + 6;
+pub const COLOR_SUCCESS: u8 = PAL_UI_START + 7;
+pub const COLOR_WARNING: u8 = PAL_UI_START + 8;
+pub const COLOR_INFO: u8 = PAL_UI_START + 9;
+pub const COLOR_DISABLED: u8 = PAL_UI_START + 10;
+pub const COLOR_HP_GREEN: u8 = PAL_UI_START + 11;
+pub const COLOR_HP_YELLOW: u8 = PAL_UI_START + 12;
+pub const COLOR_HP_RED: u8 = PAL_UI_START + 13;
+pub const COLOR_HP_BG: u8 = PAL_UI_START + 14;
+pub const COLOR_OVERLAY_HIGHLIGHT: u8 = PAL_UI_START + 15;
 
 /// Set a single VGA palette entry
 ///
@@ -49,6 +80,7 @@ pub const COLOR_OVERLAY_SHADOW: u8 = PAL_UI_START + 5;
 #[inline]
 pub fn set_palette_entry(index: u8, r: u8, g: u8, b: u8) {
     unsafe {
+        DAC_SHADOW[index as usize] = [r & 0x3F, g & 0x3F, b & 0x3F];
         outb(DAC_WRITE_INDEX, index);
         outb(DAC_DATA, r & 0x3F);  // Mask to 6 bits
         outb(DAC_DATA, g & 0x3F);
@@ -56,15 +88,21 @@ pub fn set_palette_entry(index: u8, r: u8, g: u8, b: u8) {
     }
 }
 
+/// Current color of a palette entry as 8-bit RGB
+#[inline]
+pub fn palette_rgb8(index: u8) -> (u8, u8, u8) {
+    let [r, g, b] = unsafe { DAC_SHADOW[index as usize] };
+    // Replicate the top bits so 63 maps to 255
+    ((r << 2) | (r >> 4), (g << 2) | (g >> 4), (b << 2) | (b >> 4))
+}
+
 /// Set a VGA palette entry from GBC 5-bit RGB values
 ///
-/// Converts 5-bit GBC components to 6-bit VGA components
+/// Converts through the active color curve
 #[inline]
 pub fn set_palette_entry_gbc(index: u8, r: u8, g: u8, b: u8) {
-    // GBC uses 5 bits (0-31), VGA uses 6 bits (0-63)
-    // Simple conversion: multiply by 2 (or shift left by 1)
-    // For better accuracy: (val * 63) / 31, but *2 is close enough and faster
-    set_palette_entry(index, r << 1, g << 1, b << 1);
+    let [r, g, b] = gbc_to_dac(r, g, b);
+    set_palette_entry(index, r, g, b);
 }
 
 /// Set a VGA palette entry from 8-bit RGB values
@@ -86,8 +124,7 @@ pub fn sync_gbc_bg_palettes(cbgpal: &[[[u8; 3]; 4]; 8]) {
         for col in 0..4 {
             let index = PAL_GBC_BG_START + (pal * 4 + col) as u8;
             let rgb = &cbgpal[pal][col];
-            // 5-bit (0-31) to 6-bit (0-63): shift left by 1
-            set_palette_entry(index, rgb[0] << 1, rgb[1] << 1, rgb[2] << 1);
+            set_palette_entry_gbc(index, rgb[0], rgb[1], rgb[2]);
         }
     }
 }
@@ -102,33 +139,57 @@ pub fn sync_gbc_sprite_palettes(csprit: &[[[u8; 3]; 4]; 8]) {
         for col in 0..4 {
             let index = PAL_GBC_SPRITE_START + (pal * 4 + col) as u8;
             let rgb = &csprit[pal][col];
-            // 5-bit (0-31) to 6-bit (0-63): shift left by 1
-            set_palette_entry(index, rgb[0] << 1, rgb[1] << 1, rgb[2] << 1);
+            set_palette_entry_gbc(index, rgb[0], rgb[1], rgb[2]);
         }
     }
 }
 
-/// Sync DMG grayscale palette
+/// Sync DMG palettes, colored by the active `dmg_palettes` preset
 ///
 /// # Arguments
 /// * `palb` - Background palette (4 shades)
 /// * `pal0` - Object palette 0 (4 shades)
 /// * `pal1` - Object palette 1 (4 shades)
 pub fn sync_dmg_palettes(palb: &[u8; 4], pal0: &[u8; 4], pal1: &[u8; 4]) {
-    // DMG uses 8-bit grayscale values (255, 192, 96, 0)
-    // Map to VGA grayscale
-    for i in 0..4 {
-        let gray = palb[i];
-        set_palette_entry_rgb8(PAL_DMG_START + i as u8, gray, gray, gray);
+    // The GPU gives each entry a gray level (255, 192, 96, 0); its shade
+    // picks the preset's RGB555 color, which goes through the color
+    // curve like CGB colors
+    let preset = dmg_palettes::active();
+    for (p, pal) in [palb, pal0, pal1].iter().enumerate() {
+        for i in 0..4 {
+            let color = preset.color(p, dmg_palettes::shade_of(pal[i]));
+            let slot = p * 4 + i;
+            unsafe { DMG_RGB555[slot] = color; }
+            let (r, g, b) = (color & 0x1F, (color >> 5) & 0x1F, (color >> 10) & 0x1F);
+            set_palette_entry_gbc(PAL_DMG_START + slot as u8, r as u8, g as u8, b as u8);
+        }
     }
-    for i in 0..4 {
-        let gray = pal0[i];
-        set_palette_entry_rgb8(PAL_DMG_START + 4 + i as u8, gray, gray, gray);
+}
+
+/// Uncorrected RGB555 color of a DMG entry (64-75), as last synced
+pub fn dmg_rgb555(index: u8) -> Option<u16> {
+    let slot = index.checked_sub(PAL_DMG_START)? as usize;
+    if slot < 12 {
+        Some(unsafe { DMG_RGB555[slot] })
+    } else {
+        None
     }
-    for i in 0..4 {
-        let gray = pal1[i];
-        set_palette_entry_rgb8(PAL_DMG_START + 8 + i as u8, gray, gray, gray);
+}
+
+/// Set a UI palette entry (6-bit RGB) through the active vision filter
+fn set_ui_entry(index: u8, r: u8, g: u8, b: u8) {
+    if vision_filter() == VisionFilter::Off {
+        set_palette_entry(index, r, g, b);
+        return;
     }
+    let matrix = unsafe { VISION_MATRIX };
+    let lin = [r, g, b].map(|c| DISPLAY_22[((c << 2) | (c >> 4)) as usize & 0xFF] as i32);
+    let mut out = [0u8; 3];
+    for (channel, row) in matrix.iter().enumerate() {
+        let mixed = (0..3).map(|i| row[i] * lin[i]).sum::<i32>() >> 10;
+        out[channel] = vision_tone(encode_22(mixed.clamp(0, 65535) as u32)) >> 2;
+    }
+    set_palette_entry(index, out[0], out[1], out[2]);
 }
 
 /// Initialize UI palette entries
@@ -136,29 +197,30 @@ pub fn sync_dmg_palettes(palb: &[u8; 4], pal0: &[u8; 4], pal1: &[u8; 4]) {
 /// Sets up colors used for borders, overlay, text, etc.
 pub fn init_ui_palette() {
     // Basic colors
-    set_palette_entry(COLOR_BLACK, 0, 0, 0);
-    set_palette_entry(COLOR_WHITE, 63, 63, 63);
+    set_ui_entry(COLOR_BLACK, 0, 0, 0);
+    set_ui_entry(COLOR_WHITE, 63, 63, 63);
 
     // Game Boy border (classic gray-green)
-    set_palette_entry(COLOR_GB_BORDER, 20, 24, 20);
+    set_ui_entry(COLOR_GB_BORDER, 20, 24, 20);
 
     // Overlay colors
-    set_palette_entry(COLOR_OVERLAY_BG, 4, 4, 8);       // Dark blue-ish
-    set_palette_entry(COLOR_OVERLAY_TEXT, 63, 63, 63); // White
-    set_palette_entry(COLOR_OVERLAY_SHADOW, 0, 0, 0);  // Black
+    set_ui_entry(COLOR_OVERLAY_BG, 4, 4, 8);       // Dark blue-ish
+    set_ui_entry(COLOR_OVERLAY_TEXT, 63, 63, 63); // White
+    set_ui_entry(COLOR_OVERLAY_SHADOW, 0, 0, 0);  // Black
 
     // Additional UI colors (6-15 in UI range)
-    set_palette_entry(PAL_UI_START + 6, 63, 0, 0);     // Red (errors)
-    set_palette_entry(PAL_UI_START + 7, 0, 63, 0);     // Green (success)
-    set_palette_entry(PAL_UI_START + 8, 63, 63, 0);    // Yellow (warnings)
-    set_palette_entry(PAL_UI_START + 9, 0, 32, 63);    // Blue (info)
-    set_palette_entry(PAL_UI_START + 10, 32, 32, 32);  // Gray (disabled)
+    set_ui_entry(COLOR_ERROR, 63, 0, 0);          // Red
+    set_ui_entry(COLOR_SUCCESS, 0, 63, 0);        // Green
+    set_ui_entry(COLOR_WARNING, 63, 63, 0);       // Yellow
+    set_ui_entry(COLOR_INFO, 0, 32, 63);          // Blue
+    set_ui_entry(COLOR_DISABLED, 42, 42, 42);     // Gray
 
     // HP bar colors for Pokemon overlay
-    set_palette_entry(PAL_UI_START + 11, 0, 63, 0);    // HP green (full)
-    set_palette_entry(PAL_UI_START + 12, 63, 63, 0);   // HP yellow (medium)
-    set_palette_entry(PAL_UI_START + 13, 63, 0, 0);    // HP red (low)
-    set_palette_entry(PAL_UI_START + 14, 16, 16, 16);  // HP background
+    set_ui_entry(COLOR_HP_GREEN, 0, 63, 0);       // Full
+    set_ui_entry(COLOR_HP_YELLOW, 63, 63, 0);     // Medium
+    set_ui_entry(COLOR_HP_RED, 63, 0, 0);         // Low
+    set_ui_entry(COLOR_HP_BG, 16, 16, 16);        // Background
+    set_ui_entry(COLOR_OVERLAY_HIGHLIGHT, 21, 63, 63); // Cyan (headings)
 }
 
 /// Initialize the full VGA palette for GBC emulation
@@ -218,3 +280,464 @@ pub const fn gbc_sprite_index(palette: u8, color: u8) -> u8 {
 pub const fn dmg_index(palette_type: u8, color: u8) -> u8 {
     PAL_DMG_START + palette_type * 4 + color
 }
+
+// =============================================================================
+// Color Correction Curves
+// =============================================================================
+
+/// How Game Boy colors are turned into DAC colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorCurve {
+    /// Straight 5-bit to 6-bit scaling (very saturated)
+    Raw,
+    /// GBC LCD: muted, channels bleed into each other
+    GbcLcd,
+    /// GBA SP front-lit screen: darker and flatter still
+    GbaSp,
+    /// Raw colors with a user-chosen gamma
+    UserGamma,
+}
+
+impl ColorCurve {
+    pub const ALL: [ColorCurve; 4] =
+        [ColorCurve::Raw, ColorCurve::GbcLcd, ColorCurve::GbaSp, ColorCurve::UserGamma];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorCurve::Raw => "RAW",
+            ColorCurve::GbcLcd => "GBC LCD",
+            ColorCurve::GbaSp => "GBA SP",
+            ColorCurve::UserGamma => "GAMMA",
+        }
+    }
+
+    /// Stable number for saving preferences
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ColorCurve::Raw => 0,
+            ColorCurve::GbcLcd => 1,
+            ColorCurve::GbaSp => 2,
+            ColorCurve::UserGamma => 3,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<ColorCurve> {
+        ColorCurve::ALL.iter().copied().find(|c| c.to_u8() == value)
+    }
+}
+
+/// User gamma range and default, in tenths
+pub const GAMMA_MIN: u8 = 10;
+pub const GAMMA_MAX: u8 = 30;
+pub const GAMMA_DEFAULT: u8 = 22;
+
+/// Linear light scale used inside the tables
+const LINEAR_MAX: usize = 4095;
+
+/// Channel mixing matrices (rows: output R, G, B), in 1/1024ths
+///
+/// Hand-tuned approximations: each channel picks up some of the others
+/// and the panel never reaches full brightness (rows sum to 0.94 for the
+/// GBC, 0.88 for the front-lit SP).
+const IDENTITY_MATRIX: [[i32; 3]; 3] = [[1024, 0, 0], [0, 1024, 0], [0, 0, 1024]];
+const GBC_LCD_MATRIX: [[i32; 3]; 3] = [[751, 154, 58], [116, 693, 154], [96, 173, 694]];
+const GBA_SP_MATRIX: [[i32; 3]; 3] = [[667, 180, 54], [126, 595, 180], [108, 198, 595]];
+
+/// Active curve and user gamma (tenths)
+static mut CURVE: ColorCurve = ColorCurve::Raw;
+static mut GAMMA: u8 = GAMMA_DEFAULT;
+
+/// RGB555 -> 8-bit RGB for the active curve (the DAC takes the top 6 bits)
+static mut CURVE_LUT: [[u8; 3]; 32768] = [[0; 3]; 32768];
+static mut CURVE_LUT_BUILT: bool = false;
+
+/// Current curve and user gamma (tenths)
+pub fn color_curve() -> (ColorCurve, u8) {
+    unsafe { (CURVE, GAMMA) }
+}
+
+/// Select a curve and gamma, rebuilding the lookup table
+///
+/// Entries already in the DAC keep their old colors until they are set
+/// again (the DMG palettes every frame; dynamic CGB entries after
+/// `FrameQuantizer::invalidate_dac`). True-color frames use it from the
+/// next one.
+pub fn set_color_curve(curve: ColorCurve, gamma: u8) {
+    let gamma = gamma.clamp(GAMMA_MIN, GAMMA_MAX);
+    unsafe {
+        CURVE = curve;
+        GAMMA = gamma;
+    }
+    build_curve_lut(curve, gamma);
+}
+
+/// Convert a 5-bit GBC color through the active curve to 8-bit RGB
+#[inline]
+pub fn gbc_to_rgb8(r: u8, g: u8, b: u8) -> [u8; 3] {
+    unsafe {
+        if !CURVE_LUT_BUILT {
+            build_curve_lut(CURVE, GAMMA);
+        }
+        let index = (r as usize & 0x1F) | (g as usize & 0x1F) << 5 | (b as usize & 0x1F) << 10;
+        CURVE_LUT[index]
+    }
+}
+
+/// Convert a 5-bit GBC color to 6-bit DAC components
+#[inline]
+pub fn gbc_to_dac(r: u8, g: u8, b: u8) -> [u8; 3] {
+    gbc_to_rgb8(r, g, b).map(|c| c >> 2)
+}
+
+/// Put a GBC frame (8-bit RGB expanded from RGB555) through the active
+/// curve and vision filter, for true-color output
+pub fn correct_frame_rgb8(rgb: &mut [u8]) {
+    for px in rgb.chunks_exact_mut(3) {
+        px.copy_from_slice(&gbc_to_rgb8(px[0] >> 3, px[1] >> 3, px[2] >> 3));
+    }
+}
+
+/// 5-bit channel value to linear light (0-`LINEAR_MAX`) for each decoding
+/// gamma from `GAMMA_MIN` to `GAMMA_MAX`: `round((c / 31)^(g / 10) * 4095)`
+const TO_LINEAR: [[u16; 32]; (GAMMA_MAX - GAMMA_MIN + 1) as usize] = [
+    // 1.0
+    [
+        0, 132, 264, 396, 528, 660, 793, 925, 1057, 1189, 1321, 1453, 1585, 1717, 1849, 1981, 2114,
+        2246, 2378, 2510, 2642, 2774, 2906, 3038, 3170, 3302, 3435, 3567, 3699, 3831, 3963, 4095,
+    ],
+    // 1.1
+    [
+        0, 94, 201, 314, 431, 550, 673, 797, 923, 1051, 1180, 1310, 1442, 1574, 1708, 1843, 1978,
+        2115, 2252, 2390, 2529, 2668, 2808, 2949, 3090, 3232, 3375, 3518, 3661, 3805, 3950, 4095,
+    ],
+    // 1.2
+    [
+        0, 66, 153, 248, 351, 459, 571, 687, 806, 928, 1053, 1181, 1311, 1443, 1578, 1714, 1852,
+        1991, 2133, 2276, 2420, 2566, 2713, 2862, 3012, 3163, 3316, 3469, 3624, 3780, 3937, 4095,
+    ],
+    // 1.3
+    [
+        0, 47, 116, 197, 286, 382, 484, 592, 704, 820, 941, 1065, 1192, 1323, 1457, 1594, 1733,
+        1875, 2020, 2167, 2316, 2468, 2622, 2778, 2936, 3096, 3258, 3422, 3587, 3755, 3924, 4095,
+    ],
+    // 1.4
+    [
+        0, 33, 88, 156, 233, 318, 411, 510, 615, 725, 840, 960, 1084, 1213, 1346, 1482, 1622, 1766,
+        1913, 2063, 2217, 2374, 2534, 2696, 2862, 3030, 3201, 3375, 3551, 3730, 3911, 4095,
+    ],
+    // 1.5
+    [
+        0, 24, 67, 123, 190, 265, 349, 439, 537, 641, 750, 866, 986, 1112, 1243, 1378, 1518, 1663,
+        1812, 1965, 2122, 2283, 2448, 2617, 2790, 2966, 3145, 3329, 3515, 3705, 3898, 4095,
+    ],
+    // 1.6
+    [
+        0, 17, 51, 98, 155, 221, 296, 379, 469, 566, 670, 780, 897, 1019, 1148, 1282, 1421, 1566,
+        1716, 1871, 2031, 2196, 2366, 2540, 2719, 2903, 3091, 3283, 3480, 3681, 3886, 4095,
+    ],
+    // 1.7
+    [
+        0, 12, 39, 77, 126, 184, 251, 326, 409, 500, 598, 704, 816, 935, 1060, 1192, 1330, 1475,
+        1625, 1782, 1944, 2112, 2286, 2465, 2650, 2841, 3037, 3238, 3444, 3656, 3873, 4095,
+    ],
+    // 1.8
+    [
+        0, 8, 29, 61, 103, 153, 213, 281, 358, 442, 534, 634, 742, 857, 979, 1109, 1245, 1389, 1539,
+        1697, 1861, 2031, 2209, 2393, 2583, 2780, 2984, 3193, 3409, 3632, 3860, 4095,
+    ],
+    // 1.9
+    [
+        0, 6, 22, 48, 84, 128, 181, 242, 312, 391, 477, 572, 675, 786, 904, 1031, 1165, 1308, 1458,
+        1615, 1781, 1954, 2134, 2322, 2518, 2721, 2932, 3150, 3375, 3608, 3848, 4095,
+    ],
+    // 2.0
+    [
+        0, 4, 17, 38, 68, 107, 153, 209, 273, 345, 426, 516, 614, 720, 835, 959, 1091, 1231, 1381,
+        1538, 1704, 1879, 2062, 2254, 2454, 2663, 2881, 3106, 3341, 3584, 3835, 4095,
+    ],
+    // 2.1
+    [
+        0, 3, 13, 30, 56, 89, 130, 180, 238, 305, 381, 465, 558, 660, 771, 892, 1021, 1160, 1308,
+        1465, 1631, 1807, 1993, 2188, 2392, 2607, 2830, 3064, 3307, 3560, 3823, 4095,
+    ],
+    // 2.2
+    [
+        0, 2, 10, 24, 45, 74, 110, 155, 208, 270, 340, 419, 508, 605, 712, 829, 956, 1092, 1238,
+        1395, 1561, 1738, 1926, 2124, 2332, 2551, 2781, 3022, 3273, 3536, 3810, 4095,
+    ],
+    // 2.3
+    [
+        0, 2, 7, 19, 37, 62, 94, 134, 182, 238, 303, 378, 462, 555, 658, 771, 895, 1028, 1173, 1328,
+        1494, 1672, 1861, 2061, 2273, 2497, 2733, 2980, 3240, 3513, 3798, 4095,
+    ],
+    // 2.4
+    [
+        0, 1, 6, 15, 30, 51, 80, 115, 159, 210, 271, 341, 420, 509, 608, 717, 837, 968, 1111, 1265,
+        1430, 1608, 1798, 2000, 2216, 2444, 2685, 2939, 3207, 3489, 3785, 4095,
+    ],
+    // 2.5
+    [
+        0, 1, 4, 12, 24, 43, 67, 99, 139, 186, 242, 307, 382, 466, 561, 667, 784, 912, 1052, 1204,
+        1369, 1547, 1737, 1942, 2160, 2392, 2638, 2899, 3175, 3466, 3773, 4095,
+    ],
+    // 2.6
+    [
+        0, 1, 3, 9, 20, 36, 57, 86, 121, 164, 216, 277, 347, 428, 518, 620, 734, 859, 996, 1147,
+        1310, 1488, 1679, 1885, 2105, 2341, 2592, 2859, 3143, 3443, 3760, 4095,
+    ],
+    // 2.7
+    [
+        0, 0, 3, 7, 16, 30, 49, 74, 106, 145, 193, 250, 316, 392, 479, 577, 687, 809, 944, 1092,
+        1254, 1431, 1622, 1829, 2052, 2291, 2547, 2820, 3111, 3420, 3748, 4095,
+    ],
+    // 2.8
+    [
+        0, 0, 2, 6, 13, 25, 41, 63, 92, 128, 172, 225, 287, 359, 442, 536, 643, 762, 894, 1040,
+        1200, 1376, 1568, 1775, 2000, 2242, 2502, 2781, 3080, 3397, 3736, 4095,
+    ],
+    // 2.9
+    [
+        0, 0, 1, 5, 11, 21, 35, 55, 81, 113, 154, 203, 261, 329, 408, 499, 602, 717, 846, 990, 1149,
+        1324, 1515, 1723, 1949, 2194, 2459, 2743, 3048, 3375, 3724, 4095,
+    ],
+    // 3.0
+    [
+        0, 0, 1, 4, 9, 17, 30, 47, 70, 100, 137, 183, 238, 302, 377, 464, 563, 675, 802, 943, 1100,
+        1273, 1464, 1672, 1900, 2148, 2416, 2706, 3017, 3352, 3711, 4095,
+    ],
+];
+
+/// 8-bit level on a 2.2 gamma display to linear light, 0-65535:
+/// `round((v / 255)^2.2 * 65535)`
+const DISPLAY_22: [u16; 256] = [
+    0, 0, 2, 4, 7, 11, 17, 24, 32, 42, 53, 65, 79, 94, 111, 129, 148, 169, 192, 216, 242, 270, 299,
+    330, 362, 396, 432, 469, 508, 549, 591, 635, 681, 729, 779, 830, 883, 938, 995, 1053, 1113,
+    1175, 1239, 1305, 1373, 1443, 1514, 1587, 1663, 1740, 1819, 1900, 1983, 2068, 2155, 2243, 2334,
+    2427, 2521, 2618, 2717, 2817, 2920, 3024, 3131, 3240, 3350, 3463, 3578, 3694, 3813, 3934, 4057,
+    4182, 4309, 4438, 4570, 4703, 4838, 4976, 5115, 5257, 5401, 5547, 5695, 5845, 5998, 6152, 6309,
+    6468, 6629, 6792, 6957, 7124, 7294, 7466, 7640, 7816, 7994, 8175, 8358, 8543, 8730, 8919, 9111,
+    9305, 9501, 9699, 9900, 10102, 10307, 10515, 10724, 10936, 11150, 11366, 11585, 11806, 12029,
+    12254, 12482, 12712, 12944, 13179, 13416, 13655, 13896, 14140, 14386, 14635, 14885, 15138,
+    15394, 15652, 15912, 16174, 16439, 16706, 16975, 17247, 17521, 17798, 18077, 18358, 18642,
+    18928, 19216, 19507, 19800, 20095, 20393, 20694, 20996, 21301, 21609, 21919, 22231, 22546,
+    22863, 23182, 23504, 23829, 24156, 24485, 24817, 25151, 25487, 25826, 26168, 26512, 26858,
+    27207, 27558, 27912, 28268, 28627, 28988, 29351, 29717, 30086, 30457, 30830, 31206, 31585,
+    31966, 32349, 32735, 33124, 33514, 33908, 34304, 34702, 35103, 35507, 35913, 36321, 36732,
+    37146, 37562, 37981, 38402, 38825, 39252, 39680, 40112, 40546, 40982, 41421, 41862, 42306,
+    42753, 43202, 43654, 44108, 44565, 45025, 45487, 45951, 46418, 46888, 47360, 47835, 48313,
+    48793, 49275, 49761, 50249, 50739, 51232, 51728, 52226, 52727, 53230, 53736, 54245, 54756,
+    55270, 55787, 56306, 56828, 57352, 57879, 58409, 58941, 59476, 60014, 60554, 61097, 61642,
+    62190, 62741, 63295, 63851, 64410, 64971, 65535,
+];
+
+/// Level on a 2.2 gamma display (0-255) closest to linear light `lin`
+/// (0-65535)
+fn encode_22(lin: u32) -> u8 {
+    let above = DISPLAY_22.partition_point(|&v| (v as u32) < lin);
+    if above == 0 {
+        return 0;
+    }
+    if above == DISPLAY_22.len() {
+        return 255;
+    }
+    // Between two levels: the nearer one
+    let (lo, hi) = (DISPLAY_22[above - 1] as u32, DISPLAY_22[above] as u32);
+    (if lin - lo <= hi - lin { above - 1 } else { above }) as u8
+}
+
+/// Bake `curve` (and the vision filter) into `CURVE_LUT`
+///
+/// Each channel is decoded to linear light with the curve's gamma,
+/// mixed by its matrix and the vision filter's, then encoded for a 2.2
+/// display. The gamma curves come from tables; the raw curve stays
+/// linear throughout.
+fn build_curve_lut(curve: ColorCurve, gamma: u8) {
+    // Decoding gamma in tenths, and whether to encode for a 2.2 display
+    let (decode, encode, matrix) = match curve {
+        ColorCurve::Raw => (10, false, IDENTITY_MATRIX),
+        ColorCurve::GbcLcd => (22, true, GBC_LCD_MATRIX),
+        ColorCurve::GbaSp => (26, true, GBA_SP_MATRIX),
+        ColorCurve::UserGamma => (gamma, true, IDENTITY_MATRIX),
+    };
+    let to_linear = &TO_LINEAR[(decode.clamp(GAMMA_MIN, GAMMA_MAX) - GAMMA_MIN) as usize];
+
+    let mut to_rgb8 = [0u8; LINEAR_MAX + 1];
+    for (lin, out) in to_rgb8.iter_mut().enumerate() {
+        let level = if encode {
+            encode_22((lin * 65535 / LINEAR_MAX) as u32)
+        } else {
+            ((lin * 255 + LINEAR_MAX / 2) / LINEAR_MAX) as u8
+        };
+        *out = vision_tone(level);
+    }
+
+    // The vision filter works on linear light after the curve's mixing
+    let vision = unsafe { VISION_MATRIX };
+    let mut matrix_v = [[0i32; 3]; 3];
+    for (r, row) in matrix_v.iter_mut().enumerate() {
+        for (c, out) in row.iter_mut().enumerate() {
+            *out = (0..3).map(|k| vision[r][k] * matrix[k][c]).sum::<i32>() >> 10;
+        }
+    }
+    let matrix = matrix_v;
+
+    for index in 0..32768usize {
+        let rgb = [index & 0x1F, (index >> 5) & 0x1F, (index >> 10) & 0x1F];
+        let mut out = [0u8; 3];
+        for (channel, row) in matrix.iter().enumerate() {
+            let mixed: i32 = (0..3).map(|i| row[i] * to_linear[rgb[i]] as i32).sum::<i32>() >> 10;
+            out[channel] = to_rgb8[mixed.clamp(0, LINEAR_MAX as i32) as usize];
+        }
+        unsafe { CURVE_LUT[index] = out; }
+    }
+    unsafe { CURVE_LUT_BUILT = true; }
+}
+
+// =============================================================================
+// Vision Filters
+// =============================================================================
+
+/// Color remapping for color vision deficiencies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisionFilter {
+    Off,
+    /// Red-blind: red/green differences moved into blue
+    Protanopia,
+    /// Green-blind: likewise
+    Deuteranopia,
+    /// Blue-blind: blue/yellow differences moved into red and green
+    Tritanopia,
+    /// More saturation and contrast
+    HighContrast,
+}
+
+impl VisionFilter {
+    pub const ALL: [VisionFilter; 5] = [
+        VisionFilter::Off,
+        VisionFilter::Protanopia,
+        VisionFilter::Deuteranopia,
+        VisionFilter::Tritanopia,
+        VisionFilter::HighContrast,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VisionFilter::Off => "OFF",
+            VisionFilter::Protanopia => "PROTAN",
+            VisionFilter::Deuteranopia => "DEUTAN",
+            VisionFilter::Tritanopia => "TRITAN",
+            VisionFilter::HighContrast => "CONTRAST",
+        }
+    }
+}
+
+/// How each deficiency sees linear RGB (Machado et al. 2009, full severity)
+const PROTAN_SIM: [[f32; 3]; 3] =
+    [[0.152286, 1.052583, -0.204868], [0.114503, 0.786281, 0.099216], [-0.003882, -0.048116, 1.051998]];
+const DEUTAN_SIM: [[f32; 3]; 3] =
+    [[0.367322, 0.860646, -0.227968], [0.280085, 0.672501, 0.047413], [-0.011820, 0.042940, 0.968881]];
+const TRITAN_SIM: [[f32; 3]; 3] =
+    [[1.255528, -0.076749, -0.178779], [-0.078411, 0.930809, 0.147602], [0.004733, 0.691367, 0.303900]];
+
+/// Where the invisible part of a color goes (rows: output R, G, B)
+const RED_GREEN_SHIFT: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+const BLUE_YELLOW_SHIFT: [[f32; 3]; 3] = [[1.0, 0.0, 0.7], [0.0, 1.0, 0.7], [0.0, 0.0, 0.0]];
+
+/// Saturation factor of the high-contrast filter
+const HIGH_CONTRAST_SATURATION: f32 = 1.6;
+
+/// Contrast factor of the high-contrast filter (around mid-gray)
+const HIGH_CONTRAST_SLOPE: f32 = 1.3;
+
+static mut VISION: VisionFilter = VisionFilter::Off;
+
+/// Active vision filter as a matrix on linear RGB, in 1/1024ths
+static mut VISION_MATRIX: [[i32; 3]; 3] = IDENTITY_MATRIX;
+
+/// Current vision filter
+pub fn vision_filter() -> VisionFilter {
+    unsafe { VISION }
+}
+
+/// Select a vision filter
+///
+/// Rebuilds the color lookup table and reprograms the UI colors; Game Boy
+/// colors already in the DAC change when they are next set (as with
+/// `set_color_curve`).
+pub fn set_vision_filter(filter: VisionFilter) {
+    let m = match filter {
+        VisionFilter::Off => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        VisionFilter::Protanopia => daltonize(&PROTAN_SIM, &RED_GREEN_SHIFT),
+        VisionFilter::Deuteranopia => daltonize(&DEUTAN_SIM, &RED_GREEN_SHIFT),
+        VisionFilter::Tritanopia => daltonize(&TRITAN_SIM, &BLUE_YELLOW_SHIFT),
+        VisionFilter::HighContrast => {
+            // Mix of the color and its luma, pushed away from the luma
+            let luma = [0.2126, 0.7152, 0.0722];
+            let s = HIGH_CONTRAST_SATURATION;
+            let mut m = [[0.0; 3]; 3];
+            for (r, row) in m.iter_mut().enumerate() {
+                for (c, out) in row.iter_mut().enumerate() {
+                    *out = (1.0 - s) * luma[c] + if r == c { s } else { 0.0 };
+                }
+            }
+            m
+        }
+    };
+    unsafe {
+        VISION = filter;
+        VISION_MATRIX = m.map(|row| row.map(|v| (v * 1024.0 + if v < 0.0 { -0.5 } else { 0.5 }) as i32));
+        build_curve_lut(CURVE, GAMMA);
+    }
+    init_ui_palette();
+}
+
+/// Daltonization: `I + shift * (I - sim)`
+///
+/// What the viewer can't see (the color minus its simulation) is added
+/// back into channels they can.
+fn daltonize(sim: &[[f32; 3]; 3], shift: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut m = [[0.0; 3]; 3];
+    for (r, row) in m.iter_mut().enumerate() {
+        for (c, out) in row.iter_mut().enumerate() {
+            let lost: f32 = (0..3)
+                .map(|k| shift[r][k] * (if k == c { 1.0 } else { 0.0 } - sim[k][c]))
+                .sum();
+            *out = if r == c { 1.0 } else { 0.0 } + lost;
+        }
+    }
+    m
+}
+
+/// Contrast curve of the high-contrast filter on an encoded level (0-255)
+fn vision_tone(level: u8) -> u8 {
+    if vision_filter() == VisionFilter::HighContrast {
+        contrast(level as i32)
+    } else {
+        level
+    }
+}
+
+/// High-contrast slope around mid-gray, on an 8-bit level
+fn contrast(level: i32) -> u8 {
+    ((((level - 128) * (HIGH_CONTRAST_SLOPE * 256.0) as i32) >> 8) + 128).clamp(0, 255) as u8
+}
+
+/// Apply the vision filter to 8-bit RGB pixels (true-color output)
+///
+/// Uses the same matrix on the encoded values, which is close enough
+/// for a remapping filter and keeps it cheap per pixel.
+pub fn vision_filter_rgb8(rgb: &mut [u8]) {
+    let filter = vision_filter();
+    if filter == VisionFilter::Off {
+        return;
+    }
+    let m = unsafe { VISION_MATRIX };
+    let high_contrast = filter == VisionFilter::HighContrast;
+    for px in rgb.chunks_exact_mut(3) {
+        let src = [px[0] as i32, px[1] as i32, px[2] as i32];
+        for (out, row) in px.iter_mut().zip(m.iter()) {
+            let v = (row[0] * src[0] + row[1] * src[1] + row[2] * src[2]) >> 10;
+            *out = if high_contrast { contrast(v) } else { v.clamp(0, 255) as u8 };
+        }
+    }
+}
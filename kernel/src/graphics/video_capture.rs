@@ -0,0 +1,282 @@
+//! Gameplay Video Capture
+//!
+//! Records every emulated frame, losslessly, to `VIDnnnn.GBV` in the root
+//! directory of the ROM disk. `tools/gbvconvert` turns a recording into
+//! raw RGB or PPM frames for ffmpeg and friends.
+//!
+//! # File Format
+//!
+//! ```text
+//! Header (16 bytes)
+//!   0   4   Magic "GBV1"
+//!   4   2   Width (160)
+//!   6   2   Height (144)
+//!   8   4   Frame rate in millihertz (59727)
+//!   12  4   Zero
+//!
+//! Frames, until the end of the file
+//!   0   4   Length of the runs that follow, in bytes
+//!   4   ..  Runs covering all width x height pixels, row by row:
+//!           0x00-0x7F c lo hi  c+1 pixels of RGB555 color hi:lo
+//!           0x80-0xFF          c-0x7F pixels unchanged from the last
+//!                              frame (all black before the first)
+//! ```
+//!
+//! Colors are the game's own (`Device::frame_rgb555`): DMG frames as
+//! colored by the active preset, no display filters.
+//!
+//! Frames are encoded into a RAM buffer. Fixed-size chunks go to disk in
+//! the time left over at the end of each frame (`flush`), so recording
+//! keeps the frame pacing unless the buffer fills up faster than that.
+//! Each chunk is a VFS write, which brings the file's size up to date, so
+//! a recording cut short keeps everything written before it.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::arch::x86::pit;
+use crate::gameboy::Device;
+use crate::graphics::dmg_palettes;
+use crate::gui::layout::{GB_HEIGHT, GB_WIDTH};
+use crate::fs::{vfs, FsError, OpenFlags};
+
+const PIXELS: usize = GB_WIDTH * GB_HEIGHT;
+
+/// Encoded frames waiting for the disk
+const BUFFER_SIZE: usize = 512 * 1024;
+
+/// Bytes written to the file at a time
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Largest encoded frame: a length and a color run per pixel
+const MAX_FRAME_SIZE: usize = 4 + PIXELS * 3;
+
+const HEADER_SIZE: usize = 16;
+const MAGIC: &[u8; 4] = b"GBV1";
+const FRAME_RATE_MHZ: u32 = 59727;
+
+/// Longest run one control byte covers
+const MAX_RUN: usize = 128;
+
+/// Highest file number (`VID9999.GBV`)
+const MAX_VIDEOS: u32 = 10000;
+
+/// Buffers, allocated on the first recording and kept
+struct Buffers {
+    prev: Box<[u16; PIXELS]>,
+    cur: Box<[u16; PIXELS]>,
+    /// Encoded data; `start..end` is not on disk yet
+    data: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+/// Records frames to disk while active
+pub struct VideoRecorder {
+    buffers: Option<Buffers>,
+    file: Option<vfs::Fd>,
+    frames: u32,
+    next: u32,
+}
+
+impl VideoRecorder {
+    pub const fn new() -> VideoRecorder {
+        VideoRecorder { buffers: None, file: None, frames: 0, next: 0 }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Start a new recording; returns its file name (`VIDnnnn.GBV`)
+    pub fn start(&mut self) -> Result<[u8; 11], &'static str> {
+        if self.is_recording() {
+            return Err("Already recording");
+        }
+
+        let mut file = None;
+        let mut path = [0u8; 12];
+        while self.next < MAX_VIDEOS && file.is_none() {
+            path = video_path(self.next);
+            self.next += 1;
+            // Written as ASCII digits
+            let path_str = core::str::from_utf8(&path).unwrap_or("");
+            match vfs::stat(path_str) {
+                Err(FsError::NotFound) => {}
+                Err(e) => return Err(e.as_str()),
+                Ok(_) => continue,
+            }
+            let flags = OpenFlags::write_only().with_create().with_append();
+            file = Some(vfs::open(path_str, flags).map_err(|e| e.as_str())?);
+        }
+        let file = file.ok_or("No free video number")?;
+
+        let buffers = self.buffers.get_or_insert_with(|| Buffers {
+            prev: Box::new([0; PIXELS]),
+            cur: Box::new([0; PIXELS]),
+            data: vec![0; BUFFER_SIZE],
+            start: 0,
+            end: 0,
+        });
+        buffers.prev.fill(0);
+        buffers.start = 0;
+        buffers.end = HEADER_SIZE;
+        let header = &mut buffers.data[..HEADER_SIZE];
+        header.fill(0);
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..6].copy_from_slice(&(GB_WIDTH as u16).to_le_bytes());
+        header[6..8].copy_from_slice(&(GB_HEIGHT as u16).to_le_bytes());
+        header[8..12].copy_from_slice(&FRAME_RATE_MHZ.to_le_bytes());
+
+        self.file = Some(file);
+        self.frames = 0;
+
+        let mut name = [0u8; 11];
+        name.copy_from_slice(&path[1..]);
+        Ok(name)
+    }
+
+    /// Encode the device's last frame
+    ///
+    /// Only waits for the disk if the buffer can't take another frame.
+    pub fn capture(&mut self, device: &Device) -> Result<(), &'static str> {
+        if !self.is_recording() {
+            return Ok(());
+        }
+        if self.free_space() < MAX_FRAME_SIZE {
+            self.write_chunks(None)?;
+            self.compact();
+        }
+
+        let Some(b) = self.buffers.as_mut() else { return Ok(()) };
+        device.frame_rgb555(&mut b.cur[..], &dmg_palettes::active().colors);
+        let len = encode_frame(&b.prev, &b.cur, &mut b.data[b.end + 4..]);
+        b.data[b.end..b.end + 4].copy_from_slice(&(len as u32).to_le_bytes());
+        b.end += 4 + len;
+        core::mem::swap(&mut b.prev, &mut b.cur);
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Write buffered chunks to disk until the PIT reaches `deadline`
+    pub fn flush(&mut self, deadline: u32) -> Result<(), &'static str> {
+        if !self.is_recording() {
+            return Ok(());
+        }
+        self.write_chunks(Some(deadline))
+    }
+
+    /// Write everything out and close the file; returns the frame count
+    pub fn stop(&mut self) -> Result<u32, &'static str> {
+        let result = self.finish();
+        self.file = None;
+        result.map(|_| self.frames)
+    }
+
+    fn finish(&mut self) -> Result<(), &'static str> {
+        self.write_chunks(None)?;
+        let (Some(b), Some(file)) = (self.buffers.as_mut(), self.file) else {
+            return Ok(());
+        };
+        let result = write_all(file, &b.data[b.start..b.end]);
+        b.start = b.end;
+        let closed = vfs::close(file).map_err(|e| e.as_str());
+        result.and(closed)
+    }
+
+    /// Room left at the end of the buffer
+    fn free_space(&self) -> usize {
+        self.buffers.as_ref().map_or(0, |b| b.data.len() - b.end)
+    }
+
+    /// Move the unwritten data to the front of the buffer
+    fn compact(&mut self) {
+        if let Some(b) = self.buffers.as_mut() {
+            b.data.copy_within(b.start..b.end, 0);
+            b.end -= b.start;
+            b.start = 0;
+        }
+    }
+
+    /// Append whole chunks, one at a time until `deadline` (PIT ticks),
+    /// or all of them (`None`)
+    fn write_chunks(&mut self, deadline: Option<u32>) -> Result<(), &'static str> {
+        let (Some(b), Some(file)) = (self.buffers.as_mut(), self.file) else {
+            return Ok(());
+        };
+
+        while b.end - b.start >= CHUNK_SIZE {
+            if let Some(deadline) = deadline {
+                // Stop once the deadline is reached (wrapping compare)
+                if pit::ticks().wrapping_sub(deadline) < 0x8000_0000 {
+                    break;
+                }
+            }
+            if let Err(e) = write_all(file, &b.data[b.start..b.start + CHUNK_SIZE]) {
+                // Keep what made it to disk readable
+                let _ = vfs::close(file);
+                self.file = None;
+                return Err(e);
+            }
+            b.start += CHUNK_SIZE;
+        }
+
+        if b.start == b.end {
+            b.start = 0;
+            b.end = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Path of recording `n` (`/VIDnnnn.GBV`)
+fn video_path(n: u32) -> [u8; 12] {
+    let mut path = *b"/VID0000.GBV";
+    for (i, place) in [1000, 100, 10, 1].iter().enumerate() {
+        path[4 + i] = b'0' + (n / place % 10) as u8;
+    }
+    path
+}
+
+/// Append all of `data` to `file`
+fn write_all(file: vfs::Fd, mut data: &[u8]) -> Result<(), &'static str> {
+    while !data.is_empty() {
+        match vfs::write(file, data) {
+            Ok(0) => return Err(FsError::NoSpace.as_str()),
+            Ok(n) => data = &data[n..],
+            Err(e) => return Err(e.as_str()),
+        }
+    }
+    Ok(())
+}
+
+/// Encode `cur` against `prev` into `out`; returns the length
+fn encode_frame(prev: &[u16; PIXELS], cur: &[u16; PIXELS], out: &mut [u8]) -> usize {
+    let mut len = 0;
+    let mut i = 0;
+    while i < PIXELS {
+        let limit = (i + MAX_RUN).min(PIXELS);
+        if cur[i] == prev[i] {
+            let mut j = i + 1;
+            while j < limit && cur[j] == prev[j] {
+                j += 1;
+            }
+            out[len] = 0x80 | (j - i - 1) as u8;
+            len += 1;
+            i = j;
+        } else {
+            let color = cur[i];
+            let mut j = i + 1;
+            while j < limit && cur[j] == color {
+                j += 1;
+            }
+            out[len] = (j - i - 1) as u8;
+            out[len + 1..len + 3].copy_from_slice(&color.to_le_bytes());
+            len += 3;
+            i = j;
+        }
+    }
+    len
+}
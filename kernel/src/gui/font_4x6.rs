@@ -7,6 +7,7 @@
 //! Designed for overlay rendering where the 8x8 font is too large.
 
 use crate::graphics::vga_mode13h::{self, SCREEN_WIDTH};
+use crate::graphics::vga_modex;
 
 // Re-export colors for compatibility with existing code
 pub use crate::graphics::vga_mode13h::colors;
@@ -814,3 +815,32 @@ pub fn draw_string_centered_vga(y: usize, s: &str, color: u8) {
     let x = (SCREEN_WIDTH.saturating_sub(width)) / 2;
     draw_string_vga(x, y, s, color);
 }
+
+// ============================================================================
+// Mode X Rendering (planar, to the current Mode X draw page)
+// ============================================================================
+
+/// Draw a single character to the Mode X draw page
+#[inline(never)]
+pub fn draw_char_modex(x: usize, y: usize, ch: u8, color: u8) {
+    let bitmap = get_char_bitmap(ch);
+    vga_modex::draw_bitmap_4x6(x, y, bitmap, color);
+}
+
+/// Draw a string to the Mode X draw page
+#[inline(never)]
+pub fn draw_string_modex(x: usize, y: usize, s: &str, color: u8) {
+    let mut cx = x;
+    for ch in s.bytes() {
+        draw_char_modex(cx, y, ch, color);
+        cx += CELL_WIDTH;
+    }
+}
+
+/// Draw a string centered horizontally on the Mode X draw page
+#[inline(never)]
+pub fn draw_string_centered_modex(y: usize, s: &str, color: u8) {
+    let width = s.len() * CELL_WIDTH;
+    let x = (vga_modex::SCREEN_WIDTH.saturating_sub(width)) / 2;
+    draw_string_modex(x, y, s, color);
+}
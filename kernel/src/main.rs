@@ -31,7 +31,9 @@ mod event_chains;
 mod graphics;
 mod gui;
 mod storage;
+mod fs;
 mod rom_browser;
+mod settings_menu;
 
 // GameBoy emulator
 mod gameboy;
@@ -149,6 +151,10 @@ extern "C" fn kernel_main(_boot_info_ptr: u32) -> ! {
     set_last_operation(OperationId::AtaInit);
     let storage_result = storage::init();
 
+    // Timer at 1 kHz from here on (the ROM browser's scrolling names and
+    // the emulator's frame pacing count its ticks as milliseconds)
+    arch::x86::pit::set_frequency(1000);
+
     // Enable interrupts
     unsafe { core::arch::asm!("sti"); }
 
@@ -156,16 +162,20 @@ extern "C" fn kernel_main(_boot_info_ptr: u32) -> ! {
     if storage_result.ata_devices > 0 {
         storage::test_read();
 
-        // Mount FAT32
+        // Mount the FAT and exFAT volumes of every disk, the first as the
+        // root, then any CD (the root itself when booted from a game disc)
         set_last_operation(OperationId::Fat32Mount);
-        let mount_result = storage::fat32::mount(0);
+        let mounted = fs::vfs::mount_all();
+
+        if mounted > 0 {
+            // Extra DMG palette presets (optional PALETTES.TXT)
+            let _ = graphics::dmg_palettes::load_custom();
 
-        if mount_result.is_ok() {
             // Show ROM browser and get selection
-            if let Some(rom_index) = rom_browser::select_rom() {
+            if let Some(rom) = rom_browser::select_rom() {
                 // Load selected ROM
                 set_last_operation(OperationId::RomLoad);
-                if let Some((rom_ptr, rom_size)) = load_rom(rom_index) {
+                if let Some((rom_ptr, rom_size)) = load_rom(&rom) {
                     // Clear screen
                     clear_screen(0x00);
 
@@ -174,7 +184,7 @@ extern "C" fn kernel_main(_boot_info_ptr: u32) -> ! {
 
                     // Run emulator with selected ROM
                     set_last_operation(OperationId::EmulatorInit);
-                    run_gameboy_emulator_with_rom(rom_ptr, rom_size);
+                    run_gameboy_emulator_with_rom(rom_ptr, rom_size, storage::savefile::SaveFile::beside(&rom));
                 }
             }
         }
@@ -193,22 +203,13 @@ extern "C" fn kernel_main(_boot_info_ptr: u32) -> ! {
 // Static ROM buffer - must be outside the function to have stable address
 static mut ROM_BUFFER: [u8; 2 * 1024 * 1024] = [0; 2 * 1024 * 1024]; // 2MB max
 
-/// Load ROM at given index from FAT32
+/// Load the ROM file at `path` (picked in the browser)
 /// Returns (pointer to ROM data, size) if successful
-fn load_rom(index: usize) -> Option<(*const u8, usize)> {
-    // Find ROM at index
-    let (cluster, size) = match storage::fat32::get_fs().find_rom(index) {
-        Some(info) => info,
-        None => {
-            return None;
-        }
-    };
-
-
+fn load_rom(path: &str) -> Option<(*const u8, usize)> {
     // Load ROM data into static buffer
     let rom_buf = unsafe { &mut ROM_BUFFER };
 
-    match storage::fat32::get_fs().read_file(cluster, size, rom_buf) {
+    match fs::vfs::read_file(path, rom_buf) {
         Ok(bytes_read) => {
             // Verify we got some data
             if bytes_read == 0 {
@@ -244,6 +245,42 @@ fn clear_screen(color: u8) {
     }
 }
 
+/// Show the back buffer in Mode X (with `status` below it) or Mode 13h
+fn present_low_res(modex: Option<&mut graphics::page_flip::ModeXScreen>, status: &str) {
+    match modex {
+        Some(screen) => {
+            screen.draw_status(status);
+            screen.present(double_buffer::back_buffer_ref());
+            screen.flip();
+        }
+        None => double_buffer::flip_vsync(),
+    }
+}
+
+/// Emulator status line for the Mode X status strip
+fn status_text(buf: &mut [u8; 40], jit: bool, run_ahead: u8, recording: bool) -> &str {
+    let parts: [&[u8]; 5] = [
+        if recording { b"REC  " } else { b"" },
+        if jit { b"JIT ON" } else { b"JIT OFF" },
+        b"  RUN-AHEAD ",
+        &[b'0' + run_ahead.min(9)],
+        b"  F9 SETTINGS",
+    ];
+    let len = join_parts(buf, &parts);
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+/// Concatenate `parts` into `buf` (cut off at its end); returns the length
+fn join_parts(buf: &mut [u8; 40], parts: &[&[u8]]) -> usize {
+    let mut len = 0;
+    for part in parts {
+        let n = part.len().min(buf.len() - len);
+        buf[len..len + n].copy_from_slice(&part[..n]);
+        len += n;
+    }
+    len
+}
+
 /// Draw border around Game Boy screen area (to back buffer)
 fn draw_gb_border(buffer: &mut [u8]) {
     let border_left = GB_X.saturating_sub(GB_BORDER);
@@ -302,19 +339,17 @@ fn draw_gb_border(buffer: &mut [u8]) {
 /// - Double buffering for flicker-free display
 /// - VSync to prevent tearing
 /// - Dirty region tracking to minimize overlay updates
-fn run_gameboy_emulator_with_rom(rom_ptr: *const u8, rom_size: usize) -> ! {
+fn run_gameboy_emulator_with_rom(rom_ptr: *const u8, rom_size: usize, save_file: storage::savefile::SaveFile) -> ! {
     use alloc::vec::Vec;
     use crate::overlay::{Game, RamReader, render_overlay_efficient, init_overlay};
     use crate::storage::savefile;
     use crate::storage::savefile::SaveTracker;
+    use crate::storage::game_prefs::{self, GamePrefs};
 
     // ========================================================================
     // INITIALIZATION
     // ========================================================================
 
-    // Initialize PIT for accurate timing (1000 Hz = 1ms per tick)
-    arch::x86::pit::set_frequency(1000);
-
     // Initialize double buffer system
     double_buffer::init();
 
@@ -336,18 +371,42 @@ fn run_gameboy_emulator_with_rom(rom_ptr: *const u8, rom_size: usize) -> ! {
         }
     };
 
+    // High-resolution output if a BGA adapter is present; otherwise Mode 13h
+    // with scanlines rendered straight into the back buffer
+    let mut hires = graphics::hires::HiResScreen::init();
+    // Without BGA, page-flip in Mode X if chosen in the settings menu
+    let mut modex = if hires.is_none() && graphics::page_flip::enabled() {
+        Some(graphics::page_flip::ModeXScreen::init())
+    } else {
+        None
+    };
+    match hires.as_ref() {
+        Some(screen) => {
+            let cgb = device.mode() == GbMode::Color;
+            device.set_frame_sink(alloc::boxed::Box::new(screen.frame_sink(cgb)))
+        }
+        None => device.set_frame_sink(alloc::boxed::Box::new(gameboy::display::Mode13hSink)),
+    };
+
     // =========================================================================
     // LOAD SAVE ON STARTUP
     // =========================================================================
+    // Battery saves live in <ROMNAME>.SAV beside the ROM
     if device.ram_is_battery_backed() {
-        let _ = savefile::load_sram(&mut device);
+        let _ = savefile::load_sram(&mut device, &save_file);
         // Ignore result - NoSaveFound is fine for new games
     }
 
+    // Color curve and DMG palette chosen for this game last time (F9 menu),
+    // kept in <ROMNAME>.CFG beside the save
+    let prefs_file = save_file.sibling(game_prefs::PREFS_EXTENSION);
+    let mut prefs = game_prefs::load(&prefs_file).unwrap_or(GamePrefs::DEFAULT);
+    prefs.apply();
+
     // =========================================================================
     // CREATE SAVE TRACKER
     // =========================================================================
-    let mut save_tracker = SaveTracker::new();
+    let mut save_tracker = SaveTracker::new(save_file);
 
     // Detect game for overlay (do once at startup)
     let game = Game::detect(&device.romname());
@@ -356,6 +415,41 @@ fn run_gameboy_emulator_with_rom(rom_ptr: *const u8, rom_size: usize) -> ! {
     // Create input handler
     let input_state = gameboy::input::InputState::new();
 
+    // Maps each frame's GBC colors onto DAC entries 96-255
+    let mut quantizer = graphics::palette_quantizer::FrameQuantizer::new();
+
+    // Blends each frame with the last when enabled in the settings menu
+    let mut blender = graphics::frame_blend::PaletteBlender::new();
+
+    // Display settings (F9; pauses the game while open)
+    let mut settings_menu = settings_menu::SettingsMenu::new();
+
+    // DMG games: holding a direction (alone or with A or B) during the
+    // first two seconds picks a CGB boot ROM palette, as on a real CGB.
+    // Those keys reach the game only once the two seconds are over, and
+    // the choice lasts this boot only.
+    let mut combo_picker = graphics::dmg_palettes::ComboPicker::new();
+    let mut boot_combo = None;
+    let mut combo_frames = if device.mode() == GbMode::Color {
+        0
+    } else {
+        graphics::dmg_palettes::COMBO_FRAMES
+    };
+
+    // Screenshots (F12: Game Boy frame, Shift+F12: whole screen)
+    let mut screenshots = graphics::screenshot::Screenshots::new();
+
+    // Video recording (F8 starts and stops)
+    let mut recorder = graphics::video_capture::VideoRecorder::new();
+
+    // Short message shown in the status strip instead of the status line
+    const NOTICE_FRAMES: u32 = 120;
+    let mut notice = ([0u8; 40], 0usize);
+    let mut notice_frames = 0u32;
+
+    // Run-ahead (F11 cycles 0-4 frames; off by default, each frame costs a full emulated frame)
+    let mut run_ahead = gameboy::runahead::RunAhead::new(0);
+
     // Draw initial border around GB screen area (to back buffer)
     draw_gb_border(double_buffer::back_buffer());
 
@@ -363,9 +457,6 @@ fn run_gameboy_emulator_with_rom(rom_ptr: *const u8, rom_size: usize) -> ! {
     const TICKS_PER_FRAME: u32 = 17;
     let mut last_frame_ticks = arch::x86::pit::ticks();
 
-    // Main emulation loop
-    const CYCLES_PER_FRAME: u32 = 70224;
-
     // ========================================================================
     // MAIN EMULATION LOOP - with double buffering and dirty region tracking
     // ========================================================================
@@ -381,20 +472,135 @@ fn run_gameboy_emulator_with_rom(rom_ptr: *const u8, rom_size: usize) -> ! {
         }
 
         // ====================================================================
-        // Run one frame of emulation
+        // Process keyboard input (before the frame, so it is seen this frame)
         // ====================================================================
-        set_last_operation(OperationId::CpuCycle);
-        let mut cycles: u32 = 0;
-        while cycles < CYCLES_PER_FRAME {
-            cycles += device.do_cycle();
+        set_last_operation(OperationId::KeyboardPoll);
+        while let Some(key) = drivers::keyboard::get_key() {
+            // The menu takes presses; releases still reach the game so
+            // nothing stays held
+            if settings_menu.is_open() && key.pressed {
+                settings_menu.handle_key(key.keycode);
+                if !settings_menu.is_open() {
+                    // Colors may have changed: reprogram the DAC next frame
+                    quantizer.invalidate_dac();
+                    // Mode X may have been switched on or off
+                    let want_modex = hires.is_none() && graphics::page_flip::enabled();
+                    if want_modex && modex.is_none() {
+                        modex = Some(graphics::page_flip::ModeXScreen::init());
+                    } else if !want_modex {
+                        if let Some(screen) = modex.take() {
+                            screen.leave();
+                        }
+                    }
+                    // Remember new color settings for this game, but not
+                    // a boot combo palette left as it was
+                    let mut current = GamePrefs::current();
+                    if boot_combo == Some(current.dmg_palette) {
+                        current.dmg_palette = prefs.dmg_palette;
+                    }
+                    if current != prefs {
+                        prefs = current;
+                        let _ = game_prefs::save(&prefs_file, &prefs);
+                    }
+                }
+                continue;
+            }
+            if key.keycode == drivers::keyboard::KeyCode::F8 {
+                if key.pressed {
+                    notice.1 = if recorder.is_recording() {
+                        match recorder.stop() {
+                            Ok(_) => join_parts(&mut notice.0, &[b"RECORDING SAVED"]),
+                            Err(e) => join_parts(&mut notice.0, &[b"RECORDING: ", e.as_bytes()]),
+                        }
+                    } else {
+                        match recorder.start() {
+                            Ok(name) => join_parts(&mut notice.0, &[b"RECORDING ", &name]),
+                            Err(e) => join_parts(&mut notice.0, &[b"RECORDING: ", e.as_bytes()]),
+                        }
+                    };
+                    notice_frames = NOTICE_FRAMES;
+                }
+                continue;
+            }
+            if key.keycode == drivers::keyboard::KeyCode::F9 {
+                if key.pressed {
+                    settings_menu.toggle();
+                }
+                continue;
+            }
+            if key.keycode == drivers::keyboard::KeyCode::F10 {
+                if key.pressed {
+                    let enabled = device.jit_enabled();
+                    device.set_jit(!enabled);
+                }
+                continue;
+            }
+            if key.keycode == drivers::keyboard::KeyCode::F11 {
+                if key.pressed {
+                    run_ahead.cycle_frames();
+                }
+                continue;
+            }
+            if key.keycode == drivers::keyboard::KeyCode::F12 {
+                if key.pressed {
+                    use graphics::screenshot::Capture;
+                    let kind = if drivers::keyboard::is_shift_pressed() {
+                        Capture::Screen
+                    } else {
+                        Capture::GameBoy
+                    };
+                    let back = double_buffer::back_buffer_ref();
+                    notice.1 = match screenshots.capture(kind, &device, back, hires.is_some()) {
+                        Ok(name) => join_parts(&mut notice.0, &[b"SAVED ", &name]),
+                        Err(e) => join_parts(&mut notice.0, &[b"SCREENSHOT: ", e.as_bytes()]),
+                    };
+                    notice_frames = NOTICE_FRAMES;
+                }
+                continue;
+            }
+            if let Some(gb_key) = input_state.map_keycode(key.keycode) {
+                if combo_frames > 0 && graphics::dmg_palettes::is_combo_key(gb_key) {
+                    if let Some(preset) = combo_picker.key(gb_key, key.pressed) {
+                        graphics::dmg_palettes::select(preset);
+                        boot_combo = Some(preset);
+                    }
+                    continue;
+                }
+                if key.pressed {
+                    device.keydown(gb_key);
+                } else {
+                    device.keyup(gb_key);
+                }
+            }
         }
 
-        // =====================================================================
-        // SAVE TRACKING - call every frame
-        // =====================================================================
-        // This detects when the game writes to SRAM (player selected SAVE)
-        // and persists to disk after writes settle (~2 seconds)
-        savefile::update(&mut save_tracker, &mut device);
+        // ====================================================================
+        // Settings menu: game paused, menu drawn over the GB screen
+        // ====================================================================
+        if settings_menu.is_open() {
+            set_last_operation(OperationId::GpuRender);
+            settings_menu.draw(double_buffer::back_buffer(), hires.is_some());
+            if let Some(screen) = hires.as_mut() {
+                screen.present_gb_area(double_buffer::back_buffer_ref());
+            } else {
+                present_low_res(modex.as_mut(), "PAUSED");
+            }
+        } else {
+            // ================================================================
+            // Run one frame of emulation (plus run-ahead frames if enabled)
+            // ================================================================
+            set_last_operation(OperationId::CpuCycle);
+            run_ahead.run_frame(&mut device);
+            if combo_frames > 0 {
+                combo_frames -= 1;
+                if combo_frames == 0 {
+                    // Combo keys still held go to the game now
+                    for gb_key in combo_picker.held() {
+                        device.keydown(gb_key);
+                    }
+                }
+            }
+        }
 
         // ====================================================================
         // Render if GPU updated
@@ -402,23 +608,39 @@ fn run_gameboy_emulator_with_rom(rom_ptr: *const u8, rom_size: usize) -> ! {
         if device.check_and_reset_gpu_updated() {
             set_last_operation(OperationId::GpuRender);
 
-            // Sync GBC palettes to VGA DAC
-            if device.mode() == GbMode::Color {
-                vga_palette::sync_gbc_bg_palettes(device.get_cbgpal());
-                vga_palette::sync_gbc_sprite_palettes(device.get_csprit());
-            } else {
+            // Sync palettes to VGA DAC (GBC: allocate this frame's colors
+            // per scanline and remap the GB screen in the back buffer)
+            // (not needed in high resolution, where the GPU writes RGB)
+            let cgb = device.mode() == GbMode::Color;
+            if !cgb {
                 let (palb, pal0, pal1) = device.get_dmg_palettes();
                 vga_palette::sync_dmg_palettes(palb, pal0, pal1);
             }
+            if hires.is_none() {
+                // Frame blending gives every blended color its own DAC entry
+                let blend = graphics::filters::settings().blend;
+                if blend != graphics::filters::FrameBlend::Off {
+                    let palettes = if cgb { Some(device.line_palettes()) } else { None };
+                    blender.apply(blend, palettes, double_buffer::back_buffer(), &mut quantizer);
+                } else {
+                    blender.reset();
+                    if cgb {
+                        quantizer.apply(device.line_palettes(), double_buffer::back_buffer());
+                    }
+                }
+            }
+
+            // Record the frame (a disk error ends the recording)
+            if let Err(e) = recorder.capture(&device) {
+                notice.1 = join_parts(&mut notice.0, &[b"RECORDING: ", e.as_bytes()]);
+                notice_frames = NOTICE_FRAMES;
+            }
 
             // ================================================================
             // ALL DRAWING GOES TO BACK BUFFER
+            // (the GB screen is already there, written scanline by scanline)
             // ================================================================
 
-            // Blit GB screen to back buffer
-            set_last_operation(OperationId::VgaBlit);
-            double_buffer::blit_gb_to_backbuffer(device.get_pal_data());
-
             // Render overlay to back buffer (uses dirty tracking internally)
             // Only redraws regions that actually changed
             if overlay_enabled {
@@ -430,29 +652,47 @@ fn run_gameboy_emulator_with_rom(rom_ptr: *const u8, rom_size: usize) -> ! {
             // FLIP WITH VSYNC
             // Waits for vertical retrace, then copies entire back buffer
             // to VGA in one atomic operation. Zero flicker guaranteed.
+            // In high resolution only the overlay panels are copied; the
+            // GB screen is already on the framebuffer. In Mode X only what
+            // changed is copied and the flip is a page switch.
             // ================================================================
-            double_buffer::flip_vsync();
-        }
-
-        // ====================================================================
-        // Process keyboard input
-        // ====================================================================
-        set_last_operation(OperationId::KeyboardPoll);
-        while let Some(key) = drivers::keyboard::get_key() {
-            if let Some(gb_key) = input_state.map_keycode(key.keycode) {
-                if key.pressed {
-                    device.keydown(gb_key);
+            if let Some(screen) = hires.as_mut() {
+                screen.present_panels(double_buffer::back_buffer_ref());
+            } else {
+                let mut status = [0u8; 40];
+                let text = if notice_frames > 0 {
+                    notice_frames -= 1;
+                    core::str::from_utf8(&notice.0[..notice.1]).unwrap_or("")
                 } else {
-                    device.keyup(gb_key);
-                }
+                    status_text(&mut status, device.jit_enabled(), run_ahead.frames(), recorder.is_recording())
+                };
+                present_low_res(modex.as_mut(), text);
             }
         }
 
+        // Back to the real timeline (no-op when run-ahead is off)
+        run_ahead.rollback(&mut device);
+
+        // =====================================================================
+        // SAVE TRACKING - call every frame
+        // =====================================================================
+        // This detects when the game writes to SRAM (player selected SAVE)
+        // and persists to disk after writes settle (~2 seconds)
+        savefile::update(&mut save_tracker, &mut device);
+
         // ====================================================================
         // Frame timing - wait until next frame time
         // ====================================================================
         set_last_operation(OperationId::FrameEnd);
         let target_ticks = last_frame_ticks.wrapping_add(TICKS_PER_FRAME);
+
+        // Spare time goes to writing the recording, leaving a tick or two
+        // of margin so the frame isn't late
+        if let Err(e) = recorder.flush(target_ticks.wrapping_sub(2)) {
+            notice.1 = join_parts(&mut notice.0, &[b"RECORDING: ", e.as_bytes()]);
+            notice_frames = NOTICE_FRAMES;
+        }
+
         while arch::x86::pit::ticks().wrapping_sub(target_ticks) > 0x8000_0000 {
             unsafe { core::arch::asm!("hlt"); }
         }
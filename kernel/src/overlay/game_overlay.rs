@@ -19,23 +19,26 @@ use crate::overlay::move_pp::{get_actual_max_pp, extract_pp_ups, extract_current
 use crate::overlay::catch_rate::{get_catch_rate, get_catch_tier};
 use crate::gui::font_4x6;
 use crate::gui::layout::{element, LayoutCursor, Region, GB_X, GB_WIDTH, GB_BOTTOM};
-use crate::graphics::vga_mode13h::{colors, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::graphics::vga_mode13h::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::graphics::vga_palette;
 
 // =============================================================================
 // Colors
 // =============================================================================
 
-const HP_GREEN: u8 = colors::GREEN;
-const HP_YELLOW: u8 = colors::YELLOW;
-const HP_RED: u8 = colors::RED;
-const HP_BG: u8 = colors::DARK_GRAY;
-const TEXT: u8 = colors::WHITE;
-const TEXT_DIM: u8 = colors::LIGHT_GRAY;
-const TEXT_HIGHLIGHT: u8 = colors::CYAN;
-const BG: u8 = colors::BLACK;
-const BADGE_EMPTY: u8 = colors::DARK_GRAY;
-const BADGE_FILLED: u8 = colors::YELLOW;
-const BADGE_KANTO: u8 = colors::LIGHT_BLUE;
+// UI palette entries, so they follow the vision filter (see
+// `vga_palette::init_ui_palette`)
+const HP_GREEN: u8 = vga_palette::COLOR_HP_GREEN;
+const HP_YELLOW: u8 = vga_palette::COLOR_HP_YELLOW;
+const HP_RED: u8 = vga_palette::COLOR_HP_RED;
+const HP_BG: u8 = vga_palette::COLOR_HP_BG;
+const TEXT: u8 = vga_palette::COLOR_OVERLAY_TEXT;
+const TEXT_DIM: u8 = vga_palette::COLOR_DISABLED;
+const TEXT_HIGHLIGHT: u8 = vga_palette::COLOR_OVERLAY_HIGHLIGHT;
+const BG: u8 = vga_palette::COLOR_BLACK;
+const BADGE_EMPTY: u8 = vga_palette::COLOR_HP_BG;
+const BADGE_FILLED: u8 = vga_palette::COLOR_WARNING;
+const BADGE_KANTO: u8 = vga_palette::COLOR_INFO;
 
 const CHAR_W: usize = font_4x6::CELL_WIDTH;
 
@@ -51,8 +51,10 @@
 //! // Detect game from ROM name
 //! let game = Game::detect(&device.romname());
 //!
-//! // In main loop:
-//! double_buffer::blit_gb_to_backbuffer(device.get_pal_data());
+//! // Once, after creating the device:
+//! device.set_frame_sink(Box::new(gameboy::display::Mode13hSink));
+//!
+//! // In main loop (the GB screen is already in the back buffer):
 //! let reader = RamReader::new(device.mmu(), game);
 //! render_overlay_efficient(double_buffer::back_buffer(), &reader, game);
 //! double_buffer::flip_vsync();
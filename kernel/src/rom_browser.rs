@@ -1,12 +1,29 @@
-//! ROM Browser - Select Game Boy ROMs from FAT32 storage
+//! ROM Browser - Select Game Boy ROMs from the mounted volume
 //!
-//! Displays a list of available .gb/.gbc files and allows
-//! the user to select which one to boot.
+//! Displays the folders and .gb/.gbc files of a directory and allows
+//! the user to walk into folders and select which ROM to boot.
+//!
+//! Files are listed by their long names, drawn in the font's code page 437
+//! (other characters as `?`). Names too long for the list are cut short
+//! with "..", except the selected one, which scrolls.
+//!
+//! Other partitions are mounted as folders at the top (`disk0p2`), so
+//! the browser walks into them like any folder.
+//!
+//! The folder and ROM booted last are kept in `LASTDIR.TXT` in the root
+//! directory (the path, a newline, then the ROM's name), and the browser
+//! opens there next time.
 
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::arch::x86::pit;
 use crate::drivers::keyboard::{self, KeyCode};
+use crate::fs::{vfs, DirEntry, FileType, MAX_FILENAME, MAX_PATH};
 use crate::graphics::vga_mode13h::{self, colors, SCREEN_WIDTH};
 use crate::gui::font_8x8;
-use crate::storage::fat32;
+use crate::storage::fat32::ucs2_to_cp437;
 
 // ============================================================================
 // UI Layout Constants
@@ -18,6 +35,16 @@ const LIST_ITEM_HEIGHT: usize = 12;
 const LIST_X: usize = 40;
 const MAX_VISIBLE_ITEMS: usize = 10;
 
+/// Highlight bar width, and the name characters that fit in it after the
+/// selector arrow
+const ITEM_WIDTH: usize = 232;
+const NAME_X: usize = LIST_X + 12;
+const NAME_CHARS: usize = (LIST_X - 4 + ITEM_WIDTH - NAME_X) / font_8x8::CHAR_WIDTH;
+
+/// Selected name scrolling: pause at either end, then one character per step
+const SCROLL_PAUSE_MS: u32 = 1200;
+const SCROLL_STEP_MS: u32 = 150;
+
 /// Border dimensions
 const BORDER_X: usize = 20;
 const BORDER_Y: usize = 10;
@@ -28,14 +55,33 @@ const BORDER_THICKNESS: usize = 3;
 /// Debug rows preserved at bottom of screen
 const DEBUG_ROWS: usize = 5;
 
+/// Path characters shown in the breadcrumb (longer paths lose their start)
+const BREADCRUMB_CHARS: usize = 20;
+
+/// Right edge of the ROM count beside the breadcrumb
+const COUNT_RIGHT: usize = LIST_X - 4 + ITEM_WIDTH;
+
+/// Last folder and ROM booted
+const LAST_DIR_FILE: &str = "/LASTDIR.TXT";
+
 // ============================================================================
 // ROM Browser
 // ============================================================================
 
 pub struct RomBrowser {
+    /// Current directory's `..`, subfolders, then ROM files, each in
+    /// directory order
+    entries: Vec<DirEntry>,
     rom_count: usize,
+    /// Path of the current directory from the root, without a leading
+    /// `/` (long names, `/`-separated)
+    path: String,
     selected: usize,
     scroll_offset: usize,
+    /// Characters the selected name is scrolled by, and when it next moves
+    /// (PIT ticks)
+    name_scroll: usize,
+    name_scroll_at: u32,
 }
 
 impl RomBrowser {
@@ -43,7 +89,7 @@ impl RomBrowser {
         // Debug: show mount status on row 195
         unsafe {
             let vga = vga_mode13h::VGA_ADDR;
-            let is_mounted = fat32::is_mounted();
+            let is_mounted = vfs::is_mounted("/");
             core::ptr::write_volatile(vga.add(195 * 320), if is_mounted { 0x0A } else { 0x04 });
             core::ptr::write_volatile(
                 vga.add(195 * 320 + 1),
@@ -51,11 +97,21 @@ impl RomBrowser {
             );
         }
 
-        let rom_count = fat32::get_fs().count_roms();
+        let mut browser = Self {
+            entries: Vec::new(),
+            rom_count: 0,
+            path: String::new(),
+            selected: 0,
+            scroll_offset: 0,
+            name_scroll: 0,
+            name_scroll_at: 0,
+        };
+        browser.open_last_dir();
 
         // Debug: show rom_count on row 195
         unsafe {
             let vga = vga_mode13h::VGA_ADDR;
+            let rom_count = browser.rom_count;
             core::ptr::write_volatile(vga.add(195 * 320 + 5), rom_count as u8);
             core::ptr::write_volatile(vga.add(195 * 320 + 6), rom_count as u8);
             // Green bar if count > 0, red if 0
@@ -65,22 +121,20 @@ impl RomBrowser {
             }
         }
 
-        Self {
-            rom_count,
-            selected: 0,
-            scroll_offset: 0,
-        }
+        browser
     }
 
     /// Run the browser UI loop
-    /// Returns the selected ROM index, or None if no ROMs available
-    pub fn run(&mut self) -> Option<usize> {
-        if self.rom_count == 0 {
+    /// Returns the selected ROM's path, or None if no ROMs or folders
+    /// available
+    pub fn run(&mut self) -> Option<String> {
+        if self.entries.is_empty() {
             self.draw_no_roms_screen();
             return None;
         }
 
         // Initial draw
+        self.reset_name_scroll();
         self.draw_screen();
 
         // Input loop
@@ -96,25 +150,51 @@ impl RomBrowser {
                         if self.selected > 0 {
                             self.selected -= 1;
                             self.adjust_scroll();
+                            self.reset_name_scroll();
                             self.draw_list();
                         }
                     }
                     // Down arrow or S
                     KeyCode::Down | KeyCode::S => {
-                        if self.selected < self.rom_count - 1 {
+                        if self.selected < self.entries.len() - 1 {
                             self.selected += 1;
                             self.adjust_scroll();
+                            self.reset_name_scroll();
                             self.draw_list();
                         }
                     }
-                    // Enter or Space
+                    // Enter or Space: open a folder, or boot a ROM
                     KeyCode::Enter | KeyCode::Space => {
-                        return Some(self.selected);
+                        let entry = &self.entries[self.selected];
+                        if is_parent(entry) {
+                            self.go_up();
+                        } else if entry.file_type == FileType::Directory {
+                            // Folders nested too deep for a path stay shut
+                            if self.path.len() + 2 + entry.name_len <= MAX_PATH {
+                                if !self.path.is_empty() {
+                                    self.path.push('/');
+                                }
+                                self.path.push_str(entry.name());
+                                self.open_dir(None);
+                                self.draw_screen();
+                            }
+                        } else {
+                            self.remember_dir();
+                            return Some(self.entry_path(self.selected));
+                        }
+                    }
+                    // Backspace, Escape or left arrow: parent folder
+                    KeyCode::Backspace | KeyCode::Escape | KeyCode::Left => {
+                        if !self.path.is_empty() {
+                            self.go_up();
+                        }
                     }
                     _ => {}
                 }
             }
 
+            self.step_name_scroll();
+
             // Small delay to prevent busy-waiting
             for _ in 0..10000 {
                 unsafe {
@@ -124,6 +204,124 @@ impl RomBrowser {
         }
     }
 
+    /// List the directory whose path is in `path`, selecting the entry
+    /// named `select` if there is one
+    ///
+    /// A directory that can't be read lists as empty (bar `..`).
+    fn open_dir(&mut self, select: Option<&str>) {
+        self.entries.clear();
+        if !self.path.is_empty() {
+            self.entries.push(parent_entry());
+        }
+
+        // Folders first, each group in directory order
+        let mut roms = Vec::new();
+        let dir = self.entry_path_of("");
+        let _ = vfs::for_each_entry(&dir, |entry| {
+            if entry.hidden {
+                return;
+            }
+            if entry.file_type == FileType::Directory {
+                self.entries.push(entry);
+            } else if entry.is_rom() {
+                roms.push(entry);
+            }
+        });
+        self.rom_count = roms.len();
+        self.entries.append(&mut roms);
+
+        self.selected = select
+            .and_then(|name| self.entries.iter().position(|e| !is_parent(e) && e.name() == name))
+            .unwrap_or(0);
+        self.scroll_offset = 0;
+        self.adjust_scroll();
+        self.reset_name_scroll();
+    }
+
+    /// Go back to the parent folder, selecting the one we were in
+    fn go_up(&mut self) {
+        let (parent_len, name_start) = match self.path.rfind('/') {
+            Some(slash) => (slash, slash + 1),
+            None => (0, 0),
+        };
+        let left = self.path.split_off(name_start);
+        self.path.truncate(parent_len);
+
+        if !vfs::is_dir(&self.entry_path_of("")) {
+            self.path.clear();
+        }
+        self.open_dir(Some(&left));
+        self.draw_screen();
+    }
+
+    /// Open the folder of the last ROM booted, or the root directory
+    fn open_last_dir(&mut self) {
+        let mut text = [0u8; MAX_PATH + 1 + MAX_FILENAME];
+        let len = vfs::read_file(LAST_DIR_FILE, &mut text).unwrap_or(0);
+        let text = core::str::from_utf8(&text[..len]).unwrap_or("");
+        let (path, rom) = text.split_once('\n').unwrap_or((text, ""));
+
+        self.path.push_str(path.trim_matches('/'));
+        if !vfs::is_dir(&self.entry_path_of("")) {
+            self.path.clear();
+        }
+        self.open_dir(Some(rom));
+    }
+
+    /// Note the current folder and selected ROM in `LAST_DIR_FILE`
+    fn remember_dir(&self) {
+        let mut text = String::with_capacity(self.path.len() + 1 + MAX_FILENAME);
+        text.push_str(&self.path);
+        text.push('\n');
+        text.push_str(self.entries[self.selected].name());
+        // Only a convenience: booting goes ahead if the disk won't take it
+        let _ = vfs::write_file(LAST_DIR_FILE, text.as_bytes());
+    }
+
+    /// VFS path of entry `index`
+    fn entry_path(&self, index: usize) -> String {
+        self.entry_path_of(self.entries[index].name())
+    }
+
+    /// VFS path of `name` in the current directory (the directory itself
+    /// for an empty name)
+    fn entry_path_of(&self, name: &str) -> String {
+        let mut path = String::with_capacity(self.path.len() + name.len() + 2);
+        path.push('/');
+        path.push_str(&self.path);
+        if !self.path.is_empty() && !name.is_empty() {
+            path.push('/');
+        }
+        path.push_str(name);
+        path
+    }
+
+    /// Show the start of the newly selected name, and hold it there a while
+    fn reset_name_scroll(&mut self) {
+        self.name_scroll = 0;
+        self.name_scroll_at = pit::ticks().wrapping_add(ms_to_ticks(SCROLL_PAUSE_MS));
+    }
+
+    /// Move the selected name along if it is too long to show and it is time
+    fn step_name_scroll(&mut self) {
+        let len = display_len(&self.entries[self.selected]);
+        let now = pit::ticks();
+        if len <= NAME_CHARS || now.wrapping_sub(self.name_scroll_at) >= 0x8000_0000 {
+            return;
+        }
+
+        let end = len - NAME_CHARS;
+        if self.name_scroll == end {
+            // Shown the end: back to the start
+            self.reset_name_scroll();
+        } else {
+            self.name_scroll += 1;
+            let wait = if self.name_scroll == end { SCROLL_PAUSE_MS } else { SCROLL_STEP_MS };
+            self.name_scroll_at = now.wrapping_add(ms_to_ticks(wait));
+        }
+        self.draw_item(self.selected - self.scroll_offset);
+    }
+
     fn adjust_scroll(&mut self) {
         // Scroll up if selected is above visible area
         if self.selected < self.scroll_offset {
@@ -142,7 +340,7 @@ impl RomBrowser {
 
         self.draw_border();
         self.draw_title();
-        self.draw_rom_count();
+        self.draw_breadcrumb();
         self.draw_list();
         self.draw_instructions();
     }
@@ -163,7 +361,7 @@ impl RomBrowser {
         unsafe {
             let vga = vga_mode13h::VGA_ADDR;
             // Row 195: mounted status
-            let is_mounted = fat32::is_mounted();
+            let is_mounted = vfs::is_mounted("/");
             let mount_color = if is_mounted { 0x0A } else { 0x04 };
             for i in 0..20 {
                 core::ptr::write_volatile(vga.add(195 * 320 + i), mount_color);
@@ -207,34 +405,49 @@ impl RomBrowser {
         font_8x8::draw_string_centered_vga(TITLE_Y + 12, "ROM SELECTOR", colors::LIGHT_GRAY);
     }
 
+    /// Current path on the left above the list, the ROM count on the right
     #[inline(never)]
-    fn draw_rom_count(&self) {
+    fn draw_breadcrumb(&self) {
+        let y = LIST_START_Y - 12;
+        let x = LIST_X - 4;
+        font_8x8::draw_char_vga(x, y, b'/', colors::LIGHT_GRAY);
+        let mut buf = [0u8; MAX_PATH];
+        let path = to_cp437(&self.path, &mut buf);
+        if path.len() <= BREADCRUMB_CHARS - 1 {
+            font_8x8::draw_bytes_vga(x + font_8x8::CHAR_WIDTH, y, path, colors::LIGHT_GRAY);
+        } else {
+            let tail = &path[path.len() - (BREADCRUMB_CHARS - 3)..];
+            font_8x8::draw_bytes_vga(x + font_8x8::CHAR_WIDTH, y, b"..", colors::LIGHT_GRAY);
+            font_8x8::draw_bytes_vga(x + 3 * font_8x8::CHAR_WIDTH, y, tail, colors::LIGHT_GRAY);
+        }
+
         let mut buf = [0u8; 20];
-        let count_str = self.format_count(&mut buf);
-        font_8x8::draw_string_centered_vga(LIST_START_Y - 12, count_str, colors::DARK_GRAY);
+        let count = self.format_count(&mut buf);
+        font_8x8::draw_bytes_vga(COUNT_RIGHT - count.len() * font_8x8::CHAR_WIDTH, y, count, colors::DARK_GRAY);
     }
 
-    fn format_count<'a>(&self, buf: &'a mut [u8; 20]) -> &'a str {
-        // Format "X ROMS FOUND"
-        let mut pos = 0;
+    /// "N ROMS" (or "1 ROM")
+    fn format_count<'a>(&self, buf: &'a mut [u8; 20]) -> &'a [u8] {
+        let suffix: &[u8] = if self.rom_count == 1 { b" ROM" } else { b" ROMS" };
 
-        // Number
-        if self.rom_count >= 10 {
-            buf[pos] = b'0' + (self.rom_count / 10) as u8;
-            pos += 1;
-        }
-        buf[pos] = b'0' + (self.rom_count % 10) as u8;
-        pos += 1;
-
-        // " ROMS FOUND"
-        let suffix = b" ROMS FOUND";
-        for &c in suffix {
-            buf[pos] = c;
-            pos += 1;
+        // Digits, right to left
+        let mut digits = [0u8; 10];
+        let mut n = self.rom_count;
+        let mut count = 0;
+        loop {
+            digits[count] = b'0' + (n % 10) as u8;
+            count += 1;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
         }
 
-        // Safe because we only use ASCII
-        unsafe { core::str::from_utf8_unchecked(&buf[..pos]) }
+        for i in 0..count {
+            buf[i] = digits[count - 1 - i];
+        }
+        buf[count..count + suffix.len()].copy_from_slice(suffix);
+        &buf[..count + suffix.len()]
     }
 
     #[inline(never)]
@@ -253,68 +466,119 @@ impl RomBrowser {
             core::ptr::write_volatile(0xA0000 as *mut u8, 0x00);
         }
 
-        let visible_count = (self.rom_count - self.scroll_offset).min(MAX_VISIBLE_ITEMS);
+        let visible_count = (self.entries.len() - self.scroll_offset).min(MAX_VISIBLE_ITEMS);
 
         for i in 0..visible_count {
-            let rom_index = self.scroll_offset + i;
-            let y = LIST_START_Y + i * LIST_ITEM_HEIGHT;
-            let is_selected = rom_index == self.selected;
-
-            // Draw selection highlight
-            if is_selected {
-                vga_mode13h::fill_rect(
-                    LIST_X - 4,
-                    y - 1,
-                    232,
-                    LIST_ITEM_HEIGHT,
-                    colors::HIGHLIGHT_BG,
-                );
-            }
-
-            // Get ROM name
-            let mut name_buf = [0u8; 12];
-            if fat32::get_fs().get_rom_name(rom_index, &mut name_buf) {
-                // Convert to string (stop at null or end)
-                let name_len = name_buf.iter().position(|&c| c == 0).unwrap_or(12);
-                let name = unsafe { core::str::from_utf8_unchecked(&name_buf[..name_len]) };
-
-                // Draw selector arrow
-                if is_selected {
-                    font_8x8::draw_char_vga(LIST_X, y, b'>', colors::WHITE);
-                }
-
-                // Draw filename
-                let text_color = if is_selected {
-                    colors::WHITE
-                } else {
-                    colors::LIGHT_GRAY
-                };
-                font_8x8::draw_string_vga(LIST_X + 12, y, name, text_color);
-            }
+            self.draw_item(i);
         }
 
         // Draw scroll indicators if needed
         if self.scroll_offset > 0 {
             font_8x8::draw_char_vga(SCREEN_WIDTH - 35, LIST_START_Y, b'^', colors::LIGHT_GRAY);
         }
-        if self.scroll_offset + MAX_VISIBLE_ITEMS < self.rom_count {
+        if self.scroll_offset + MAX_VISIBLE_ITEMS < self.entries.len() {
             let y = LIST_START_Y + (MAX_VISIBLE_ITEMS - 1) * LIST_ITEM_HEIGHT;
             font_8x8::draw_char_vga(SCREEN_WIDTH - 35, y, font_8x8::DOWN_ARROW, colors::LIGHT_GRAY);
         }
     }
 
+    /// Draw visible row `row` (0 = top of the list)
+    #[inline(never)]
+    fn draw_item(&self, row: usize) {
+        let index = self.scroll_offset + row;
+        let Some(entry) = self.entries.get(index) else { return };
+        let y = LIST_START_Y + row * LIST_ITEM_HEIGHT;
+        let is_selected = index == self.selected;
+
+        // Draw selection highlight (or clear a row redrawn on its own)
+        let background = if is_selected { colors::HIGHLIGHT_BG } else { colors::BLACK };
+        vga_mode13h::fill_rect(LIST_X - 4, y - 1, ITEM_WIDTH, LIST_ITEM_HEIGHT, background);
+
+        // Draw selector arrow
+        if is_selected {
+            font_8x8::draw_char_vga(LIST_X, y, b'>', colors::WHITE);
+        }
+
+        // Draw filename (folders with a trailing '/'): scrolled when
+        // selected, else cut short
+        let mut buf = [0u8; MAX_FILENAME + 1];
+        let name = display_name(entry, &mut buf);
+        let text_color = if is_selected {
+            colors::WHITE
+        } else {
+            colors::LIGHT_GRAY
+        };
+        if name.len() <= NAME_CHARS {
+            font_8x8::draw_bytes_vga(NAME_X, y, name, text_color);
+        } else if is_selected {
+            let start = self.name_scroll.min(name.len() - NAME_CHARS);
+            font_8x8::draw_bytes_vga(NAME_X, y, &name[start..start + NAME_CHARS], text_color);
+        } else {
+            font_8x8::draw_bytes_vga(NAME_X, y, &name[..NAME_CHARS - 2], text_color);
+            font_8x8::draw_bytes_vga(NAME_X + (NAME_CHARS - 2) * font_8x8::CHAR_WIDTH, y, b"..", text_color);
+        }
+    }
+
     fn draw_instructions(&self) {
-        font_8x8::draw_string_centered_vga(175, "UP/DOWN:SELECT  ENTER:BOOT", colors::DARK_GRAY);
+        font_8x8::draw_string_centered_vga(175, "UP/DN:SELECT ENTER:OPEN BKSP:UP", colors::DARK_GRAY);
     }
 }
 
+/// The `..` entry listed first in a subfolder
+fn parent_entry() -> DirEntry {
+    let mut entry = DirEntry {
+        name: [0; MAX_FILENAME],
+        name_len: 2,
+        file_type: FileType::Directory,
+        inode: 0,
+        hidden: false,
+    };
+    entry.name[..2].copy_from_slice(b"..");
+    entry
+}
+
+/// True for the `..` entry
+fn is_parent(entry: &DirEntry) -> bool {
+    entry.file_type == FileType::Directory && entry.name() == ".."
+}
+
+/// UTF-8 text in code page 437 for the font, as much as fits in `buf`
+fn to_cp437<'a>(text: &str, buf: &'a mut [u8]) -> &'a [u8] {
+    let mut len = 0;
+    for (slot, c) in buf.iter_mut().zip(text.chars()) {
+        *slot = ucs2_to_cp437(u16::try_from(c as u32).unwrap_or(0xFFFD));
+        len += 1;
+    }
+    &buf[..len]
+}
+
+/// Name as listed, in code page 437: folders get a trailing '/'
+fn display_name<'a>(entry: &DirEntry, buf: &'a mut [u8; MAX_FILENAME + 1]) -> &'a [u8] {
+    let len = to_cp437(entry.name(), &mut buf[..MAX_FILENAME]).len();
+    if entry.file_type != FileType::Directory {
+        return &buf[..len];
+    }
+    buf[len] = b'/';
+    &buf[..len + 1]
+}
+
+/// Length of `display_name`
+fn display_len(entry: &DirEntry) -> usize {
+    entry.name().chars().count() + (entry.file_type == FileType::Directory) as usize
+}
+
+/// Milliseconds as PIT ticks at the current timer rate
+fn ms_to_ticks(ms: u32) -> u32 {
+    (ms * pit::frequency() / 1000).max(1)
+}
+
 // ============================================================================
 // Public API
 // ============================================================================
 
-/// Show ROM browser and return selected ROM index
+/// Show ROM browser and return the selected ROM's path
 /// Returns None if no ROMs found
-pub fn select_rom() -> Option<usize> {
+pub fn select_rom() -> Option<String> {
     let mut browser = RomBrowser::new();
     browser.run()
 }
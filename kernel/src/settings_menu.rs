@@ -0,0 +1,221 @@
+//! Settings Menu - In-game display options
+//!
+//! Opened with F9 while a game runs; emulation pauses while it is up.
+//! The menu is drawn into the Game Boy area of the back buffer, so it
+//! shows the same way in Mode 13h (flip) and in high resolution
+//! (`HiResScreen::present_gb_area`). The next emulated frame paints over
+//! it once the menu is closed.
+
+use crate::drivers::keyboard::KeyCode;
+use crate::graphics::{dmg_palettes, page_flip};
+use crate::graphics::filters::{self, FrameBlend, LcdMask, Tint, Upscaler};
+use crate::graphics::vga_mode13h::SCREEN_WIDTH;
+use crate::graphics::vga_palette::{
+    self, ColorCurve, VisionFilter, COLOR_GB_BORDER, COLOR_OVERLAY_BG, COLOR_WHITE, GAMMA_MAX,
+    GAMMA_MIN,
+};
+use crate::gui::font_8x8::{self, CHAR_WIDTH};
+use crate::gui::layout::{GB_HEIGHT, GB_WIDTH, GB_X, GB_Y};
+
+// ============================================================================
+// UI Layout Constants
+// ============================================================================
+
+const TITLE_Y: usize = GB_Y + 8;
+const LIST_START_Y: usize = GB_Y + 22;
+const LIST_ITEM_HEIGHT: usize = 9;
+const LABEL_X: usize = GB_X + 14;
+const VALUE_RIGHT: usize = GB_X + GB_WIDTH - 6;
+const FOOTER_Y: usize = GB_Y + GB_HEIGHT - 14;
+
+// ============================================================================
+// Items
+// ============================================================================
+
+/// One adjustable setting
+#[derive(Clone, Copy)]
+enum Item {
+    Upscale,
+    Lcd,
+    Tint,
+    Blend,
+    Curve,
+    Gamma,
+    Palette,
+    Vision,
+    ModeX,
+}
+
+const ITEMS: [Item; 9] = [
+    Item::Upscale,
+    Item::Lcd,
+    Item::Tint,
+    Item::Blend,
+    Item::Curve,
+    Item::Gamma,
+    Item::Palette,
+    Item::Vision,
+    Item::ModeX,
+];
+
+impl Item {
+    fn label(self) -> &'static str {
+        match self {
+            Item::Upscale => "UPSCALE",
+            Item::Lcd => "LCD",
+            Item::Tint => "TINT",
+            Item::Blend => "BLEND",
+            Item::Curve => "COLOR",
+            Item::Gamma => "GAMMA",
+            Item::Palette => "PALETTE",
+            Item::Vision => "VISION",
+            Item::ModeX => "MODE X",
+        }
+    }
+
+    /// Whether the setting only affects true-color output
+    fn needs_hires(self) -> bool {
+        matches!(self, Item::Upscale | Item::Lcd | Item::Tint)
+    }
+
+    /// Whether the setting only affects the 256-color modes
+    fn needs_palette(self) -> bool {
+        matches!(self, Item::ModeX)
+    }
+
+    /// Current value as text (`buf` holds the gamma, e.g. "2.2")
+    fn value(self, buf: &mut [u8; 3]) -> &str {
+        let s = filters::settings();
+        let (curve, gamma) = vga_palette::color_curve();
+        match self {
+            Item::Upscale => s.upscaler.name(),
+            Item::Lcd => s.lcd.name(),
+            Item::Tint => s.tint.name(),
+            Item::Blend => s.blend.name(),
+            Item::Curve => curve.name(),
+            Item::Gamma => {
+                *buf = [b'0' + gamma / 10, b'.', b'0' + gamma % 10];
+                core::str::from_utf8(buf).unwrap_or("")
+            }
+            Item::Palette => dmg_palettes::active().name,
+            Item::Vision => vga_palette::vision_filter().name(),
+            Item::ModeX => if page_flip::enabled() { "ON" } else { "OFF" },
+        }
+    }
+
+    /// Step the setting forwards (`delta` = 1) or backwards (-1)
+    fn change(self, delta: i32) {
+        let mut s = filters::settings();
+        match self {
+            Item::Upscale => s.upscaler = filters::cycle(&Upscaler::ALL, s.upscaler, delta),
+            Item::Lcd => s.lcd = filters::cycle(&LcdMask::ALL, s.lcd, delta),
+            Item::Tint => s.tint = filters::cycle(&Tint::ALL, s.tint, delta),
+            Item::Blend => s.blend = filters::cycle(&FrameBlend::ALL, s.blend, delta),
+            Item::Curve | Item::Gamma => {
+                let (mut curve, mut gamma) = vga_palette::color_curve();
+                if let Item::Curve = self {
+                    curve = filters::cycle(&ColorCurve::ALL, curve, delta);
+                } else {
+                    gamma = (gamma as i32 + delta).clamp(GAMMA_MIN as i32, GAMMA_MAX as i32) as u8;
+                }
+                vga_palette::set_color_curve(curve, gamma);
+                return;
+            }
+            Item::Palette => return dmg_palettes::cycle(delta),
+            Item::Vision => {
+                let filter = filters::cycle(&VisionFilter::ALL, vga_palette::vision_filter(), delta);
+                return vga_palette::set_vision_filter(filter);
+            }
+            Item::ModeX => return page_flip::set_enabled(!page_flip::enabled()),
+        }
+        filters::set_settings(s);
+    }
+}
+
+// ============================================================================
+// Settings Menu
+// ============================================================================
+
+pub struct SettingsMenu {
+    open: bool,
+    selected: usize,
+}
+
+impl SettingsMenu {
+    pub fn new() -> Self {
+        Self { open: false, selected: 0 }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Open or close the menu
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    /// Handle a key press while the menu is open
+    pub fn handle_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Up => {
+                self.selected = (self.selected + ITEMS.len() - 1) % ITEMS.len();
+            }
+            KeyCode::Down => {
+                self.selected = (self.selected + 1) % ITEMS.len();
+            }
+            KeyCode::Left => ITEMS[self.selected].change(-1),
+            KeyCode::Right | KeyCode::Enter => ITEMS[self.selected].change(1),
+            KeyCode::Escape | KeyCode::F9 => self.open = false,
+            _ => {}
+        }
+    }
+
+    /// Draw the menu over the Game Boy area of `buffer` (320x200)
+    ///
+    /// Settings without an effect in the current mode (`hires`: BGA
+    /// true-color output, else the 256-color palette modes) are marked.
+    pub fn draw(&self, buffer: &mut [u8], hires: bool) {
+        for y in GB_Y..GB_Y + GB_HEIGHT {
+            let row = y * SCREEN_WIDTH + GB_X;
+            buffer[row..row + GB_WIDTH].fill(COLOR_OVERLAY_BG);
+        }
+
+        draw_centered(buffer, TITLE_Y, "SETTINGS", COLOR_WHITE);
+
+        for (i, item) in ITEMS.iter().enumerate() {
+            let y = LIST_START_Y + i * LIST_ITEM_HEIGHT;
+            let color = if i == self.selected { COLOR_WHITE } else { COLOR_GB_BORDER };
+            if i == self.selected {
+                font_8x8::draw_char(buffer, GB_X + 4, y, b'>', COLOR_WHITE);
+            }
+            font_8x8::draw_str(buffer, LABEL_X, y, item.label(), color);
+            let mark = if !hires && item.needs_hires() {
+                Some(b'*')
+            } else if hires && item.needs_palette() {
+                Some(b'+')
+            } else {
+                None
+            };
+            if let Some(mark) = mark {
+                let x = LABEL_X + item.label().len() * CHAR_WIDTH;
+                font_8x8::draw_char(buffer, x, y, mark, color);
+            }
+
+            let mut buf = [0; 3];
+            let value = item.value(&mut buf);
+            let x = VALUE_RIGHT - value.len() * CHAR_WIDTH;
+            font_8x8::draw_str(buffer, x, y, value, color);
+        }
+
+        let note = if hires { "+PALETTE MODES ONLY" } else { "*NEEDS BGA DISPLAY" };
+        draw_centered(buffer, FOOTER_Y - 12, note, COLOR_GB_BORDER);
+        draw_centered(buffer, FOOTER_Y, "F9:CLOSE", COLOR_GB_BORDER);
+    }
+}
+
+/// Draw `s` centered in the Game Boy area
+fn draw_centered(buffer: &mut [u8], y: usize, s: &str, color: u8) {
+    let x = GB_X + (GB_WIDTH - s.len() * CHAR_WIDTH) / 2;
+    font_8x8::draw_str(buffer, x, y, s, color);
+}
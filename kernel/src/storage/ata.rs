@@ -1,17 +1,25 @@
 //! ATA/IDE Driver
 //!
-//! PIO-mode ATA driver for the Intel PIIX4 IDE controller.
-//! Uses polling (not IRQs) for maximum compatibility and debuggability.
+//! ATA driver for the Intel PIIX4 IDE controller.
+//! Commands use polling (not IRQs) for maximum compatibility and
+//! debuggability. Disks that negotiate Ultra DMA move data through
+//! `storage::busmaster` instead of the CPU, but the caller still waits
+//! for each transfer to finish.
 //!
 //! Supports:
-//! - ATA hard drives (IDENTIFY, READ SECTORS)
+//! - ATA hard drives (IDENTIFY, READ SECTORS, READ/WRITE DMA)
 //! - ATAPI CD-ROM drives (IDENTIFY PACKET, READ(12))
 //!
+//! A failed DMA command resets the channel and puts it back on PIO for
+//! the rest of the session; the command is then retried with PIO.
+//!
 //! Based on Armada E500 PIIX4M documentation:
 //! - Primary IDE: 0x1F0-0x1F7, control 0x3F6, IRQ 14
 //! - Secondary IDE: 0x170-0x177, control 0x376, IRQ 15
 
 use crate::arch::x86::io::{inb, outb, inw, outw, inl, outl};
+use core::ptr::addr_of_mut;
+use super::busmaster;
 
 // =============================================================================
 // Port Addresses
@@ -58,12 +66,33 @@ pub mod cmd {
     pub const IDENTIFY_PACKET: u8 = 0xA1;
     pub const READ_SECTORS: u8 = 0x20;
     pub const READ_SECTORS_EXT: u8 = 0x24;  // 48-bit LBA
+    pub const READ_DMA: u8 = 0xC8;
+    pub const READ_DMA_EXT: u8 = 0x25;      // 48-bit LBA
+    pub const WRITE_DMA: u8 = 0xCA;
+    pub const WRITE_DMA_EXT: u8 = 0x35;     // 48-bit LBA
     pub const WRITE_SECTORS: u8 = 0x30;
+    pub const WRITE_SECTORS_EXT: u8 = 0x34; // 48-bit LBA
     pub const PACKET: u8 = 0xA0;
     pub const SET_FEATURES: u8 = 0xEF;
     pub const FLUSH_CACHE: u8 = 0xE7;
 }
 
+/// SET FEATURES subcommands (features register)
+pub mod feature {
+    pub const SET_TRANSFER_MODE: u8 = 0x03;
+}
+
+/// SET TRANSFER MODE value for Ultra DMA (sector count register, plus the mode)
+const TRANSFER_MODE_UDMA: u8 = 0x40;
+
+/// SCSI commands carried in an ATAPI PACKET
+pub mod scsi {
+    pub const READ_12: u8 = 0xA8;
+}
+
+/// Sector size of CD/DVD media behind an ATAPI drive
+pub const ATAPI_SECTOR_SIZE: usize = 2048;
+
 // =============================================================================
 // Status Register Bits
 // =============================================================================
@@ -103,6 +132,9 @@ pub const DRIVE_MASTER_LBA: u8 = 0xE0;
 /// Select slave drive with LBA mode
 pub const DRIVE_SLAVE_LBA: u8 = 0xF0;
 
+/// Device control: drive interrupts off (nIEN)
+const CONTROL_NIEN: u8 = 0x02;
+
 // =============================================================================
 // Timeouts (in loop iterations, roughly ~1ms per 1000 iterations)
 // =============================================================================
@@ -110,6 +142,7 @@ pub const DRIVE_SLAVE_LBA: u8 = 0xF0;
 const TIMEOUT_BSY: u32 = 100_000;      // Wait for not-busy
 const TIMEOUT_DRQ: u32 = 100_000;      // Wait for data ready
 const TIMEOUT_IDENTIFY: u32 = 500_000; // IDENTIFY can be slow
+const TIMEOUT_DMA: u32 = 5_000_000;    // Whole DMA command, seek included
 
 // =============================================================================
 // Channel Abstraction
@@ -146,6 +179,14 @@ impl Channel {
             Channel::Secondary => 15,
         }
     }
+
+    /// 0 for primary, 1 for secondary
+    pub fn index(&self) -> usize {
+        match self {
+            Channel::Primary => 0,
+            Channel::Secondary => 1,
+        }
+    }
 }
 
 // =============================================================================
@@ -202,6 +243,8 @@ pub struct AtaDevice {
     pub sectors: u64,           // Total sectors (LBA48 or LBA28)
     pub supports_lba48: bool,
     pub sector_size: u32,       // Usually 512
+    pub udma_modes: u8,         // Supported Ultra DMA modes (bit N = mode N)
+    pub udma_mode: Option<u8>,  // Mode set by `enable_dma`, if any
 }
 
 impl AtaDevice {
@@ -216,6 +259,8 @@ impl AtaDevice {
             sectors: 0,
             supports_lba48: false,
             sector_size: 512,
+            udma_modes: 0,
+            udma_mode: None,
         }
     }
 
@@ -241,7 +286,7 @@ impl AtaDevice {
 // =============================================================================
 
 /// Maximum devices (2 channels × 2 drives)
-const MAX_DEVICES: usize = 4;
+pub const MAX_DEVICES: usize = 4;
 
 /// Detected ATA devices
 static mut ATA_DEVICES: [AtaDevice; MAX_DEVICES] = [
@@ -254,6 +299,9 @@ static mut ATA_DEVICES: [AtaDevice; MAX_DEVICES] = [
 /// Number of detected devices
 static mut ATA_DEVICE_COUNT: usize = 0;
 
+/// Channels put back on PIO after a DMA command failed
+static mut PIO_ONLY: [bool; 2] = [false; 2];
+
 // =============================================================================
 // Low-Level I/O
 // =============================================================================
@@ -639,6 +687,11 @@ fn parse_identify_data(device: &mut AtaDevice, data: &[u16; 256]) {
     // Word 83 bit 10: LBA48 supported
     device.supports_lba48 = (data[83] & (1 << 10)) != 0;
 
+    // Word 88 low byte: supported Ultra DMA modes (valid if word 53 bit 2)
+    if (data[53] & (1 << 2)) != 0 {
+        device.udma_modes = (data[88] & 0xFF) as u8;
+    }
+
     if device.device_type == DeviceType::Ata {
         if device.supports_lba48 {
             // Words 100-103: LBA48 sector count
@@ -976,6 +1029,15 @@ pub fn read_sectors(
         return Err("LBA out of range");
     }
 
+    // Ultra DMA straight into the buffer, if it can take it
+    let len = actual_count * sector_size;
+    if uses_dma(device) && busmaster::prepare(device.channel, buffer.as_mut_ptr(), len, true).is_ok() {
+        match dma_command(device, lba, count, cmd::READ_DMA, cmd::READ_DMA_EXT) {
+            Ok(()) => return Ok(actual_count),
+            Err(_) => fall_back_to_pio(device.channel),
+        }
+    }
+
     let base = device.channel.base_port();
 
     // Select drive and set up LBA
@@ -1042,6 +1104,385 @@ pub fn read_sectors(
     Ok(actual_count)
 }
 
+// =============================================================================
+// Sector Writing
+// =============================================================================
+
+/// Write sectors to an ATA device and flush its cache
+///
+/// - `device`: Device to write to
+/// - `lba`: Starting logical block address
+/// - `count`: Number of sectors to write (1-256, 0 means 256)
+/// - `buffer`: Data to write (must be count * sector_size bytes)
+///
+/// Returns number of sectors written, or error message
+pub fn write_sectors(
+    device: &AtaDevice,
+    lba: u64,
+    count: u8,
+    buffer: &[u8]
+) -> Result<usize, &'static str> {
+    if device.device_type != DeviceType::Ata {
+        return Err("Not an ATA device");
+    }
+
+    let sector_size = device.sector_size as usize;
+    let actual_count = if count == 0 { 256 } else { count as usize };
+
+    if buffer.len() < actual_count * sector_size {
+        return Err("Buffer too small");
+    }
+
+    // Check LBA range
+    let end = lba + actual_count as u64;
+    if end > device.sectors {
+        return Err("LBA out of range");
+    }
+    if !device.supports_lba48 && end > 0x0FFFFFFF {
+        return Err("LBA beyond 28 bits");
+    }
+
+    // Ultra DMA when the disk uses it; PIO below if not or if it failed
+    if let Some(written) = write_sectors_dma(device, lba, count, buffer) {
+        return Ok(written);
+    }
+
+    let base = device.channel.base_port();
+
+    // Select drive and set up LBA
+    if !select_drive(device.channel, device.drive) {
+        return Err("Drive select timeout");
+    }
+
+    // Wait for drive ready
+    if !wait_ready(device.channel, TIMEOUT_BSY) {
+        return Err("Drive not ready");
+    }
+
+    unsafe {
+        if end > 0x0FFFFFFF {
+            // LBA48 mode
+            outb(base + 2, 0);                          // Sector count high
+            outb(base + 3, ((lba >> 24) & 0xFF) as u8); // LBA 24-31
+            outb(base + 4, ((lba >> 32) & 0xFF) as u8); // LBA 32-39
+            outb(base + 5, ((lba >> 40) & 0xFF) as u8); // LBA 40-47
+            outb(base + 2, count);                       // Sector count low
+            outb(base + 3, (lba & 0xFF) as u8);         // LBA 0-7
+            outb(base + 4, ((lba >> 8) & 0xFF) as u8);  // LBA 8-15
+            outb(base + 5, ((lba >> 16) & 0xFF) as u8); // LBA 16-23
+            outb(base + 7, cmd::WRITE_SECTORS_EXT);
+        } else {
+            // LBA28 mode
+            let drive_byte = device.drive.select_byte() | ((lba >> 24) & 0x0F) as u8;
+            outb(base + 6, drive_byte);
+            outb(base + 2, count);
+            outb(base + 3, (lba & 0xFF) as u8);
+            outb(base + 4, ((lba >> 8) & 0xFF) as u8);
+            outb(base + 5, ((lba >> 16) & 0xFF) as u8);
+            outb(base + 7, cmd::WRITE_SECTORS);
+        }
+    }
+
+    // Write each sector
+    let mut offset = 0;
+    for _ in 0..actual_count {
+        match wait_drq(device.channel, TIMEOUT_DRQ) {
+            Ok(()) => {}
+            Err(0xFE) => return Err("Timeout waiting for DRQ"),
+            Err(0xFF) => return Err("Drive fault"),
+            Err(_) => return Err("Write error"),
+        }
+
+        // Write sector data (256 words = 512 bytes)
+        let words = sector_size / 2;
+        for _ in 0..words {
+            let word = (buffer[offset] as u16) | ((buffer[offset + 1] as u16) << 8);
+            unsafe { outw(base, word); }
+            offset += 2;
+        }
+
+        // Small delay between sectors
+        io_delay(device.channel);
+    }
+
+    // Flush cache
+    if !wait_not_busy(device.channel, TIMEOUT_BSY) {
+        return Err("Drive not ready");
+    }
+    unsafe {
+        outb(base + 7, cmd::FLUSH_CACHE);
+    }
+    io_delay(device.channel);
+
+    // Wait for completion
+    if !wait_ready(device.channel, TIMEOUT_IDENTIFY) {
+        return Err("Flush timeout");
+    }
+
+    Ok(actual_count)
+}
+
+// =============================================================================
+// Ultra DMA
+// =============================================================================
+
+/// Switch every disk that supports Ultra DMA to the fastest mode the
+/// controller runs; returns how many were switched
+///
+/// Call after `init` and a successful `busmaster::init`.
+pub fn enable_dma() -> usize {
+    if !busmaster::supports_udma() {
+        return 0;
+    }
+
+    let devices = unsafe { &mut *addr_of_mut!(ATA_DEVICES) };
+    let mut enabled = 0;
+    for device in devices.iter_mut().filter(|d| d.device_type == DeviceType::Ata) {
+        let usable = device.udma_modes & ((1 << (busmaster::MAX_UDMA_MODE + 1)) - 1);
+        if usable == 0 {
+            continue;
+        }
+        let mode = 7 - usable.leading_zeros() as u8;
+        if set_transfer_mode(device, TRANSFER_MODE_UDMA | mode) {
+            busmaster::set_udma_mode(device.channel, device.drive, mode);
+            device.udma_mode = Some(mode);
+            enabled += 1;
+        }
+    }
+    enabled
+}
+
+/// SET FEATURES / SET TRANSFER MODE; true if the drive accepted `mode`
+fn set_transfer_mode(device: &AtaDevice, mode: u8) -> bool {
+    let base = device.channel.base_port();
+
+    if !select_drive(device.channel, device.drive) || !wait_ready(device.channel, TIMEOUT_BSY) {
+        return false;
+    }
+
+    unsafe {
+        outb(base + 1, feature::SET_TRANSFER_MODE);
+        outb(base + 2, mode);
+        outb(base + 7, cmd::SET_FEATURES);
+    }
+    io_delay(device.channel);
+
+    wait_not_busy(device.channel, TIMEOUT_IDENTIFY)
+        && read_status(device.channel) & (status::ERR | status::DF) == 0
+}
+
+/// True if transfers for `device` should try DMA
+fn uses_dma(device: &AtaDevice) -> bool {
+    device.device_type == DeviceType::Ata
+        && device.udma_mode.is_some()
+        && !unsafe { (*addr_of_mut!(PIO_ONLY))[device.channel.index()] }
+}
+
+/// After a failed DMA command: reset the channel and keep it on PIO
+fn fall_back_to_pio(channel: Channel) {
+    unsafe { (*addr_of_mut!(PIO_ONLY))[channel.index()] = true; }
+    reset_channel(channel);
+}
+
+/// Issue a DMA read or write for a transfer `busmaster::prepare`d on the
+/// device's channel and wait for it to complete
+///
+/// The wait is a poll, as for PIO; the drive's interrupt only ends it
+/// early.
+fn dma_command(device: &AtaDevice, lba: u64, count: u8, lba28_cmd: u8, lba48_cmd: u8) -> Result<(), &'static str> {
+    let channel = device.channel;
+    let base = channel.base_port();
+
+    if !select_drive(channel, device.drive) {
+        return Err("Drive select timeout");
+    }
+    if !wait_ready(channel, TIMEOUT_BSY) {
+        return Err("Drive not ready");
+    }
+
+    // Completion is signalled on the drive's interrupt line (the bus-master
+    // status only latches it while nIEN is clear)
+    write_control(channel, 0);
+
+    unsafe {
+        let end = lba + if count == 0 { 256 } else { count as u64 };
+        if device.supports_lba48 && end > 0x0FFFFFFF {
+            outb(base + 6, device.drive.select_byte());
+            outb(base + 2, 0);                          // Sector count high
+            outb(base + 3, ((lba >> 24) & 0xFF) as u8); // LBA 24-31
+            outb(base + 4, ((lba >> 32) & 0xFF) as u8); // LBA 32-39
+            outb(base + 5, ((lba >> 40) & 0xFF) as u8); // LBA 40-47
+            outb(base + 2, count);                       // Sector count low
+            outb(base + 3, (lba & 0xFF) as u8);         // LBA 0-7
+            outb(base + 4, ((lba >> 8) & 0xFF) as u8);  // LBA 8-15
+            outb(base + 5, ((lba >> 16) & 0xFF) as u8); // LBA 16-23
+            outb(base + 7, lba48_cmd);
+        } else {
+            let drive_byte = device.drive.select_byte() | ((lba >> 24) & 0x0F) as u8;
+            outb(base + 6, drive_byte);
+            outb(base + 2, count);
+            outb(base + 3, (lba & 0xFF) as u8);
+            outb(base + 4, ((lba >> 8) & 0xFF) as u8);
+            outb(base + 5, ((lba >> 16) & 0xFF) as u8);
+            outb(base + 7, lba28_cmd);
+        }
+    }
+    busmaster::start(channel);
+
+    let transferred = busmaster::finish(channel, TIMEOUT_DMA);
+    let settled = wait_not_busy(channel, TIMEOUT_BSY);
+    let status = read_status(channel);
+    write_control(channel, CONTROL_NIEN);
+
+    transferred?;
+    if !settled {
+        return Err("Drive not ready");
+    }
+    if status & (status::ERR | status::DF) != 0 {
+        return Err("DMA command failed");
+    }
+    Ok(())
+}
+
+/// Write sectors with Ultra DMA and flush the drive's cache
+///
+/// `None` if the device isn't using DMA, the buffer can't be used for it,
+/// or the command failed (which puts the channel on PIO): `write_sectors`
+/// writes it with PIO instead.
+fn write_sectors_dma(
+    device: &AtaDevice,
+    lba: u64,
+    count: u8,
+    buffer: &[u8]
+) -> Option<usize> {
+    let actual_count = if count == 0 { 256 } else { count as usize };
+    let len = actual_count * device.sector_size as usize;
+    if !uses_dma(device) || buffer.len() < len || lba >= device.sectors {
+        return None;
+    }
+    busmaster::prepare(device.channel, buffer.as_ptr(), len, false).ok()?;
+
+    let written = dma_command(device, lba, count, cmd::WRITE_DMA, cmd::WRITE_DMA_EXT).and_then(|()| {
+        unsafe { outb(device.channel.base_port() + 7, cmd::FLUSH_CACHE); }
+        if wait_ready(device.channel, TIMEOUT_IDENTIFY) { Ok(()) } else { Err("Flush timeout") }
+    });
+    match written {
+        Ok(()) => Some(actual_count),
+        Err(_) => {
+            fall_back_to_pio(device.channel);
+            None
+        }
+    }
+}
+
+/// Read 2048-byte sectors from an ATAPI drive using PACKET + READ(12)
+///
+/// A drive that just had its media inserted (or was reset) fails the first
+/// command with a UNIT ATTENTION check condition, so each transfer is retried
+/// a few times before giving up.
+pub fn read_atapi_sectors(
+    device: &AtaDevice,
+    lba: u32,
+    count: u8,
+    buffer: &mut [u8]
+) -> Result<usize, &'static str> {
+    if device.device_type != DeviceType::Atapi {
+        return Err("Not an ATAPI device");
+    }
+    if count == 0 {
+        return Ok(0);
+    }
+    if buffer.len() < count as usize * ATAPI_SECTOR_SIZE {
+        return Err("Buffer too small");
+    }
+
+    let mut result = Err("Read error");
+    for _ in 0..3 {
+        result = atapi_read_12(device, lba, count, buffer);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// Issue a single READ(12) packet and transfer the data in PIO mode
+fn atapi_read_12(
+    device: &AtaDevice,
+    lba: u32,
+    count: u8,
+    buffer: &mut [u8]
+) -> Result<usize, &'static str> {
+    let base = device.channel.base_port();
+
+    if !select_drive(device.channel, device.drive) {
+        return Err("Drive select timeout");
+    }
+    if !wait_not_busy(device.channel, TIMEOUT_BSY) {
+        return Err("Drive not ready");
+    }
+
+    unsafe {
+        outb(base + 1, 0);                                      // PIO, no overlap
+        outb(base + 4, (ATAPI_SECTOR_SIZE & 0xFF) as u8);       // Byte count limit low
+        outb(base + 5, (ATAPI_SECTOR_SIZE >> 8) as u8);         // Byte count limit high
+        outb(base + 7, cmd::PACKET);
+    }
+    io_delay(device.channel);
+
+    // The drive asks for the 12-byte command packet
+    if wait_drq(device.channel, TIMEOUT_DRQ).is_err() {
+        return Err("Packet not accepted");
+    }
+
+    let lba = lba.to_be_bytes();
+    let packet: [u8; 12] = [
+        scsi::READ_12, 0,
+        lba[0], lba[1], lba[2], lba[3],
+        0, 0, 0, count,
+        0, 0,
+    ];
+    for pair in packet.chunks_exact(2) {
+        unsafe { outw(base, u16::from_le_bytes([pair[0], pair[1]])); }
+    }
+    io_delay(device.channel);
+
+    // Each DRQ block carries up to the byte count limit; the drive reports
+    // the actual size in the LBA mid/high registers
+    let total = count as usize * ATAPI_SECTOR_SIZE;
+    let mut offset = 0;
+    while offset < total {
+        match wait_drq(device.channel, TIMEOUT_IDENTIFY) {
+            Ok(()) => {}
+            Err(0xFE) => return Err("Timeout waiting for data"),
+            Err(0xFF) => return Err("Drive fault"),
+            Err(_) => return Err("Read error"),
+        }
+
+        let bytes = unsafe { inb(base + 4) as usize | ((inb(base + 5) as usize) << 8) };
+        if bytes == 0 || offset + bytes > total {
+            return Err("Bad transfer size");
+        }
+
+        for _ in 0..bytes / 2 {
+            let word = unsafe { inw(base) };
+            buffer[offset] = (word & 0xFF) as u8;
+            buffer[offset + 1] = (word >> 8) as u8;
+            offset += 2;
+        }
+    }
+
+    // Command completes once the drive drops BSY
+    if !wait_not_busy(device.channel, TIMEOUT_BSY) {
+        return Err("Drive not ready");
+    }
+    if read_status(device.channel) & status::ERR != 0 {
+        return Err("Read error");
+    }
+
+    Ok(count as usize)
+}
+
 // =============================================================================
 // Debug Helper
 // =============================================================================
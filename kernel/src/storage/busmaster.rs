@@ -0,0 +1,284 @@
+//! Bus-Master IDE DMA
+//!
+//! The PIIX4 IDE function moves sector data between the drive and memory
+//! on its own: the driver points it at a table of physical regions (PRD
+//! table), starts it, and the drive raises IRQ 14/15 when the command is
+//! done. `storage::ata` issues the READ/WRITE DMA commands around this.
+//!
+//! - Registers come from BAR4: 8 I/O ports per channel (command, status,
+//!   PRD table address)
+//! - Memory is identity mapped, so a buffer's address is its physical one
+//! - A PRD region can't cross a 64 KB boundary; buffers are split at them
+//! - Ultra DMA timing is programmed on the PIIX4 only (modes 0-2, 33 MB/s);
+//!   other controllers stay on PIO
+
+use core::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+use crate::arch::x86::io::{inb, outb, outl};
+use crate::arch::x86::pic;
+use super::ata::{Channel, Drive};
+use super::pci::{self, PciDevice, INTEL_VENDOR, PIIX4_IDE};
+
+// =============================================================================
+// Registers
+// =============================================================================
+
+/// Register offsets from a channel's bus-master base
+mod reg {
+    pub const COMMAND: u16 = 0;
+    pub const STATUS: u16 = 2;
+    pub const PRD_TABLE: u16 = 4;
+}
+
+/// Bus-master command register bits
+mod command {
+    pub const START: u8 = 0x01;
+    pub const TO_MEMORY: u8 = 0x08;   // Direction: drive -> memory (a read)
+}
+
+/// Bus-master status register bits
+mod status {
+    pub const ACTIVE: u8 = 0x01;
+    pub const ERROR: u8 = 0x02;       // Write 1 to clear
+    pub const IRQ: u8 = 0x04;         // Write 1 to clear
+    pub const DRIVE0_DMA: u8 = 0x20;  // Set by software: master is DMA capable
+    pub const DRIVE1_DMA: u8 = 0x40;  // Set by software: slave is DMA capable
+}
+
+/// PIIX4 PCI config registers for Ultra DMA
+mod piix4 {
+    /// One enable bit per drive (primary master = bit 0)
+    pub const UDMACTL: u8 = 0x48;
+    /// Two mode bits per drive, a nibble apart (primary master = bits 0-1)
+    pub const UDMATIM: u8 = 0x4A;
+}
+
+/// Highest Ultra DMA mode the PIIX4 runs (UDMA/33)
+pub const MAX_UDMA_MODE: u8 = 2;
+
+// =============================================================================
+// PRD Tables
+// =============================================================================
+
+/// Physical region descriptor
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Prd {
+    /// Physical address (even)
+    address: u32,
+    /// Byte count (even); 0 means 64 KB
+    bytes: u16,
+    /// Bit 15 marks the last entry
+    flags: u16,
+}
+
+const PRD_END_OF_TABLE: u16 = 0x8000;
+
+/// Regions per transfer; a 128 KB buffer needs at most 3
+const MAX_PRDS: usize = 8;
+
+/// One table per channel, aligned so it can't cross 64 KB
+#[repr(C, align(64))]
+struct PrdTable([Prd; MAX_PRDS]);
+
+const EMPTY_PRD: Prd = Prd { address: 0, bytes: 0, flags: 0 };
+static mut PRD_TABLES: [PrdTable; 2] = [PrdTable([EMPTY_PRD; MAX_PRDS]), PrdTable([EMPTY_PRD; MAX_PRDS])];
+
+// =============================================================================
+// State
+// =============================================================================
+
+/// Bus-master I/O base from BAR4 (0 until `init` finds one)
+static mut BUS_MASTER_BASE: u16 = 0;
+
+/// PIIX4 config address, for the Ultra DMA timing registers
+static mut PIIX4: Option<PciDevice> = None;
+
+/// Set by the IRQ handler when a channel's command completes
+static IRQ_SEEN: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+/// Bus-master register base for `channel`, if DMA is available
+fn channel_base(channel: Channel) -> Option<u16> {
+    let base = unsafe { BUS_MASTER_BASE };
+    if base == 0 {
+        return None;
+    }
+    Some(base + channel.index() as u16 * 8)
+}
+
+/// Find the controller's bus-master registers and enable bus mastering;
+/// false if the controller can't do DMA
+pub fn init(controller: &PciDevice) -> bool {
+    let (bus, dev, func) = (controller.bus, controller.device, controller.function);
+
+    // Bus mastering is only defined for IDE controllers that say so in
+    // their programming interface (bit 7)
+    if controller.prog_if & 0x80 == 0 {
+        return false;
+    }
+
+    let bar4 = pci::config_read32(bus, dev, func, pci::reg::BAR4);
+    if bar4 & 1 == 0 || bar4 & 0xFFF0 == 0 {
+        return false;
+    }
+
+    let command = pci::config_read16(bus, dev, func, pci::reg::COMMAND);
+    pci::config_write16(bus, dev, func, pci::reg::COMMAND, command | pci::cmd::IO_SPACE | pci::cmd::BUS_MASTER);
+
+    unsafe {
+        BUS_MASTER_BASE = (bar4 & 0xFFF0) as u16;
+        if controller.vendor_id == INTEL_VENDOR && controller.device_id == PIIX4_IDE {
+            PIIX4 = Some(*controller);
+        }
+    }
+
+    for channel in [Channel::Primary, Channel::Secondary] {
+        if let Some(base) = channel_base(channel) {
+            unsafe {
+                outb(base + reg::COMMAND, 0);
+                outb(base + reg::STATUS, status::ERROR | status::IRQ);
+            }
+        }
+    }
+    true
+}
+
+/// True if `init` set up bus-master DMA
+pub fn is_available() -> bool {
+    unsafe { BUS_MASTER_BASE != 0 }
+}
+
+/// True if the controller's Ultra DMA timing can be set (PIIX4 only)
+pub fn supports_udma() -> bool {
+    is_available() && unsafe { (*core::ptr::addr_of!(PIIX4)).is_some() }
+}
+
+/// Program the controller for Ultra DMA `mode` on `drive` (after the drive
+/// accepted the same mode through SET FEATURES) and unmask the channel's
+/// IRQ
+pub fn set_udma_mode(channel: Channel, drive: Drive, mode: u8) {
+    let Some(piix4) = (unsafe { *core::ptr::addr_of!(PIIX4) }) else { return };
+    let Some(base) = channel_base(channel) else { return };
+    let slot = channel.index() * 2 + if drive == Drive::Slave { 1 } else { 0 };
+    let (bus, dev, func) = (piix4.bus, piix4.device, piix4.function);
+
+    let timing = pci::config_read16(bus, dev, func, piix4::UDMATIM) & !(0x3 << (slot * 4));
+    pci::config_write16(bus, dev, func, piix4::UDMATIM, timing | ((mode.min(MAX_UDMA_MODE) as u16) << (slot * 4)));
+    let enable = pci::config_read8(bus, dev, func, piix4::UDMACTL);
+    pci::config_write8(bus, dev, func, piix4::UDMACTL, enable | (1 << slot));
+
+    // Record the drive as DMA capable; the interrupt and error bits are
+    // written as 0 so they're left alone
+    let capable = if drive == Drive::Slave { status::DRIVE1_DMA } else { status::DRIVE0_DMA };
+    unsafe {
+        let current = inb(base + reg::STATUS) & (status::DRIVE0_DMA | status::DRIVE1_DMA);
+        outb(base + reg::STATUS, current | capable);
+    }
+    pic::enable_irq(channel.irq());
+}
+
+// =============================================================================
+// Transfers
+// =============================================================================
+
+/// Build `channel`'s PRD table for `len` bytes at `buffer` and load it,
+/// ready for a read (`to_memory`) or write command
+///
+/// The buffer must be at an even address, `len` even and nonzero.
+pub fn prepare(channel: Channel, buffer: *const u8, len: usize, to_memory: bool) -> Result<(), &'static str> {
+    let base = channel_base(channel).ok_or("DMA unavailable")?;
+    let address = buffer as usize;
+    if address % 2 != 0 || len % 2 != 0 || len == 0 {
+        return Err("Buffer not aligned");
+    }
+    if address.checked_add(len).is_none_or(|end| end > u32::MAX as usize) {
+        return Err("Buffer out of reach");
+    }
+
+    let table = unsafe { &mut (*core::ptr::addr_of_mut!(PRD_TABLES))[channel.index()] };
+    let mut region = address;
+    let end = address + len;
+    let mut count = 0;
+    while region < end {
+        if count == MAX_PRDS {
+            return Err("Buffer too fragmented");
+        }
+        // Up to the next 64 KB boundary
+        let boundary = (region & !0xFFFF) + 0x10000;
+        let bytes = boundary.min(end) - region;
+        table.0[count] = Prd { address: region as u32, bytes: (bytes & 0xFFFF) as u16, flags: 0 };
+        region += bytes;
+        count += 1;
+    }
+    table.0[count - 1].flags = PRD_END_OF_TABLE;
+
+    IRQ_SEEN[channel.index()].store(false, Ordering::SeqCst);
+    compiler_fence(Ordering::SeqCst);
+    unsafe {
+        outb(base + reg::COMMAND, 0);
+        outl(base + reg::PRD_TABLE, table.0.as_ptr() as u32);
+        outb(base + reg::COMMAND, if to_memory { command::TO_MEMORY } else { 0 });
+        let capable = inb(base + reg::STATUS) & (status::DRIVE0_DMA | status::DRIVE1_DMA);
+        outb(base + reg::STATUS, capable | status::ERROR | status::IRQ);
+    }
+    Ok(())
+}
+
+/// Start the prepared transfer (after the drive got its DMA command)
+pub fn start(channel: Channel) {
+    if let Some(base) = channel_base(channel) {
+        unsafe {
+            let command = inb(base + reg::COMMAND);
+            outb(base + reg::COMMAND, command | command::START);
+        }
+    }
+}
+
+/// Wait up to `timeout` polls for the transfer to finish, then stop the
+/// engine
+///
+/// This busy-waits: transfers are synchronous. Completion is the IRQ
+/// handler's flag, or the controller's interrupt bit when IRQs are masked
+/// or disabled.
+pub fn finish(channel: Channel, timeout: u32) -> Result<(), &'static str> {
+    let base = channel_base(channel).ok_or("DMA unavailable")?;
+    let irq_seen = &IRQ_SEEN[channel.index()];
+
+    let mut result = Err("DMA timeout");
+    for _ in 0..timeout {
+        let status = unsafe { inb(base + reg::STATUS) };
+        if status & status::ERROR != 0 {
+            result = Err("DMA error");
+            break;
+        }
+        if irq_seen.load(Ordering::SeqCst) || status & status::IRQ != 0 {
+            // Still active means the drive stopped short of the PRD table
+            let status = unsafe { inb(base + reg::STATUS) };
+            result = if status & status::ACTIVE != 0 { Err("DMA short transfer") } else { Ok(()) };
+            break;
+        }
+        core::hint::spin_loop();
+    }
+
+    unsafe {
+        let command = inb(base + reg::COMMAND);
+        outb(base + reg::COMMAND, command & !command::START);
+        let capable = inb(base + reg::STATUS) & (status::DRIVE0_DMA | status::DRIVE1_DMA);
+        outb(base + reg::STATUS, capable | status::ERROR | status::IRQ);
+    }
+    compiler_fence(Ordering::SeqCst);
+    result
+}
+
+/// IRQ 14/15 handler body: note a finished bus-master command and
+/// acknowledge the drive (EOI is the caller's)
+pub fn handle_irq(channel: Channel) {
+    if let Some(base) = channel_base(channel) {
+        let status = unsafe { inb(base + reg::STATUS) };
+        if status & status::IRQ != 0 {
+            IRQ_SEEN[channel.index()].store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Reading the drive's status register drops its interrupt line
+    unsafe { inb(channel.base_port() + 7); }
+}
@@ -1,6 +1,27 @@
 //! FAT32 Filesystem Driver - Clean Version
 //!
-//! Provides read-only FAT32 filesystem support for loading ROM files.
+//! Provides FAT32 filesystem support for walking directories and loading
+//! ROM files, and for writing files: creating them whole, writing at any
+//! offset, overwriting, truncating, extending and deleting them.
+//!
+//! FAT12 (floppies) and FAT16 (CF and small SD cards) volumes are handled
+//! too. The type comes from the cluster count, as the Microsoft spec
+//! says, never from the label in the boot sector. Their root directory is
+//! a fixed region before the data area rather than a cluster chain; it is
+//! addressed as directory cluster 0, which is what `..` entries use for
+//! the root anyway.
+//!
+//! Writes keep the volume consistent for host tools (`fsck.vfat`):
+//! - Cluster allocation goes to every copy of the FAT
+//! - The FSInfo free cluster count and next-free hint are kept up to date
+//!   (a count the volume marks as unknown stays unknown)
+//! - File data is written before the FAT and the FAT before the directory
+//!   entry, so an interrupted write can only lose clusters
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use crate::storage::partition::BlockDevice;
 
 // =============================================================================
 // Constants
@@ -9,107 +30,251 @@
 const SECTOR_SIZE: usize = 512;
 const FIRST_DATA_CLUSTER: u32 = 2;
 
+/// FAT entry values (low 28 bits; FAT12/16 keep the low 12/16 bits of
+/// `FAT_EOC`, which are their own end-of-chain values)
+const FAT_FREE: u32 = 0;
+const FAT_EOC: u32 = 0x0FFFFFFF;
+
+/// Volumes with fewer clusters than these are FAT12, then FAT16
+const FAT12_MAX_CLUSTERS: u32 = 4085;
+const FAT16_MAX_CLUSTERS: u32 = 65525;
+
+/// Directory entry size, first-byte marker of a deleted entry, attributes
+const DIR_ENTRY_SIZE: usize = 32;
+const DELETED_ENTRY: u8 = 0xE5;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_VOLUME: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// Attribute combination marking a long file name entry
+const ATTR_LFN: u8 = 0x0F;
+
+/// FSInfo sector signatures, and the "not known" free count / hint
+const FSINFO_LEAD_SIG: u32 = 0x41615252;
+const FSINFO_STRUCT_SIG: u32 = 0x61417272;
+const FSINFO_TRAIL_SIG: u32 = 0xAA550000;
+const FSINFO_UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// 1980-01-01, the earliest FAT date (there is no clock to stamp files)
+const FAT_EPOCH_DATE: u16 = (1 << 5) | 1;
+
 // =============================================================================
 // FAT32 Filesystem
 // =============================================================================
 
-/// FAT32 filesystem state
+/// FAT variant, by the width of a FAT entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Lower-case name (`"fat16"`)
+    pub fn name(self) -> &'static str {
+        match self {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    /// Bits per FAT entry
+    fn entry_bits(self) -> u32 {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+}
+
+/// FAT filesystem state
 pub struct Fat32 {
-    device_index: usize,
+    /// The volume; all sector numbers below are within it
+    volume: BlockDevice,
     mounted: bool,
-    partition_start: u32,
+    fat_type: FatType,
     bytes_per_sector: u32,
     sectors_per_cluster: u32,
     fat_start_sector: u32,
     sectors_per_fat: u32,
     data_start_sector: u32,
+    /// First cluster of the root directory (0 on FAT12/16, whose root is
+    /// the fixed region below)
     root_cluster: u32,
+    /// Fixed root directory region of FAT12/16: first sector and length
+    /// (0 sectors on FAT32)
+    root_dir_sector: u32,
+    root_dir_sectors: u32,
+    num_fats: u32,
+    /// Data clusters (valid cluster numbers are 2..cluster_count + 2)
+    cluster_count: u32,
+    /// FSInfo sector LBA (0 if the volume has none)
+    fsinfo_sector: u32,
+    /// Free clusters (`FSINFO_UNKNOWN` if not known)
+    free_count: u32,
+    /// Where to start looking for a free cluster
+    next_free: u32,
 }
 
 impl Fat32 {
     pub const fn new() -> Self {
         Self {
-            device_index: 0,
+            volume: BlockDevice::new(0, 0, 0),
             mounted: false,
-            partition_start: 0,
+            fat_type: FatType::Fat32,
             bytes_per_sector: 512,
             sectors_per_cluster: 1,
             fat_start_sector: 0,
             sectors_per_fat: 0,
             data_start_sector: 0,
             root_cluster: 0,
+            root_dir_sector: 0,
+            root_dir_sectors: 0,
+            num_fats: 0,
+            cluster_count: 0,
+            fsinfo_sector: 0,
+            free_count: FSINFO_UNKNOWN,
+            next_free: FIRST_DATA_CLUSTER,
         }
     }
 
-    /// Mount FAT32 filesystem
-    pub fn mount(&mut self, device_index: usize) -> Result<(), &'static str> {
-        self.device_index = device_index;
-
-        let device = crate::storage::ata::get_device(device_index)
-            .ok_or("Device not found")?;
+    /// Mount the FAT12, FAT16 or FAT32 volume `volume` (a partition, or
+    /// a whole disk without a partition table)
+    pub fn mount(&mut self, volume: BlockDevice) -> Result<(), &'static str> {
+        self.mounted = false;
+        self.volume = volume;
 
-        // Read sector 0
+        // Read the boot sector
         let mut sector = [0u8; SECTOR_SIZE];
-        crate::storage::ata::read_sectors(device, 0, 1, &mut sector)
-            .map_err(|_| "Failed to read sector 0")?;
+        self.read_sector(0, &mut sector)
+            .map_err(|_| "Failed to read boot sector")?;
+
+        let fsinfo_sector = self.parse_bpb(&sector, volume.sectors())?;
+        self.load_fsinfo(fsinfo_sector);
+
+        self.mounted = true;
+        Ok(())
+    }
 
+    /// Take the volume layout and FAT type from the boot sector of a
+    /// volume `volume_sectors` long; returns the FSInfo sector (0 if none)
+    fn parse_bpb(&mut self, sector: &[u8; SECTOR_SIZE], volume_sectors: u64) -> Result<u16, &'static str> {
         // Check signature
         if sector[510] != 0x55 || sector[511] != 0xAA {
             return Err("Invalid signature");
         }
 
-        // Check if MBR or VBR
-        let potential_bps = u16::from_le_bytes([sector[11], sector[12]]);
-        let is_valid_bps = potential_bps == 512 || potential_bps == 1024 ||
-            potential_bps == 2048 || potential_bps == 4096;
-        let part1_type = sector[446 + 4];
-        let has_fat32_partition = part1_type == 0x0B || part1_type == 0x0C;
-
-        // If MBR with FAT32 partition, read VBR
-        if has_fat32_partition && !is_valid_bps {
-            self.partition_start = u32::from_le_bytes([
-                sector[446 + 8], sector[446 + 9],
-                sector[446 + 10], sector[446 + 11],
-            ]);
-
-            crate::storage::ata::read_sectors(device, self.partition_start as u64, 1, &mut sector)
-                .map_err(|_| "Failed to read VBR")?;
-
-            if sector[510] != 0x55 || sector[511] != 0xAA {
-                return Err("Invalid VBR signature");
-            }
-        } else {
-            self.partition_start = 0;
-        }
-
         // Parse BPB
         let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]);
         let sectors_per_cluster = sector[13];
         let reserved_sectors = u16::from_le_bytes([sector[14], sector[15]]);
         let num_fats = sector[16];
-        let sectors_per_fat_32 = u32::from_le_bytes([sector[36], sector[37], sector[38], sector[39]]);
-        let root_cluster = u32::from_le_bytes([sector[44], sector[45], sector[46], sector[47]]);
+        let root_entries = u16::from_le_bytes([sector[17], sector[18]]);
+        let sectors_per_fat = match u16::from_le_bytes([sector[22], sector[23]]) {
+            0 => u32::from_le_bytes([sector[36], sector[37], sector[38], sector[39]]),
+            small => small as u32,
+        };
+        let total_sectors = match u16::from_le_bytes([sector[19], sector[20]]) {
+            0 => u32::from_le_bytes([sector[32], sector[33], sector[34], sector[35]]),
+            small => small as u32,
+        };
 
         // Validate
         if bytes_per_sector < 512 || bytes_per_sector > 4096 { return Err("Bad BPS"); }
         if sectors_per_cluster == 0 { return Err("Bad SPC"); }
         if num_fats == 0 { return Err("Bad FATs"); }
-        if root_cluster < 2 { return Err("Bad root"); }
+        if sectors_per_fat == 0 { return Err("Bad FAT size"); }
+
+        // Regions, and the FAT type from the data cluster count
+        let root_dir_sectors = (root_entries as u32 * DIR_ENTRY_SIZE as u32).div_ceil(bytes_per_sector as u32);
+        let fat_start_sector = reserved_sectors as u32;
+        let root_dir_sector = fat_start_sector + num_fats as u32 * sectors_per_fat;
+        let data_start_sector = root_dir_sector + root_dir_sectors;
+        // The type comes from the BPB's own size, as the spec has it: a
+        // partition cut short must not change how its FAT is read
+        let data_sectors = total_sectors.saturating_sub(data_start_sector);
+        let cluster_count = data_sectors / sectors_per_cluster as u32;
+        self.fat_type = if cluster_count < FAT12_MAX_CLUSTERS {
+            FatType::Fat12
+        } else if cluster_count < FAT16_MAX_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let mut fsinfo_sector = 0;
+        if self.fat_type == FatType::Fat32 {
+            let root_cluster = u32::from_le_bytes([sector[44], sector[45], sector[46], sector[47]]);
+            if root_entries != 0 { return Err("Bad root entries"); }
+            if root_cluster < 2 { return Err("Bad root"); }
+            self.root_cluster = root_cluster;
+            fsinfo_sector = u16::from_le_bytes([sector[48], sector[49]]);
+        } else {
+            if root_entries == 0 { return Err("Bad root entries"); }
+            self.root_cluster = 0;
+        }
 
         // Store parameters
         self.bytes_per_sector = bytes_per_sector as u32;
         self.sectors_per_cluster = sectors_per_cluster as u32;
-        self.fat_start_sector = self.partition_start + reserved_sectors as u32;
-        self.sectors_per_fat = sectors_per_fat_32;
-        self.root_cluster = root_cluster;
-        self.data_start_sector = self.fat_start_sector + (num_fats as u32 * sectors_per_fat_32);
+        self.fat_start_sector = fat_start_sector;
+        self.sectors_per_fat = sectors_per_fat;
+        self.root_dir_sector = root_dir_sector;
+        self.root_dir_sectors = root_dir_sectors;
+        self.data_start_sector = data_start_sector;
+        self.num_fats = num_fats as u32;
+        // Never use clusters past the end of the device, or more than the
+        // FAT itself can hold
+        let device_sectors = volume_sectors.min(u32::MAX as u64) as u32;
+        let device_clusters = device_sectors.saturating_sub(data_start_sector) / sectors_per_cluster as u32;
+        let fat_entries = sectors_per_fat * (SECTOR_SIZE as u32 * 8 / self.fat_type.entry_bits());
+        self.cluster_count = cluster_count
+            .min(device_clusters)
+            .min(fat_entries.saturating_sub(FIRST_DATA_CLUSTER));
+        if self.cluster_count == 0 { return Err("No data clusters"); }
+
+        Ok(fsinfo_sector)
+    }
 
-        self.mounted = true;
-        Ok(())
+    /// Take the free count and next-free hint from the FSInfo sector,
+    /// ignoring values that can't be right
+    fn load_fsinfo(&mut self, fsinfo_sector: u16) {
+        self.fsinfo_sector = 0;
+        self.free_count = FSINFO_UNKNOWN;
+        self.next_free = FIRST_DATA_CLUSTER;
+        if fsinfo_sector == 0 || fsinfo_sector == 0xFFFF {
+            return;
+        }
+
+        let mut sector = [0u8; SECTOR_SIZE];
+        if self.read_sector(fsinfo_sector as u64, &mut sector).is_err() || !fsinfo_valid(&sector) {
+            return;
+        }
+        self.fsinfo_sector = fsinfo_sector as u32;
+
+        let free_count = u32::from_le_bytes([sector[488], sector[489], sector[490], sector[491]]);
+        if free_count <= self.cluster_count {
+            self.free_count = free_count;
+        }
+        let next_free = u32::from_le_bytes([sector[492], sector[493], sector[494], sector[495]]);
+        if self.is_data_cluster(next_free) {
+            self.next_free = next_free;
+        }
     }
 
     pub fn is_mounted(&self) -> bool { self.mounted }
 
+    /// FAT variant of the mounted volume
+    pub fn fat_type(&self) -> FatType { self.fat_type }
+
+    /// Stop using the volume (nothing is cached, so nothing to write back)
+    pub fn unmount(&mut self) {
+        self.mounted = false;
+    }
+
     fn cluster_to_sector(&self, cluster: u32) -> u64 {
         let offset = cluster - FIRST_DATA_CLUSTER;
         (self.data_start_sector + offset * self.sectors_per_cluster) as u64
@@ -117,311 +282,1493 @@ impl Fat32 {
 
     /// Read a sector
     fn read_sector(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
-        let device = crate::storage::ata::get_device(self.device_index)
-            .ok_or("No device")?;
-        crate::storage::ata::read_sectors(device, lba, 1, buf)
-            .map_err(|_| "Read failed")?;
-        Ok(())
+        self.volume.read_sectors(lba, 1, buf)
+    }
+
+    /// List the directory starting at `cluster`
+    ///
+    /// Cluster 0 is the root directory, as in the `..` entry of a
+    /// directory directly below it.
+    pub fn read_dir(&self, cluster: u32) -> DirIter<'_> {
+        let cluster = if cluster == 0 { self.root_cluster } else { cluster };
+        let is_dir_start = self.is_data_cluster(cluster) || (cluster == 0 && self.root_dir_sectors != 0);
+        DirIter {
+            fs: self,
+            start: cluster,
+            cluster,
+            sector_index: 0,
+            entry_index: 0,
+            index: 0,
+            sector: [0; SECTOR_SIZE],
+            loaded: false,
+            done: !self.mounted || !is_dir_start,
+            error: None,
+            lfn: LfnBuilder::new(),
+        }
+    }
+
+    /// List the root directory
+    pub fn root_dir(&self) -> DirIter<'_> {
+        self.read_dir(self.root_cluster)
+    }
+
+    /// First cluster of the root directory (0 on FAT12/16)
+    pub fn root_cluster(&self) -> u32 {
+        self.root_cluster
     }
 
-    /// Find a .gb or .gbc file in root directory
+    /// Find a directory by path and return its first cluster
+    ///
+    /// `path` is `/`-separated and relative to the root (a leading `/` is
+    /// allowed); each part matches a long or 8.3 name, ignoring ASCII case.
+    /// An empty path is the root.
+    pub fn find_dir(&self, path: &[u8]) -> Result<u32, &'static str> {
+        if !self.mounted { return Err("Not mounted"); }
+
+        let mut cluster = self.root_cluster;
+        for part in path.split(|&c| c == b'/').filter(|part| !part.is_empty()) {
+            let mut entries = self.read_dir(cluster);
+            let Some(entry) = entries.find(|e| e.is_dir() && e.matches_name(part)) else {
+                return Err(entries.error().unwrap_or("Directory not found"));
+            };
+            cluster = if entry.first_cluster == 0 { self.root_cluster } else { entry.first_cluster };
+        }
+        Ok(cluster)
+    }
+
+    /// Find a file in the root directory by its 8.3 directory name
+    /// (`b"PALETTESTXT"`: name and extension space-padded, no dot)
     /// Returns (first_cluster, file_size) if found
-    pub fn find_rom(&self, index: usize) -> Option<(u32, u32)> {
-        if !self.mounted { return None; }
+    pub fn find_file(&self, name: &[u8; 11]) -> Option<(u32, u32)> {
+        self.find_file_in(self.root_cluster, name)
+    }
 
-        let mut sector = [0u8; SECTOR_SIZE];
-        let mut rom_index = 0usize;
-        let mut current_cluster = self.root_cluster;
+    /// Find a file in the directory at `dir` (as for `find_file`)
+    pub fn find_file_in(&self, dir: u32, name: &[u8; 11]) -> Option<(u32, u32)> {
+        let slot = self.find_slot(dir, name).ok()??;
+        Some((slot.first_cluster, slot.size))
+    }
+
+    /// Count ROM files in root directory
+    pub fn count_roms(&self) -> usize {
+        self.root_dir().filter(DirEntry::is_rom).count()
+    }
 
-        // Follow cluster chain for root directory
-        while current_cluster >= 2 && current_cluster < 0x0FFFFFF8 {
+    /// Read file data into buffer
+    /// Returns bytes read
+    pub fn read_file(&self, cluster: u32, size: u32, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        if !self.mounted { return Err("Not mounted"); }
+        if cluster < 2 { return Err("Invalid cluster"); }
+
+        let to_read = (size as usize).min(buffer.len());
+        let mut bytes_read = 0usize;
+        let mut current_cluster = cluster;
+
+        let mut sector_buf = [0u8; SECTOR_SIZE];
+
+        while bytes_read < to_read && self.is_data_cluster(current_cluster) {
             let cluster_lba = self.cluster_to_sector(current_cluster);
 
-            // Read each sector in this cluster
-            for sector_offset in 0..self.sectors_per_cluster {
-                if self.read_sector(cluster_lba + sector_offset as u64, &mut sector).is_err() {
-                    return None;
-                }
+            // Read each sector in cluster
+            for s in 0..self.sectors_per_cluster {
+                if bytes_read >= to_read { break; }
 
-                for i in 0..16 {  // 16 entries per sector
-                    let offset = i * 32;
-                    let first_byte = sector[offset];
-
-                    if first_byte == 0x00 { return None; }  // End of directory
-                    if first_byte == 0xE5 { continue; }  // Deleted
-
-                    let attr = sector[offset + 11];
-                    if attr == 0x0F { continue; }  // LFN entry
-                    if attr == 0x08 { continue; }  // Volume label
-                    if (attr & 0x10) != 0 { continue; }  // Directory
-
-                    // Check extension (offset 8-10) - case insensitive
-                    let ext0 = sector[offset + 8].to_ascii_uppercase();
-                    let ext1 = sector[offset + 9].to_ascii_uppercase();
-                    let ext2 = sector[offset + 10].to_ascii_uppercase();
-
-                    // Match .GB or .GBC
-                    let is_gb = ext0 == b'G' && ext1 == b'B' && (ext2 == b' ' || ext2 == b'C');
-
-                    if is_gb {
-                        if rom_index == index {
-                            let cluster_lo = u16::from_le_bytes([sector[offset + 26], sector[offset + 27]]);
-                            let cluster_hi = u16::from_le_bytes([sector[offset + 20], sector[offset + 21]]);
-                            let cluster = ((cluster_hi as u32) << 16) | (cluster_lo as u32);
-                            let size = u32::from_le_bytes([
-                                sector[offset + 28], sector[offset + 29],
-                                sector[offset + 30], sector[offset + 31],
-                            ]);
-                            return Some((cluster, size));
-                        }
-                        rom_index += 1;
-                    }
-                }
+                self.read_sector(cluster_lba + s as u64, &mut sector_buf)?;
+
+                let copy_len = (to_read - bytes_read).min(SECTOR_SIZE);
+                buffer[bytes_read..bytes_read + copy_len].copy_from_slice(&sector_buf[..copy_len]);
+                bytes_read += copy_len;
             }
 
-            // Get next cluster in chain
-            current_cluster = match self.get_next_cluster(current_cluster) {
-                Ok(next) => next,
-                Err(_) => break,
-            };
+            // Get next cluster from FAT
+            current_cluster = self.get_next_cluster(current_cluster)?;
         }
 
-        None
+        Ok(bytes_read)
     }
 
-    /// Get filename of ROM at index (8.3 format)
-    pub fn get_rom_name(&self, index: usize, name_buf: &mut [u8; 12]) -> bool {
-        if !self.mounted { return false; }
+    fn get_next_cluster(&self, cluster: u32) -> Result<u32, &'static str> {
+        let mut window = [0u8; 2 * SECTOR_SIZE];
+        let (fat_sector, at) = self.fat_position(cluster);
+        self.read_fat_window(fat_sector, &mut window)?;
+        Ok(self.fat_value(&window, at, cluster))
+    }
 
-        let mut sector = [0u8; SECTOR_SIZE];
-        let mut rom_index = 0usize;
-        let mut current_cluster = self.root_cluster;
+    // -------------------------------------------------------------------------
+    // FAT entries
+    // -------------------------------------------------------------------------
+    //
+    // FAT sectors are handled through a two-sector window: FAT12 entries
+    // are a byte and a half, so one can start in the last byte of a sector
+    // and end in the next.
+
+    /// FAT sector holding the start of `cluster`'s entry, and the entry's
+    /// byte offset in that sector
+    fn fat_position(&self, cluster: u32) -> (u32, usize) {
+        let offset = match self.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        };
+        (offset / SECTOR_SIZE as u32, (offset % SECTOR_SIZE as u32) as usize)
+    }
 
-        // Follow cluster chain for root directory
-        while current_cluster >= 2 && current_cluster < 0x0FFFFFF8 {
-            let cluster_lba = self.cluster_to_sector(current_cluster);
+    /// True if a window starting at FAT sector `fat_sector` takes in the
+    /// sector after it too
+    fn fat_window_spans(&self, fat_sector: u32) -> bool {
+        self.fat_type == FatType::Fat12 && fat_sector + 1 < self.sectors_per_fat
+    }
 
-            for sector_offset in 0..self.sectors_per_cluster {
-                if self.read_sector(cluster_lba + sector_offset as u64, &mut sector).is_err() {
-                    return false;
-                }
+    /// Read FAT sector `fat_sector` (of the first FAT) into `window`, with
+    /// the next one after it where an entry can cross into it
+    fn read_fat_window(&self, fat_sector: u32, window: &mut [u8; 2 * SECTOR_SIZE]) -> Result<(), &'static str> {
+        let lba = (self.fat_start_sector + fat_sector) as u64;
+        self.read_sector(lba, &mut window[..SECTOR_SIZE])?;
+        if self.fat_window_spans(fat_sector) {
+            self.read_sector(lba + 1, &mut window[SECTOR_SIZE..])?;
+        }
+        Ok(())
+    }
 
-                for i in 0..16 {
-                    let offset = i * 32;
-                    let first_byte = sector[offset];
-
-                    if first_byte == 0x00 { return false; }
-                    if first_byte == 0xE5 { continue; }
-
-                    let attr = sector[offset + 11];
-                    if attr == 0x0F { continue; }
-                    if attr == 0x08 { continue; }
-                    if (attr & 0x10) != 0 { continue; }
-
-                    let ext0 = sector[offset + 8].to_ascii_uppercase();
-                    let ext1 = sector[offset + 9].to_ascii_uppercase();
-                    let ext2 = sector[offset + 10].to_ascii_uppercase();
-
-                    let is_gb = ext0 == b'G' && ext1 == b'B' && (ext2 == b' ' || ext2 == b'C');
-
-                    if is_gb {
-                        if rom_index == index {
-                            // Copy name (8 chars) + dot + ext (3 chars)
-                            let mut pos = 0;
-                            for j in 0..8 {
-                                let c = sector[offset + j];
-                                if c != b' ' {
-                                    name_buf[pos] = c;
-                                    pos += 1;
-                                }
-                            }
-                            name_buf[pos] = b'.';
-                            pos += 1;
-                            for j in 0..3 {
-                                let c = sector[offset + 8 + j];
-                                if c != b' ' {
-                                    name_buf[pos] = c;
-                                    pos += 1;
-                                }
-                            }
-                            while pos < 12 {
-                                name_buf[pos] = 0;
-                                pos += 1;
-                            }
-                            return true;
-                        }
-                        rom_index += 1;
-                    }
+    /// Entry of `cluster`, at byte `at` of `window`
+    fn fat_value(&self, window: &[u8], at: usize, cluster: u32) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let pair = u16::from_le_bytes([window[at], window[at + 1]]);
+                (if cluster & 1 == 1 { pair >> 4 } else { pair & 0x0FFF }) as u32
+            }
+            FatType::Fat16 => u16::from_le_bytes([window[at], window[at + 1]]) as u32,
+            FatType::Fat32 => u32::from_le_bytes([
+                window[at], window[at + 1], window[at + 2], window[at + 3],
+            ]) & 0x0FFFFFFF,
+        }
+    }
+
+    /// Set the entry of `cluster`, at byte `at` of `window`, keeping the
+    /// bits around it (the other half of a FAT12 byte, the reserved top 4
+    /// bits of a FAT32 entry)
+    fn set_fat_value(&self, window: &mut [u8], at: usize, cluster: u32, value: u32) {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let old = u16::from_le_bytes([window[at], window[at + 1]]);
+                let value = (value & 0x0FFF) as u16;
+                let new = if cluster & 1 == 1 { (old & 0x000F) | (value << 4) } else { (old & 0xF000) | value };
+                window[at..at + 2].copy_from_slice(&new.to_le_bytes());
+            }
+            FatType::Fat16 => window[at..at + 2].copy_from_slice(&(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                let old = u32::from_le_bytes([window[at], window[at + 1], window[at + 2], window[at + 3]]);
+                let new = (old & 0xF0000000) | (value & 0x0FFFFFFF);
+                window[at..at + 4].copy_from_slice(&new.to_le_bytes());
+            }
+        }
+    }
+}
+
+// =============================================================================
+// Directory Listing
+// =============================================================================
+
+/// Longest long file name (VFAT)
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Directory entries per sector
+const ENTRIES_PER_SECTOR: usize = SECTOR_SIZE / DIR_ENTRY_SIZE;
+
+/// Characters in one LFN entry, and where their UCS-2 code units are
+const LFN_CHARS: usize = 13;
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// Most LFN entries one name can take (20 x 13 >= 255 characters)
+const LFN_MAX_ENTRIES: u8 = 20;
+
+/// Flag on the sequence number of the last LFN entry (stored first)
+const LFN_LAST_ENTRY: u8 = 0x40;
+
+/// Byte 12 flags (Windows NT): 8.3 base name / extension shown lower case
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+/// One file or subdirectory from a directory listing
+#[derive(Clone)]
+pub struct DirEntry {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    /// 8.3 directory name (as for `find_file`)
+    pub short_name: [u8; 11],
+    pub attr: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+    /// Timestamps, date in the high half and time in the low half (the
+    /// last access has no time), and the creation time's 10 ms units
+    pub created: u32,
+    pub created_10ms: u8,
+    pub modified: u32,
+    pub accessed: u32,
+    /// Directory holding the entry, the 8.3 entry's position in it, and
+    /// the number of LFN entries before it
+    dir_cluster: u32,
+    index: u32,
+    lfn_count: u8,
+}
+
+impl DirEntry {
+    /// Name in UTF-8: the long name if the entry has a valid one, else the
+    /// 8.3 name as `NAME.EXT`
+    ///
+    /// Long names that don't fit `MAX_NAME_LEN` bytes are cut short.
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    /// True for entries marked hidden (`System Volume Information` too)
+    pub fn is_hidden(&self) -> bool {
+        self.attr & ATTR_HIDDEN != 0
+    }
+
+    /// True for the `.` and `..` entries of a subdirectory
+    pub fn is_dot(&self) -> bool {
+        self.short_name[0] == b'.'
+    }
+
+    /// First cluster of the directory holding the entry
+    pub fn dir_cluster(&self) -> u32 {
+        self.dir_cluster
+    }
+
+    /// True if both are the same entry of the same directory
+    pub fn is_same(&self, other: &DirEntry) -> bool {
+        self.dir_cluster == other.dir_cluster && self.index == other.index
+    }
+
+    /// True if `name` is the entry's long or 8.3 name, ignoring ASCII case
+    pub fn matches_name(&self, name: &[u8]) -> bool {
+        if self.name().eq_ignore_ascii_case(name) {
+            return true;
+        }
+        let mut short = [0u8; MAX_NAME_LEN];
+        let len = short_display_name(&self.short_name, 0, &mut short);
+        short[..len].eq_ignore_ascii_case(name)
+    }
+
+    /// True for a .gb or .gbc file
+    pub fn is_rom(&self) -> bool {
+        let ext = &self.short_name[8..];
+        !self.is_dir()
+            && ext[0].eq_ignore_ascii_case(&b'G')
+            && ext[1].eq_ignore_ascii_case(&b'B')
+            && (ext[2] == b' ' || ext[2].eq_ignore_ascii_case(&b'C'))
+    }
+
+    /// Build from an 8.3 entry and the long name gathered before it
+    fn from_raw(raw: &[u8], lfn: &mut LfnBuilder, dir_cluster: u32, index: u32) -> DirEntry {
+        let mut entry = DirEntry {
+            name: [0; MAX_NAME_LEN],
+            name_len: 0,
+            short_name: [0; 11],
+            attr: raw[11],
+            first_cluster: (u16::from_le_bytes([raw[20], raw[21]]) as u32) << 16
+                | u16::from_le_bytes([raw[26], raw[27]]) as u32,
+            size: u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]),
+            created: u32::from_le_bytes([raw[14], raw[15], raw[16], raw[17]]),
+            created_10ms: raw[13],
+            modified: u32::from_le_bytes([raw[22], raw[23], raw[24], raw[25]]),
+            accessed: (u16::from_le_bytes([raw[18], raw[19]]) as u32) << 16,
+            dir_cluster,
+            index,
+            lfn_count: 0,
+        };
+        entry.short_name.copy_from_slice(&raw[..11]);
+
+        let (len, count) = lfn.finish(lfn_checksum(&entry.short_name), &mut entry.name);
+        entry.name_len = len;
+        entry.lfn_count = count;
+        if len == 0 {
+            entry.name_len = short_display_name(&entry.short_name, raw[12], &mut entry.name);
+        }
+        entry
+    }
+}
+
+/// Iterator over a directory's entries (see `Fat32::read_dir`)
+///
+/// Skips deleted entries, LFN entries (their names go into the entry
+/// they belong to) and the volume label. Ends at the end of the
+/// directory or at a read error (`error`).
+pub struct DirIter<'a> {
+    fs: &'a Fat32,
+    /// First and current cluster of the directory (0 for the FAT12/16
+    /// fixed root, read as one long cluster)
+    start: u32,
+    cluster: u32,
+    /// Position: sector in the cluster, entry in the sector, entry in the
+    /// directory
+    sector_index: u32,
+    entry_index: usize,
+    index: u32,
+    sector: [u8; SECTOR_SIZE],
+    loaded: bool,
+    done: bool,
+    error: Option<&'static str>,
+    lfn: LfnBuilder,
+}
+
+impl DirIter<'_> {
+    /// The read error that ended the listing early, if any
+    pub fn error(&self) -> Option<&'static str> {
+        self.error
+    }
+
+    /// Step to the next entry slot, loading sectors and clusters as needed
+    fn advance(&mut self) {
+        self.entry_index += 1;
+        self.index += 1;
+        if self.entry_index < ENTRIES_PER_SECTOR {
+            return;
+        }
+        self.entry_index = 0;
+        self.sector_index += 1;
+        self.loaded = false;
+        if self.sector_index < self.fs.dir_unit_sectors(self.cluster) {
+            return;
+        }
+        self.sector_index = 0;
+        if self.cluster == 0 {
+            self.done = true;
+            return;
+        }
+        match self.fs.get_next_cluster(self.cluster) {
+            Ok(next) if self.fs.is_data_cluster(next) => self.cluster = next,
+            Ok(_) => self.done = true,
+            Err(e) => {
+                self.error = Some(e);
+                self.done = true;
+            }
+        }
+    }
+}
+
+impl Iterator for DirIter<'_> {
+    type Item = DirEntry;
+
+    fn next(&mut self) -> Option<DirEntry> {
+        while !self.done {
+            if !self.loaded {
+                let lba = self.fs.dir_unit_lba(self.cluster) + self.sector_index as u64;
+                if let Err(e) = self.fs.read_sector(lba, &mut self.sector) {
+                    self.error = Some(e);
+                    self.done = true;
+                    break;
                 }
+                self.loaded = true;
             }
 
-            current_cluster = match self.get_next_cluster(current_cluster) {
-                Ok(next) => next,
-                Err(_) => break,
+            let offset = self.entry_index * DIR_ENTRY_SIZE;
+            let raw = &self.sector[offset..offset + DIR_ENTRY_SIZE];
+            let index = self.index;
+
+            if raw[0] == 0x00 {
+                self.done = true;
+                break;
+            }
+            let attr = raw[11];
+            let entry = if raw[0] == DELETED_ENTRY || (attr != ATTR_LFN && attr & ATTR_VOLUME != 0) {
+                self.lfn.reset();
+                None
+            } else if attr == ATTR_LFN {
+                self.lfn.add(raw);
+                None
+            } else {
+                let mut entry = DirEntry::from_raw(raw, &mut self.lfn, self.start, index);
+                if self.fs.fat_type != FatType::Fat32 {
+                    // The high half is the OS/2 extended attribute handle
+                    entry.first_cluster &= 0xFFFF;
+                }
+                Some(entry)
             };
+
+            self.advance();
+            if entry.is_some() {
+                return entry;
+            }
         }
+        None
+    }
+}
 
-        false
+/// Collects a long name from the LFN entries stored before an 8.3 entry
+/// (last part first, each entry numbered)
+struct LfnBuilder {
+    chars: [u16; LFN_MAX_ENTRIES as usize * LFN_CHARS],
+    /// Entries in the name (0 = no name being collected)
+    count: u8,
+    /// Sequence number of the entry expected next (counts down to 1)
+    expected: u8,
+    checksum: u8,
+}
+
+impl LfnBuilder {
+    const fn new() -> LfnBuilder {
+        LfnBuilder { chars: [0; LFN_MAX_ENTRIES as usize * LFN_CHARS], count: 0, expected: 0, checksum: 0 }
     }
 
-    /// Count ROM files in root directory
-    pub fn count_roms(&self) -> usize {
-        if !self.mounted { return 0; }
+    fn reset(&mut self) {
+        self.count = 0;
+        self.expected = 0;
+    }
 
-        let mut sector = [0u8; SECTOR_SIZE];
-        let mut count = 0;
-        let mut current_cluster = self.root_cluster;
+    fn add(&mut self, raw: &[u8]) {
+        let seq = raw[0] & !LFN_LAST_ENTRY;
+        if raw[0] & LFN_LAST_ENTRY != 0 {
+            if seq == 0 || seq > LFN_MAX_ENTRIES {
+                self.reset();
+                return;
+            }
+            self.count = seq;
+            self.checksum = raw[13];
+        } else if self.expected == 0 || seq != self.expected || raw[13] != self.checksum {
+            // Out of order or from another name: an orphan
+            self.reset();
+            return;
+        }
 
-        // Debug row 197: show root_cluster value
-        unsafe {
-            let vga = 0xA0000 as *mut u8;
-            core::ptr::write_volatile(vga.add(197 * 320), (self.root_cluster & 0xFF) as u8);
-            core::ptr::write_volatile(vga.add(197 * 320 + 1), ((self.root_cluster >> 8) & 0xFF) as u8);
-            core::ptr::write_volatile(vga.add(197 * 320 + 2), ((self.root_cluster >> 16) & 0xFF) as u8);
+        let base = (seq as usize - 1) * LFN_CHARS;
+        for (i, &at) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            self.chars[base + i] = u16::from_le_bytes([raw[at], raw[at + 1]]);
         }
+        self.expected = seq - 1;
+    }
 
-        // Follow cluster chain for root directory
-        while current_cluster >= 2 && current_cluster < 0x0FFFFFF8 {
-            let cluster_lba = self.cluster_to_sector(current_cluster);
+    /// Write the collected name into `out` if it is complete and belongs to
+    /// the 8.3 entry with `checksum`; returns (length, LFN entries). Always
+    /// starts over for the next entry.
+    fn finish(&mut self, checksum: u8, out: &mut [u8; MAX_NAME_LEN]) -> (usize, u8) {
+        let complete = self.count > 0 && self.expected == 0 && self.checksum == checksum;
+        let count = self.count;
+        self.reset();
+        if !complete {
+            return (0, 0);
+        }
 
-            // Debug row 197: show cluster LBA
-            unsafe {
-                let vga = 0xA0000 as *mut u8;
-                core::ptr::write_volatile(vga.add(197 * 320 + 10), (cluster_lba & 0xFF) as u8);
-                core::ptr::write_volatile(vga.add(197 * 320 + 11), ((cluster_lba >> 8) & 0xFF) as u8);
-                core::ptr::write_volatile(vga.add(197 * 320 + 12), ((cluster_lba >> 16) & 0xFF) as u8);
+        let chars = &self.chars[..count as usize * LFN_CHARS];
+        let name = chars.iter().copied().take_while(|&c| c != 0x0000);
+        let mut len = 0;
+        for c in char::decode_utf16(name) {
+            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+            if len + c.len_utf8() > MAX_NAME_LEN {
+                break;
             }
+            len += c.encode_utf8(&mut out[len..]).len();
+        }
+        (len, count)
+    }
+}
 
-            for sector_offset in 0..self.sectors_per_cluster {
-                let read_result = self.read_sector(cluster_lba + sector_offset as u64, &mut sector);
+/// Checksum of an 8.3 name, stored in each of its LFN entries
+fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
 
-                // Debug row 198: show read result
-                unsafe {
-                    let vga = 0xA0000 as *mut u8;
-                    let color = if read_result.is_ok() { 0x0A } else { 0x04 };
-                    core::ptr::write_volatile(vga.add(198 * 320 + sector_offset as usize * 3), color);
-                }
+/// `NAME.EXT` from an 8.3 name, lower-cased as the NT flags ask
+fn short_display_name(short_name: &[u8; 11], nt_flags: u8, out: &mut [u8; MAX_NAME_LEN]) -> usize {
+    let mut len = 0;
+    let mut push = |part: &[u8], lower: bool| {
+        for &c in part.iter().filter(|&&c| c != b' ') {
+            let c = cp437_to_char(if lower { c.to_ascii_lowercase() } else { c });
+            len += c.encode_utf8(&mut out[len..]).len();
+        }
+    };
+    // 0x05 stands for a leading 0xE5 (which marks deleted entries)
+    let first = if short_name[0] == 0x05 { 0xE5 } else { short_name[0] };
+    push(&[first], nt_flags & NT_LOWER_BASE != 0);
+    push(&short_name[1..8], nt_flags & NT_LOWER_BASE != 0);
+    if short_name[8..] != *b"   " {
+        push(b".", false);
+        push(&short_name[8..], nt_flags & NT_LOWER_EXT != 0);
+    }
+    len
+}
 
-                if read_result.is_err() {
-                    return count;
-                }
+/// Code page 437 for U+00A0-U+00FF (0 = no match, see `ucs2_to_cp437`)
+#[rustfmt::skip]
+const LATIN1_CP437: [u8; 96] = [
+    0xFF, 0xAD, 0x9B, 0x9C, 0,    0x9D, 0x7C, 0x15, 0,    0,    0xA6, 0xAE, 0xAA, b'-', 0,    0,
+    0xF8, 0xF1, 0xFD, 0,    b'\'', 0xE6, 0x14, 0xFA, 0,    0,    0xA7, 0xAF, 0xAC, 0xAB, 0,    0xA8,
+    b'A', b'A', b'A', b'A', 0x8E, 0x8F, 0x92, 0x80, b'E', 0x90, b'E', b'E', b'I', b'I', b'I', b'I',
+    b'D', 0xA5, b'O', b'O', b'O', b'O', 0x99, b'x', b'O', b'U', b'U', b'U', 0x9A, b'Y', 0,    0xE1,
+    0x85, 0xA0, 0x83, b'a', 0x84, 0x86, 0x91, 0x87, 0x8A, 0x82, 0x88, 0x89, 0x8D, 0xA1, 0x8C, 0x8B,
+    b'd', 0xA4, 0x95, 0xA2, 0x93, b'o', 0x94, 0xF6, b'o', 0x97, 0xA3, 0x96, 0x81, b'y', 0,    0x98,
+];
+
+/// A UCS-2 character as the font can draw it: ASCII as is, Latin-1 as the
+/// code page 437 letter (or the plain letter without its accent), a few
+/// typographic marks as their ASCII lookalikes, anything else as `?`
+pub fn ucs2_to_cp437(c: u16) -> u8 {
+    match c {
+        0x20..=0x7E => c as u8,
+        0xA0..=0xFF => match LATIN1_CP437[c as usize - 0xA0] {
+            0 => b'?',
+            b => b,
+        },
+        0x2010..=0x2015 => b'-',
+        0x2018 | 0x2019 => b'\'',
+        0x201C | 0x201D => b'"',
+        0x2022 => 0x07,
+        _ => b'?',
+    }
+}
 
-                // Debug row 199: show first 16 bytes of directory
-                unsafe {
-                    let vga = 0xA0000 as *mut u8;
-                    for i in 0..16 {
-                        core::ptr::write_volatile(vga.add(199 * 320 + i), sector[i]);
-                    }
-                }
+/// A code page 437 byte of an 8.3 name as a character: the Latin-1 letters
+/// `ucs2_to_cp437` maps there, anything else above ASCII as U+FFFD
+fn cp437_to_char(b: u8) -> char {
+    if b < 0x80 {
+        return b as char;
+    }
+    LATIN1_CP437
+        .iter()
+        .position(|&c| c == b)
+        .and_then(|i| char::from_u32(0xA0 + i as u32))
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+// =============================================================================
+// Writing
+// =============================================================================
+
+/// Location of a file's directory entry, and what it records
+struct DirSlot {
+    lba: u64,
+    offset: usize,
+    first_cluster: u32,
+    size: u32,
+    /// Directory holding the entry, position in it and LFN entries before it
+    dir_cluster: u32,
+    index: u32,
+    lfn_count: u8,
+}
+
+impl Fat32 {
+    /// Write a sector
+    fn write_sector(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.write_sectors(lba, 1, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, count: u32, buf: &[u8]) -> Result<(), &'static str> {
+        self.volume.write_sectors(lba, count as u8, buf)
+    }
+
+    /// Bytes per cluster
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= FIRST_DATA_CLUSTER && cluster < self.cluster_count + FIRST_DATA_CLUSTER
+    }
+
+    /// Sectors in each cluster of a directory (the whole region for the
+    /// FAT12/16 fixed root, cluster 0)
+    fn dir_unit_sectors(&self, cluster: u32) -> u32 {
+        if cluster == 0 { self.root_dir_sectors } else { self.sectors_per_cluster }
+    }
+
+    /// First sector of a directory's cluster (as for `dir_unit_sectors`)
+    fn dir_unit_lba(&self, cluster: u32) -> u64 {
+        if cluster == 0 { self.root_dir_sector as u64 } else { self.cluster_to_sector(cluster) }
+    }
 
-                for i in 0..16 {
-                    let offset = i * 32;
-                    let first_byte = sector[offset];
-
-                    if first_byte == 0x00 {
-                        // Debug: yellow pixel = hit end marker
-                        unsafe {
-                            let vga = 0xA0000 as *mut u8;
-                            core::ptr::write_volatile(vga.add(198 * 320 + 20), 0x0E);
-                        }
-                        return count;
+    /// Clusters of the directory at `dir` (`[0]` for the FAT12/16 fixed
+    /// root)
+    fn dir_chain(&self, dir: u32) -> Result<Vec<u32>, &'static str> {
+        let dir = if dir == 0 { self.root_cluster } else { dir };
+        if dir == 0 {
+            return Ok(alloc::vec![0]);
+        }
+        self.chain(dir)
+    }
+
+    /// Clusters of the chain starting at `first` (empty for cluster 0)
+    fn chain(&self, first: u32) -> Result<Vec<u32>, &'static str> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while self.is_data_cluster(cluster) {
+            if chain.len() as u32 >= self.cluster_count {
+                return Err("Cluster chain loops");
+            }
+            chain.push(cluster);
+            cluster = self.get_next_cluster(cluster)?;
+        }
+        Ok(chain)
+    }
+
+    // -------------------------------------------------------------------------
+    // Cluster allocation
+    // -------------------------------------------------------------------------
+
+    /// Find `count` free clusters, starting at the FSInfo next-free hint
+    /// and wrapping around to the start of the volume
+    fn find_free_clusters(&self, count: usize) -> Result<Vec<u32>, &'static str> {
+        if self.free_count != FSINFO_UNKNOWN && (self.free_count as usize) < count {
+            return Err("Disk full");
+        }
+
+        let mut free = Vec::with_capacity(count);
+        let mut window = [0u8; 2 * SECTOR_SIZE];
+        let mut loaded = u32::MAX;
+        let start = self.next_free - FIRST_DATA_CLUSTER;
+
+        for n in 0..self.cluster_count {
+            if free.len() == count {
+                break;
+            }
+            let cluster = FIRST_DATA_CLUSTER + (start + n) % self.cluster_count;
+            let (fat_sector, at) = self.fat_position(cluster);
+            if fat_sector != loaded {
+                self.read_fat_window(fat_sector, &mut window)?;
+                loaded = fat_sector;
+            }
+            if self.fat_value(&window, at, cluster) == FAT_FREE {
+                free.push(cluster);
+            }
+        }
+
+        if free.len() < count {
+            return Err("Disk full");
+        }
+        Ok(free)
+    }
+
+    /// Set FAT entries, in every copy of the FAT
+    ///
+    /// Entries in the same FAT sector should be next to each other (sort
+    /// by cluster) so each sector is read and written once.
+    fn set_fat_entries(&self, entries: &[(u32, u32)]) -> Result<(), &'static str> {
+        let mut window = [0u8; 2 * SECTOR_SIZE];
+        let mut i = 0;
+        while i < entries.len() {
+            let (fat_sector, _) = self.fat_position(entries[i].0);
+            self.read_fat_window(fat_sector, &mut window)?;
+            while i < entries.len() && self.fat_position(entries[i].0).0 == fat_sector {
+                let (cluster, value) = entries[i];
+                self.set_fat_value(&mut window, self.fat_position(cluster).1, cluster, value);
+                i += 1;
+            }
+            let sectors = if self.fat_window_spans(fat_sector) { 2 } else { 1 };
+            for fat in 0..self.num_fats {
+                let lba = self.fat_start_sector + fat * self.sectors_per_fat + fat_sector;
+                self.write_sectors(lba as u64, sectors, &window[..sectors as usize * SECTOR_SIZE])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark `clusters` in use as one chain, linked from `after` (the last
+    /// cluster of an existing chain) if given
+    fn claim_clusters(&mut self, clusters: &[u32], after: Option<u32>) -> Result<(), &'static str> {
+        let Some(&last) = clusters.last() else { return Ok(()) };
+        let mut entries: Vec<(u32, u32)> = clusters
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (c, clusters.get(i + 1).copied().unwrap_or(FAT_EOC)))
+            .collect();
+        entries.sort_unstable();
+        self.set_fat_entries(&entries)?;
+        // Link last, so the old chain never points at a half-built one
+        if let Some(prev) = after {
+            self.set_fat_entries(&[(prev, clusters[0])])?;
+        }
+
+        self.next_free = if self.is_data_cluster(last + 1) { last + 1 } else { FIRST_DATA_CLUSTER };
+        if self.free_count != FSINFO_UNKNOWN {
+            self.free_count = self.free_count.saturating_sub(clusters.len() as u32);
+        }
+        self.write_fsinfo()
+    }
+
+    /// Mark `clusters` free
+    fn release_clusters(&mut self, clusters: &[u32]) -> Result<(), &'static str> {
+        if clusters.is_empty() {
+            return Ok(());
+        }
+        let mut entries: Vec<(u32, u32)> = clusters.iter().map(|&c| (c, FAT_FREE)).collect();
+        entries.sort_unstable();
+        self.set_fat_entries(&entries)?;
+
+        if self.free_count != FSINFO_UNKNOWN {
+            self.free_count = (self.free_count + clusters.len() as u32).min(self.cluster_count);
+        }
+        self.write_fsinfo()
+    }
+
+    /// Store the free count and next-free hint in the FSInfo sector
+    fn write_fsinfo(&self) -> Result<(), &'static str> {
+        if self.fsinfo_sector == 0 {
+            return Ok(());
+        }
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(self.fsinfo_sector as u64, &mut sector)?;
+        if !fsinfo_valid(&sector) {
+            return Ok(());
+        }
+        sector[488..492].copy_from_slice(&self.free_count.to_le_bytes());
+        sector[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        self.write_sector(self.fsinfo_sector as u64, &sector)
+    }
+
+    /// Write `data` into `clusters`, zero-padding the last one (an empty
+    /// `data` zeroes them all)
+    fn write_clusters(&self, clusters: &[u32], data: &[u8]) -> Result<(), &'static str> {
+        let cluster_bytes = self.cluster_size();
+        for (i, &cluster) in clusters.iter().enumerate() {
+            let lba = self.cluster_to_sector(cluster);
+            let start = (i * cluster_bytes).min(data.len());
+            let chunk = &data[start..(start + cluster_bytes).min(data.len())];
+            if chunk.len() == cluster_bytes {
+                self.write_sectors(lba, self.sectors_per_cluster, chunk)?;
+                continue;
+            }
+            for s in 0..self.sectors_per_cluster as usize {
+                let mut sector = [0u8; SECTOR_SIZE];
+                let from = (s * SECTOR_SIZE).min(chunk.len());
+                let to = ((s + 1) * SECTOR_SIZE).min(chunk.len());
+                sector[..to - from].copy_from_slice(&chunk[from..to]);
+                self.write_sector(lba + s as u64, &sector)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Zero `cluster` from byte `from` to its end
+    fn zero_cluster_tail(&self, cluster: u32, from: usize) -> Result<(), &'static str> {
+        let lba = self.cluster_to_sector(cluster);
+        let mut sector = [0u8; SECTOR_SIZE];
+        for s in from / SECTOR_SIZE..self.sectors_per_cluster as usize {
+            let keep = from.saturating_sub(s * SECTOR_SIZE);
+            if keep > 0 {
+                self.read_sector(lba + s as u64, &mut sector)?;
+            }
+            sector[keep..].fill(0);
+            self.write_sector(lba + s as u64, &sector)?;
+        }
+        Ok(())
+    }
+
+    // -------------------------------------------------------------------------
+    // Directory entries
+    // -------------------------------------------------------------------------
+
+    /// Find a file's directory entry in the directory at `dir`
+    fn find_slot(&self, dir: u32, name: &[u8; 11]) -> Result<Option<DirSlot>, &'static str> {
+        if !self.mounted { return Err("Not mounted"); }
+
+        let mut entries = self.read_dir(dir);
+        let Some(entry) = entries.find(|e| !e.is_dir() && e.short_name.eq_ignore_ascii_case(name)) else {
+            return entries.error().map_or(Ok(None), Err);
+        };
+        self.slot_of(&entry).map(Some)
+    }
+
+    /// Where a listed entry's 8.3 entry is
+    fn slot_of(&self, entry: &DirEntry) -> Result<DirSlot, &'static str> {
+        let (lba, offset) = self.entry_location(entry.dir_cluster, entry.index)?;
+        Ok(DirSlot {
+            lba,
+            offset,
+            first_cluster: entry.first_cluster,
+            size: entry.size,
+            dir_cluster: entry.dir_cluster,
+            index: entry.index,
+            lfn_count: entry.lfn_count,
+        })
+    }
+
+    /// Sector and byte offset of entry `index` of the directory at `dir_cluster`
+    fn entry_location(&self, dir_cluster: u32, index: u32) -> Result<(u64, usize), &'static str> {
+        self.entry_location_in(&self.dir_chain(dir_cluster)?, index)
+    }
+
+    /// Sector and byte offset of entry `index` of the directory made of
+    /// `chain` (from `dir_chain`)
+    fn entry_location_in(&self, chain: &[u32], index: u32) -> Result<(u64, usize), &'static str> {
+        let first = *chain.first().ok_or("Bad directory entry")?;
+        let per_cluster = self.dir_unit_sectors(first) * ENTRIES_PER_SECTOR as u32;
+        let cluster = *chain.get((index / per_cluster) as usize).ok_or("Bad directory entry")?;
+        let within = index % per_cluster;
+        let lba = self.dir_unit_lba(cluster) + (within / ENTRIES_PER_SECTOR as u32) as u64;
+        Ok((lba, (within as usize % ENTRIES_PER_SECTOR) * DIR_ENTRY_SIZE))
+    }
+
+    /// Put directory entries (LFN entries, then the 8.3 entry) in the first
+    /// run of free slots of the directory at `dir` that holds them all,
+    /// growing it if there is none; returns the 8.3 entry's slot
+    fn add_entries(&mut self, dir: u32, entries: &[[u8; DIR_ENTRY_SIZE]]) -> Result<DirSlot, &'static str> {
+        let dir = if dir == 0 { self.root_cluster } else { dir };
+        let Some(last) = entries.last() else { return Err("No directory entries") };
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut chain = self.dir_chain(dir)?;
+        let mut index = 0u32;
+        let mut run = 0;
+        let mut start = None;
+
+        'scan: for &cluster in &chain {
+            let cluster_lba = self.dir_unit_lba(cluster);
+            for sector_offset in 0..self.dir_unit_sectors(cluster) {
+                self.read_sector(cluster_lba + sector_offset as u64, &mut sector)?;
+                for offset in (0..SECTOR_SIZE).step_by(DIR_ENTRY_SIZE) {
+                    index += 1;
+                    if sector[offset] != 0x00 && sector[offset] != DELETED_ENTRY {
+                        run = 0;
+                        continue;
                     }
-                    if first_byte == 0xE5 { continue; }
-
-                    let attr = sector[offset + 11];
-                    if attr == 0x0F { continue; }
-                    if attr == 0x08 { continue; }
-                    if (attr & 0x10) != 0 { continue; }
-
-                    let ext0 = sector[offset + 8].to_ascii_uppercase();
-                    let ext1 = sector[offset + 9].to_ascii_uppercase();
-                    let ext2 = sector[offset + 10].to_ascii_uppercase();
-
-                    let is_gb = ext0 == b'G' && ext1 == b'B' && (ext2 == b' ' || ext2 == b'C');
-
-                    if is_gb {
-                        count += 1;
-                        // Debug: green pixel for each ROM found
-                        unsafe {
-                            let vga = 0xA0000 as *mut u8;
-                            core::ptr::write_volatile(vga.add(198 * 320 + 30 + count), 0x0A);
-                        }
+                    run += 1;
+                    if run == entries.len() {
+                        start = Some(index - run as u32);
+                        break 'scan;
                     }
                 }
             }
+        }
 
-            current_cluster = match self.get_next_cluster(current_cluster) {
-                Ok(next) => next,
-                Err(_) => break,
-            };
+        let start = match start {
+            Some(start) => start,
+            None if dir == 0 => return Err("Root directory full"),
+            None => {
+                // Directory full: append zeroed clusters, which the free
+                // slots at its end run on into
+                let per_cluster = self.sectors_per_cluster as usize * ENTRIES_PER_SECTOR;
+                let added = self.find_free_clusters((entries.len() - run).div_ceil(per_cluster))?;
+                self.write_clusters(&added, &[])?;
+                self.claim_clusters(&added, chain.last().copied())?;
+                chain.extend_from_slice(&added);
+                index - run as u32
+            }
+        };
+
+        let mut location = (0, 0);
+        for (i, entry) in entries.iter().enumerate() {
+            location = self.entry_location_in(&chain, start + i as u32)?;
+            self.read_sector(location.0, &mut sector)?;
+            sector[location.1..location.1 + DIR_ENTRY_SIZE].copy_from_slice(entry);
+            self.write_sector(location.0, &sector)?;
         }
+        Ok(DirSlot {
+            lba: location.0,
+            offset: location.1,
+            first_cluster: (u16::from_le_bytes([last[20], last[21]]) as u32) << 16
+                | u16::from_le_bytes([last[26], last[27]]) as u32,
+            size: u32::from_le_bytes([last[28], last[29], last[30], last[31]]),
+            dir_cluster: dir,
+            index: start + entries.len() as u32 - 1,
+            lfn_count: entries.len() as u8 - 1,
+        })
+    }
 
-        count
+    /// Mark a file's 8.3 entry and its LFN entries deleted
+    fn delete_slot(&self, slot: &DirSlot) -> Result<(), &'static str> {
+        let chain = self.dir_chain(slot.dir_cluster)?;
+        let mut sector = [0u8; SECTOR_SIZE];
+        for index in slot.index - slot.lfn_count as u32..=slot.index {
+            let (lba, offset) = self.entry_location_in(&chain, index)?;
+            self.read_sector(lba, &mut sector)?;
+            sector[offset] = DELETED_ENTRY;
+            self.write_sector(lba, &sector)?;
+        }
+        Ok(())
     }
 
-    /// Read file data into buffer
-    /// Returns bytes read
-    pub fn read_file(&self, cluster: u32, size: u32, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    /// New file entry for `name`
+    fn new_entry(name: &[u8; 11], first: u32, size: u32) -> [u8; DIR_ENTRY_SIZE] {
+        let mut entry = [0u8; DIR_ENTRY_SIZE];
+        entry[0..11].copy_from_slice(name);
+        entry[11] = ATTR_ARCHIVE;
+        for date in [16, 18, 24] {
+            entry[date..date + 2].copy_from_slice(&FAT_EPOCH_DATE.to_le_bytes());
+        }
+        Self::set_entry_data(&mut entry, first, size);
+        entry
+    }
+
+    /// Store first cluster and size in a directory entry
+    fn set_entry_data(entry: &mut [u8], first: u32, size: u32) {
+        entry[20..22].copy_from_slice(&((first >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(first as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// Rewrite a directory entry's first cluster and size (and flag the
+    /// file for backup, as DOS does on every change)
+    fn update_slot(&self, slot: &mut DirSlot, first: u32, size: u32) -> Result<(), &'static str> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(slot.lba, &mut sector)?;
+        let entry = &mut sector[slot.offset..slot.offset + DIR_ENTRY_SIZE];
+        Self::set_entry_data(entry, first, size);
+        entry[11] |= ATTR_ARCHIVE;
+        self.write_sector(slot.lba, &sector)?;
+        slot.first_cluster = first;
+        slot.size = size;
+        Ok(())
+    }
+
+    /// Truncate or extend a file to `size` bytes; returns its chain
+    ///
+    /// Bytes added by extending are zero unless `zero_fill` is false (the
+    /// caller is about to overwrite them).
+    fn resize_slot(&mut self, slot: &mut DirSlot, size: u32, zero_fill: bool) -> Result<Vec<u32>, &'static str> {
+        let chain = self.extend_chain(slot, size, zero_fill)?;
+        self.finish_resize(slot, chain, size)
+    }
+
+    /// The chain of `slot`, extended with newly allocated and linked
+    /// clusters if it is too short for `size` bytes (a longer one is
+    /// returned whole)
+    ///
+    /// The directory entry is left alone, so the new clusters are lost
+    /// rather than part of the file until `finish_resize`: data written to
+    /// them in between is never visible half done.
+    fn extend_chain(&mut self, slot: &DirSlot, size: u32, zero_fill: bool) -> Result<Vec<u32>, &'static str> {
+        let cluster_bytes = self.cluster_size();
+        let mut chain = self.chain(slot.first_cluster)?;
+        let needed = (size as usize).div_ceil(cluster_bytes);
+
+        // Growing: clear the rest of the old last cluster, then add clusters
+        let old_size = slot.size as usize;
+        if zero_fill && size as usize > old_size && old_size % cluster_bytes != 0 {
+            if let Some(&last) = chain.get(old_size / cluster_bytes) {
+                self.zero_cluster_tail(last, old_size % cluster_bytes)?;
+            }
+        }
+        if needed > chain.len() {
+            let added = self.find_free_clusters(needed - chain.len())?;
+            if zero_fill {
+                self.write_clusters(&added, &[])?;
+            }
+            self.claim_clusters(&added, chain.last().copied())?;
+            chain.extend_from_slice(&added);
+        }
+        Ok(chain)
+    }
+
+    /// Give `slot` the size `size` over `chain` (from `extend_chain`),
+    /// then free the clusters past what it needs; returns the chain kept
+    fn finish_resize(&mut self, slot: &mut DirSlot, mut chain: Vec<u32>, size: u32) -> Result<Vec<u32>, &'static str> {
+        let needed = (size as usize).div_ceil(self.cluster_size());
+
+        // Entry first: a crash then loses clusters instead of leaving the
+        // file pointing at freed ones
+        let first = if needed == 0 { 0 } else { chain[0] };
+        self.update_slot(slot, first, size)?;
+        if needed < chain.len() {
+            if needed > 0 {
+                self.set_fat_entries(&[(chain[needed - 1], FAT_EOC)])?;
+            }
+            let freed = chain.split_off(needed);
+            self.release_clusters(&freed)?;
+        }
+        Ok(chain)
+    }
+
+    // -------------------------------------------------------------------------
+    // Files
+    // -------------------------------------------------------------------------
+
+    /// Create a file in the root directory holding `data`
+    ///
+    /// `name` is the 8.3 directory name as for `find_file`. Fails if the
+    /// file already exists.
+    pub fn create_file(&mut self, name: &[u8; 11], data: &[u8]) -> Result<(), &'static str> {
+        self.create_file_in(self.root_cluster, name, data)
+    }
+
+    /// Create a file in the directory at `dir` (as for `create_file`)
+    pub fn create_file_in(&mut self, dir: u32, name: &[u8; 11], data: &[u8]) -> Result<(), &'static str> {
+        if self.find_slot(dir, name)?.is_some() { return Err("File exists"); }
+
+        let clusters = self.find_free_clusters(data.len().div_ceil(self.cluster_size()))?;
+
+        // Data first, then the chain, then the entry: an interrupted write
+        // leaves at worst lost clusters, never a file pointing at garbage
+        self.write_clusters(&clusters, data)?;
+        self.claim_clusters(&clusters, None)?;
+
+        let first = clusters.first().copied().unwrap_or(0);
+        self.add_entries(dir, &[Self::new_entry(name, first, data.len() as u32)])?;
+        Ok(())
+    }
+
+    /// Replace a file's contents with `data`, creating it if needed
+    ///
+    /// An existing file keeps its clusters where the sizes allow, so
+    /// rewriting a file of the same size (a save file) allocates nothing.
+    pub fn write_file(&mut self, name: &[u8; 11], data: &[u8]) -> Result<(), &'static str> {
+        self.write_file_in(self.root_cluster, name, data)
+    }
+
+    /// Replace or create a file in the directory at `dir` (as for
+    /// `write_file`)
+    pub fn write_file_in(&mut self, dir: u32, name: &[u8; 11], data: &[u8]) -> Result<(), &'static str> {
+        let Some(mut slot) = self.find_slot(dir, name)? else {
+            return self.create_file_in(dir, name, data);
+        };
+        // Data into the clusters first, then the entry (see `extend_chain`)
+        let size = data.len() as u32;
+        let chain = self.extend_chain(&slot, size, false)?;
+        let needed = data.len().div_ceil(self.cluster_size());
+        self.write_clusters(&chain[..needed], data)?;
+        self.finish_resize(&mut slot, chain, size).map(|_| ())
+    }
+
+    /// Truncate or extend a file to `size` bytes (extending adds zeros)
+    pub fn set_file_size(&mut self, name: &[u8; 11], size: u32) -> Result<(), &'static str> {
+        let mut slot = self.find_slot(self.root_cluster, name)?.ok_or("File not found")?;
+        self.resize_slot(&mut slot, size, true).map(|_| ())
+    }
+
+    /// Delete a file (and its long name) from the root directory, freeing
+    /// its clusters
+    pub fn delete_file(&mut self, name: &[u8; 11]) -> Result<(), &'static str> {
+        self.delete_file_in(self.root_cluster, name)
+    }
+
+    /// Delete a file from the directory at `dir` (as for `delete_file`)
+    pub fn delete_file_in(&mut self, dir: u32, name: &[u8; 11]) -> Result<(), &'static str> {
+        let slot = self.find_slot(dir, name)?.ok_or("File not found")?;
+        let chain = self.chain(slot.first_cluster)?;
+        self.delete_slot(&slot)?;
+        self.release_clusters(&chain)
+    }
+}
+
+// =============================================================================
+// Files by Name
+// =============================================================================
+
+/// Characters an 8.3 name may hold besides letters and digits
+const SHORT_NAME_PUNCTUATION: &[u8] = b"$%'-_@~`!(){}^#&";
+
+/// Characters no name may hold
+const INVALID_NAME_CHARS: &[u8] = b"\"*/:<>?\\|";
+
+/// Most `~N` tails tried for a generated 8.3 name
+const MAX_NAME_TAIL: u32 = 999_999;
+
+impl Fat32 {
+    /// Find the file or directory named `name` (long or 8.3, any ASCII
+    /// case) in the directory at `dir`
+    pub fn find_entry(&self, dir: u32, name: &[u8]) -> Result<Option<DirEntry>, &'static str> {
         if !self.mounted { return Err("Not mounted"); }
-        if cluster < 2 { return Err("Invalid cluster"); }
 
-        let to_read = (size as usize).min(buffer.len());
-        let mut bytes_read = 0usize;
-        let mut current_cluster = cluster;
+        let mut entries = self.read_dir(dir);
+        match entries.find(|e| !e.is_dot() && e.matches_name(name)) {
+            Some(entry) => Ok(Some(entry)),
+            None => entries.error().map_or(Ok(None), Err),
+        }
+    }
 
-        let mut sector_buf = [0u8; SECTOR_SIZE];
+    /// First cluster of the directory holding the subdirectory at `dir`,
+    /// from its `..` entry (the root is its own parent)
+    pub fn parent_dir(&self, dir: u32) -> Result<u32, &'static str> {
+        if !self.mounted { return Err("Not mounted"); }
+        if dir == 0 || dir == self.root_cluster { return Ok(self.root_cluster); }
 
-        while bytes_read < to_read && current_cluster >= 2 && current_cluster < 0x0FFFFFF8 {
-            let cluster_lba = self.cluster_to_sector(current_cluster);
+        let mut entries = self.read_dir(dir);
+        match entries.find(|e| e.is_dot() && e.short_name[1] == b'.') {
+            Some(dotdot) if dotdot.first_cluster == 0 => Ok(self.root_cluster),
+            Some(dotdot) => Ok(dotdot.first_cluster),
+            None => Err(entries.error().unwrap_or("Bad directory entry")),
+        }
+    }
 
-            // Read each sector in cluster
-            for s in 0..self.sectors_per_cluster {
-                if bytes_read >= to_read { break; }
+    /// Create an empty file, or an empty directory, named `name` in the
+    /// directory at `dir`
+    ///
+    /// `name` is UTF-8. Unless it is an 8.3 name in upper case it gets
+    /// long name entries, beside the 8.3 name itself or one made up from
+    /// it (`Pokemon Red.sav` -> `POKEMO~1.SAV`).
+    pub fn create_entry(&mut self, dir: u32, name: &[u8], directory: bool) -> Result<DirEntry, &'static str> {
+        let dir = if dir == 0 { self.root_cluster } else { dir };
+        if self.find_entry(dir, name)?.is_some() { return Err("File exists"); }
+
+        let mut entry = Self::new_entry(b"           ", 0, 0);
+        if directory {
+            // One zeroed cluster holding `.` and `..` (0 for the root)
+            let cluster = self.find_free_clusters(1)?[0];
+            let parent = if dir == self.root_cluster { 0 } else { dir };
+            let mut dots = [0u8; 2 * DIR_ENTRY_SIZE];
+            for (i, (dot, first)) in [(b".          ", cluster), (b"..         ", parent)].into_iter().enumerate() {
+                let mut raw = Self::new_entry(dot, first, 0);
+                raw[11] = ATTR_DIRECTORY;
+                dots[i * DIR_ENTRY_SIZE..(i + 1) * DIR_ENTRY_SIZE].copy_from_slice(&raw);
+            }
+            self.write_clusters(&[cluster], &dots)?;
+            self.claim_clusters(&[cluster], None)?;
+            Self::set_entry_data(&mut entry, cluster, 0);
+            entry[11] = ATTR_DIRECTORY;
+        }
 
-                self.read_sector(cluster_lba + s as u64, &mut sector_buf)?;
+        let entries = self.named_entries(dir, name, entry)?;
+        self.add_entries(dir, &entries)?;
+        self.find_entry(dir, name)?.ok_or("Created entry not found")
+    }
 
-                let copy_len = (to_read - bytes_read).min(SECTOR_SIZE);
-                buffer[bytes_read..bytes_read + copy_len].copy_from_slice(&sector_buf[..copy_len]);
-                bytes_read += copy_len;
+    /// Read from byte `offset` of a file into `buf`; returns the bytes read
+    /// (fewer than `buf` holds at the end of the file)
+    pub fn read_at(&self, entry: &DirEntry, offset: u32, buf: &mut [u8]) -> Result<usize, &'static str> {
+        if !self.mounted { return Err("Not mounted"); }
+        let offset = offset as usize;
+        if offset >= entry.size as usize {
+            return Ok(0);
+        }
+
+        let to_read = (entry.size as usize - offset).min(buf.len());
+        let cluster_bytes = self.cluster_size();
+        let mut cluster = entry.first_cluster;
+        for _ in 0..offset / cluster_bytes {
+            cluster = self.get_next_cluster(cluster)?;
+        }
+
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut within = offset % cluster_bytes;
+        let mut done = 0;
+        while done < to_read {
+            if !self.is_data_cluster(cluster) {
+                return Err("Cluster chain ends early");
+            }
+            let cluster_lba = self.cluster_to_sector(cluster);
+            while within < cluster_bytes && done < to_read {
+                let at = within % SECTOR_SIZE;
+                self.read_sector(cluster_lba + (within / SECTOR_SIZE) as u64, &mut sector)?;
+                let n = (SECTOR_SIZE - at).min(to_read - done);
+                buf[done..done + n].copy_from_slice(&sector[at..at + n]);
+                done += n;
+                within += n;
             }
+            within = 0;
+            if done < to_read {
+                cluster = self.get_next_cluster(cluster)?;
+            }
+        }
+        Ok(done)
+    }
 
-            // Get next cluster from FAT
-            current_cluster = self.get_next_cluster(current_cluster)?;
+    /// Write `data` at byte `offset` of a file, extending it as needed
+    /// (with zeros up to `offset` if that is past the end)
+    pub fn write_at(&mut self, entry: &mut DirEntry, offset: u32, data: &[u8]) -> Result<(), &'static str> {
+        if !self.mounted { return Err("Not mounted"); }
+        let end = offset as u64 + data.len() as u64;
+        if end > u32::MAX as u64 { return Err("File too large"); }
+
+        let grow = if end as u32 > entry.size { Some(self.slot_of(entry)?) } else { None };
+        let chain = match &grow {
+            // Only a gap before `offset` needs clearing; `data` covers the rest
+            Some(slot) => self.extend_chain(slot, end as u32, offset > entry.size)?,
+            None => self.chain(entry.first_cluster)?,
+        };
+
+        let cluster_bytes = self.cluster_size();
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset as usize + done;
+            let cluster = *chain.get(pos / cluster_bytes).ok_or("Cluster chain ends early")?;
+            let within = pos % cluster_bytes;
+            let lba = self.cluster_to_sector(cluster) + (within / SECTOR_SIZE) as u64;
+            let at = within % SECTOR_SIZE;
+            let n = (SECTOR_SIZE - at).min(data.len() - done);
+            if n < SECTOR_SIZE {
+                self.read_sector(lba, &mut sector)?;
+            }
+            sector[at..at + n].copy_from_slice(&data[done..done + n]);
+            self.write_sector(lba, &sector)?;
+            done += n;
         }
 
-        Ok(bytes_read)
+        // The new size goes in only once the data is there (see `extend_chain`)
+        if let Some(mut slot) = grow {
+            self.finish_resize(&mut slot, chain, end as u32)?;
+            entry.first_cluster = slot.first_cluster;
+            entry.size = slot.size;
+        }
+        Ok(())
     }
 
-    fn get_next_cluster(&self, cluster: u32) -> Result<u32, &'static str> {
-        let fat_offset = cluster * 4;
-        let fat_sector = self.fat_start_sector + (fat_offset / self.bytes_per_sector);
-        let entry_offset = (fat_offset % self.bytes_per_sector) as usize;
+    /// Truncate or extend a file to `size` bytes (extending adds zeros)
+    pub fn set_size(&mut self, entry: &mut DirEntry, size: u32) -> Result<(), &'static str> {
+        let mut slot = self.slot_of(entry)?;
+        self.resize_slot(&mut slot, size, true)?;
+        entry.first_cluster = slot.first_cluster;
+        entry.size = slot.size;
+        Ok(())
+    }
 
+    /// Delete a file, or a directory with nothing in it, freeing its
+    /// clusters
+    pub fn delete_entry(&mut self, entry: &DirEntry) -> Result<(), &'static str> {
+        if entry.is_dir() {
+            let mut entries = self.read_dir(entry.first_cluster);
+            if entries.any(|e| !e.is_dot()) {
+                return Err("Directory not empty");
+            }
+            if let Some(e) = entries.error() {
+                return Err(e);
+            }
+        }
+        let chain = self.chain(entry.first_cluster)?;
+        self.delete_slot(&self.slot_of(entry)?)?;
+        self.release_clusters(&chain)
+    }
+
+    /// Move a file or directory to `name` in the directory at `dir`,
+    /// returning its new entry; its clusters stay where they are
+    pub fn rename_entry(&mut self, entry: &DirEntry, dir: u32, name: &[u8]) -> Result<DirEntry, &'static str> {
+        let dir = if dir == 0 { self.root_cluster } else { dir };
+        if self.find_entry(dir, name)?.is_some() { return Err("File exists"); }
+
+        // Same attributes, dates, clusters and size under the new name
+        let slot = self.slot_of(entry)?;
         let mut sector = [0u8; SECTOR_SIZE];
-        self.read_sector(fat_sector as u64, &mut sector)?;
+        self.read_sector(slot.lba, &mut sector)?;
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw.copy_from_slice(&sector[slot.offset..slot.offset + DIR_ENTRY_SIZE]);
+
+        // New entry first: a crash then leaves the file under both names
+        let entries = self.named_entries(dir, name, raw)?;
+        self.add_entries(dir, &entries)?;
+        self.delete_slot(&self.slot_of(entry)?)?;
+
+        if entry.is_dir() && dir != entry.dir_cluster {
+            // The `..` entry follows the directory to its new parent
+            let parent = if dir == self.root_cluster { 0 } else { dir };
+            let (lba, offset) = self.entry_location(entry.first_cluster, 1)?;
+            self.read_sector(lba, &mut sector)?;
+            if sector[offset..offset + 11] == *b"..         " {
+                let size = u32::from_le_bytes([sector[offset + 28], sector[offset + 29], sector[offset + 30], sector[offset + 31]]);
+                Self::set_entry_data(&mut sector[offset..offset + DIR_ENTRY_SIZE], parent, size);
+                self.write_sector(lba, &sector)?;
+            }
+        }
+        self.find_entry(dir, name)?.ok_or("Renamed entry not found")
+    }
+
+    /// Directory entries naming `entry` (an 8.3 entry with everything but
+    /// the name filled in) `name` in the directory at `dir`: LFN entries
+    /// where needed, then `entry`
+    fn named_entries(&self, dir: u32, name: &[u8], mut entry: [u8; DIR_ENTRY_SIZE]) -> Result<Vec<[u8; DIR_ENTRY_SIZE]>, &'static str> {
+        let text = core::str::from_utf8(name).map_err(|_| "Bad file name")?;
+        let trimmed = text.trim_end_matches(['.', ' ']);
+        if trimmed.is_empty()
+            || trimmed.len() != text.len()
+            || text.bytes().any(|c| c < 0x20 || INVALID_NAME_CHARS.contains(&c))
+        {
+            return Err("Bad file name");
+        }
+
+        let short = match short_name(name) {
+            Some(short) => short,
+            None => self.unique_short_name(dir, name)?,
+        };
+        entry[..11].copy_from_slice(&short);
+        entry[12] = 0;
+
+        let mut shown = [0u8; MAX_NAME_LEN];
+        let shown_len = short_display_name(&short, 0, &mut shown);
+        let mut entries = Vec::new();
+        if shown[..shown_len] != *name {
+            let chars: Vec<u16> = text.encode_utf16().collect();
+            if chars.len() > MAX_NAME_LEN { return Err("File name too long"); }
+            let count = chars.len().div_ceil(LFN_CHARS);
+            let checksum = lfn_checksum(&short);
+            for seq in (1..=count).rev() {
+                let mut lfn = [0u8; DIR_ENTRY_SIZE];
+                lfn[0] = seq as u8 | if seq == count { LFN_LAST_ENTRY } else { 0 };
+                lfn[11] = ATTR_LFN;
+                lfn[13] = checksum;
+                for (i, &at) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                    // The name ends with a NUL if there is room, then 0xFFFF
+                    let pos = (seq - 1) * LFN_CHARS + i;
+                    let c = match pos.cmp(&chars.len()) {
+                        core::cmp::Ordering::Less => chars[pos],
+                        core::cmp::Ordering::Equal => 0x0000,
+                        core::cmp::Ordering::Greater => 0xFFFF,
+                    };
+                    lfn[at..at + 2].copy_from_slice(&c.to_le_bytes());
+                }
+                entries.push(lfn);
+            }
+        }
+        entries.push(entry);
+        Ok(entries)
+    }
 
-        let next = u32::from_le_bytes([
-            sector[entry_offset],
-            sector[entry_offset + 1],
-            sector[entry_offset + 2],
-            sector[entry_offset + 3],
-        ]) & 0x0FFFFFFF;
+    /// An 8.3 name for the long name `name` no entry of the directory at
+    /// `dir` has: its first letters and digits in upper case with a `~N`
+    /// tail, and the start of its extension
+    fn unique_short_name(&self, dir: u32, name: &[u8]) -> Result<[u8; 11], &'static str> {
+        let (base, ext) = match name.iter().rposition(|&c| c == b'.') {
+            Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+            _ => (name, &[][..]),
+        };
+        // Spaces and dots go; other characters 8.3 can't hold become `_`
+        // (one per UTF-8 sequence)
+        let clean = |part: &[u8], out: &mut [u8]| -> usize {
+            let chars = part
+                .iter()
+                .filter(|&&c| c != b' ' && c != b'.' && !(0x80..0xC0).contains(&c))
+                .map(|&c| match c {
+                    c if c.is_ascii_alphanumeric() || SHORT_NAME_PUNCTUATION.contains(&c) => c.to_ascii_uppercase(),
+                    _ => b'_',
+                });
+            let mut len = 0;
+            for (slot, c) in out.iter_mut().zip(chars) {
+                *slot = c;
+                len += 1;
+            }
+            len
+        };
+
+        let mut basis = [b' '; 11];
+        let base_len = clean(base, &mut basis[..8]).max(1);
+        if basis[0] == b' ' {
+            basis[0] = b'_';
+        }
+        clean(ext, &mut basis[8..]);
 
-        Ok(next)
+        let mut entries = self.read_dir(dir);
+        let taken: Vec<[u8; 11]> = entries.by_ref().map(|e| e.short_name).collect();
+        if let Some(e) = entries.error() {
+            return Err(e);
+        }
+
+        for n in 1..=MAX_NAME_TAIL {
+            let mut tail = [0u8; 8];
+            let mut tail_len = 0;
+            let mut rest = n;
+            while rest > 0 {
+                tail[7 - tail_len] = b'0' + (rest % 10) as u8;
+                tail_len += 1;
+                rest /= 10;
+            }
+            tail[7 - tail_len] = b'~';
+            tail_len += 1;
+
+            let keep = base_len.min(8 - tail_len);
+            let mut candidate = basis;
+            candidate[keep..keep + tail_len].copy_from_slice(&tail[8 - tail_len..]);
+            candidate[keep + tail_len..8].fill(b' ');
+            if !taken.contains(&candidate) {
+                return Ok(candidate);
+            }
+        }
+        Err("No free 8.3 name")
     }
 }
 
-// Global instance
-static mut FAT32_FS: Fat32 = Fat32::new();
+/// The 8.3 directory name for `name` if it is a valid one in any case
+/// (`Tetris.sav` -> `TETRIS  SAV`)
+pub fn short_name(name: &[u8]) -> Option<[u8; 11]> {
+    let (base, ext) = match name.iter().rposition(|&c| c == b'.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, &[][..]),
+    };
+    let valid = |c: &u8| c.is_ascii_alphanumeric() || SHORT_NAME_PUNCTUATION.contains(c);
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || !base.iter().all(valid) || !ext.iter().all(valid) {
+        return None;
+    }
 
-pub fn get_fs() -> &'static mut Fat32 {
-    unsafe { &mut FAT32_FS }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base);
+    short[8..8 + ext.len()].copy_from_slice(ext);
+    short.make_ascii_uppercase();
+    Some(short)
 }
 
-pub fn mount(device_index: usize) -> Result<(), &'static str> {
-    get_fs().mount(device_index)
+/// True if an FSInfo sector has all three signatures
+fn fsinfo_valid(sector: &[u8]) -> bool {
+    let sig = |at: usize| u32::from_le_bytes([sector[at], sector[at + 1], sector[at + 2], sector[at + 3]]);
+    sig(0) == FSINFO_LEAD_SIG && sig(484) == FSINFO_STRUCT_SIG && sig(508) == FSINFO_TRAIL_SIG
 }
 
-pub fn is_mounted() -> bool {
-    get_fs().is_mounted()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fat12() -> Fat32 {
+        let mut fs = Fat32::new();
+        fs.fat_type = FatType::Fat12;
+        fs.sectors_per_fat = 9;
+        fs
+    }
+
+    #[test]
+    fn test_fat12_entries_across_sectors() {
+        let fs = fat12();
+        // (cluster, FAT sector, byte in it, bytes of 0xABC stored there)
+        let cases = [
+            (340, 0, 510, [0xBC, 0x0A]),
+            (341, 0, 511, [0xC0, 0xAB]),
+            (342, 1, 1, [0xBC, 0x0A]),
+            (680, 1, 508, [0xBC, 0x0A]),
+            (681, 1, 509, [0xC0, 0xAB]),
+            (682, 1, 511, [0xBC, 0x0A]),
+        ];
+        for (cluster, sector, at, bytes) in cases {
+            assert_eq!(fs.fat_position(cluster), (sector, at), "cluster {}", cluster);
+            assert!(fs.fat_window_spans(sector));
+
+            let mut window = [0u8; 2 * SECTOR_SIZE];
+            fs.set_fat_value(&mut window, at, cluster, 0xABC);
+            assert_eq!(window[at..at + 2], bytes, "cluster {}", cluster);
+            assert_eq!(fs.fat_value(&window, at, cluster), 0xABC, "cluster {}", cluster);
+        }
+        // The last FAT sector has nothing after it to read
+        assert!(!fs.fat_window_spans(8));
+    }
+
+    #[test]
+    fn test_fat12_neighbours_kept() {
+        let fs = fat12();
+        // Every entry of the first two sectors' window, written one by
+        // one: each write leaves the entries sharing its bytes alone
+        let mut window = [0u8; 2 * SECTOR_SIZE];
+        let value = |cluster: u32| (cluster * 0x9E5) & 0x0FFF;
+        let clusters = 0..(2 * SECTOR_SIZE as u32 * 2 / 3);
+        for cluster in clusters.clone() {
+            let (sector, at) = fs.fat_position(cluster);
+            fs.set_fat_value(&mut window, sector as usize * SECTOR_SIZE + at, cluster, value(cluster));
+        }
+        for cluster in clusters {
+            let (sector, at) = fs.fat_position(cluster);
+            assert_eq!(fs.fat_value(&window, sector as usize * SECTOR_SIZE + at, cluster), value(cluster), "cluster {}", cluster);
+        }
+    }
+
+    #[test]
+    fn test_fat_type_from_bpb_not_device() {
+        // 4 GiB FAT32 volume, 8-sector clusters, 2 FATs of 8192 sectors
+        let mut sector = [0u8; SECTOR_SIZE];
+        sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+        sector[13] = 8;
+        sector[14..16].copy_from_slice(&32u16.to_le_bytes());
+        sector[16] = 2;
+        sector[32..36].copy_from_slice(&8_388_608u32.to_le_bytes());
+        sector[36..40].copy_from_slice(&8192u32.to_le_bytes());
+        sector[44..48].copy_from_slice(&2u32.to_le_bytes());
+        sector[48..50].copy_from_slice(&1u16.to_le_bytes());
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+
+        // A device cut down to a FAT16-sized cluster count
+        let mut fs = Fat32::new();
+        assert_eq!(fs.parse_bpb(&sector, 200_000), Ok(1));
+        assert_eq!(fs.fat_type, FatType::Fat32);
+        assert_eq!(fs.data_start_sector, 32 + 2 * 8192);
+        assert_eq!(fs.cluster_count, (200_000 - (32 + 2 * 8192)) / 8);
+
+        // The whole volume: as many clusters as its FAT holds
+        assert_eq!(fs.parse_bpb(&sector, 8_388_608), Ok(1));
+        assert_eq!(fs.cluster_count, (8_388_608 - (32 + 2 * 8192)) / 8);
+    }
 }
@@ -0,0 +1,120 @@
+//! Per-Game Preferences
+//!
+//! Display preferences remembered for each ROM, stored through the VFS in a
+//! `.cfg` file beside the game's save (`/GAMES/Tetris.sav` ->
+//! `/GAMES/Tetris.cfg`).
+//!
+//! # File Layout
+//!
+//! ```text
+//! Offset  Size  Field
+//! 0       4     Magic "GBPF"
+//! 4       1     Record version
+//! 5       1     Color curve (vga_palette::ColorCurve::to_u8)
+//! 6       1     User gamma, tenths
+//! 7       1     Zero
+//! 8       8     DMG palette preset name (null-padded; by name so
+//!               that PALETTES.TXT can change without shifting it)
+//! ```
+
+use crate::fs::{vfs, FsResult};
+use crate::graphics::dmg_palettes::{self, NAME_LEN};
+use crate::graphics::vga_palette::{self, ColorCurve, GAMMA_DEFAULT};
+use crate::storage::savefile::SaveFile;
+
+// =============================================================================
+// Constants
+// =============================================================================
+
+/// Extension of the preferences file
+pub const PREFS_EXTENSION: &str = "cfg";
+
+/// Magic bytes for a record
+const PREFS_MAGIC: [u8; 4] = [b'G', b'B', b'P', b'F'];
+
+/// Current record version
+const PREFS_VERSION: u8 = 2;
+
+/// Size of a record
+const RECORD_SIZE: usize = 16;
+
+// =============================================================================
+// Preferences
+// =============================================================================
+
+/// Preferences for one game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GamePrefs {
+    pub color_curve: ColorCurve,
+    /// User gamma, tenths
+    pub gamma: u8,
+    /// `dmg_palettes` preset index
+    pub dmg_palette: usize,
+}
+
+impl GamePrefs {
+    pub const DEFAULT: GamePrefs =
+        GamePrefs { color_curve: ColorCurve::Raw, gamma: GAMMA_DEFAULT, dmg_palette: 0 };
+
+    /// Preferences as currently in effect
+    pub fn current() -> GamePrefs {
+        let (color_curve, gamma) = vga_palette::color_curve();
+        GamePrefs { color_curve, gamma, dmg_palette: dmg_palettes::selected() }
+    }
+
+    /// Put these preferences into effect
+    pub fn apply(&self) {
+        vga_palette::set_color_curve(self.color_curve, self.gamma);
+        dmg_palettes::select(self.dmg_palette);
+    }
+
+    fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&PREFS_MAGIC);
+        bytes[4] = PREFS_VERSION;
+        bytes[5] = self.color_curve.to_u8();
+        bytes[6] = self.gamma;
+        let preset = dmg_palettes::get(self.dmg_palette).name.as_bytes();
+        bytes[8..8 + preset.len()].copy_from_slice(preset);
+        bytes
+    }
+
+    /// Parse a record; unknown values fall back to the defaults
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Option<GamePrefs> {
+        if bytes[0..4] != PREFS_MAGIC || bytes[4] == 0 {
+            return None;
+        }
+        let preset = &bytes[8..8 + NAME_LEN];
+        let preset_len = preset.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+        let dmg_palette = core::str::from_utf8(&preset[..preset_len])
+            .ok()
+            .and_then(dmg_palettes::find)
+            .unwrap_or(Self::DEFAULT.dmg_palette);
+        Some(GamePrefs {
+            color_curve: ColorCurve::from_u8(bytes[5]).unwrap_or(Self::DEFAULT.color_curve),
+            gamma: match bytes[6] {
+                0 => Self::DEFAULT.gamma,
+                gamma => gamma,
+            },
+            dmg_palette,
+        })
+    }
+}
+
+// =============================================================================
+// Load / Save
+// =============================================================================
+
+/// Stored preferences from `prefs_file`, if it holds any
+pub fn load(prefs_file: &SaveFile) -> Option<GamePrefs> {
+    let mut bytes = [0u8; RECORD_SIZE];
+    match vfs::read_file(prefs_file.path(), &mut bytes) {
+        Ok(RECORD_SIZE) => GamePrefs::from_bytes(&bytes),
+        _ => None,
+    }
+}
+
+/// Store preferences in `prefs_file`
+pub fn save(prefs_file: &SaveFile, prefs: &GamePrefs) -> FsResult<()> {
+    vfs::write_file(prefs_file.path(), &prefs.to_bytes())
+}
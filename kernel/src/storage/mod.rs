@@ -5,11 +5,15 @@
 
 pub mod pci;
 pub mod ata;
+pub mod busmaster;
+pub mod partition;
 pub mod fat32;
 
 pub mod savefile;
+pub mod game_prefs;
 
 use crate::arch::x86::io::outb;
+use crate::fs::vfs;
 
 // =============================================================================
 // Debug Output
@@ -88,6 +92,8 @@ pub struct StorageInitResult {
     pub ide_found: bool,
     /// Number of ATA devices found
     pub ata_devices: usize,
+    /// Number of disks switched to Ultra DMA
+    pub dma_disks: usize,
     /// Error stage (0 = no error)
     pub error_stage: u8,
     /// Error message
@@ -106,6 +112,7 @@ pub fn init() -> StorageInitResult {
         pci_devices: 0,
         ide_found: false,
         ata_devices: 0,
+        dma_disks: 0,
         error_stage: 0,
         error_msg: None,
     };
@@ -158,6 +165,13 @@ pub fn init() -> StorageInitResult {
     // Show ATA device count
     debug_hex(7, result.ata_devices as u32);
 
+    // Bus-master DMA for the disks that negotiate Ultra DMA
+    if let Some(ide) = pci::find_ide_controller() {
+        if result.ata_devices > 0 && busmaster::init(ide) {
+            result.dma_disks = ata::enable_dma();
+        }
+    }
+
     // Stage 4: Overall status
     if result.ata_devices > 0 {
         debug_bar(5, 4, colors::GREEN);
@@ -274,7 +288,7 @@ pub fn read_sectors(
 // FAT32 Integration
 // =============================================================================
 
-/// Mount FAT32 filesystem from first ATA device
+/// Mount the first FAT or exFAT volume on the first ATA device at `/`
 /// Returns true if successful
 pub fn mount_fat32() -> bool {
     // Debug: show we're starting
@@ -299,16 +313,18 @@ pub fn mount_fat32() -> bool {
 
     debug_bar(9, 1, colors::YELLOW);  // Found device, attempting mount
 
-    match fat32::mount(device_index) {
-        Ok(()) => {
-            debug_bar(9, 1, colors::GREEN);
-            debug_bar(9, 2, colors::GREEN);
-            true
-        }
-        Err(_) => {
-            debug_bar(9, 1, colors::RED);
-            false
-        }
+    // First partition holding a FAT or exFAT volume, at the top of the VFS
+    let mounted = vfs::is_mounted("/") || partition::scan(device_index)
+        .iter()
+        .filter(|p| p.kind.is_supported())
+        .any(|p| vfs::mount_volume(p.volume, "/").is_ok());
+    if mounted {
+        debug_bar(9, 1, colors::GREEN);
+        debug_bar(9, 2, colors::GREEN);
+        true
+    } else {
+        debug_bar(9, 1, colors::RED);
+        false
     }
 }
 
@@ -345,12 +361,11 @@ fn find_ata_device_index() -> Option<usize> {
 /// List ROM files (.gb, .gbc) in root directory
 /// Returns count of files found
 pub fn list_rom_files() -> usize {
-    if !fat32::is_mounted() {
+    let mut count = 0;
+    if vfs::for_each_entry("/", |entry| count += entry.is_rom() as usize).is_err() {
         return 0;
     }
 
-    let count = fat32::get_fs().count_roms();
-
     // Show count on debug bar
     debug_bar(9, 3, if count > 0 { colors::GREEN } else { colors::BROWN });
     debug_hex(9, count as u32);
@@ -366,10 +381,6 @@ pub fn test_fat32() -> bool {
         return false;
     }
 
-    let rom_count = fat32::get_fs().count_roms();
-
-    debug_bar(9, 3, if rom_count > 0 { colors::GREEN } else { colors::BROWN });
-    debug_hex(9, rom_count as u32);
-
-    rom_count > 0
+    // Shows the count on the debug bar
+    list_rom_files() > 0
 }
@@ -0,0 +1,588 @@
+//! Partition Tables
+//!
+//! Finds the volumes on an ATA disk and hands each one out as a
+//! `BlockDevice`: a run of the disk's sectors that a filesystem driver
+//! addresses from 0, whatever partition scheme it came from.
+//!
+//! - MBR: all four primary entries, and the logical partitions of an
+//!   extended partition by following its chain of EBRs
+//! - GPT (behind a protective MBR entry of type 0xEE): the header and the
+//!   partition entry array must match their CRC32s; if the primary header
+//!   fails, the backup at the end of the disk is tried
+//! - A disk whose sector 0 is a FAT or exFAT boot sector has no partition
+//!   table and is one volume
+//!
+//! Partitions are numbered as Linux does: MBR primaries 1-4, logical
+//! partitions from 5, GPT entries by their place in the array.
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::storage::ata;
+
+// =============================================================================
+// Constants
+// =============================================================================
+
+const SECTOR_SIZE: usize = 512;
+
+/// MBR partition table: offset, entry size, boot signature offset
+const MBR_TABLE: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const BOOT_SIGNATURE: usize = 510;
+
+/// MBR partition types
+const MBR_FAT_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
+const MBR_EXFAT: u8 = 0x07;
+const MBR_EXTENDED_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+const MBR_EFI_SYSTEM: u8 = 0xEF;
+
+/// Logical partitions followed before an EBR chain is taken to loop
+const MAX_LOGICAL: usize = 64;
+
+/// GPT header signature and the sizes we accept
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN: usize = 92;
+const GPT_ENTRY_MIN: usize = 128;
+const GPT_MAX_ENTRIES: usize = 256;
+
+/// GPT type GUIDs, in their on-disk byte order (the first three fields
+/// little-endian)
+const GUID_EFI_SYSTEM: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11,
+    0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+const GUID_BASIC_DATA: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+    0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+// =============================================================================
+// Block Device
+// =============================================================================
+
+/// A volume's sectors on an ATA device, addressed from 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockDevice {
+    device_index: usize,
+    start: u64,
+    sectors: u64,
+}
+
+impl BlockDevice {
+    /// `sectors` sectors of ATA device `device_index` from LBA `start`
+    pub const fn new(device_index: usize, start: u64, sectors: u64) -> Self {
+        Self { device_index, start, sectors }
+    }
+
+    /// All of ATA device `device_index`
+    pub fn whole_disk(device_index: usize) -> Option<Self> {
+        let device = ata::get_device(device_index)?;
+        Some(Self::new(device_index, 0, device.sectors))
+    }
+
+    pub fn device_index(&self) -> usize {
+        self.device_index
+    }
+
+    /// First sector on the disk
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Length in sectors
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    /// Read `count` sectors from `lba` (within the volume)
+    pub fn read_sectors(&self, lba: u64, count: u8, buf: &mut [u8]) -> Result<(), &'static str> {
+        let device = self.device(lba, count)?;
+        ata::read_sectors(device, self.start + lba, count, buf).map_err(|_| "Read failed")?;
+        Ok(())
+    }
+
+    /// Write `count` sectors at `lba` (within the volume)
+    pub fn write_sectors(&self, lba: u64, count: u8, buf: &[u8]) -> Result<(), &'static str> {
+        let device = self.device(lba, count)?;
+        ata::write_sectors(device, self.start + lba, count, buf).map_err(|_| "Write failed")?;
+        Ok(())
+    }
+
+    /// The ATA device, if `count` sectors from `lba` are inside the volume
+    fn device(&self, lba: u64, count: u8) -> Result<&'static ata::AtaDevice, &'static str> {
+        if count == 0 || lba.saturating_add(count as u64) > self.sectors {
+            return Err("Beyond end of volume");
+        }
+        ata::get_device(self.device_index).ok_or("No device")
+    }
+}
+
+/// Where partition tables are read from: a `BlockDevice`, or a disk
+/// image in memory for the tests
+trait SectorSource {
+    /// The disk the sectors belong to
+    fn disk(&self) -> BlockDevice;
+
+    /// Read the sector at `lba` into `buf`
+    fn read_sector(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str>;
+}
+
+impl SectorSource for BlockDevice {
+    fn disk(&self) -> BlockDevice {
+        *self
+    }
+
+    fn read_sector(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.read_sectors(lba, 1, buf)
+    }
+}
+
+// =============================================================================
+// Partitions
+// =============================================================================
+
+/// What a partition table says a partition holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// MBR FAT12/16/32 types
+    Fat,
+    /// MBR type 0x07, exFAT or NTFS
+    Exfat,
+    /// EFI system partition (FAT)
+    EfiSystem,
+    /// GPT basic data partition (FAT, exFAT or NTFS)
+    BasicData,
+    /// A disk without a partition table
+    WholeDisk,
+    /// Any other MBR type (0 for GPT types not listed here)
+    Other(u8),
+}
+
+impl PartitionKind {
+    /// True if a FAT or exFAT driver may find its volume here
+    pub fn is_supported(self) -> bool {
+        !matches!(self, PartitionKind::Other(_))
+    }
+
+    fn from_mbr_type(partition_type: u8) -> PartitionKind {
+        match partition_type {
+            t if MBR_FAT_TYPES.contains(&t) => PartitionKind::Fat,
+            MBR_EXFAT => PartitionKind::Exfat,
+            MBR_EFI_SYSTEM => PartitionKind::EfiSystem,
+            t => PartitionKind::Other(t),
+        }
+    }
+
+    fn from_gpt_type(guid: &[u8]) -> PartitionKind {
+        if guid == GUID_EFI_SYSTEM {
+            PartitionKind::EfiSystem
+        } else if guid == GUID_BASIC_DATA {
+            PartitionKind::BasicData
+        } else {
+            PartitionKind::Other(0)
+        }
+    }
+}
+
+/// One volume found on a disk
+#[derive(Debug, Clone, Copy)]
+pub struct Partition {
+    /// Number on its disk (see the module docs; 0 for a whole disk)
+    pub number: u32,
+    pub kind: PartitionKind,
+    pub volume: BlockDevice,
+}
+
+/// Partitions of every ATA disk, disk by disk
+pub fn scan_all() -> Vec<Partition> {
+    (0..ata::MAX_DEVICES)
+        .filter(|&i| ata::get_device(i).is_some_and(|d| d.device_type == ata::DeviceType::Ata))
+        .flat_map(scan)
+        .collect()
+}
+
+/// Partitions of ATA device `device_index`, in table order
+///
+/// Empty if the disk can't be read or sector 0 is neither a partition
+/// table nor a boot sector.
+pub fn scan(device_index: usize) -> Vec<Partition> {
+    let Some(disk) = BlockDevice::whole_disk(device_index) else { return Vec::new() };
+    let mut sector = [0u8; SECTOR_SIZE];
+    if disk.read_sectors(0, 1, &mut sector).is_err() || sector[BOOT_SIGNATURE..] != [0x55, 0xAA] {
+        return Vec::new();
+    }
+
+    if is_boot_sector(&sector) {
+        return vec![Partition { number: 0, kind: PartitionKind::WholeDisk, volume: disk }];
+    }
+
+    let entries: Vec<MbrEntry> = (0..4).map(|i| MbrEntry::parse(&sector, i)).collect();
+    if entries.iter().any(|e| e.partition_type == MBR_GPT_PROTECTIVE) {
+        if let Some(partitions) = gpt_partitions(&disk) {
+            return partitions;
+        }
+        // A hybrid MBR may still list the volumes
+    }
+
+    let mut partitions = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.partition_type == 0 || entry.partition_type == MBR_GPT_PROTECTIVE {
+            continue;
+        }
+        if MBR_EXTENDED_TYPES.contains(&entry.partition_type) {
+            logical_partitions(&disk, entry.start, &mut partitions);
+        } else if let Some(volume) = slice(&disk, entry.start, entry.sectors) {
+            partitions.push(Partition {
+                number: i as u32 + 1,
+                kind: PartitionKind::from_mbr_type(entry.partition_type),
+                volume,
+            });
+        }
+    }
+    partitions
+}
+
+/// True if `sector` is a FAT or exFAT boot sector rather than an MBR
+fn is_boot_sector(sector: &[u8; SECTOR_SIZE]) -> bool {
+    if &sector[3..11] == b"EXFAT   " {
+        return true;
+    }
+    let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]);
+    let sectors_per_cluster = sector[13];
+    let reserved_sectors = u16::from_le_bytes([sector[14], sector[15]]);
+    let num_fats = sector[16];
+    (512..=4096).contains(&bytes_per_sector)
+        && bytes_per_sector.is_power_of_two()
+        && sectors_per_cluster.is_power_of_two()
+        && reserved_sectors != 0
+        && (num_fats == 1 || num_fats == 2)
+}
+
+/// The part of `disk` a table entry describes, if it is inside the disk
+fn slice(disk: &BlockDevice, start: u64, sectors: u64) -> Option<BlockDevice> {
+    let end = start.checked_add(sectors)?;
+    (start != 0 && sectors != 0 && end <= disk.sectors())
+        .then(|| BlockDevice::new(disk.device_index(), disk.start() + start, sectors))
+}
+
+// =============================================================================
+// MBR
+// =============================================================================
+
+/// A partition table entry (of an MBR or EBR)
+struct MbrEntry {
+    partition_type: u8,
+    start: u64,
+    sectors: u64,
+}
+
+impl MbrEntry {
+    fn parse(sector: &[u8; SECTOR_SIZE], index: usize) -> MbrEntry {
+        let e = &sector[MBR_TABLE + index * MBR_ENTRY_SIZE..MBR_TABLE + (index + 1) * MBR_ENTRY_SIZE];
+        MbrEntry {
+            partition_type: e[4],
+            start: u32::from_le_bytes([e[8], e[9], e[10], e[11]]) as u64,
+            sectors: u32::from_le_bytes([e[12], e[13], e[14], e[15]]) as u64,
+        }
+    }
+}
+
+/// Add the logical partitions of the extended partition at `extended`
+///
+/// Each EBR's first entry is a logical partition, from the EBR itself;
+/// its second links the next EBR, from the start of the extended
+/// partition. The chain ends early at an EBR already visited.
+fn logical_partitions(source: &impl SectorSource, extended: u64, out: &mut Vec<Partition>) {
+    let disk = source.disk();
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut visited = Vec::new();
+    let mut ebr = extended;
+    for number in 5..5 + MAX_LOGICAL as u32 {
+        if ebr >= disk.sectors()
+            || visited.contains(&ebr)
+            || source.read_sector(ebr, &mut sector).is_err()
+            || sector[BOOT_SIGNATURE..] != [0x55, 0xAA]
+        {
+            return;
+        }
+        visited.push(ebr);
+
+        let logical = MbrEntry::parse(&sector, 0);
+        if logical.partition_type != 0 {
+            if let Some(volume) = slice(&disk, ebr + logical.start, logical.sectors) {
+                out.push(Partition { number, kind: PartitionKind::from_mbr_type(logical.partition_type), volume });
+            }
+        }
+
+        let next = MbrEntry::parse(&sector, 1);
+        if !MBR_EXTENDED_TYPES.contains(&next.partition_type) || next.start == 0 {
+            return;
+        }
+        ebr = extended + next.start;
+    }
+}
+
+// =============================================================================
+// GPT
+// =============================================================================
+
+/// Partitions listed by the GPT, from the primary header or else the
+/// backup; `None` if neither checks out
+fn gpt_partitions(source: &impl SectorSource) -> Option<Vec<Partition>> {
+    [1, source.disk().sectors().saturating_sub(1)].into_iter().find_map(|lba| read_gpt(source, lba))
+}
+
+/// Partitions listed by the GPT header at `lba`
+fn read_gpt(source: &impl SectorSource, lba: u64) -> Option<Vec<Partition>> {
+    let disk = source.disk();
+    let mut header = [0u8; SECTOR_SIZE];
+    source.read_sector(lba, &mut header).ok()?;
+
+    let u32_at = |at: usize| u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+    let u64_at = |at: usize| u32_at(at) as u64 | (u32_at(at + 4) as u64) << 32;
+    let header_size = u32_at(12) as usize;
+    if &header[..8] != GPT_SIGNATURE || !(GPT_HEADER_MIN..=SECTOR_SIZE).contains(&header_size) || u64_at(24) != lba {
+        return None;
+    }
+    let mut checked = header;
+    checked[16..20].fill(0);
+    if crc32(&checked[..header_size]) != u32_at(16) {
+        return None;
+    }
+
+    // Partition entry array
+    let entries_lba = u64_at(72);
+    let entry_count = u32_at(80) as usize;
+    let entry_size = u32_at(84) as usize;
+    if entry_count > GPT_MAX_ENTRIES || entry_size < GPT_ENTRY_MIN || !entry_size.is_power_of_two() || entry_size > SECTOR_SIZE {
+        return None;
+    }
+    let array_sectors = (entry_count * entry_size).div_ceil(SECTOR_SIZE);
+    let mut array = vec![0u8; array_sectors * SECTOR_SIZE];
+    for (i, chunk) in array.chunks_mut(SECTOR_SIZE).enumerate() {
+        source.read_sector(entries_lba + i as u64, chunk).ok()?;
+    }
+    if crc32(&array[..entry_count * entry_size]) != u32_at(88) {
+        return None;
+    }
+
+    let mut partitions = Vec::new();
+    for (i, entry) in array.chunks(entry_size).take(entry_count).enumerate() {
+        let type_guid = &entry[..16];
+        if type_guid.iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first = u64::from_le_bytes(entry[32..40].try_into().ok()?);
+        let last = u64::from_le_bytes(entry[40..48].try_into().ok()?);
+        if last < first {
+            continue;
+        }
+        if let Some(volume) = slice(&disk, first, last - first + 1) {
+            partitions.push(Partition { number: i as u32 + 1, kind: PartitionKind::from_gpt_type(type_guid), volume });
+        }
+    }
+    Some(partitions)
+}
+
+/// CRC-32 (IEEE 802.3, as GPT uses)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A disk image: sectors not listed read as zeros
+    struct Image {
+        sectors: u64,
+        data: Vec<(u64, [u8; SECTOR_SIZE])>,
+    }
+
+    impl Image {
+        fn new(sectors: u64) -> Image {
+            Image { sectors, data: Vec::new() }
+        }
+
+        fn sector(&mut self, lba: u64) -> &mut [u8; SECTOR_SIZE] {
+            if let Some(i) = self.data.iter().position(|(at, _)| *at == lba) {
+                return &mut self.data[i].1;
+            }
+            self.data.push((lba, [0; SECTOR_SIZE]));
+            &mut self.data.last_mut().unwrap().1
+        }
+    }
+
+    impl SectorSource for Image {
+        fn disk(&self) -> BlockDevice {
+            BlockDevice::new(0, 0, self.sectors)
+        }
+
+        fn read_sector(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+            match self.data.iter().find(|(at, _)| *at == lba) {
+                Some((_, sector)) => buf.copy_from_slice(sector),
+                None => buf.fill(0),
+            }
+            Ok(())
+        }
+    }
+
+    /// Write partition table entry `index` of `sector`, with the boot
+    /// signature
+    fn set_entry(sector: &mut [u8; SECTOR_SIZE], index: usize, partition_type: u8, start: u32, sectors: u32) {
+        let e = &mut sector[MBR_TABLE + index * MBR_ENTRY_SIZE..MBR_TABLE + (index + 1) * MBR_ENTRY_SIZE];
+        e[4] = partition_type;
+        e[8..12].copy_from_slice(&start.to_le_bytes());
+        e[12..16].copy_from_slice(&sectors.to_le_bytes());
+        sector[BOOT_SIGNATURE..].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    const DISK_SECTORS: u64 = 0x10000;
+
+    /// A GPT with its header at `lba` and entry array at `entries_lba`,
+    /// listing a basic data partition at 2048-4095 as entry 1 and an EFI
+    /// system partition at 4096-8191 as entry 3
+    fn write_gpt(image: &mut Image, lba: u64, entries_lba: u64) {
+        let mut array = [0u8; 4 * GPT_ENTRY_MIN];
+        for (slot, guid, first, last) in [(0, GUID_BASIC_DATA, 2048u64, 4095u64), (2, GUID_EFI_SYSTEM, 4096, 8191)] {
+            let entry = &mut array[slot * GPT_ENTRY_MIN..(slot + 1) * GPT_ENTRY_MIN];
+            entry[..16].copy_from_slice(&guid);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+        }
+        image.sector(entries_lba).copy_from_slice(&array[..SECTOR_SIZE]);
+
+        let header = image.sector(lba);
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[12..16].copy_from_slice(&(GPT_HEADER_MIN as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&(GPT_ENTRY_MIN as u32).to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(&array).to_le_bytes());
+        let crc = crc32(&header[..GPT_HEADER_MIN]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn summary(partitions: &[Partition]) -> Vec<(u32, PartitionKind, u64, u64)> {
+        partitions.iter().map(|p| (p.number, p.kind, p.volume.start(), p.volume.sectors())).collect()
+    }
+
+    #[test]
+    fn test_crc32() {
+        let cases: [(&[u8], u32); 4] = [
+            (b"", 0),
+            (b"a", 0xE8B7_BE43),
+            (b"123456789", 0xCBF4_3926),
+            (b"The quick brown fox jumps over the lazy dog", 0x414F_A339),
+        ];
+        for (data, crc) in cases {
+            assert_eq!(crc32(data), crc, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn test_read_gpt() {
+        let good = [(1, PartitionKind::BasicData, 2048, 2048), (3, PartitionKind::EfiSystem, 4096, 4096)];
+        // (header byte to flip, array byte to flip, valid)
+        let cases = [
+            (None, None, true),
+            // Past the header size, so outside its CRC
+            (Some(100), None, true),
+            (Some(0), None, false),
+            (Some(16), None, false),
+            (Some(40), None, false),
+            (None, Some(0), false),
+            (None, Some(300), false),
+        ];
+        for (header_byte, array_byte, valid) in cases {
+            let mut image = Image::new(DISK_SECTORS);
+            write_gpt(&mut image, 1, 2);
+            if let Some(at) = header_byte {
+                image.sector(1)[at] ^= 0x01;
+            }
+            if let Some(at) = array_byte {
+                image.sector(2)[at] ^= 0x01;
+            }
+            let found = read_gpt(&image, 1).map(|p| summary(&p));
+            assert_eq!(found, valid.then(|| good.to_vec()), "header {:?} array {:?}", header_byte, array_byte);
+        }
+    }
+
+    #[test]
+    fn test_gpt_backup() {
+        let mut image = Image::new(DISK_SECTORS);
+        write_gpt(&mut image, 1, 2);
+        write_gpt(&mut image, DISK_SECTORS - 1, DISK_SECTORS - 33);
+        image.sector(1)[16] ^= 0x01;
+        assert_eq!(gpt_partitions(&image).map(|p| p.len()), Some(2));
+
+        image.sector(DISK_SECTORS - 1)[16] ^= 0x01;
+        assert!(gpt_partitions(&image).is_none());
+    }
+
+    #[test]
+    fn test_logical_partitions() {
+        // EBRs at extended-relative offsets, each: (partition start from
+        // the EBR, sectors, next EBR or 0)
+        let cases: [(&[(u64, u32, u32, u32)], &[(u32, u64)]); 4] = [
+            // One logical partition
+            (&[(0, 63, 100, 0)], &[(5, 1063)]),
+            // Two, linked
+            (&[(0, 63, 100, 200), (200, 63, 100, 0)], &[(5, 1063), (6, 1263)]),
+            // The second EBR links back to itself
+            (&[(0, 63, 100, 200), (200, 63, 100, 200)], &[(5, 1063), (6, 1263)]),
+            // The third links back to the second
+            (&[(0, 63, 100, 200), (200, 63, 100, 400), (400, 63, 100, 200)], &[(5, 1063), (6, 1263), (7, 1463)]),
+        ];
+        for (ebrs, expected) in cases {
+            let mut image = Image::new(DISK_SECTORS);
+            let extended = 1000;
+            for &(at, start, sectors, next) in ebrs {
+                let sector = image.sector(extended + at);
+                set_entry(sector, 0, 0x0C, start, sectors);
+                if next != 0 {
+                    set_entry(sector, 1, 0x05, next, 100);
+                }
+            }
+
+            let mut found = Vec::new();
+            logical_partitions(&image, extended, &mut found);
+            let found: Vec<(u32, u64)> = found.iter().map(|p| (p.number, p.volume.start())).collect();
+            assert_eq!(found, expected, "{:?}", ebrs);
+        }
+    }
+
+    #[test]
+    fn test_is_boot_sector() {
+        // (bytes per sector, sectors per cluster, reserved, FATs, OEM name)
+        let cases: [(u16, u8, u16, u8, &[u8; 8], bool); 7] = [
+            (512, 8, 32, 2, b"MSWIN4.1", true),
+            (4096, 1, 1, 1, b"mkfs.fat", true),
+            (0, 0, 0, 0, b"EXFAT   ", true),
+            (0, 0, 0, 0, b"\0\0\0\0\0\0\0\0", false),
+            (513, 8, 32, 2, b"MSWIN4.1", false),
+            (512, 3, 32, 2, b"MSWIN4.1", false),
+            (512, 8, 32, 3, b"MSWIN4.1", false),
+        ];
+        for (bytes_per_sector, sectors_per_cluster, reserved, fats, oem, expected) in cases {
+            let mut sector = [0u8; SECTOR_SIZE];
+            sector[3..11].copy_from_slice(oem);
+            sector[11..13].copy_from_slice(&bytes_per_sector.to_le_bytes());
+            sector[13] = sectors_per_cluster;
+            sector[14..16].copy_from_slice(&reserved.to_le_bytes());
+            sector[16] = fats;
+            assert_eq!(is_boot_sector(&sector), expected, "{:?}", &sector[3..17]);
+        }
+    }
+}
@@ -1,28 +1,32 @@
 //! Save File Management
 //!
 //! Persists Game Boy cartridge SRAM to disk so game saves survive power cycles.
-//! Uses a dedicated save area on disk (bypasses filesystem for simplicity).
+//! Saves are `.sav` files next to the ROM with the ROM's name, holding the raw
+//! cartridge RAM like other emulators' saves, so they can be copied back and
+//! forth. They are read and written through the VFS, on any volume it mounts.
 //!
 //! # How It Works
 //!
 //! 1. Game writes to cartridge RAM (0xA000-0xBFFF) - this is the game "saving"
-//! 2. We periodically dump that RAM to disk sectors
+//! 2. We periodically dump that RAM to the save file
 //! 3. On ROM load, we restore any existing save back to RAM
 //!
-//! # Disk Layout
+//! # Old Slot Area
 //!
-//! Save area starts at sector 0x10000 (32MB offset):
+//! Earlier versions kept saves in raw sectors from 0x10000 (32MB offset):
 //! - Slot 0: Sectors 0x10000-0x1003F (32KB)
 //! - Slot 1: Sectors 0x10040-0x1007F
-//! - Up to 16 ROM saves supported
+//! - Up to 16 ROM saves
 //!
-//! Each slot has a header sector followed by raw SRAM data.
+//! Each slot has a header sector followed by raw SRAM data. A ROM without a
+//! save file gets its slot's save moved into one on first launch; nothing
+//! new is written there.
 
 extern crate alloc;
 
 use alloc::vec::Vec;
-use crate::arch::x86::io::{inb, outb, inw, outw};
-use crate::storage::ata::{self, AtaDevice, Channel, Drive, cmd, status};
+use crate::storage::ata;
+use crate::fs::{vfs, FsError, MAX_PATH};
 
 // =============================================================================
 // Constants
@@ -131,7 +135,7 @@ impl SaveHeader {
 // =============================================================================
 
 /// Simple hash function for ROM names
-fn hash_rom_name(name: &str) -> u32 {
+pub fn hash_rom_name(name: &str) -> u32 {
     let mut hash: u32 = 0x811c9dc5; // FNV-1a offset basis
     for byte in name.bytes() {
         hash ^= byte as u32;
@@ -140,155 +144,6 @@ fn hash_rom_name(name: &str) -> u32 {
     hash
 }
 
-// =============================================================================
-// ATA Write Implementation
-// =============================================================================
-
-/// Write sectors to an ATA device
-///
-/// - `device`: Device to write to
-/// - `lba`: Starting logical block address
-/// - `count`: Number of sectors to write (1-256, 0 means 256)
-/// - `buffer`: Buffer containing data to write (must be count * 512 bytes)
-pub fn write_sectors(
-    device: &AtaDevice,
-    lba: u64,
-    count: u8,
-    buffer: &[u8]
-) -> Result<usize, &'static str> {
-    use crate::storage::ata::DeviceType;
-
-    if device.device_type != DeviceType::Ata {
-        return Err("Not an ATA device");
-    }
-
-    let sector_size = device.sector_size as usize;
-    let actual_count = if count == 0 { 256 } else { count as usize };
-
-    if buffer.len() < actual_count * sector_size {
-        return Err("Buffer too small");
-    }
-
-    // Check LBA range
-    if lba >= device.sectors {
-        return Err("LBA out of range");
-    }
-
-    let base = device.channel.base_port();
-
-    // Select drive
-    if !select_drive(device.channel, device.drive) {
-        return Err("Drive select timeout");
-    }
-
-    // Wait for drive ready
-    if !wait_ready(device.channel, 100_000) {
-        return Err("Drive not ready");
-    }
-
-    unsafe {
-        // LBA28 mode (sufficient for our save area)
-        let drive_byte = device.drive.select_byte() | ((lba >> 24) & 0x0F) as u8;
-        outb(base + 6, drive_byte);
-        outb(base + 2, count);
-        outb(base + 3, (lba & 0xFF) as u8);
-        outb(base + 4, ((lba >> 8) & 0xFF) as u8);
-        outb(base + 5, ((lba >> 16) & 0xFF) as u8);
-        outb(base + 7, cmd::WRITE_SECTORS);
-    }
-
-    // Write each sector
-    let mut offset = 0;
-    for _ in 0..actual_count {
-        // Wait for DRQ
-        if !wait_drq(device.channel, 100_000) {
-            return Err("Timeout waiting for DRQ");
-        }
-
-        // Write sector data (256 words = 512 bytes)
-        let words = sector_size / 2;
-        for _ in 0..words {
-            let word = (buffer[offset] as u16) | ((buffer[offset + 1] as u16) << 8);
-            unsafe { outw(base, word); }
-            offset += 2;
-        }
-
-        // Small delay between sectors
-        io_delay(device.channel);
-    }
-
-    // Flush cache
-    unsafe {
-        outb(base + 7, cmd::FLUSH_CACHE);
-    }
-
-    // Wait for completion
-    if !wait_ready(device.channel, 500_000) {
-        return Err("Flush timeout");
-    }
-
-    Ok(actual_count)
-}
-
-// Helper functions (reuse from ata module or copy here)
-
-fn select_drive(channel: Channel, drive: Drive) -> bool {
-    let base = channel.base_port();
-
-    unsafe {
-        outb(base + 6, drive.select_byte());
-    }
-
-    // Wait for drive to be selected (read status 15 times as delay)
-    for _ in 0..15 {
-        io_delay(channel);
-    }
-
-    // Check BSY cleared
-    for _ in 0..10000 {
-        let s = unsafe { inb(channel.control_port()) };
-        if s & status::BSY == 0 {
-            return true;
-        }
-    }
-
-    false
-}
-
-fn wait_ready(channel: Channel, timeout: u32) -> bool {
-    for _ in 0..timeout {
-        let s = unsafe { inb(channel.control_port()) };
-        if s & status::BSY == 0 {
-            return true;
-        }
-        core::hint::spin_loop();
-    }
-    false
-}
-
-fn wait_drq(channel: Channel, timeout: u32) -> bool {
-    for _ in 0..timeout {
-        let s = unsafe { inb(channel.control_port()) };
-        if s & status::BSY == 0 {
-            if s & status::DRQ != 0 {
-                return true;
-            }
-            if s & (status::ERR | status::DF) != 0 {
-                return false;
-            }
-        }
-        core::hint::spin_loop();
-    }
-    false
-}
-
-fn io_delay(channel: Channel) {
-    // Read alternate status register 4 times for 400ns delay
-    for _ in 0..4 {
-        unsafe { inb(channel.control_port()); }
-    }
-}
-
 // =============================================================================
 // Save/Load Operations
 // =============================================================================
@@ -314,107 +169,134 @@ pub enum LoadResult {
     SizeMismatch,
 }
 
-/// Find save slot for a ROM (returns slot index if found)
-pub fn find_save_slot(rom_name: &str) -> Option<usize> {
-    let device = ata::find_ata_disk()?;
-    let target_hash = hash_rom_name(rom_name);
-
-    let mut sector = [0u8; SECTOR_SIZE];
+/// Where a ROM's save file goes
+#[derive(Clone, Copy)]
+pub struct SaveFile {
+    path: [u8; MAX_PATH],
+    len: usize,
+}
 
-    for slot in 0..MAX_SAVE_SLOTS {
-        let lba = SAVE_AREA_START + (slot as u64 * SECTORS_PER_SLOT);
+impl SaveFile {
+    /// The save file beside the ROM at `rom_path`: the ROM's name with the
+    /// extension changed to `.sav` (`/GAMES/Tetris.gb` -> `/GAMES/Tetris.sav`)
+    ///
+    /// A path too long to change gives a save file that can't be written.
+    pub fn beside(rom_path: &str) -> SaveFile {
+        let name_start = rom_path.rfind('/').map_or(0, |slash| slash + 1);
+        let stem = match rom_path[name_start..].rfind('.') {
+            Some(dot) if dot > 0 => &rom_path[..name_start + dot],
+            _ => rom_path,
+        };
 
-        if ata::read_sectors(device, lba, 1, &mut sector).is_ok() {
-            let header = SaveHeader::from_bytes(&sector);
-            if header.is_valid() && header.rom_hash == target_hash {
-                return Some(slot);
-            }
+        let mut save = SaveFile { path: [0; MAX_PATH], len: 0 };
+        let len = stem.len() + b".sav".len();
+        if len <= MAX_PATH {
+            save.path[..stem.len()].copy_from_slice(stem.as_bytes());
+            save.path[stem.len()..len].copy_from_slice(b".sav");
+            save.len = len;
+        }
+        save
+    }
+
+    /// The file beside the save with the same name and extension `ext`
+    /// (`/GAMES/Tetris.sav` -> `/GAMES/Tetris.cfg`)
+    pub fn sibling(&self, ext: &str) -> SaveFile {
+        let stem = self.len.saturating_sub(b".sav".len());
+        let mut file = SaveFile { path: [0; MAX_PATH], len: 0 };
+        let len = stem + 1 + ext.len();
+        if self.len != 0 && len <= MAX_PATH {
+            file.path[..stem].copy_from_slice(&self.path[..stem]);
+            file.path[stem] = b'.';
+            file.path[stem + 1..len].copy_from_slice(ext.as_bytes());
+            file.len = len;
         }
+        file
     }
 
-    None
+    /// VFS path of the save
+    pub fn path(&self) -> &str {
+        // Copied from a `&str` and cut at an ASCII `.`
+        core::str::from_utf8(&self.path[..self.len]).unwrap_or("")
+    }
 }
 
-/// Find first empty save slot
-fn find_empty_slot() -> Option<usize> {
-    let device = ata::find_ata_disk()?;
-
-    let mut sector = [0u8; SECTOR_SIZE];
-
-    for slot in 0..MAX_SAVE_SLOTS {
-        let lba = SAVE_AREA_START + (slot as u64 * SECTORS_PER_SLOT);
-
-        if ata::read_sectors(device, lba, 1, &mut sector).is_ok() {
-            let header = SaveHeader::from_bytes(&sector);
-            if !header.is_valid() {
-                return Some(slot);
-            }
-        } else {
-            // Read failed - might be uninitialized, use it
-            return Some(slot);
-        }
+/// Save game RAM to `save_file` (written in place if it exists)
+pub fn save_game(save_file: &SaveFile, ram_data: &[u8]) -> SaveResult {
+    if ram_data.is_empty() {
+        return SaveResult::InvalidData;
     }
 
-    // All slots full - overwrite oldest (slot 0 for simplicity)
-    Some(0)
+    match vfs::write_file(save_file.path(), ram_data) {
+        Ok(()) => SaveResult::Success,
+        Err(FsError::NotMounted) => SaveResult::NoDevice,
+        Err(_) => SaveResult::WriteError,
+    }
 }
 
-/// Save game RAM to disk
-pub fn save_game(rom_name: &str, ram_data: &[u8]) -> SaveResult {
-    // Get device
-    let device = match ata::find_ata_disk() {
-        Some(d) => d,
-        None => return SaveResult::NoDevice,
+/// Load game RAM from `save_file`, filling all of `ram_buffer`
+///
+/// Without a save file, a save for `rom_name` in the old slot area is
+/// loaded instead and moved into `save_file`.
+pub fn load_game(save_file: &SaveFile, rom_name: &str, ram_buffer: &mut [u8]) -> LoadResult {
+    let size = match vfs::stat(save_file.path()) {
+        Ok(metadata) => metadata.size,
+        Err(FsError::NotMounted) => return LoadResult::NoDevice,
+        Err(FsError::NotFound) => return migrate_slot(save_file, rom_name, ram_buffer),
+        Err(_) => return LoadResult::ReadError,
     };
 
-    if ram_data.is_empty() {
-        return SaveResult::InvalidData;
+    // Longer is fine: other emulators put RTC data after the RAM
+    if (size as usize) < ram_buffer.len() {
+        return LoadResult::SizeMismatch;
+    }
+    match vfs::read_file(save_file.path(), ram_buffer) {
+        Ok(read) if read == ram_buffer.len() => LoadResult::Success,
+        _ => LoadResult::ReadError,
     }
+}
 
-    // Find or allocate slot
-    let slot = find_save_slot(rom_name).or_else(|| find_empty_slot());
-    let slot = match slot {
-        Some(s) => s,
-        None => return SaveResult::WriteError,
-    };
+/// Check if a save file exists
+pub fn has_save(save_file: &SaveFile) -> bool {
+    vfs::exists(save_file.path())
+}
 
-    let base_lba = SAVE_AREA_START + (slot as u64 * SECTORS_PER_SLOT);
+/// Delete a save file
+pub fn delete_save(save_file: &SaveFile) -> bool {
+    vfs::remove(save_file.path()).is_ok()
+}
 
-    // Create and write header
-    let header = SaveHeader::new(rom_name, ram_data.len());
-    let header_bytes = header.to_bytes();
+// =============================================================================
+// Slot Area Migration
+// =============================================================================
 
-    if write_sectors(device, base_lba, 1, &header_bytes).is_err() {
-        return SaveResult::WriteError;
-    }
+/// Find the old save slot of a ROM (returns slot index if found)
+fn find_save_slot(rom_name: &str) -> Option<usize> {
+    let device = ata::find_ata_disk()?;
+    let target_hash = hash_rom_name(rom_name);
 
-    // Write RAM data
-    let sectors_needed = (ram_data.len() + SECTOR_SIZE - 1) / SECTOR_SIZE;
-    let mut padded_data = alloc::vec![0u8; sectors_needed * SECTOR_SIZE];
-    padded_data[..ram_data.len()].copy_from_slice(ram_data);
+    let mut sector = [0u8; SECTOR_SIZE];
 
-    for (i, chunk) in padded_data.chunks(SECTOR_SIZE).enumerate() {
-        let lba = base_lba + 1 + i as u64;
-        let mut sector = [0u8; SECTOR_SIZE];
-        sector[..chunk.len()].copy_from_slice(chunk);
+    for slot in 0..MAX_SAVE_SLOTS {
+        let lba = SAVE_AREA_START + (slot as u64 * SECTORS_PER_SLOT);
 
-        if write_sectors(device, lba, 1, &sector).is_err() {
-            return SaveResult::WriteError;
+        if ata::read_sectors(device, lba, 1, &mut sector).is_ok() {
+            let header = SaveHeader::from_bytes(&sector);
+            if header.is_valid() && header.rom_hash == target_hash {
+                return Some(slot);
+            }
         }
     }
 
-    SaveResult::Success
+    None
 }
 
-/// Load game RAM from disk
-pub fn load_game(rom_name: &str, ram_buffer: &mut [u8]) -> LoadResult {
-    // Get device
+/// Load a ROM's save from the old slot area
+fn load_slot(rom_name: &str, ram_buffer: &mut [u8]) -> LoadResult {
     let device = match ata::find_ata_disk() {
         Some(d) => d,
         None => return LoadResult::NoDevice,
     };
 
-    // Find save slot
     let slot = match find_save_slot(rom_name) {
         Some(s) => s,
         None => return LoadResult::NoSaveFound,
@@ -435,7 +317,7 @@ pub fn load_game(rom_name: &str, ram_buffer: &mut [u8]) -> LoadResult {
     }
 
     let save_size = header.ram_size as usize;
-    if save_size > ram_buffer.len() {
+    if save_size != ram_buffer.len() {
         return LoadResult::SizeMismatch;
     }
 
@@ -458,28 +340,23 @@ pub fn load_game(rom_name: &str, ram_buffer: &mut [u8]) -> LoadResult {
     LoadResult::Success
 }
 
-/// Check if a save exists for a ROM
-pub fn has_save(rom_name: &str) -> bool {
-    find_save_slot(rom_name).is_some()
-}
-
-/// Delete save for a ROM
-pub fn delete_save(rom_name: &str) -> bool {
-    let device = match ata::find_ata_disk() {
-        Some(d) => d,
-        None => return false,
-    };
-
-    let slot = match find_save_slot(rom_name) {
-        Some(s) => s,
-        None => return false,
-    };
-
-    let lba = SAVE_AREA_START + (slot as u64 * SECTORS_PER_SLOT);
-
-    // Zero out the header to invalidate the slot
-    let zeros = [0u8; SECTOR_SIZE];
-    write_sectors(device, lba, 1, &zeros).is_ok()
+/// Move a ROM's save from the old slot area into `save_file`
+///
+/// The slot is only cleared once the file is written, so a failed
+/// migration is retried on the next launch.
+fn migrate_slot(save_file: &SaveFile, rom_name: &str, ram_buffer: &mut [u8]) -> LoadResult {
+    let result = load_slot(rom_name, ram_buffer);
+    if result != LoadResult::Success {
+        return result;
+    }
+
+    if save_game(save_file, ram_buffer) == SaveResult::Success {
+        if let (Some(device), Some(slot)) = (ata::find_ata_disk(), find_save_slot(rom_name)) {
+            let lba = SAVE_AREA_START + (slot as u64 * SECTORS_PER_SLOT);
+            let _ = ata::write_sectors(device, lba, 1, &[0u8; SECTOR_SIZE]);
+        }
+    }
+    LoadResult::Success
 }
 
 // =============================================================================
@@ -488,38 +365,37 @@ pub fn delete_save(rom_name: &str) -> bool {
 
 use crate::gameboy::Device;
 
-/// Save the current cartridge RAM to disk
+/// Save the current cartridge RAM to `save_file`
 /// Call this periodically or when the game signals a save
-pub fn save_sram(device: &Device) -> SaveResult {
+pub fn save_sram(device: &Device, save_file: &SaveFile) -> SaveResult {
     if !device.ram_is_battery_backed() {
         return SaveResult::NoBattery;
     }
 
-    let rom_name = device.romname();
     let ram_data = device.dumpram();
 
     if ram_data.is_empty() {
         return SaveResult::InvalidData;
     }
 
-    save_game(&rom_name, &ram_data)
+    save_game(save_file, &ram_data)
 }
 
-/// Load saved RAM into the cartridge
+/// Load `save_file` into the cartridge RAM
 /// Call this after creating the Device but before starting emulation
-pub fn load_sram(device: &mut Device) -> LoadResult {
+pub fn load_sram(device: &mut Device, save_file: &SaveFile) -> LoadResult {
     if !device.ram_is_battery_backed() {
         return LoadResult::NoSaveFound;
     }
 
-    let rom_name = device.romname();
-
-    // Allocate buffer for max possible RAM size (32KB)
-    let mut ram_buffer = alloc::vec![0u8; 0x8000];
+    // The MBC only takes RAM of exactly its own size
+    let mut ram_buffer = device.dumpram();
+    if ram_buffer.is_empty() {
+        return LoadResult::NoSaveFound;
+    }
 
-    match load_game(&rom_name, &mut ram_buffer) {
+    match load_game(save_file, &device.romname(), &mut ram_buffer) {
         LoadResult::Success => {
-            // Load into device - the MBC will validate size
             match device.loadram(&ram_buffer) {
                 Ok(_) => LoadResult::Success,
                 Err(_) => LoadResult::SizeMismatch,
@@ -529,14 +405,11 @@ pub fn load_sram(device: &mut Device) -> LoadResult {
     }
 }
 
-/// Check if a save file exists for this ROM
-pub fn has_save_for(device: &Device) -> bool {
-    has_save(&device.romname())
-}
-
 /// Debounced save state tracker
 /// When the game writes to SRAM, we wait for writes to settle before persisting
 pub struct SaveTracker {
+    /// Save file the RAM goes to
+    save_file: SaveFile,
     /// Frames since last RAM write detected
     frames_since_write: u32,
     /// Whether we're waiting to save (RAM was modified)
@@ -548,13 +421,13 @@ pub struct SaveTracker {
 const SAVE_DEBOUNCE_FRAMES: u32 = 120;
 
 impl SaveTracker {
-    pub const fn new() -> Self {
+    pub const fn new(save_file: SaveFile) -> Self {
         Self {
+            save_file,
             frames_since_write: 0,
             pending_save: false,
         }
     }
-
     /// Call this every frame. Returns true if a save should be performed now.
     ///
     /// Logic:
@@ -593,7 +466,7 @@ impl SaveTracker {
 /// Returns true if a save was performed
 pub fn update(tracker: &mut SaveTracker, device: &mut Device) -> bool {
     if tracker.tick(device) {
-        save_sram(device) == SaveResult::Success
+        save_sram(device, &tracker.save_file) == SaveResult::Success
     } else {
         false
     }
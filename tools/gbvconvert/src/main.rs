@@ -0,0 +1,214 @@
+//! gbvconvert - GameBoy OS Recording Decoder
+//!
+//! Decodes the `.GBV` recordings made with F8 in GameBoy OS into raw
+//! 24-bit RGB (for ffmpeg) or a numbered series of PPM images.
+//!
+//! # Usage
+//! ```
+//! gbvconvert <input.gbv>                  # raw rgb24 frames on stdout
+//! gbvconvert <input.gbv> <prefix>         # prefix000000.ppm, ...
+//! ```
+//!
+//! To make an MP4:
+//! ```
+//! gbvconvert VID0000.GBV | ffmpeg -f rawvideo -pixel_format rgb24 \
+//!     -video_size 160x144 -framerate 59.727 -i - -vf scale=640:576:flags=neighbor out.mp4
+//! ```
+//!
+//! # GBV Format
+//!
+//! ```text
+//! Header (16 bytes)
+//!   Offset 0x00: Magic "GBV1" (4 bytes)
+//!   Offset 0x04: Width (2 bytes, little-endian)
+//!   Offset 0x06: Height (2 bytes, little-endian)
+//!   Offset 0x08: Frame rate in millihertz (4 bytes, little-endian)
+//!   Offset 0x0C: Reserved (4 bytes)
+//!
+//! Frames, until the end of the file
+//!   Offset 0x00: Length of the runs (4 bytes, little-endian)
+//!   Offset 0x04: Runs covering every pixel, row by row:
+//!     0x00-0x7F c lo hi  c+1 pixels of RGB555 color hi:lo
+//!     0x80-0xFF          c-0x7F pixels unchanged from the previous frame
+//!                        (black before the first frame)
+//! ```
+//!
+//! A recording cut short (power off while recording) holds what reached
+//! the disk before then: every chunk written updates the file's size, so
+//! it usually ends in a partial frame, which is dropped.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+/// Magic bytes: "GBV1"
+const MAGIC: &[u8; 4] = b"GBV1";
+
+/// Header size
+const HEADER_SIZE: usize = 16;
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 2 {
+        eprintln!("gbvconvert - GameBoy OS Recording Decoder");
+        eprintln!();
+        eprintln!("Usage: {} <input.gbv> [ppm-prefix]", args[0]);
+        eprintln!();
+        eprintln!("Without a prefix, raw rgb24 frames are written to stdout:");
+        eprintln!("  {} VID0000.GBV | ffmpeg -f rawvideo -pixel_format rgb24 \\", args[0]);
+        eprintln!("      -video_size 160x144 -framerate 59.727 -i - out.mp4");
+        std::process::exit(1);
+    }
+
+    let data = fs::read(&args[1])?;
+    let video = match Video::parse(&data) {
+        Ok(video) => video,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("{}x{} at {}.{:03} fps",
+              video.width, video.height, video.frame_rate / 1000, video.frame_rate % 1000);
+
+    let mut frames = 0usize;
+    let mut rgb = Vec::with_capacity(video.width * video.height * 3);
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    let result = video.decode(|pixels| {
+        rgb.clear();
+        rgb.extend(pixels.iter().flat_map(|&c| rgb555_to_rgb8(c)));
+        match args.get(2) {
+            Some(prefix) => {
+                let mut ppm = BufWriter::new(File::create(format!("{}{:06}.ppm", prefix, frames))?);
+                write!(ppm, "P6\n{} {}\n255\n", video.width, video.height)?;
+                ppm.write_all(&rgb)?;
+            }
+            None => stdout.write_all(&rgb)?,
+        }
+        frames += 1;
+        Ok(())
+    });
+    stdout.flush()?;
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    eprintln!("{} frames", frames);
+    Ok(())
+}
+
+/// A parsed recording
+struct Video<'a> {
+    width: usize,
+    height: usize,
+    /// Millihertz
+    frame_rate: u32,
+    frames: &'a [u8],
+}
+
+impl<'a> Video<'a> {
+    fn parse(data: &'a [u8]) -> Result<Video<'a>, String> {
+        if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+            return Err("not a GBV recording".into());
+        }
+        let width = u16::from_le_bytes([data[4], data[5]]) as usize;
+        let height = u16::from_le_bytes([data[6], data[7]]) as usize;
+        let frame_rate = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+        if width == 0 || height == 0 {
+            return Err("empty frame size".into());
+        }
+        Ok(Video { width, height, frame_rate, frames: &data[HEADER_SIZE..] })
+    }
+
+    /// Call `frame` with each frame's RGB555 pixels
+    fn decode(&self, mut frame: impl FnMut(&[u16]) -> io::Result<()>) -> Result<(), String> {
+        let mut pixels = vec![0u16; self.width * self.height];
+        let mut pos = 0;
+        while pos + 4 <= self.frames.len() {
+            let len = u32::from_le_bytes(self.frames[pos..pos + 4].try_into().unwrap()) as usize;
+            let Some(runs) = self.frames.get(pos + 4..pos + 4 + len) else {
+                break; // partial frame at the end
+            };
+            apply_runs(runs, &mut pixels)
+                .map_err(|e| format!("frame at offset {}: {}", HEADER_SIZE + pos, e))?;
+            frame(&pixels).map_err(|e| e.to_string())?;
+            pos += 4 + len;
+        }
+        Ok(())
+    }
+}
+
+/// Update `pixels` from one frame's runs
+fn apply_runs(runs: &[u8], pixels: &mut [u16]) -> Result<(), &'static str> {
+    let mut i = 0;
+    let mut at = 0;
+    while i < runs.len() {
+        let c = runs[i] as usize;
+        if c & 0x80 != 0 {
+            at += (c & 0x7F) + 1;
+            i += 1;
+        } else {
+            let color = runs.get(i + 1..i + 3).ok_or("truncated run")?;
+            let color = u16::from_le_bytes([color[0], color[1]]);
+            let end = at + c + 1;
+            pixels.get_mut(at..end).ok_or("runs overflow the frame")?.fill(color);
+            at = end;
+            i += 3;
+        }
+    }
+    if at != pixels.len() {
+        return Err("runs don't cover the frame");
+    }
+    Ok(())
+}
+
+fn rgb555_to_rgb8(c: u16) -> [u8; 3] {
+    let (r, g, b) = ((c & 0x1F) as u8, (c >> 5 & 0x1F) as u8, (c >> 10 & 0x1F) as u8);
+    [r << 3 | r >> 2, g << 3 | g >> 2, b << 3 | b >> 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs() {
+        let mut pixels = vec![0u16; 6];
+        // 3 pixels of 0x7FFF, 2 skipped, 1 of 0x001F
+        apply_runs(&[0x02, 0xFF, 0x7F, 0x81, 0x00, 0x1F, 0x00], &mut pixels).unwrap();
+        assert_eq!(pixels, [0x7FFF, 0x7FFF, 0x7FFF, 0, 0, 0x001F]);
+
+        // Everything skipped keeps the last frame
+        apply_runs(&[0x85], &mut pixels).unwrap();
+        assert_eq!(pixels, [0x7FFF, 0x7FFF, 0x7FFF, 0, 0, 0x001F]);
+    }
+
+    #[test]
+    fn test_bad_runs() {
+        let mut pixels = vec![0u16; 4];
+        assert!(apply_runs(&[0x82], &mut pixels).is_err());
+        assert!(apply_runs(&[0x84], &mut pixels).is_err());
+        assert!(apply_runs(&[0x03, 0xFF], &mut pixels).is_err());
+    }
+
+    #[test]
+    fn test_partial_frame_dropped() {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&59727u32.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(&[0x01, 0x1F, 0x00]);
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.push(0x81);
+
+        let video = Video::parse(&data).unwrap();
+        let mut frames = Vec::new();
+        video.decode(|p| { frames.push(p.to_vec()); Ok(()) }).unwrap();
+        assert_eq!(frames, [vec![0x001F, 0x001F]]);
+    }
+}